@@ -69,6 +69,7 @@ impl GuiTexture {
             size,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             dimension: blade_graphics::TextureDimension::D2,
             usage: blade_graphics::TextureUsage::COPY | blade_graphics::TextureUsage::RESOURCE,
         });
@@ -132,6 +133,7 @@ impl GuiPainter {
             name: "gui",
             data_layouts: &[&globals_layout, &locals_layout],
             vertex: shader.at("vs_main"),
+            vertex_fetches: &[],
             primitive: blade_graphics::PrimitiveState {
                 topology: blade_graphics::PrimitiveTopology::TriangleList,
                 ..Default::default()
@@ -143,6 +145,9 @@ impl GuiPainter {
                 blend: Some(blade_graphics::BlendState::ALPHA_BLENDING),
                 write_mask: blade_graphics::ColorWrites::all(),
             }],
+            multisample_state: blade_graphics::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
 
         let belt = BufferBelt::new(BeltDescriptor {
@@ -253,11 +258,12 @@ impl GuiPainter {
                     Some([x, y]) => [x as u32, y as u32, 0],
                     None => [0; 3],
                 },
+                aspects: blade_graphics::TexelAspects::empty(),
             };
             copies.push((src, dst, extent));
         }
 
-        if let mut transfer = command_encoder.transfer() {
+        if let mut transfer = command_encoder.transfer_labeled("egui texture update") {
             for (src, dst, extent) in copies {
                 transfer.copy_buffer_to_texture(src, 4 * extent.width, dst, extent);
             }
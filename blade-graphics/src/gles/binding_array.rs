@@ -0,0 +1,838 @@
+//! Lowering of the binding arrays, which GLSL ES can't express.
+//!
+//! Every element of an array becomes a separate global variable,
+//! which is bound to its own slot. Constant indices refer to the element
+//! variables directly, while the operations on a dynamically indexed element
+//! are moved into helper functions that `switch` over the elements.
+//! Implicit derivatives in such operations are only defined
+//! if the index is uniform.
+
+use crate::lowering;
+use naga::{Expression as E, Handle, Statement as S};
+use std::collections::HashMap;
+
+/// Element variables of the lowered binding arrays, mapped to the element index.
+pub type Elements = HashMap<Handle<naga::GlobalVariable>, crate::ResourceIndex>;
+
+pub fn has_binding_arrays(module: &naga::Module) -> bool {
+    module.global_variables.iter().any(|(_, var)| {
+        matches!(
+            module.types[var.ty].inner,
+            naga::TypeInner::BindingArray { .. }
+        )
+    })
+}
+
+/// Lower the binding arrays of a validated module,
+/// returning the element variables and the new module info.
+pub fn lower(
+    module: &mut naga::Module,
+    info: &naga::valid::ModuleInfo,
+) -> Result<(Elements, naga::valid::ModuleInfo), String> {
+    let mut arrays = HashMap::default();
+    let mut elements = Elements::default();
+    let array_vars = module
+        .global_variables
+        .iter()
+        .filter_map(|(handle, var)| match module.types[var.ty].inner {
+            naga::TypeInner::BindingArray { base, size } => Some((handle, base, size)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (handle, base, size) in array_vars {
+        let var = module.global_variables[handle].clone();
+        let count = match size {
+            naga::ArraySize::Constant(count) => count.get(),
+            naga::ArraySize::Dynamic => {
+                return Err(format!(
+                    "Binding array {:?} doesn't have a fixed size",
+                    var.name
+                ))
+            }
+        };
+        let span = module.global_variables.get_span(handle);
+        let element_vars = (0..count)
+            .map(|index| {
+                let element = module.global_variables.append(
+                    naga::GlobalVariable {
+                        ty: base,
+                        binding: None,
+                        ..var.clone()
+                    },
+                    span,
+                );
+                elements.insert(element, index);
+                element
+            })
+            .collect::<Vec<_>>();
+        arrays.insert(handle, element_vars);
+    }
+
+    let mut planner = Planner {
+        types: &mut module.types,
+        arrays: &arrays,
+        helpers: Vec::new(),
+    };
+    let function_plans = module
+        .functions
+        .iter()
+        .map(|(handle, fun)| planner.plan(fun, &info[handle]))
+        .collect::<Result<Vec<_>, _>>()?;
+    let entry_point_plans = module
+        .entry_points
+        .iter()
+        .enumerate()
+        .map(|(index, ep)| planner.plan(&ep.function, info.get_entry_point(index)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let helpers = planner
+        .helpers
+        .into_iter()
+        .map(|helper| module.functions.append(helper, naga::Span::UNDEFINED))
+        .collect::<Vec<_>>();
+    for ((_, fun), plan) in module.functions.iter_mut().zip(function_plans) {
+        plan.apply(fun, &helpers);
+    }
+    for (ep, plan) in module.entry_points.iter_mut().zip(entry_point_plans) {
+        plan.apply(&mut ep.function, &helpers);
+    }
+    // Helpers don't call anything, so they go first,
+    // and every function can still only call the previous ones.
+    lowering::move_functions_first(module, |handle| helpers.contains(&handle));
+
+    let mut validator = naga::valid::Validator::new(
+        // The original module has already been checked for uniformity,
+        // and the dynamically indexed operations are now in the control flow.
+        naga::valid::ValidationFlags::all()
+            ^ naga::valid::ValidationFlags::BINDINGS
+            ^ naga::valid::ValidationFlags::CONTROL_FLOW_UNIFORMITY,
+        naga::valid::Capabilities::all(),
+    );
+    let new_info = validator
+        .validate(module)
+        .map_err(|e| format!("Lowered binding arrays are invalid: {:?}", e))?;
+    Ok((elements, new_info))
+}
+
+/// Helper function call replacing an operation.
+struct HelperCall {
+    helper: usize,
+    arguments: Vec<Handle<E>>,
+}
+
+#[derive(Default)]
+struct Plan {
+    /// Expressions that become the element variables.
+    elements: HashMap<Handle<E>, Handle<naga::GlobalVariable>>,
+    /// Operations moved into helpers, by the result expression.
+    calls: HashMap<Handle<E>, HelperCall>,
+    /// Stores moved into helpers, by the pointer and the value.
+    stores: HashMap<(Handle<E>, Handle<E>), HelperCall>,
+}
+
+struct Planner<'a> {
+    types: &'a mut naga::UniqueArena<naga::Type>,
+    arrays: &'a HashMap<Handle<naga::GlobalVariable>, Vec<Handle<naga::GlobalVariable>>>,
+    helpers: Vec<naga::Function>,
+}
+
+impl Planner<'_> {
+    fn plan(
+        &mut self,
+        fun: &naga::Function,
+        info: &naga::valid::FunctionInfo,
+    ) -> Result<Plan, String> {
+        let mut plan = Plan::default();
+        // Array variables, and the dynamically indexed elements
+        // with everything derived from them, mapped to the element access.
+        let mut array_exprs = HashMap::new();
+        let mut pointers = HashMap::new();
+        for (handle, expr) in fun.expressions.iter() {
+            match *expr {
+                E::GlobalVariable(var) => {
+                    if let Some(element_vars) = self.arrays.get(&var) {
+                        array_exprs.insert(handle, element_vars);
+                        plan.elements.insert(handle, element_vars[0]);
+                    }
+                }
+                E::AccessIndex { base, index } => {
+                    if let Some(element_vars) = array_exprs.get(&base) {
+                        let var = *element_vars.get(index as usize).ok_or_else(|| {
+                            format!("Binding array index {} is out of bounds", index)
+                        })?;
+                        plan.elements.insert(handle, var);
+                    } else if let Some(&root) = pointers.get(&base) {
+                        pointers.insert(handle, root);
+                    }
+                }
+                E::Access { base, .. } => {
+                    if let Some(element_vars) = array_exprs.get(&base) {
+                        pointers.insert(handle, handle);
+                        plan.elements.insert(handle, element_vars[0]);
+                    } else if let Some(&root) = pointers.get(&base) {
+                        pointers.insert(handle, root);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if array_exprs.is_empty() {
+            return Ok(plan);
+        }
+
+        let is_array_use =
+            |handle: Handle<E>| array_exprs.contains_key(&handle) || pointers.contains_key(&handle);
+        for (handle, expr) in fun.expressions.iter() {
+            if plan.elements.contains_key(&handle) || pointers.contains_key(&handle) {
+                continue;
+            }
+            let element = match *expr {
+                E::ImageSample { image, .. }
+                | E::ImageLoad { image, .. }
+                | E::ImageQuery { image, .. } => image,
+                E::Load { pointer } | E::ArrayLength(pointer) => pointer,
+                _ => {
+                    let mut operands = expr.clone();
+                    let mut supported = true;
+                    map_operands(&mut operands, &mut |operand| {
+                        supported &= !is_array_use(operand);
+                        operand
+                    });
+                    if supported {
+                        continue;
+                    }
+                    return Err(format!("Binding array is used by unsupported {:?}", expr));
+                }
+            };
+            let mut operands = expr.clone();
+            let mut supported = true;
+            map_operands(&mut operands, &mut |operand| {
+                supported &= operand == element || !is_array_use(operand);
+                operand
+            });
+            if !supported || array_exprs.contains_key(&element) {
+                return Err(format!("Binding array is used by unsupported {:?}", expr));
+            }
+            if let Some(&root) = pointers.get(&element) {
+                let operation = Operation::Expression(handle);
+                let call = self.make_helper(fun, info, root, element, operation);
+                plan.calls.insert(handle, call);
+            }
+        }
+
+        let mut result = Ok(());
+        for_each_statement(&fun.body, &mut |statement| {
+            if let S::Store { pointer, value } = *statement {
+                if let Some(&root) = pointers.get(&pointer) {
+                    if !is_array_use(value) {
+                        let operation = Operation::Store(value);
+                        let call = self.make_helper(fun, info, root, pointer, operation);
+                        plan.stores.insert((pointer, value), call);
+                        return;
+                    }
+                }
+            }
+            statement_operands(statement, &mut |operand| {
+                if is_array_use(operand) {
+                    result = Err(format!(
+                        "Binding array is used by unsupported {:?}",
+                        statement
+                    ));
+                }
+            });
+        });
+        result.map(|()| plan)
+    }
+
+    /// Create a helper for the operation on a dynamically indexed element.
+    fn make_helper(
+        &mut self,
+        fun: &naga::Function,
+        info: &naga::valid::FunctionInfo,
+        root: Handle<E>,
+        pointer: Handle<E>,
+        operation: Operation,
+    ) -> HelperCall {
+        let (array, index) = match fun.expressions[root] {
+            E::Access { base, index } => (base, index),
+            _ => unreachable!(),
+        };
+        let element_vars = match fun.expressions[array] {
+            E::GlobalVariable(var) => &self.arrays[&var],
+            _ => unreachable!(),
+        };
+
+        // Expressions to evaluate in the helper, in order
+        let mut inner = Vec::new();
+        let mut handle = pointer;
+        while handle != root {
+            inner.push(handle);
+            handle = match fun.expressions[handle] {
+                E::Access { base, .. } | E::AccessIndex { base, .. } => base,
+                _ => unreachable!(),
+            };
+        }
+        inner.reverse();
+        if let Operation::Expression(result) = operation {
+            inner.push(result);
+        }
+
+        let mut builder = HelperBuilder {
+            function: naga::Function {
+                name: Some(format!("_binding_array_helper{}", self.helpers.len())),
+                result: match operation {
+                    Operation::Expression(result) => Some(naga::FunctionResult {
+                        ty: resolve_type(self.types, &info[result].ty),
+                        binding: None,
+                    }),
+                    Operation::Store(_) => None,
+                },
+                ..Default::default()
+            },
+            arguments: Vec::new(),
+            map: HashMap::default(),
+        };
+        builder.capture(index, fun, info, self.types);
+        for &handle in inner.iter() {
+            let mut operands = fun.expressions[handle].clone();
+            map_operands(&mut operands, &mut |operand| {
+                if operand != root && !inner.contains(&operand) {
+                    builder.capture(operand, fun, info, self.types);
+                }
+                operand
+            });
+        }
+        if let Operation::Store(value) = operation {
+            builder.capture(value, fun, info, self.types);
+        }
+
+        let signed = match *info[index].ty.inner_with(self.types) {
+            naga::TypeInner::Scalar {
+                kind: naga::ScalarKind::Sint,
+                ..
+            } => true,
+            _ => false,
+        };
+        let cases = element_vars
+            .iter()
+            .enumerate()
+            .map(|(element, &var)| {
+                let expressions = &mut builder.function.expressions;
+                let mut map = builder.map.clone();
+                map.insert(
+                    root,
+                    expressions.append(E::GlobalVariable(var), naga::Span::UNDEFINED),
+                );
+                let mut body = naga::Block::new();
+                if let Some(&last) = inner.last() {
+                    let mut first = None;
+                    for &handle in inner.iter() {
+                        let mut expr = fun.expressions[handle].clone();
+                        map_operands(&mut expr, &mut |operand| map[&operand]);
+                        let new_handle = expressions.append(expr, naga::Span::UNDEFINED);
+                        first.get_or_insert(new_handle);
+                        map.insert(handle, new_handle);
+                    }
+                    body.push(
+                        S::Emit(naga::Range::new_from_bounds(first.unwrap(), map[&last])),
+                        naga::Span::UNDEFINED,
+                    );
+                }
+                body.push(
+                    match operation {
+                        Operation::Expression(result) => S::Return {
+                            value: Some(map[&result]),
+                        },
+                        Operation::Store(value) => S::Store {
+                            pointer: map[&pointer],
+                            value: map[&value],
+                        },
+                    },
+                    naga::Span::UNDEFINED,
+                );
+                naga::SwitchCase {
+                    value: if element + 1 == element_vars.len() {
+                        naga::SwitchValue::Default
+                    } else if signed {
+                        naga::SwitchValue::I32(element as i32)
+                    } else {
+                        naga::SwitchValue::U32(element as u32)
+                    },
+                    body,
+                    fall_through: false,
+                }
+            })
+            .collect();
+        let selector = builder.map[&index];
+        builder
+            .function
+            .body
+            .push(S::Switch { selector, cases }, naga::Span::UNDEFINED);
+
+        self.helpers.push(builder.function);
+        HelperCall {
+            helper: self.helpers.len() - 1,
+            arguments: builder.arguments,
+        }
+    }
+}
+
+/// Operation on a dynamically indexed element.
+#[derive(Clone, Copy)]
+enum Operation {
+    /// Expression using the element.
+    Expression(Handle<E>),
+    /// Store of the value into the element.
+    Store(Handle<E>),
+}
+
+struct HelperBuilder {
+    function: naga::Function,
+    /// Expressions of the caller, passed as arguments.
+    arguments: Vec<Handle<E>>,
+    map: HashMap<Handle<E>, Handle<E>>,
+}
+
+impl HelperBuilder {
+    /// Make an expression of the caller available to the helper,
+    /// copying the global and constant values, and passing the others.
+    fn capture(
+        &mut self,
+        handle: Handle<E>,
+        fun: &naga::Function,
+        info: &naga::valid::FunctionInfo,
+        types: &mut naga::UniqueArena<naga::Type>,
+    ) {
+        if self.map.contains_key(&handle) {
+            return;
+        }
+        let expr = match fun.expressions[handle] {
+            ref
+            expr @ (E::Literal(_) | E::Constant(_) | E::ZeroValue(_) | E::GlobalVariable(_)) => {
+                expr.clone()
+            }
+            _ => {
+                self.function.arguments.push(naga::FunctionArgument {
+                    name: None,
+                    ty: resolve_type(types, &info[handle].ty),
+                    binding: None,
+                });
+                self.arguments.push(handle);
+                E::FunctionArgument(self.arguments.len() as u32 - 1)
+            }
+        };
+        let new_handle = self
+            .function
+            .expressions
+            .append(expr, naga::Span::UNDEFINED);
+        self.map.insert(handle, new_handle);
+    }
+}
+
+fn resolve_type(
+    types: &mut naga::UniqueArena<naga::Type>,
+    resolution: &naga::proc::TypeResolution,
+) -> Handle<naga::Type> {
+    match *resolution {
+        naga::proc::TypeResolution::Handle(ty) => ty,
+        naga::proc::TypeResolution::Value(ref inner) => types.insert(
+            naga::Type {
+                name: None,
+                inner: inner.clone(),
+            },
+            naga::Span::UNDEFINED,
+        ),
+    }
+}
+
+impl Plan {
+    fn apply(&self, fun: &mut naga::Function, helpers: &[Handle<naga::Function>]) {
+        for (&handle, &var) in self.elements.iter() {
+            fun.expressions[handle] = E::GlobalVariable(var);
+        }
+        let mut call_arguments = HashMap::default();
+        for (&handle, call) in self.calls.iter() {
+            fun.expressions[handle] = E::CallResult(helpers[call.helper]);
+            call_arguments.insert(handle, call.arguments.clone());
+        }
+        lowering::rewrite_block(
+            &mut fun.body,
+            &fun.expressions,
+            &call_arguments,
+            &mut |statement| {
+                if let S::Store { pointer, value } = *statement {
+                    if let Some(call) = self.stores.get(&(pointer, value)) {
+                        *statement = S::Call {
+                            function: helpers[call.helper],
+                            arguments: call.arguments.clone(),
+                            result: None,
+                        };
+                    }
+                }
+            },
+        );
+    }
+}
+
+fn for_each_statement(block: &naga::Block, f: &mut impl FnMut(&S)) {
+    for statement in block.iter() {
+        f(statement);
+        match *statement {
+            S::Block(ref inner) => for_each_statement(inner, f),
+            S::If {
+                ref accept,
+                ref reject,
+                ..
+            } => {
+                for_each_statement(accept, f);
+                for_each_statement(reject, f);
+            }
+            S::Switch { ref cases, .. } => {
+                for case in cases.iter() {
+                    for_each_statement(&case.body, f);
+                }
+            }
+            S::Loop {
+                ref body,
+                ref continuing,
+                ..
+            } => {
+                for_each_statement(body, f);
+                for_each_statement(continuing, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Visit the expressions used by a statement, not including the nested blocks.
+fn statement_operands(statement: &S, f: &mut impl FnMut(Handle<E>)) {
+    match *statement {
+        S::Emit(_)
+        | S::Block(_)
+        | S::Break
+        | S::Continue
+        | S::Kill
+        | S::Barrier(_)
+        | S::Return { value: None } => {}
+        S::If { condition, .. } => f(condition),
+        S::Switch { selector, .. } => f(selector),
+        S::Loop { break_if, .. } => break_if.into_iter().for_each(f),
+        S::Return { value: Some(value) } => f(value),
+        S::Store { pointer, value } => {
+            f(pointer);
+            f(value);
+        }
+        S::ImageStore {
+            image,
+            coordinate,
+            array_index,
+            value,
+        } => {
+            f(image);
+            f(coordinate);
+            array_index.into_iter().for_each(&mut *f);
+            f(value);
+        }
+        S::Atomic {
+            pointer,
+            ref fun,
+            value,
+            result: _,
+        } => {
+            f(pointer);
+            if let naga::AtomicFunction::Exchange {
+                compare: Some(compare),
+            } = *fun
+            {
+                f(compare);
+            }
+            f(value);
+        }
+        S::WorkGroupUniformLoad { pointer, result: _ } => f(pointer),
+        S::Call { ref arguments, .. } => arguments.iter().cloned().for_each(f),
+        S::RayQuery { query, ref fun } => {
+            f(query);
+            if let naga::RayQueryFunction::Initialize {
+                acceleration_structure,
+                descriptor,
+            } = *fun
+            {
+                f(acceleration_structure);
+                f(descriptor);
+            }
+        }
+    }
+}
+
+/// Replace the expressions used by an expression,
+/// not including the constant expressions.
+fn map_operands(expr: &mut E, f: &mut impl FnMut(Handle<E>) -> Handle<E>) {
+    let mut map = |handle: &mut Handle<E>| *handle = f(*handle);
+    match *expr {
+        E::Literal(_)
+        | E::Constant(_)
+        | E::ZeroValue(_)
+        | E::FunctionArgument(_)
+        | E::GlobalVariable(_)
+        | E::LocalVariable(_)
+        | E::CallResult(_)
+        | E::AtomicResult { .. }
+        | E::WorkGroupUniformLoadResult { .. }
+        | E::RayQueryProceedResult => {}
+        E::Compose {
+            ref mut components, ..
+        } => components.iter_mut().for_each(map),
+        E::Access {
+            ref mut base,
+            ref mut index,
+        } => {
+            map(base);
+            map(index);
+        }
+        E::AccessIndex { ref mut base, .. } => map(base),
+        E::Splat { ref mut value, .. } => map(value),
+        E::Swizzle { ref mut vector, .. } => map(vector),
+        E::Load { ref mut pointer } => map(pointer),
+        E::ImageSample {
+            ref mut image,
+            ref mut sampler,
+            ref mut coordinate,
+            ref mut array_index,
+            ref mut level,
+            ref mut depth_ref,
+            ..
+        } => {
+            map(image);
+            map(sampler);
+            map(coordinate);
+            array_index.iter_mut().for_each(&mut map);
+            match *level {
+                naga::SampleLevel::Auto | naga::SampleLevel::Zero => {}
+                naga::SampleLevel::Exact(ref mut value)
+                | naga::SampleLevel::Bias(ref mut value) => map(value),
+                naga::SampleLevel::Gradient {
+                    ref mut x,
+                    ref mut y,
+                } => {
+                    map(x);
+                    map(y);
+                }
+            }
+            depth_ref.iter_mut().for_each(map);
+        }
+        E::ImageLoad {
+            ref mut image,
+            ref mut coordinate,
+            ref mut array_index,
+            ref mut sample,
+            ref mut level,
+        } => {
+            map(image);
+            map(coordinate);
+            array_index.iter_mut().for_each(&mut map);
+            sample.iter_mut().for_each(&mut map);
+            level.iter_mut().for_each(map);
+        }
+        E::ImageQuery {
+            ref mut image,
+            ref mut query,
+        } => {
+            map(image);
+            if let naga::ImageQuery::Size {
+                level: Some(ref mut level),
+            } = *query
+            {
+                map(level);
+            }
+        }
+        E::Unary { ref mut expr, .. }
+        | E::Derivative { ref mut expr, .. }
+        | E::As { ref mut expr, .. } => map(expr),
+        E::Binary {
+            ref mut left,
+            ref mut right,
+            ..
+        } => {
+            map(left);
+            map(right);
+        }
+        E::Select {
+            ref mut condition,
+            ref mut accept,
+            ref mut reject,
+        } => {
+            map(condition);
+            map(accept);
+            map(reject);
+        }
+        E::Relational {
+            ref mut argument, ..
+        } => map(argument),
+        E::Math {
+            ref mut arg,
+            ref mut arg1,
+            ref mut arg2,
+            ref mut arg3,
+            ..
+        } => {
+            map(arg);
+            arg1.iter_mut()
+                .chain(arg2.iter_mut())
+                .chain(arg3.iter_mut())
+                .for_each(map);
+        }
+        E::ArrayLength(ref mut array) => map(array),
+        E::RayQueryGetIntersection { ref mut query, .. } => map(query),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use naga::{Expression as E, Statement as S};
+
+    const SHADER: &str = "
+struct Data { value: vec4<f32> }
+@group(0) @binding(0) var textures: binding_array<texture_2d<f32>, 4>;
+@group(0) @binding(1) var<storage, read_write> buffers: binding_array<Data, 3>;
+@group(0) @binding(2) var<storage, read_write> output: array<vec4<f32>>;
+
+@compute @workgroup_size(1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let fixed = textureLoad(textures[2], vec2<i32>(0), 0);
+    let dynamic = textureLoad(textures[id.x], vec2<i32>(0), 0);
+    buffers[id.y].value = fixed + dynamic + buffers[1].value;
+    output[id.x] = buffers[id.x].value;
+}
+";
+
+    #[test]
+    fn lower_binding_arrays() {
+        let mut module = naga::front::wgsl::parse_str(SHADER).unwrap();
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .unwrap();
+        assert!(super::has_binding_arrays(&module));
+        let (array_vars, _) = module
+            .global_variables
+            .iter()
+            .map(|(handle, _)| handle)
+            .partition::<Vec<_>, _>(|&handle| {
+                matches!(
+                    module.types[module.global_variables[handle].ty].inner,
+                    naga::TypeInner::BindingArray { .. }
+                )
+            });
+
+        let (elements, new_info) = super::lower(&mut module, &info).unwrap();
+
+        // Each element becomes a variable of the base type, without a binding yet.
+        let mut indices = elements
+            .iter()
+            .map(|(&handle, &index)| {
+                let var = &module.global_variables[handle];
+                assert_eq!(var.binding, None);
+                (var.name.clone().unwrap(), index)
+            })
+            .collect::<Vec<_>>();
+        indices.sort();
+        let expected = [("buffers", 0..3), ("textures", 0..4)]
+            .into_iter()
+            .flat_map(|(name, range)| range.map(move |index| (name.to_string(), index)))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, expected);
+
+        // The arrays are no longer accessed, and the operations
+        // on the dynamically indexed elements are in the helpers.
+        let functions = module
+            .functions
+            .iter()
+            .map(|(_, fun)| fun)
+            .chain(module.entry_points.iter().map(|ep| &ep.function))
+            .collect::<Vec<_>>();
+        for fun in functions.iter() {
+            for (_, expr) in fun.expressions.iter() {
+                if let E::GlobalVariable(var) = *expr {
+                    assert!(!array_vars.contains(&var), "{:?} still accessed", fun.name);
+                }
+            }
+        }
+        let helpers = module
+            .functions
+            .iter()
+            .filter(|&(_, fun)| {
+                fun.name
+                    .as_deref()
+                    .unwrap()
+                    .starts_with("_binding_array_helper")
+            })
+            .map(|(_, fun)| fun)
+            .collect::<Vec<_>>();
+        // The dynamic texture load, the store, and the buffer load.
+        assert_eq!(helpers.len(), 3);
+        let mut case_counts = helpers
+            .iter()
+            .map(|helper| match *helper.body.iter().last().unwrap() {
+                S::Switch { ref cases, .. } => {
+                    assert_eq!(cases.last().unwrap().value, naga::SwitchValue::Default);
+                    cases.len()
+                }
+                ref other => panic!("Helper ends with {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        case_counts.sort();
+        assert_eq!(case_counts, [3, 3, 4]);
+
+        // The constant index refers to the element directly.
+        let texture2 = elements
+            .iter()
+            .find(|&(&handle, &index)| {
+                index == 2 && module.global_variables[handle].name.as_deref() == Some("textures")
+            })
+            .map(|(&handle, _)| handle)
+            .unwrap();
+        let main = &module.entry_points[0].function;
+        assert!(main
+            .expressions
+            .iter()
+            .any(|(_, expr)| *expr == E::GlobalVariable(texture2)));
+        assert!(main
+            .body
+            .iter()
+            .any(|statement| matches!(*statement, S::Call { .. })));
+
+        // The lowered module can be written out.
+        for (binding, (_, var)) in module.global_variables.iter_mut().enumerate() {
+            var.binding = Some(naga::ResourceBinding {
+                group: 0,
+                binding: binding as u32,
+            });
+        }
+        let mut source = String::new();
+        naga::back::glsl::Writer::new(
+            &mut source,
+            &module,
+            &new_info,
+            &naga::back::glsl::Options {
+                version: naga::back::glsl::Version::Embedded {
+                    version: 310,
+                    is_webgl: false,
+                },
+                ..Default::default()
+            },
+            &naga::back::glsl::PipelineOptions {
+                shader_stage: naga::ShaderStage::Compute,
+                entry_point: "main".to_string(),
+                multiview: None,
+            },
+            Default::default(),
+        )
+        .unwrap()
+        .write()
+        .unwrap();
+        assert!(source.contains("switch"));
+    }
+}
@@ -1,3 +1,7 @@
+use crate::software_rt;
+use std::{mem::size_of, ptr, time::Duration};
+
+const MAX_VERTEX_ATTRIBUTES: u32 = 16;
 const COLOR_ATTACHMENTS: &[u32] = &[
     glow::COLOR_ATTACHMENT0,
     glow::COLOR_ATTACHMENT1,
@@ -20,7 +24,7 @@ impl<T: bytemuck::Pod> crate::ShaderBindable for T {
         ctx.plain_data
             .extend((self_slice.len() as u32..size).map(|_| 0));
 
-        for &slot in ctx.targets[index as usize].iter() {
+        for &(slot, _) in ctx.targets[index as usize].iter() {
             ctx.commands
                 .push(super::Command::BindUniform { slot, offset, size });
         }
@@ -29,7 +33,7 @@ impl<T: bytemuck::Pod> crate::ShaderBindable for T {
 impl crate::ShaderBindable for super::TextureView {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
         let (texture, target) = self.inner.as_native();
-        for &slot in ctx.targets[index as usize].iter() {
+        for &(slot, _) in ctx.targets[index as usize].iter() {
             ctx.commands.push(super::Command::BindTexture {
                 slot,
                 texture,
@@ -39,13 +43,25 @@ impl crate::ShaderBindable for super::TextureView {
     }
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::TextureArray<N> {
-    fn bind_to(&self, _ctx: &mut super::PipelineContext, _index: u32) {
-        unimplemented!()
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        // Elements that are not allocated are left unbound
+        for &(slot, element) in ctx.targets[index as usize].iter() {
+            let view = match self.data.get(element as usize) {
+                Some(view) => view,
+                None => continue,
+            };
+            let (texture, target) = view.inner.as_native();
+            ctx.commands.push(super::Command::BindTexture {
+                slot,
+                texture,
+                target,
+            });
+        }
     }
 }
 impl crate::ShaderBindable for super::Sampler {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
-        for &slot in ctx.targets[index as usize].iter() {
+        for &(slot, _) in ctx.targets[index as usize].iter() {
             ctx.commands.push(super::Command::BindSampler {
                 slot,
                 sampler: self.raw,
@@ -55,7 +71,7 @@ impl crate::ShaderBindable for super::Sampler {
 }
 impl crate::ShaderBindable for crate::BufferPiece {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
-        for &slot in ctx.targets[index as usize].iter() {
+        for &(slot, _) in ctx.targets[index as usize].iter() {
             ctx.commands.push(super::Command::BindBuffer {
                 target: glow::SHADER_STORAGE_BUFFER,
                 slot,
@@ -65,14 +81,31 @@ impl crate::ShaderBindable for crate::BufferPiece {
     }
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::BufferArray<N> {
-    fn bind_to(&self, _ctx: &mut super::PipelineContext, _index: u32) {
-        unimplemented!()
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        for &(slot, element) in ctx.targets[index as usize].iter() {
+            let piece = match self.data.get(element as usize) {
+                Some(&piece) => piece,
+                None => continue,
+            };
+            ctx.commands.push(super::Command::BindBuffer {
+                target: glow::SHADER_STORAGE_BUFFER,
+                slot,
+                buffer: piece.into(),
+            });
+        }
     }
 }
 impl crate::ShaderBindable for super::AccelerationStructure {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
-        for _ in ctx.targets[index as usize].iter() {
-            unimplemented!()
+        for &(slot, _) in ctx.targets[index as usize].iter() {
+            ctx.commands.push(super::Command::BindBuffer {
+                target: glow::SHADER_STORAGE_BUFFER,
+                slot,
+                buffer: super::BufferPart {
+                    raw: self.raw,
+                    offset: 0,
+                },
+            });
         }
     }
 }
@@ -82,6 +115,7 @@ impl super::CommandEncoder {
         self.commands.clear();
         self.plain_data.clear();
         self.has_present = false;
+        self.timed_pass_names.clear();
     }
 
     pub fn init_texture(&mut self, _texture: super::Texture) {}
@@ -90,38 +124,127 @@ impl super::CommandEncoder {
         self.has_present = true;
     }
 
-    pub fn transfer(&mut self) -> super::PassEncoder<()> {
+    /// Wait for the work of another encoder before executing this one.
+    /// All commands are executed by a single context, so they are already ordered.
+    pub fn wait_for(&mut self, _sp: &super::SyncPoint) {}
+
+    /// Return the timings of the passes executed by the previous submission,
+    /// which are collected when the encoder is submitted again.
+    pub fn timings(&self) -> &crate::Timings {
+        &self.timings
+    }
+
+    /// Collect the timer queries of the previous submission.
+    pub(super) unsafe fn resolve_timings(&mut self, gl: &glow::Context) {
+        use glow::HasContext as _;
+        self.timings.clear();
+        let last_query = match self.timer_queries.last() {
+            Some(&(_, query)) => query,
+            None => return,
+        };
+        let available = gl.get_query_parameter_u32(last_query, glow::QUERY_RESULT_AVAILABLE) != 0;
+        // The results are undefined if the GPU timer got interrupted.
+        let disjoint = gl.get_parameter_i32(super::GPU_DISJOINT) != 0;
+        if !available {
+            log::warn!("Timer queries are not ready, the timings are skipped");
+        } else if disjoint {
+            log::warn!("GPU timer got disjoint, the timings are skipped");
+        }
+        for (name, query) in self.timer_queries.drain(..) {
+            if available && !disjoint {
+                let nanos = gl.get_query_parameter_u32(query, glow::QUERY_RESULT);
+                self.timings
+                    .push((name, Duration::from_nanos(nanos as u64)));
+            }
+            gl.delete_query(query);
+        }
+    }
+
+    fn begin_pass(&mut self, label: &str) {
+        if self.timing {
+            let index = self.timed_pass_names.len() as u32;
+            self.timed_pass_names.push(label.to_string());
+            self.commands.push(super::Command::StartTimer(index));
+        }
+    }
+
+    pub fn transfer(&mut self) -> super::PassEncoder<'_, ()> {
+        self.transfer_labeled("transfer")
+    }
+
+    /// Same as `transfer`, but the pass is labeled for debugging and timing.
+    pub fn transfer_labeled(&mut self, label: &str) -> super::PassEncoder<'_, ()> {
+        self.begin_pass(label);
         super::PassEncoder {
             commands: &mut self.commands,
             plain_data: &mut self.plain_data,
             kind: super::PassKind::Transfer,
             invalidate_attachments: Vec::new(),
+            resolve_attachments: Vec::new(),
             pipeline: Default::default(),
             limits: &self.limits,
+            software_rt: &self.software_rt,
         }
     }
 
-    pub fn acceleration_structure(&mut self) -> super::PassEncoder<()> {
-        unimplemented!()
+    pub fn acceleration_structure(&mut self) -> super::PassEncoder<'_, ()> {
+        self.acceleration_structure_labeled("acceleration structure")
     }
 
-    pub fn compute(&mut self) -> super::PassEncoder<super::ComputePipeline> {
+    /// Same as `acceleration_structure`, but the pass is labeled for debugging and timing.
+    pub fn acceleration_structure_labeled(&mut self, label: &str) -> super::PassEncoder<'_, ()> {
+        self.begin_pass(label);
+        super::PassEncoder {
+            commands: &mut self.commands,
+            plain_data: &mut self.plain_data,
+            kind: super::PassKind::AccelerationStructure,
+            invalidate_attachments: Vec::new(),
+            resolve_attachments: Vec::new(),
+            pipeline: Default::default(),
+            limits: &self.limits,
+            software_rt: &self.software_rt,
+        }
+    }
+
+    pub fn compute(&mut self) -> super::PassEncoder<'_, super::ComputePipeline> {
+        self.compute_labeled("compute")
+    }
+
+    /// Same as `compute`, but the pass is labeled for debugging and timing.
+    pub fn compute_labeled(
+        &mut self,
+        label: &str,
+    ) -> super::PassEncoder<'_, super::ComputePipeline> {
+        self.begin_pass(label);
         super::PassEncoder {
             commands: &mut self.commands,
             plain_data: &mut self.plain_data,
             kind: super::PassKind::Compute,
             invalidate_attachments: Vec::new(),
+            resolve_attachments: Vec::new(),
             pipeline: Default::default(),
             limits: &self.limits,
+            software_rt: &self.software_rt,
         }
     }
 
     pub fn render(
         &mut self,
         targets: crate::RenderTargetSet,
-    ) -> super::PassEncoder<super::RenderPipeline> {
+    ) -> super::PassEncoder<'_, super::RenderPipeline> {
+        self.render_labeled("render", targets)
+    }
+
+    /// Same as `render`, but the pass is labeled for debugging and timing.
+    pub fn render_labeled(
+        &mut self,
+        label: &str,
+        targets: crate::RenderTargetSet,
+    ) -> super::PassEncoder<'_, super::RenderPipeline> {
+        self.begin_pass(label);
         let mut target_size = [0u16; 2];
         let mut invalidate_attachments = Vec::new();
+        let mut resolve_attachments = Vec::new();
         for (i, rt) in targets.colors.iter().enumerate() {
             let attachment = glow::COLOR_ATTACHMENT0 + i as u32;
             target_size = rt.view.target_size;
@@ -129,8 +252,10 @@ impl super::CommandEncoder {
                 attachment,
                 view: rt.view,
             });
-            if let crate::FinishOp::Discard = rt.finish_op {
-                invalidate_attachments.push(attachment);
+            match rt.finish_op {
+                crate::FinishOp::Discard => invalidate_attachments.push(attachment),
+                crate::FinishOp::ResolveTo(view) => resolve_attachments.push((attachment, view)),
+                crate::FinishOp::Store | crate::FinishOp::Ignore => {}
             }
         }
         if let Some(ref rt) = targets.depth_stencil {
@@ -144,8 +269,10 @@ impl super::CommandEncoder {
                 attachment,
                 view: rt.view,
             });
-            if let crate::FinishOp::Discard = rt.finish_op {
-                invalidate_attachments.push(attachment);
+            match rt.finish_op {
+                crate::FinishOp::Discard => invalidate_attachments.push(attachment),
+                crate::FinishOp::ResolveTo(view) => resolve_attachments.push((attachment, view)),
+                crate::FinishOp::Store | crate::FinishOp::Ignore => {}
             }
         }
 
@@ -178,7 +305,14 @@ impl super::CommandEncoder {
                     } else {
                         None
                     },
-                    stencil: None, //TODO
+                    stencil: if rt.view.aspects.contains(crate::TexelAspects::STENCIL) {
+                        Some(match color {
+                            crate::TextureColor::White => !0,
+                            _ => 0,
+                        })
+                    } else {
+                        None
+                    },
                 });
             }
         }
@@ -188,12 +322,82 @@ impl super::CommandEncoder {
             plain_data: &mut self.plain_data,
             kind: super::PassKind::Render,
             invalidate_attachments,
+            resolve_attachments,
             pipeline: Default::default(),
             limits: &self.limits,
+            software_rt: &self.software_rt,
         }
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::CommandEncoder {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, ()> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, super::ComputePipeline> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, super::RenderPipeline> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
 impl super::PassEncoder<'_, super::ComputePipeline> {
     pub fn with<'b>(
         &'b mut self,
@@ -205,6 +409,8 @@ impl super::PassEncoder<'_, super::ComputePipeline> {
             commands: self.commands,
             plain_data: self.plain_data,
             bind_group_infos: &pipeline.inner.bind_group_infos,
+            vertex_buffers: &[],
+            stencil: None,
             topology: 0,
             limits: self.limits,
         }
@@ -222,15 +428,82 @@ impl super::PassEncoder<'_, super::RenderPipeline> {
             commands: self.commands,
             plain_data: self.plain_data,
             bind_group_infos: &pipeline.inner.bind_group_infos,
+            vertex_buffers: &pipeline.vertex_buffers,
+            stencil: Some(&pipeline.stencil),
             topology: map_primitive_topology(pipeline.topology),
             limits: self.limits,
         }
     }
+
+    pub fn execute_bundle(&mut self, bundle: &super::RenderBundle) {
+        let alignment = self.limits.uniform_buffer_alignment as usize;
+        let rem = self.plain_data.len() % alignment;
+        if rem != 0 {
+            self.plain_data
+                .resize(self.plain_data.len() - rem + alignment, 0);
+        }
+        let base_offset = self.plain_data.len() as u32;
+        self.plain_data.extend_from_slice(&bundle.plain_data);
+        self.commands
+            .extend(bundle.commands.iter().map(|command| match *command {
+                super::Command::BindUniform { slot, offset, size } => super::Command::BindUniform {
+                    slot,
+                    offset: base_offset + offset,
+                    size,
+                },
+                ref other => other.clone(),
+            }));
+    }
+}
+
+impl super::Context {
+    pub fn try_create_render_bundle_encoder(
+        &self,
+        _desc: crate::RenderBundleDesc,
+    ) -> Result<super::RenderBundleEncoder, crate::Error> {
+        Ok(super::RenderBundleEncoder {
+            commands: Vec::new(),
+            plain_data: Vec::new(),
+            limits: self.limits.clone(),
+        })
+    }
+
+    pub fn destroy_render_bundle(&self, _bundle: super::RenderBundle) {}
+}
+
+impl super::RenderBundleEncoder {
+    pub fn with<'b>(
+        &'b mut self,
+        pipeline: &'b super::RenderPipeline,
+    ) -> super::PipelineEncoder<'b> {
+        self.commands
+            .push(super::Command::SetProgram(pipeline.inner.program));
+        super::PipelineEncoder {
+            commands: &mut self.commands,
+            plain_data: &mut self.plain_data,
+            bind_group_infos: &pipeline.inner.bind_group_infos,
+            vertex_buffers: &pipeline.vertex_buffers,
+            stencil: Some(&pipeline.stencil),
+            topology: map_primitive_topology(pipeline.topology),
+            limits: &self.limits,
+        }
+    }
+
+    pub fn finish(self) -> super::RenderBundle {
+        super::RenderBundle {
+            commands: self.commands,
+            plain_data: self.plain_data,
+        }
+    }
 }
 
 impl<T> Drop for super::PassEncoder<'_, T> {
     fn drop(&mut self) {
         self.commands.push(super::Command::UnsetProgram);
+        for (attachment, dst) in self.resolve_attachments.drain(..) {
+            self.commands
+                .push(super::Command::ResolveAttachment { attachment, dst });
+        }
         for attachment in self.invalidate_attachments.drain(..) {
             self.commands
                 .push(super::Command::InvalidateAttachment(attachment));
@@ -239,9 +512,12 @@ impl<T> Drop for super::PassEncoder<'_, T> {
             super::PassKind::Transfer | super::PassKind::AccelerationStructure => {}
             super::PassKind::Compute => {
                 self.commands.push(super::Command::ResetAllSamplers);
+                // Make the storage writes visible to the following passes
+                self.commands.push(super::Command::Barrier);
             }
             super::PassKind::Render => {
                 self.commands.push(super::Command::ResetAllSamplers);
+                self.commands.push(super::Command::ResetVertexAttributes);
                 self.commands.push(super::Command::ResetFramebuffer);
             }
         }
@@ -290,6 +566,11 @@ impl crate::traits::TransferEncoder for super::PassEncoder<'_, ()> {
         dst: crate::TexturePiece,
         size: crate::Extent,
     ) {
+        let format = dst.texture.format;
+        if format.select_aspects(dst.aspects) != format.aspects() {
+            log::error!("Uploading a single aspect of {:?} is not supported", format);
+            return;
+        }
         self.commands.push(super::Command::CopyBufferToTexture {
             src: src.into(),
             bytes_per_row,
@@ -314,26 +595,100 @@ impl crate::traits::TransferEncoder for super::PassEncoder<'_, ()> {
     }
 }
 
+impl super::AccelerationStructure {
+    /// Storage of the structure, as seen by the build.
+    fn piece(&self) -> crate::BufferPiece {
+        super::Buffer {
+            raw: self.raw,
+            size: self.size,
+            data: ptr::null_mut(),
+        }
+        .at(0)
+    }
+}
+
+impl software_rt::BuildEncoder for super::PassEncoder<'_, ()> {
+    // There are no placeholder buffers, so the mesh transforms are always applied.
+    fn has_memory(_piece: &crate::BufferPiece) -> bool {
+        true
+    }
+
+    fn dispatch<D: crate::ShaderData>(
+        &mut self,
+        pipeline: &super::ComputePipeline,
+        data: &D,
+        groups: [u32; 3],
+    ) {
+        self.commands
+            .push(super::Command::SetProgram(pipeline.inner.program));
+        data.fill(super::PipelineContext {
+            commands: self.commands,
+            plain_data: self.plain_data,
+            targets: &pipeline.inner.bind_group_infos[0].targets,
+            limits: self.limits,
+        });
+        self.commands.push(super::Command::Dispatch(groups));
+        self.commands.push(super::Command::Barrier);
+    }
+
+    fn copy_buffer(&mut self, src: crate::BufferPiece, dst: crate::BufferPiece, size: u64) {
+        self.commands.push(super::Command::CopyBufferToBuffer {
+            src: src.into(),
+            dst: dst.into(),
+            size,
+        });
+    }
+}
+
 #[hidden_trait::expose]
 impl crate::traits::AccelerationStructureEncoder for super::PassEncoder<'_, ()> {
     fn build_bottom_level(
         &mut self,
-        _acceleration_structure: super::AccelerationStructure,
-        _meshes: &[crate::AccelerationStructureMesh],
-        _scratch_data: crate::BufferPiece,
+        acceleration_structure: super::AccelerationStructure,
+        meshes: &[crate::AccelerationStructureMesh],
+        scratch_data: crate::BufferPiece,
     ) {
-        unimplemented!()
+        let pipelines = self.software_rt.get();
+        software_rt::build_bottom_level(
+            self,
+            &pipelines,
+            meshes,
+            acceleration_structure.piece(),
+            scratch_data,
+        );
     }
 
     fn build_top_level(
         &mut self,
-        _acceleration_structure: super::AccelerationStructure,
-        _bottom_level: &[super::AccelerationStructure],
-        _instance_count: u32,
-        _instance_data: crate::BufferPiece,
-        _scratch_data: crate::BufferPiece,
+        acceleration_structure: super::AccelerationStructure,
+        bottom_level: &[super::AccelerationStructure],
+        instance_count: u32,
+        instance_data: crate::BufferPiece,
+        scratch_data: crate::BufferPiece,
     ) {
-        unimplemented!()
+        let bottom_level = bottom_level
+            .iter()
+            .map(|blas| (blas.piece(), blas.size))
+            .collect::<Vec<_>>();
+        let sizes = bottom_level
+            .iter()
+            .map(|&(_, size)| size)
+            .collect::<Vec<_>>();
+        // The storage grows to fit the copies of the bottom level structures
+        self.commands.push(super::Command::ResizeBuffer {
+            raw: acceleration_structure.raw,
+            size: software_rt::top_level_size(instance_count, &sizes),
+        });
+        let pipelines = self.software_rt.get();
+        software_rt::build_top_level(
+            self,
+            &pipelines,
+            &bottom_level,
+            instance_count,
+            instance_data,
+            acceleration_structure.piece(),
+            scratch_data,
+        );
     }
 }
 
@@ -354,6 +709,12 @@ impl crate::traits::ComputePipelineEncoder for super::PipelineEncoder<'_> {
     fn dispatch(&mut self, groups: [u32; 3]) {
         self.commands.push(super::Command::Dispatch(groups));
     }
+
+    fn dispatch_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.commands.push(super::Command::DispatchIndirect {
+            indirect_buf: indirect_buf.into(),
+        });
+    }
 }
 
 #[hidden_trait::expose]
@@ -362,6 +723,34 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_> {
         self.commands.push(super::Command::SetScissor(rect.clone()));
     }
 
+    fn set_stencil_reference(&mut self, reference: u32) {
+        let stencil = self.stencil.unwrap();
+        for (face, state) in [(glow::FRONT, &stencil.front), (glow::BACK, &stencil.back)] {
+            self.commands.push(super::Command::SetStencilFunc {
+                face,
+                function: super::map_compare_func(state.compare),
+                reference,
+                read_mask: stencil.read_mask,
+            });
+        }
+    }
+
+    fn bind_vertex_buffer(&mut self, index: u32, vertex_buf: crate::BufferPiece) {
+        let vb = &self.vertex_buffers[index as usize];
+        for at in vb.attributes.iter() {
+            self.commands.push(super::Command::SetVertexAttribute {
+                location: at.location,
+                buffer: super::BufferPart {
+                    raw: vertex_buf.buffer.raw,
+                    offset: vertex_buf.offset + at.offset as u64,
+                },
+                stride: vb.stride,
+                format: at.format,
+                instanced: vb.instanced,
+            });
+        }
+    }
+
     fn draw(
         &mut self,
         start_vertex: u32,
@@ -398,17 +787,52 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_> {
         });
     }
 
-    fn draw_indirect(&mut self, _indirect_buf: crate::BufferPiece) {
-        unimplemented!()
+    fn draw_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.multi_draw_indirect(indirect_buf, 1, None);
     }
 
     fn draw_indexed_indirect(
         &mut self,
-        _index_buf: crate::BufferPiece,
-        _index_type: crate::IndexType,
-        _indirect_buf: crate::BufferPiece,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
     ) {
-        unimplemented!()
+        self.multi_draw_indexed_indirect(index_buf, index_type, indirect_buf, 1, None);
+    }
+
+    //Note: the count buffer is ignored, and the draws are issued one by one.
+    fn multi_draw_indirect(
+        &mut self,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        _count_buf: Option<crate::BufferPiece>,
+    ) {
+        self.commands.push(super::Command::DrawIndirect {
+            topology: self.topology,
+            indirect_buf: indirect_buf.into(),
+            draw_count: max_draw_count,
+        });
+    }
+
+    fn multi_draw_indexed_indirect(
+        &mut self,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        _count_buf: Option<crate::BufferPiece>,
+    ) {
+        assert_eq!(
+            index_buf.offset, 0,
+            "Index buffer offset is not supported for indirect draws"
+        );
+        self.commands.push(super::Command::DrawIndexedIndirect {
+            topology: self.topology,
+            raw_index_buf: index_buf.buffer.raw,
+            index_type: map_index_type(index_type),
+            indirect_buf: indirect_buf.into(),
+            draw_count: max_draw_count,
+        });
     }
 }
 
@@ -485,19 +909,29 @@ impl super::Command {
             Self::DrawIndirect {
                 topology,
                 ref indirect_buf,
+                draw_count,
             } => {
+                let stride = size_of::<crate::DrawIndirectArgs>() as u64;
                 gl.bind_buffer(glow::DRAW_INDIRECT_BUFFER, Some(indirect_buf.raw));
-                gl.draw_arrays_indirect_offset(topology, indirect_buf.offset as i32);
+                for i in 0..draw_count as u64 {
+                    let offset = indirect_buf.offset + i * stride;
+                    gl.draw_arrays_indirect_offset(topology, offset as i32);
+                }
             }
             Self::DrawIndexedIndirect {
                 topology,
                 raw_index_buf,
                 index_type,
                 ref indirect_buf,
+                draw_count,
             } => {
+                let stride = size_of::<crate::DrawIndexedIndirectArgs>() as u64;
                 gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(raw_index_buf));
                 gl.bind_buffer(glow::DRAW_INDIRECT_BUFFER, Some(indirect_buf.raw));
-                gl.draw_elements_indirect_offset(topology, index_type, indirect_buf.offset as i32);
+                for i in 0..draw_count as u64 {
+                    let offset = indirect_buf.offset + i * stride;
+                    gl.draw_elements_indirect_offset(topology, index_type, offset as i32);
+                }
             }
             Self::Dispatch(groups) => {
                 gl.dispatch_compute(groups[0], groups[1], groups[2]);
@@ -569,71 +1003,109 @@ impl super::Command {
                     bytes_per_row / block_info.size as u32 * block_info.dimensions.0 as u32;
                 gl.bind_buffer(glow::PIXEL_UNPACK_BUFFER, Some(src.raw));
                 gl.bind_texture(dst.target, Some(dst.raw));
-                let unpack_data = glow::PixelUnpackData::BufferOffset(src.offset as u32);
-                match dst.target {
-                    glow::TEXTURE_3D => gl.tex_sub_image_3d(
-                        dst.target,
-                        dst.mip_level as i32,
-                        dst.origin[0] as i32,
-                        dst.origin[1] as i32,
-                        dst.origin[2] as i32,
-                        size.width as i32,
-                        size.height as i32,
-                        size.depth as i32,
-                        format_desc.external,
-                        format_desc.data_type,
-                        unpack_data,
-                    ),
-                    glow::TEXTURE_2D_ARRAY => gl.tex_sub_image_3d(
-                        dst.target,
-                        dst.mip_level as i32,
-                        dst.origin[0] as i32,
-                        dst.origin[1] as i32,
-                        dst.origin[2] as i32,
-                        size.width as i32,
-                        size.height as i32,
-                        size.depth as i32,
-                        format_desc.external,
-                        format_desc.data_type,
-                        unpack_data,
-                    ),
-                    glow::TEXTURE_2D => gl.tex_sub_image_2d(
-                        dst.target,
-                        dst.mip_level as i32,
-                        dst.origin[0] as i32,
-                        dst.origin[1] as i32,
-                        size.width as i32,
-                        size.height as i32,
-                        format_desc.external,
-                        format_desc.data_type,
-                        unpack_data,
-                    ),
-                    glow::TEXTURE_CUBE_MAP => gl.tex_sub_image_2d(
-                        CUBEMAP_FACES[dst.array_layer as usize],
-                        dst.mip_level as i32,
-                        dst.origin[0] as i32,
-                        dst.origin[1] as i32,
-                        size.width as i32,
-                        size.height as i32,
-                        format_desc.external,
-                        format_desc.data_type,
-                        unpack_data,
-                    ),
-                    //Note: not sure if this is correct!
-                    glow::TEXTURE_CUBE_MAP_ARRAY => gl.tex_sub_image_3d(
-                        dst.target,
-                        dst.mip_level as i32,
-                        dst.origin[0] as i32,
-                        dst.origin[1] as i32,
-                        dst.origin[2] as i32,
-                        size.width as i32,
-                        size.height as i32,
-                        size.depth as i32,
-                        format_desc.external,
-                        format_desc.data_type,
-                        unpack_data,
-                    ),
-                    _ => unreachable!(),
+                if block_info.dimensions != (1, 1) {
+                    let block_rows = (size.height + block_info.dimensions.1 as u32 - 1)
+                        / block_info.dimensions.1 as u32;
+                    let data_size = bytes_per_row * block_rows * size.depth;
+                    let unpack_data = glow::CompressedPixelUnpackData::BufferRange(
+                        src.offset as u32..src.offset as u32 + data_size,
+                    );
+                    match dst.target {
+                        glow::TEXTURE_2D | glow::TEXTURE_CUBE_MAP => gl
+                            .compressed_tex_sub_image_2d(
+                                if dst.target == glow::TEXTURE_CUBE_MAP {
+                                    CUBEMAP_FACES[dst.array_layer as usize]
+                                } else {
+                                    dst.target
+                                },
+                                dst.mip_level as i32,
+                                dst.origin[0] as i32,
+                                dst.origin[1] as i32,
+                                size.width as i32,
+                                size.height as i32,
+                                format_desc.internal,
+                                unpack_data,
+                            ),
+                        _ => gl.compressed_tex_sub_image_3d(
+                            dst.target,
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            dst.origin[2] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            size.depth as i32,
+                            format_desc.internal,
+                            unpack_data,
+                        ),
+                    }
+                } else {
+                    let unpack_data = glow::PixelUnpackData::BufferOffset(src.offset as u32);
+                    match dst.target {
+                        glow::TEXTURE_3D => gl.tex_sub_image_3d(
+                            dst.target,
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            dst.origin[2] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            size.depth as i32,
+                            format_desc.external,
+                            format_desc.data_type,
+                            unpack_data,
+                        ),
+                        glow::TEXTURE_2D_ARRAY => gl.tex_sub_image_3d(
+                            dst.target,
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            dst.origin[2] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            size.depth as i32,
+                            format_desc.external,
+                            format_desc.data_type,
+                            unpack_data,
+                        ),
+                        glow::TEXTURE_2D => gl.tex_sub_image_2d(
+                            dst.target,
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            format_desc.external,
+                            format_desc.data_type,
+                            unpack_data,
+                        ),
+                        glow::TEXTURE_CUBE_MAP => gl.tex_sub_image_2d(
+                            CUBEMAP_FACES[dst.array_layer as usize],
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            format_desc.external,
+                            format_desc.data_type,
+                            unpack_data,
+                        ),
+                        //Note: not sure if this is correct!
+                        glow::TEXTURE_CUBE_MAP_ARRAY => gl.tex_sub_image_3d(
+                            dst.target,
+                            dst.mip_level as i32,
+                            dst.origin[0] as i32,
+                            dst.origin[1] as i32,
+                            dst.origin[2] as i32,
+                            size.width as i32,
+                            size.height as i32,
+                            size.depth as i32,
+                            format_desc.external,
+                            format_desc.data_type,
+                            unpack_data,
+                        ),
+                        _ => unreachable!(),
+                    }
                 }
                 gl.bind_buffer(glow::PIXEL_UNPACK_BUFFER, None);
             }
@@ -643,6 +1115,11 @@ impl super::Command {
                 bytes_per_row,
                 ref size,
             } => unimplemented!(),
+            Self::ResizeBuffer { raw, size } => {
+                gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, Some(raw));
+                gl.buffer_data_size(glow::SHADER_STORAGE_BUFFER, size as i32, glow::STATIC_DRAW);
+                gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, None);
+            }
             Self::ResetFramebuffer => {
                 for &attachment in COLOR_ATTACHMENTS.iter() {
                     gl.framebuffer_renderbuffer(
@@ -687,6 +1164,60 @@ impl super::Command {
             Self::InvalidateAttachment(attachment) => {
                 gl.invalidate_framebuffer(glow::DRAW_FRAMEBUFFER, &[attachment]);
             }
+            Self::ResolveAttachment {
+                attachment,
+                ref dst,
+            } => {
+                let (dst_attachment, mask) = match attachment {
+                    glow::DEPTH_ATTACHMENT => (attachment, glow::DEPTH_BUFFER_BIT),
+                    glow::STENCIL_ATTACHMENT => (attachment, glow::STENCIL_BUFFER_BIT),
+                    glow::DEPTH_STENCIL_ATTACHMENT => (
+                        attachment,
+                        glow::DEPTH_BUFFER_BIT | glow::STENCIL_BUFFER_BIT,
+                    ),
+                    _ => (glow::COLOR_ATTACHMENT0, glow::COLOR_BUFFER_BIT),
+                };
+                gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(ec.framebuf));
+                gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, Some(ec.resolve_framebuf));
+                match dst.inner {
+                    super::TextureInner::Renderbuffer { raw } => {
+                        gl.framebuffer_renderbuffer(
+                            glow::DRAW_FRAMEBUFFER,
+                            dst_attachment,
+                            glow::RENDERBUFFER,
+                            Some(raw),
+                        );
+                    }
+                    super::TextureInner::Texture { raw, target } => {
+                        gl.framebuffer_texture_2d(
+                            glow::DRAW_FRAMEBUFFER,
+                            dst_attachment,
+                            target,
+                            Some(raw),
+                            0,
+                        );
+                    }
+                }
+                if mask == glow::COLOR_BUFFER_BIT {
+                    gl.read_buffer(attachment);
+                    gl.draw_buffers(&[dst_attachment]);
+                }
+                let [width, height] = dst.target_size;
+                gl.blit_framebuffer(
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    mask,
+                    glow::NEAREST,
+                );
+                gl.bind_framebuffer(glow::READ_FRAMEBUFFER, None);
+                gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, Some(ec.framebuf));
+            }
             Self::SetDrawColorBuffers(count) => {
                 gl.draw_buffers(&COLOR_ATTACHMENTS[..count as usize]);
             }
@@ -737,7 +1268,9 @@ impl super::Command {
                 (None, Some(s)) => gl.clear_buffer_i32_slice(glow::STENCIL, 0, &[s as i32]),
                 (None, None) => (),
             },
-            Self::Barrier => unimplemented!(),
+            Self::Barrier => {
+                gl.memory_barrier(glow::ALL_BARRIER_BITS);
+            }
             Self::SetViewport { size, ref depth } => {
                 gl.viewport(0, 0, size[0] as i32, size[1] as i32);
                 gl.depth_range_f32(depth.start, depth.end);
@@ -748,7 +1281,7 @@ impl super::Command {
                 function,
                 reference,
                 read_mask,
-            } => unimplemented!(),
+            } => gl.stencil_func_separate(face, function, reference as i32, read_mask),
             Self::SetStencilOps {
                 face,
                 write_mask,
@@ -763,6 +1296,42 @@ impl super::Command {
             Self::UnsetProgram => {
                 gl.use_program(None);
             }
+            Self::SetVertexAttribute {
+                location,
+                ref buffer,
+                stride,
+                format,
+                instanced,
+            } => {
+                let info = super::describe_vertex_format(format);
+                gl.bind_buffer(glow::ARRAY_BUFFER, Some(buffer.raw));
+                if info.integer {
+                    gl.vertex_attrib_pointer_i32(
+                        location,
+                        info.component_count,
+                        info.data_type,
+                        stride as i32,
+                        buffer.offset as i32,
+                    );
+                } else {
+                    gl.vertex_attrib_pointer_f32(
+                        location,
+                        info.component_count,
+                        info.data_type,
+                        info.normalized,
+                        stride as i32,
+                        buffer.offset as i32,
+                    );
+                }
+                gl.vertex_attrib_divisor(location, if instanced { 1 } else { 0 });
+                gl.enable_vertex_attrib_array(location);
+                gl.bind_buffer(glow::ARRAY_BUFFER, None);
+            }
+            Self::ResetVertexAttributes => {
+                for location in 0..MAX_VERTEX_ATTRIBUTES {
+                    gl.disable_vertex_attrib_array(location);
+                }
+            }
             //SetPrimitive(PrimitiveState),
             Self::SetBlendConstant(constant) => unimplemented!(),
             Self::SetColorTarget {
@@ -782,7 +1351,21 @@ impl super::Command {
                 target,
                 slot,
                 ref buffer,
-            } => unimplemented!(),
+            } => {
+                if buffer.offset == 0 {
+                    gl.bind_buffer_base(target, slot, Some(buffer.raw));
+                } else {
+                    gl.bind_buffer(target, Some(buffer.raw));
+                    let size = gl.get_buffer_parameter_i32(target, glow::BUFFER_SIZE) as u64;
+                    gl.bind_buffer_range(
+                        target,
+                        slot,
+                        Some(buffer.raw),
+                        buffer.offset as i32,
+                        (size - buffer.offset) as i32,
+                    );
+                }
+            }
             Self::BindSampler { slot, sampler } => {
                 gl.bind_sampler(slot, Some(sampler));
             }
@@ -795,6 +1378,33 @@ impl super::Command {
                 gl.bind_texture(target, Some(texture));
             }
             Self::BindImage { slot, ref binding } => unimplemented!(),
+            Self::PushDebugGroup(ref label) => {
+                if gl.supports_debug() {
+                    gl.push_debug_group(glow::DEBUG_SOURCE_APPLICATION, super::DEBUG_ID, label);
+                }
+            }
+            Self::PopDebugGroup => {
+                if gl.supports_debug() {
+                    gl.pop_debug_group();
+                }
+            }
+            Self::InsertDebugMarker(ref label) => {
+                if gl.supports_debug() {
+                    gl.debug_message_insert(
+                        glow::DEBUG_SOURCE_APPLICATION,
+                        glow::DEBUG_TYPE_MARKER,
+                        super::DEBUG_ID,
+                        glow::DEBUG_SEVERITY_NOTIFICATION,
+                        label,
+                    );
+                }
+            }
+            Self::StartTimer(index) => {
+                if index != 0 {
+                    gl.end_query(glow::TIME_ELAPSED);
+                }
+                gl.begin_query(glow::TIME_ELAPSED, ec.timer_queries[index as usize]);
+            }
             Self::ResetAllSamplers => {
                 gl.active_texture(glow::TEXTURE0);
                 for slot in 0..4 {
@@ -124,6 +124,9 @@ pub struct Context {
     inner: Mutex<ContextInner>,
     pub(super) capabilities: super::Capabilities,
     pub(super) limits: super::Limits,
+    pub(super) device_limits: crate::Limits,
+    pub(super) resource_counts: crate::util::ResourceCounts,
+    pub(super) software_rt: Arc<crate::software_rt::SharedPipelines>,
 }
 
 pub struct ContextLock<'a> {
@@ -209,9 +212,10 @@ impl Context {
         let egl_context = EglContext::init(&desc, egl, display)?;
         egl_context.make_current();
         let (glow, capabilities, limits) = egl_context.load_functions(&desc);
+        let device_limits = super::query_device_limits(&glow);
         egl_context.unmake_current();
 
-        Ok(Self {
+        let context = Self {
             wsi: None,
             inner: Mutex::new(ContextInner {
                 egl: egl_context,
@@ -220,7 +224,12 @@ impl Context {
             }),
             capabilities,
             limits,
-        })
+            device_limits,
+            resource_counts: Default::default(),
+            software_rt: Default::default(),
+        };
+        context.check_adapter(&desc.adapter)?;
+        Ok(context)
     }
 
     pub unsafe fn init_windowed<
@@ -306,11 +315,12 @@ impl Context {
         let egl_context = EglContext::init(&desc, egl, display)?;
         egl_context.make_current();
         let (glow, capabilities, limits) = egl_context.load_functions(&desc);
+        let device_limits = super::query_device_limits(&glow);
         let renderbuf = glow.create_renderbuffer().unwrap();
         let framebuf = glow.create_framebuffer().unwrap();
         egl_context.unmake_current();
 
-        Ok(Self {
+        let context = Self {
             wsi: Some(WindowSystemInterface {
                 library: wsi_library.map(Arc::new),
                 window_handle: window.raw_window_handle(),
@@ -325,7 +335,56 @@ impl Context {
             }),
             capabilities,
             limits,
-        })
+            device_limits,
+            resource_counts: Default::default(),
+            software_rt: Default::default(),
+        };
+        context.check_adapter(&desc.adapter)?;
+        Ok(context)
+    }
+
+    /// List the adapters suitable for initializing the context.
+    /// EGL only exposes the one behind the default display.
+    pub unsafe fn enumerate_adapters() -> Vec<crate::AdapterInfo> {
+        let desc = crate::ContextDesc {
+            validation: false,
+            capture: false,
+            timing: false,
+            adapter: crate::AdapterSelector::Any,
+        };
+        match Self::init(desc) {
+            Ok(context) => vec![context.adapter_info()],
+            Err(crate::NotSupportedError) => Vec::new(),
+        }
+    }
+
+    fn adapter_info(&self) -> crate::AdapterInfo {
+        use glow::HasContext as _;
+        let renderer = unsafe { self.lock().get_parameter_string(glow::RENDERER) };
+        let is_software = ["llvmpipe", "softpipe", "SwiftShader"]
+            .iter()
+            .any(|name| renderer.contains(name));
+        crate::AdapterInfo {
+            name: renderer,
+            vendor_id: 0,
+            device_type: if is_software {
+                crate::DeviceType::Software
+            } else {
+                crate::DeviceType::Other
+            },
+            driver_version: 0,
+            capabilities: self.capabilities(),
+        }
+    }
+
+    fn check_adapter(
+        &self,
+        selector: &crate::AdapterSelector,
+    ) -> Result<(), crate::NotSupportedError> {
+        match selector.select(&[self.adapter_info()]) {
+            Some(_) => Ok(()),
+            None => Err(crate::NotSupportedError),
+        }
     }
 
     pub fn resize(&self, config: crate::SurfaceConfig) -> crate::TextureFormat {
@@ -436,6 +495,7 @@ impl Context {
             extent: config.size,
         });
 
+        //TODO: respect `config.color_space`
         let format_desc = super::describe_texture_format(wsi.surface_format);
         inner.egl.make_current();
         unsafe {
@@ -737,7 +797,7 @@ impl EglContext {
         &self,
         desc: &crate::ContextDesc,
     ) -> (glow::Context, super::Capabilities, super::Limits) {
-        let gl = glow::Context::from_loader_function(|name| {
+        let mut gl = glow::Context::from_loader_function(|name| {
             self.instance
                 .get_proc_address(name)
                 .map_or(ptr::null(), |p| p as *const _)
@@ -756,7 +816,6 @@ impl EglContext {
                 );
             }
         }
-
         let extensions = gl.supported_extensions();
         log::debug!("Extensions: {:#?}", extensions);
         let vendor = gl.get_parameter_string(glow::VENDOR);
@@ -766,15 +825,47 @@ impl EglContext {
         log::info!("Renderer: {}", renderer);
         log::info!("Version: {}", version);
 
-        let mut capabilities = super::Capabilities::empty();
+        // ETC2 is a part of GLES 3.0
+        let mut capabilities = super::Capabilities::TEXTURE_ETC2;
         capabilities.set(
             super::Capabilities::BUFFER_STORAGE,
             extensions.contains("GL_EXT_buffer_storage"),
         );
+        capabilities.set(
+            super::Capabilities::TEXTURE_BC,
+            extensions.contains("GL_EXT_texture_compression_s3tc")
+                && extensions.contains("GL_EXT_texture_compression_rgtc")
+                && extensions.contains("GL_EXT_texture_compression_bptc"),
+        );
+        capabilities.set(
+            super::Capabilities::TEXTURE_ASTC,
+            extensions.contains("GL_KHR_texture_compression_astc_ldr"),
+        );
+        capabilities.set(
+            super::Capabilities::COLOR_BUFFER_FLOAT,
+            extensions.contains("GL_EXT_color_buffer_float"),
+        );
+        if desc.timing {
+            if extensions.contains("GL_EXT_disjoint_timer_query") {
+                capabilities |= super::Capabilities::TIMER_QUERY;
+            } else {
+                log::warn!("Timer queries are not supported, timing is disabled");
+            }
+        }
 
+        let gl_version = gl.version();
+        let is_es31 = (gl_version.major, gl_version.minor) >= (3, 1);
         let limits = super::Limits {
             uniform_buffer_alignment: gl.get_parameter_i32(glow::UNIFORM_BUFFER_OFFSET_ALIGNMENT)
                 as u32,
+            max_samples: gl.get_parameter_i32(glow::MAX_SAMPLES) as u32,
+            shading_language_version: if is_es31 { 310 } else { 300 },
+            max_texture_units: gl.get_parameter_i32(glow::MAX_COMBINED_TEXTURE_IMAGE_UNITS) as u32,
+            max_storage_buffers: if is_es31 {
+                gl.get_parameter_i32(glow::MAX_SHADER_STORAGE_BUFFER_BINDINGS) as u32
+            } else {
+                0
+            },
         };
         (gl, capabilities, limits)
     }
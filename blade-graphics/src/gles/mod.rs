@@ -1,3 +1,4 @@
+mod binding_array;
 mod command;
 mod pipeline;
 #[cfg_attr(not(target_arch = "wasm32"), path = "egl.rs")]
@@ -8,19 +9,33 @@ mod resource;
 type BindTarget = u32;
 
 pub use platform::Context;
-use std::{marker::PhantomData, ops::Range};
+use std::{marker::PhantomData, ops::Range, sync::Arc};
 
 const DEBUG_ID: u32 = 0;
+/// `GL_GPU_DISJOINT_EXT`, which isn't known to glow.
+const GPU_DISJOINT: u32 = 0x8FBB;
 
 bitflags::bitflags! {
     struct Capabilities: u32 {
         const BUFFER_STORAGE = 1 << 0;
+        const TEXTURE_BC = 1 << 1;
+        const TEXTURE_ASTC = 1 << 2;
+        const COLOR_BUFFER_FLOAT = 1 << 3;
+        const TEXTURE_ETC2 = 1 << 4;
+        /// Timing is requested, and the passes are measured with timer queries.
+        const TIMER_QUERY = 1 << 5;
     }
 }
 
 #[derive(Clone, Debug)]
 struct Limits {
     uniform_buffer_alignment: u32,
+    max_samples: u32,
+    /// Version of GLSL ES to generate, with compute shaders
+    /// and storage buffers starting from 310.
+    shading_language_version: u16,
+    max_texture_units: u32,
+    max_storage_buffers: u32,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq)]
@@ -80,10 +95,15 @@ pub struct Sampler {
     raw: glow::Sampler,
 }
 
+/// Storage for the software ray queries, see `software_rt`.
 #[derive(Clone, Copy, Debug, Hash, PartialEq)]
-pub struct AccelerationStructure {}
+pub struct AccelerationStructure {
+    raw: glow::Buffer,
+    size: u64,
+}
 
-type SlotList = Vec<u32>;
+/// Slots of a binding, with the element index for the binding arrays.
+type SlotList = Vec<(u32, crate::ResourceIndex)>;
 
 struct BindGroupInfo {
     targets: Box<[SlotList]>,
@@ -105,9 +125,28 @@ impl ComputePipeline {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+struct VertexAttributeInfo {
+    location: u32,
+    offset: u32,
+    format: crate::VertexFormat,
+}
+
+struct VertexBufferInfo {
+    attributes: Box<[VertexAttributeInfo]>,
+    stride: u32,
+    instanced: bool,
+}
+
+//TODO: use program binaries
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct PipelineCache {}
+
 pub struct RenderPipeline {
     inner: PipelineInner,
     topology: crate::PrimitiveTopology,
+    vertex_buffers: Box<[VertexBufferInfo]>,
+    stencil: crate::StencilState,
 }
 
 pub struct Frame {
@@ -181,7 +220,7 @@ enum ColorType {
     Sint,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum Command {
     Draw {
         topology: u32,
@@ -200,12 +239,14 @@ enum Command {
     DrawIndirect {
         topology: u32,
         indirect_buf: BufferPart,
+        draw_count: u32,
     },
     DrawIndexedIndirect {
         topology: u32,
         raw_index_buf: glow::Buffer,
         index_type: u32,
         indirect_buf: BufferPart,
+        draw_count: u32,
     },
     Dispatch([u32; 3]),
     DispatchIndirect {
@@ -238,12 +279,21 @@ enum Command {
         bytes_per_row: u32,
         size: crate::Extent,
     },
+    /// Re-specify the storage of a buffer, discarding the contents.
+    ResizeBuffer {
+        raw: glow::Buffer,
+        size: u64,
+    },
     ResetFramebuffer,
     BindAttachment {
         attachment: u32,
         view: TextureView,
     },
     InvalidateAttachment(u32),
+    ResolveAttachment {
+        attachment: u32,
+        dst: TextureView,
+    },
     SetDrawColorBuffers(u8),
     ClearColor {
         draw_buffer: u32,
@@ -276,6 +326,14 @@ enum Command {
     //ConfigureDepthStencil(crate::FormatAspects),
     SetProgram(glow::Program),
     UnsetProgram,
+    SetVertexAttribute {
+        location: u32,
+        buffer: BufferPart,
+        stride: u32,
+        format: crate::VertexFormat,
+        instanced: bool,
+    },
+    ResetVertexAttributes,
     //SetPrimitive(PrimitiveState),
     SetBlendConstant([f32; 4]),
     SetColorTarget {
@@ -306,6 +364,11 @@ enum Command {
         binding: ImageBinding,
     },
     ResetAllSamplers,
+    PushDebugGroup(String),
+    PopDebugGroup,
+    InsertDebugMarker(String),
+    /// Start measuring a pass, ending the measurement of the previous one.
+    StartTimer(u32),
 }
 
 pub struct CommandEncoder {
@@ -314,6 +377,12 @@ pub struct CommandEncoder {
     plain_data: Vec<u8>,
     has_present: bool,
     limits: Limits,
+    timings: crate::Timings,
+    timing: bool,
+    timed_pass_names: Vec<String>,
+    /// Queries of the last submission, with the names of their passes.
+    timer_queries: Vec<(String, glow::Query)>,
+    software_rt: Arc<crate::software_rt::SharedPipelines>,
 }
 
 enum PassKind {
@@ -328,8 +397,10 @@ pub struct PassEncoder<'a, P> {
     plain_data: &'a mut Vec<u8>,
     kind: PassKind,
     invalidate_attachments: Vec<u32>,
+    resolve_attachments: Vec<(u32, TextureView)>,
     pipeline: PhantomData<P>,
     limits: &'a Limits,
+    software_rt: &'a crate::software_rt::SharedPipelines,
 }
 
 pub type ComputeCommandEncoder<'a> = PassEncoder<'a, ComputePipeline>;
@@ -339,10 +410,24 @@ pub struct PipelineEncoder<'a> {
     commands: &'a mut Vec<Command>,
     plain_data: &'a mut Vec<u8>,
     bind_group_infos: &'a [BindGroupInfo],
+    vertex_buffers: &'a [VertexBufferInfo],
+    stencil: Option<&'a crate::StencilState>,
     topology: u32,
     limits: &'a Limits,
 }
 
+pub struct RenderBundleEncoder {
+    commands: Vec<Command>,
+    plain_data: Vec<u8>,
+    limits: Limits,
+}
+
+/// Recorded commands, with the uniform offsets relative to the bundle's plain data.
+pub struct RenderBundle {
+    commands: Vec<Command>,
+    plain_data: Vec<u8>,
+}
+
 pub struct PipelineContext<'a> {
     commands: &'a mut Vec<Command>,
     plain_data: &'a mut Vec<u8>,
@@ -355,14 +440,110 @@ pub struct SyncPoint {}
 
 struct ExecutionContext {
     framebuf: glow::Framebuffer,
+    resolve_framebuf: glow::Framebuffer,
     plain_buffer: glow::Buffer,
+    timer_queries: Vec<glow::Query>,
 }
 
 impl Context {
     pub fn capabilities(&self) -> crate::Capabilities {
+        let has_compute = self.limits.shading_language_version >= 310;
         crate::Capabilities {
-            ray_query: crate::ShaderVisibility::empty(),
+            // Storage buffers are only guaranteed in compute shaders
+            ray_query: if has_compute {
+                crate::ShaderVisibility::COMPUTE
+            } else {
+                crate::ShaderVisibility::empty()
+            },
+            sample_count_mask: (0..7)
+                .map(|shift| 1u32 << shift)
+                .filter(|&count| count <= self.limits.max_samples.max(1))
+                .fold(0, |mask, count| mask | count),
+            // Emulated with a slot per element
+            binding_arrays: has_compute,
+            software_ray_query: has_compute,
+            render_bundles: true,
+        }
+    }
+
+    pub fn limits(&self) -> crate::Limits {
+        self.device_limits.clone()
+    }
+
+    /// Return the usages supported by textures of the given format.
+    pub fn format_features(&self, format: crate::TextureFormat) -> crate::TextureUsage {
+        use crate::{util::Compression, TextureFormat as Tf, TextureUsage as Tu};
+        let supported = match format.compression() {
+            Compression::None => true,
+            Compression::Etc2 => self.capabilities.contains(Capabilities::TEXTURE_ETC2),
+            Compression::Bc => self.capabilities.contains(Capabilities::TEXTURE_BC),
+            Compression::Astc => self.capabilities.contains(Capabilities::TEXTURE_ASTC),
+        };
+        if !supported {
+            return Tu::empty();
         }
+        let mut usage = format.base_features();
+        match format {
+            Tf::R16Float
+            | Tf::Rg16Float
+            | Tf::Rgba16Float
+            | Tf::R32Float
+            | Tf::Rg32Float
+            | Tf::Rgba32Float
+            | Tf::Rg11b10Float
+                if !self.capabilities.contains(Capabilities::COLOR_BUFFER_FLOAT) =>
+            {
+                usage.remove(Tu::TARGET)
+            }
+            Tf::Rg8Snorm | Tf::Rgba8Snorm | Tf::Rgba16Unorm => usage.remove(Tu::TARGET),
+            _ => {}
+        }
+        // Image load/store is limited to a few formats in GLES 3.1
+        match format {
+            Tf::R32Float
+            | Tf::R32Uint
+            | Tf::R32Sint
+            | Tf::Rgba8Unorm
+            | Tf::Rgba8Snorm
+            | Tf::Rgba16Float
+            | Tf::Rgba32Float
+            | Tf::Rgba32Uint
+            | Tf::Rgba32Sint => {}
+            _ => usage.remove(Tu::STORAGE),
+        }
+        usage
+    }
+
+    pub fn memory_report(&self) -> crate::MemoryReport {
+        // GL doesn't expose the memory heaps.
+        self.resource_counts.report(Vec::new())
+    }
+}
+
+/// Query the limits reported by `Context::limits`, once at initialization.
+unsafe fn query_device_limits(gl: &glow::Context) -> crate::Limits {
+    use glow::HasContext as _;
+    // WebGL doesn't have compute shaders.
+    #[cfg(target_arch = "wasm32")]
+    let (max_workgroup_size, max_workgroup_invocations, max_workgroup_count) = ([0; 3], 0, [0; 3]);
+    #[cfg(not(target_arch = "wasm32"))]
+    let (max_workgroup_size, max_workgroup_invocations, max_workgroup_count) = (
+        [0, 1, 2]
+            .map(|i| gl.get_parameter_indexed_i32(glow::MAX_COMPUTE_WORK_GROUP_SIZE, i) as u32),
+        gl.get_parameter_i32(glow::MAX_COMPUTE_WORK_GROUP_INVOCATIONS) as u32,
+        [0, 1, 2]
+            .map(|i| gl.get_parameter_indexed_i32(glow::MAX_COMPUTE_WORK_GROUP_COUNT, i) as u32),
+    );
+    crate::Limits {
+        max_texture_dimension_2d: gl.get_parameter_i32(glow::MAX_TEXTURE_SIZE) as u32,
+        max_texture_dimension_3d: gl.get_parameter_i32(glow::MAX_3D_TEXTURE_SIZE) as u32,
+        max_texture_array_layers: gl.get_parameter_i32(glow::MAX_ARRAY_TEXTURE_LAYERS) as u32,
+        max_workgroup_size,
+        max_workgroup_invocations,
+        max_workgroup_count,
+        subgroup_size: 0,
+        timestamp_period: 0.0,
+        memory_heaps: Vec::new(),
     }
 }
 
@@ -378,10 +559,21 @@ impl crate::traits::CommandDevice for Context {
             plain_data: Vec::new(),
             has_present: false,
             limits: self.limits.clone(),
+            timings: Vec::new(),
+            timing: self.capabilities.contains(Capabilities::TIMER_QUERY),
+            timed_pass_names: Vec::new(),
+            timer_queries: Vec::new(),
+            software_rt: Arc::clone(&self.software_rt),
         }
     }
 
-    fn destroy_command_encoder(&self, _command_encoder: CommandEncoder) {}
+    fn destroy_command_encoder(&self, command_encoder: CommandEncoder) {
+        use glow::HasContext as _;
+        let gl = self.lock();
+        for (_, query) in command_encoder.timer_queries {
+            unsafe { gl.delete_query(query) };
+        }
+    }
 
     fn submit(&self, encoder: &mut CommandEncoder) -> SyncPoint {
         {
@@ -389,11 +581,15 @@ impl crate::traits::CommandDevice for Context {
 
             let gl = self.lock();
             let push_group = !encoder.name.is_empty() && gl.supports_debug();
+            if encoder.timing {
+                unsafe { encoder.resolve_timings(&gl) };
+            }
             let ec = unsafe {
                 if push_group {
                     gl.push_debug_group(glow::DEBUG_SOURCE_APPLICATION, DEBUG_ID, &encoder.name);
                 }
                 let framebuf = gl.create_framebuffer().unwrap();
+                let resolve_framebuf = gl.create_framebuffer().unwrap();
                 gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuf));
                 let plain_buffer = gl.create_buffer().unwrap();
                 if !encoder.plain_data.is_empty() {
@@ -407,7 +603,13 @@ impl crate::traits::CommandDevice for Context {
                 }
                 ExecutionContext {
                     framebuf,
+                    resolve_framebuf,
                     plain_buffer,
+                    timer_queries: encoder
+                        .timed_pass_names
+                        .iter()
+                        .map(|_| gl.create_query().unwrap())
+                        .collect(),
                 }
             };
             for command in encoder.commands.iter() {
@@ -416,11 +618,21 @@ impl crate::traits::CommandDevice for Context {
             }
             unsafe {
                 gl.delete_framebuffer(ec.framebuf);
+                gl.delete_framebuffer(ec.resolve_framebuf);
                 gl.delete_buffer(ec.plain_buffer);
+                if !ec.timer_queries.is_empty() {
+                    gl.end_query(glow::TIME_ELAPSED);
+                }
                 if push_group {
                     gl.pop_debug_group();
                 }
             }
+            encoder.timer_queries = encoder
+                .timed_pass_names
+                .iter()
+                .cloned()
+                .zip(ec.timer_queries)
+                .collect();
         }
         if encoder.has_present {
             self.present();
@@ -429,7 +641,12 @@ impl crate::traits::CommandDevice for Context {
     }
 
     fn wait_for(&self, _sp: &SyncPoint, _timeout_ms: u32) -> bool {
-        false //TODO
+        use glow::HasContext as _;
+        // All the work goes through a single context, so waiting for
+        // a sync point is finishing everything that was submitted.
+        let gl = self.lock();
+        unsafe { gl.finish() };
+        true
     }
 }
 
@@ -451,20 +668,55 @@ struct FormatInfo {
 fn describe_texture_format(format: crate::TextureFormat) -> FormatInfo {
     use crate::TextureFormat as Tf;
     let (internal, external, data_type) = match format {
+        Tf::R8Unorm => (glow::R8, glow::RED, glow::UNSIGNED_BYTE),
         Tf::Rg8Unorm => (glow::RG8, glow::RG, glow::UNSIGNED_BYTE),
         Tf::Rg8Snorm => (glow::RG8, glow::RG, glow::BYTE),
         Tf::Rgba8Unorm => (glow::RGBA8, glow::RGBA, glow::UNSIGNED_BYTE),
         Tf::Rgba8UnormSrgb => (glow::SRGB8_ALPHA8, glow::RGBA, glow::UNSIGNED_BYTE),
+        Tf::Bgra8Unorm => (glow::RGBA8, glow::BGRA, glow::UNSIGNED_BYTE),
         Tf::Bgra8UnormSrgb => (glow::SRGB8_ALPHA8, glow::BGRA, glow::UNSIGNED_BYTE),
         Tf::Rgba8Snorm => (glow::RGBA8, glow::RGBA, glow::BYTE),
+        Tf::R16Float => (glow::R16F, glow::RED, glow::HALF_FLOAT),
+        Tf::Rg16Float => (glow::RG16F, glow::RG, glow::HALF_FLOAT),
         Tf::Rgba16Float => (glow::RGBA16F, glow::RGBA, glow::FLOAT),
+        Tf::Rgba16Unorm => (glow::RGBA16, glow::RGBA, glow::UNSIGNED_SHORT),
         Tf::R32Float => (glow::R32F, glow::RED, glow::FLOAT),
         Tf::Rg32Float => (glow::RG32F, glow::RG, glow::FLOAT),
         Tf::Rgba32Float => (glow::RGBA32F, glow::RGBA, glow::FLOAT),
         Tf::R32Uint => (glow::R32UI, glow::RED, glow::UNSIGNED_INT),
         Tf::Rg32Uint => (glow::RG32UI, glow::RG, glow::UNSIGNED_INT),
         Tf::Rgba32Uint => (glow::RGBA32UI, glow::RGBA, glow::UNSIGNED_INT),
+        Tf::R32Sint => (glow::R32I, glow::RED_INTEGER, glow::INT),
+        Tf::Rg32Sint => (glow::RG32I, glow::RG_INTEGER, glow::INT),
+        Tf::Rgba32Sint => (glow::RGBA32I, glow::RGBA_INTEGER, glow::INT),
+        Tf::Rgb10a2Unorm => (
+            glow::RGB10_A2,
+            glow::RGBA,
+            glow::UNSIGNED_INT_2_10_10_10_REV,
+        ),
+        Tf::Rg11b10Float => (
+            glow::R11F_G11F_B10F,
+            glow::RGB,
+            glow::UNSIGNED_INT_10F_11F_11F_REV,
+        ),
+        Tf::Rgb9e5Ufloat => (glow::RGB9_E5, glow::RGB, glow::UNSIGNED_INT_5_9_9_9_REV),
         Tf::Depth32Float => (glow::DEPTH_COMPONENT32F, glow::DEPTH_COMPONENT, glow::FLOAT),
+        // the uploads follow the block size of `Depth32FloatStencil8`
+        Tf::Depth24PlusStencil8 => (
+            glow::DEPTH32F_STENCIL8,
+            glow::DEPTH_STENCIL,
+            glow::FLOAT_32_UNSIGNED_INT_24_8_REV,
+        ),
+        Tf::Depth32FloatStencil8 => (
+            glow::DEPTH32F_STENCIL8,
+            glow::DEPTH_STENCIL,
+            glow::FLOAT_32_UNSIGNED_INT_24_8_REV,
+        ),
+        Tf::Stencil8 => (
+            glow::STENCIL_INDEX8,
+            glow::STENCIL_INDEX,
+            glow::UNSIGNED_BYTE,
+        ),
         Tf::Bc1Unorm => (glow::COMPRESSED_RGBA_S3TC_DXT1_EXT, glow::RGBA, 0),
         Tf::Bc1UnormSrgb => (glow::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT, glow::RGBA, 0),
         Tf::Bc2Unorm => (glow::COMPRESSED_RGBA_S3TC_DXT3_EXT, glow::RGBA, 0),
@@ -475,6 +727,20 @@ fn describe_texture_format(format: crate::TextureFormat) -> FormatInfo {
         Tf::Bc4Snorm => (glow::COMPRESSED_SIGNED_RED_RGTC1, glow::RED, 0),
         Tf::Bc5Unorm => (glow::COMPRESSED_RG_RGTC2, glow::RG, 0),
         Tf::Bc5Snorm => (glow::COMPRESSED_SIGNED_RG_RGTC2, glow::RG, 0),
+        Tf::Bc6hUfloat => (glow::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, glow::RGB, 0),
+        Tf::Bc6hFloat => (glow::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, glow::RGB, 0),
+        Tf::Bc7Unorm => (glow::COMPRESSED_RGBA_BPTC_UNORM, glow::RGBA, 0),
+        Tf::Bc7UnormSrgb => (glow::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, glow::RGBA, 0),
+        Tf::Etc2Rgb8Unorm => (glow::COMPRESSED_RGB8_ETC2, glow::RGB, 0),
+        Tf::Etc2Rgb8UnormSrgb => (glow::COMPRESSED_SRGB8_ETC2, glow::RGB, 0),
+        Tf::Etc2Rgba8Unorm => (glow::COMPRESSED_RGBA8_ETC2_EAC, glow::RGBA, 0),
+        Tf::Etc2Rgba8UnormSrgb => (glow::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC, glow::RGBA, 0),
+        Tf::Astc4x4Unorm => (glow::COMPRESSED_RGBA_ASTC_4x4_KHR, glow::RGBA, 0),
+        Tf::Astc4x4UnormSrgb => (glow::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR, glow::RGBA, 0),
+        Tf::Astc6x6Unorm => (glow::COMPRESSED_RGBA_ASTC_6x6_KHR, glow::RGBA, 0),
+        Tf::Astc6x6UnormSrgb => (glow::COMPRESSED_SRGB8_ALPHA8_ASTC_6x6_KHR, glow::RGBA, 0),
+        Tf::Astc8x8Unorm => (glow::COMPRESSED_RGBA_ASTC_8x8_KHR, glow::RGBA, 0),
+        Tf::Astc8x8UnormSrgb => (glow::COMPRESSED_SRGB8_ALPHA8_ASTC_8x8_KHR, glow::RGBA, 0),
     };
     FormatInfo {
         internal,
@@ -483,6 +749,43 @@ fn describe_texture_format(format: crate::TextureFormat) -> FormatInfo {
     }
 }
 
+struct VertexFormatInfo {
+    component_count: i32,
+    data_type: u32,
+    normalized: bool,
+    integer: bool,
+}
+
+fn describe_vertex_format(format: crate::VertexFormat) -> VertexFormatInfo {
+    use crate::VertexFormat as Vf;
+    let (component_count, data_type, normalized, integer) = match format {
+        Vf::F32 => (1, glow::FLOAT, false, false),
+        Vf::F32Vec2 => (2, glow::FLOAT, false, false),
+        Vf::F32Vec3 => (3, glow::FLOAT, false, false),
+        Vf::F32Vec4 => (4, glow::FLOAT, false, false),
+        Vf::U32 => (1, glow::UNSIGNED_INT, false, true),
+        Vf::U32Vec2 => (2, glow::UNSIGNED_INT, false, true),
+        Vf::U32Vec3 => (3, glow::UNSIGNED_INT, false, true),
+        Vf::U32Vec4 => (4, glow::UNSIGNED_INT, false, true),
+        Vf::I32 => (1, glow::INT, false, true),
+        Vf::I32Vec2 => (2, glow::INT, false, true),
+        Vf::I32Vec3 => (3, glow::INT, false, true),
+        Vf::I32Vec4 => (4, glow::INT, false, true),
+        Vf::U8Vec4Norm => (4, glow::UNSIGNED_BYTE, true, false),
+        Vf::I8Vec4Norm => (4, glow::BYTE, true, false),
+        Vf::U16Vec2Norm => (2, glow::UNSIGNED_SHORT, true, false),
+        Vf::U16Vec4Norm => (4, glow::UNSIGNED_SHORT, true, false),
+        Vf::I16Vec2Norm => (2, glow::SHORT, true, false),
+        Vf::I16Vec4Norm => (4, glow::SHORT, true, false),
+    };
+    VertexFormatInfo {
+        component_count,
+        data_type,
+        normalized,
+        integer,
+    }
+}
+
 fn map_compare_func(fun: crate::CompareFunction) -> u32 {
     use crate::CompareFunction as Cf;
     match fun {
@@ -496,3 +799,325 @@ fn map_compare_func(fun: crate::CompareFunction) -> u32 {
         Cf::Always => glow::ALWAYS,
     }
 }
+
+/// Checks of the software acceleration structures, which are built and traversed
+/// by compute shaders, so they need a driver that runs them, like the software one.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+#[allow(irrefutable_let_patterns)]
+mod tests {
+    use crate::software_rt::Vector;
+    use glow::HasContext as _;
+    use std::{mem::size_of, ptr};
+
+    const QUAD_COUNT: u32 = 16;
+    const RAY_COUNT: u32 = 32;
+
+    // The rays are parallel to two of the slabs of each box,
+    // and the boxes of the quads are flat along the rays.
+    const RAY_QUERY_SHADER: &str = "
+var acc_struct: acceleration_structure;
+var<storage, read_write> hits: array<vec2<f32>>;
+
+@compute @workgroup_size(8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var rq: ray_query;
+    let origin = vec3<f32>(f32(global_id.x) + 0.5, 0.5, -5.0);
+    rayQueryInitialize(&rq, acc_struct, RayDesc(0u, 0xFFu, 0.1, 100.0, origin, vec3<f32>(0.0, 0.0, 1.0)));
+    rayQueryProceed(&rq);
+    let intersection = rayQueryGetCommittedIntersection(&rq);
+    if (intersection.kind == RAY_QUERY_INTERSECTION_NONE) {
+        hits[global_id.x] = vec2<f32>(-1.0, 0.0);
+    } else {
+        hits[global_id.x] = vec2<f32>(intersection.t, f32(intersection.instance_custom_index));
+    }
+}
+";
+
+    struct RayQueryData {
+        acc_struct: super::AccelerationStructure,
+        hits: crate::BufferPiece,
+    }
+
+    impl crate::ShaderData for RayQueryData {
+        fn layout() -> crate::ShaderDataLayout {
+            crate::ShaderDataLayout {
+                bindings: vec![
+                    ("acc_struct", crate::ShaderBinding::AccelerationStructure),
+                    ("hits", crate::ShaderBinding::Buffer),
+                ],
+            }
+        }
+        fn fill(&self, mut ctx: super::PipelineContext) {
+            use crate::ShaderBindable as _;
+            self.acc_struct.bind_to(&mut ctx, 0);
+            self.hits.bind_to(&mut ctx, 1);
+        }
+    }
+
+    fn read_acceleration_structure(
+        context: &super::Context,
+        acceleration_structure: super::AccelerationStructure,
+    ) -> Vec<Vector> {
+        let gl = context.lock();
+        unsafe {
+            gl.memory_barrier(glow::BUFFER_UPDATE_BARRIER_BIT);
+            gl.bind_buffer(
+                glow::SHADER_STORAGE_BUFFER,
+                Some(acceleration_structure.raw),
+            );
+            // The top level storage is grown by the build
+            let size = gl.get_buffer_parameter_i32(glow::SHADER_STORAGE_BUFFER, glow::BUFFER_SIZE);
+            let data =
+                gl.map_buffer_range(glow::SHADER_STORAGE_BUFFER, 0, size, glow::MAP_READ_BIT);
+            assert!(!data.is_null());
+            let mut vectors = vec![Vector::default(); size as usize / size_of::<Vector>()];
+            ptr::copy_nonoverlapping(data as *const Vector, vectors.as_mut_ptr(), vectors.len());
+            gl.unmap_buffer(glow::SHADER_STORAGE_BUFFER);
+            gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, None);
+            vectors
+        }
+    }
+
+    fn point(v: Vector) -> [f32; 3] {
+        [v[0], v[1], v[2]].map(f32::from_bits)
+    }
+
+    /// Check the bounds of the tree, and return the `(min, max)` of the root
+    /// together with the primitives in the order of the leaves.
+    fn check_nodes(data: &[Vector], primitive_size: usize) -> ([f32; 3], [f32; 3], Vec<&[Vector]>) {
+        let [node_count, primitive_count, leaf_count, _] = data[0].map(|x| x as usize);
+        assert_eq!(node_count, 2 * leaf_count - 1);
+        assert!(leaf_count.is_power_of_two() && leaf_count * 4 >= primitive_count);
+        let node = |i: usize| (point(data[1 + 2 * i]), point(data[2 + 2 * i]));
+        for i in 0..leaf_count - 1 {
+            let (lo, hi) = node(i);
+            let (left_lo, left_hi) = node(2 * i + 1);
+            let (right_lo, right_hi) = node(2 * i + 2);
+            for axis in 0..3 {
+                assert_eq!(lo[axis], left_lo[axis].min(right_lo[axis]), "node {i}");
+                assert_eq!(hi[axis], left_hi[axis].max(right_hi[axis]), "node {i}");
+            }
+        }
+        let mut primitives = Vec::new();
+        for i in leaf_count - 1..node_count {
+            let first = data[1 + 2 * i][3] as usize;
+            let count = data[2 + 2 * i][3] as usize;
+            assert!(count <= 4, "leaf {i}");
+            let expected = 1 + 2 * node_count + primitives.len() * primitive_size;
+            assert!(count == 0 || first == expected, "leaf {i}");
+            for p in 0..count {
+                primitives.push(&data[first + p * primitive_size..][..primitive_size]);
+            }
+        }
+        assert_eq!(primitives.len(), primitive_count);
+        let (lo, hi) = node(0);
+        (lo, hi, primitives)
+    }
+
+    #[test]
+    fn software_ray_query() {
+        let context = match unsafe {
+            super::Context::init(crate::ContextDesc {
+                validation: true,
+                capture: false,
+                timing: false,
+                adapter: crate::AdapterSelector::Any,
+            })
+        } {
+            Ok(context) => context,
+            Err(_) => {
+                println!("Skipping the test without a GL driver");
+                return;
+            }
+        };
+        assert!(context.capabilities().software_ray_query);
+        let shader = context.create_shader(crate::ShaderDesc {
+            source: RAY_QUERY_SHADER,
+        });
+        let pipeline = context.create_compute_pipeline(crate::ComputePipelineDesc {
+            name: "ray-query",
+            data_layouts: &[&<RayQueryData as crate::ShaderData>::layout()],
+            compute: shader.at("main"),
+            overrides: &[],
+            cache: None,
+        });
+
+        // A row of quads along X, each made of two triangles
+        let mut positions = Vec::new();
+        let mut indices = Vec::<u16>::new();
+        for i in 0..QUAD_COUNT {
+            let x = i as f32;
+            let base = positions.len() as u16;
+            positions.extend([
+                [x, 0.0, 0.0],
+                [x + 1.0, 0.0, 0.0],
+                [x, 1.0, 0.0],
+                [x + 1.0, 1.0, 0.0],
+            ]);
+            indices.extend([0, 1, 2, 2, 1, 3].map(|index| base + index));
+        }
+        let vertex_buf = context.create_buffer(crate::BufferDesc {
+            name: "vertices",
+            size: (positions.len() * size_of::<[f32; 3]>()) as u64,
+            memory: crate::Memory::Shared,
+        });
+        let index_buf = context.create_buffer(crate::BufferDesc {
+            name: "indices",
+            size: (indices.len() * size_of::<u16>()) as u64,
+            memory: crate::Memory::Shared,
+        });
+        // The mesh transforms are always applied on GLES
+        let transform: [[f32; 4]; 3] = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ];
+        let transform_buf = context.create_buffer(crate::BufferDesc {
+            name: "transform",
+            size: size_of::<[[f32; 4]; 3]>() as u64,
+            memory: crate::Memory::Shared,
+        });
+        unsafe {
+            ptr::write(transform_buf.data() as *mut [[f32; 4]; 3], transform);
+            ptr::copy_nonoverlapping(
+                positions.as_ptr(),
+                vertex_buf.data() as *mut [f32; 3],
+                positions.len(),
+            );
+            ptr::copy_nonoverlapping(
+                indices.as_ptr(),
+                index_buf.data() as *mut u16,
+                indices.len(),
+            );
+        }
+        context.sync_buffer(vertex_buf);
+        context.sync_buffer(index_buf);
+        context.sync_buffer(transform_buf);
+
+        let meshes = [crate::AccelerationStructureMesh {
+            vertex_data: vertex_buf.at(0),
+            vertex_format: crate::VertexFormat::F32Vec3,
+            vertex_stride: size_of::<[f32; 3]>() as u32,
+            vertex_count: positions.len() as u32,
+            index_data: index_buf.at(0),
+            index_type: Some(crate::IndexType::U16),
+            triangle_count: indices.len() as u32 / 3,
+            transform_data: transform_buf.at(0),
+            is_opaque: true,
+        }];
+        let blas_sizes = context.get_bottom_level_acceleration_structure_sizes(&meshes);
+        let blas = context.create_acceleration_structure(crate::AccelerationStructureDesc {
+            name: "row",
+            ty: crate::AccelerationStructureType::BottomLevel,
+            size: blas_sizes.data,
+        });
+        // The second instance is closer, and shifted to overlap half of the first one
+        let instances = [(0.0, 3.0, 1), (8.0, 1.0, 2)].map(|(x, z, custom_index)| {
+            crate::AccelerationStructureInstance {
+                acceleration_structure_index: 0,
+                transform: [[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, z]].into(),
+                mask: 0xFF,
+                custom_index,
+            }
+        });
+        let instance_buf =
+            context.create_acceleration_structure_instance_buffer(&instances, &[blas]);
+        let tlas_sizes =
+            context.get_top_level_acceleration_structure_sizes_for(instances.len() as u32, &[blas]);
+        let tlas = context.create_acceleration_structure(crate::AccelerationStructureDesc {
+            name: "scene",
+            ty: crate::AccelerationStructureType::TopLevel,
+            size: tlas_sizes.data,
+        });
+        let scratch_buf = context.create_buffer(crate::BufferDesc {
+            name: "scratch",
+            size: blas_sizes.scratch.max(tlas_sizes.scratch),
+            memory: crate::Memory::Device,
+        });
+        let hits_buf = context.create_buffer(crate::BufferDesc {
+            name: "hits",
+            size: RAY_COUNT as u64 * size_of::<[f32; 2]>() as u64,
+            memory: crate::Memory::Shared,
+        });
+
+        let mut encoder = context.create_command_encoder(crate::CommandEncoderDesc {
+            name: "ray-query",
+            command_type: crate::CommandType::General,
+            buffer_count: 1,
+        });
+        encoder.start();
+        if let mut pass = encoder.acceleration_structure_labeled("build") {
+            pass.build_bottom_level(blas, &meshes, scratch_buf.at(0));
+        }
+        if let mut pass = encoder.acceleration_structure_labeled("build") {
+            pass.build_top_level(
+                tlas,
+                &[blas],
+                instances.len() as u32,
+                instance_buf.at(0),
+                scratch_buf.at(0),
+            );
+        }
+        if let mut pass = encoder.compute_labeled("trace") {
+            let mut pc = pass.with(&pipeline);
+            pc.bind(
+                0,
+                &RayQueryData {
+                    acc_struct: tlas,
+                    hits: hits_buf.at(0),
+                },
+            );
+            pc.dispatch([RAY_COUNT / 8, 1, 1]);
+        }
+        let sync_point = context.submit(&mut encoder);
+        assert!(context.wait_for(&sync_point, !0));
+
+        let blas_data = read_acceleration_structure(&context, blas);
+        let (lo, hi, triangles) = check_nodes(&blas_data, 3);
+        assert_eq!((lo, hi), ([0.0; 3], [QUAD_COUNT as f32, 1.0, 0.0]));
+        let mut triangle_indices = triangles
+            .iter()
+            .map(|triangle| triangle[0][3])
+            .collect::<Vec<_>>();
+        triangle_indices.sort_unstable();
+        assert_eq!(triangle_indices, (0..2 * QUAD_COUNT).collect::<Vec<_>>());
+
+        let tlas_data = read_acceleration_structure(&context, tlas);
+        let (lo, hi, records) = check_nodes(&tlas_data, 7);
+        assert_eq!((lo, hi), ([0.0, 0.0, 1.0], [24.0, 1.0, 3.0]));
+        for record in records {
+            // `(custom index, mask, bottom level start, instance index)`
+            let [custom_index, mask, start, index] = record[6];
+            assert_eq!(custom_index, instances[index as usize].custom_index);
+            assert_eq!(mask, 0xFF);
+            let copy = &tlas_data[start as usize..][..blas_data.len()];
+            assert!(copy == &blas_data[..], "instance {index}");
+        }
+
+        let hits = unsafe {
+            std::slice::from_raw_parts(hits_buf.data() as *const [f32; 2], RAY_COUNT as usize)
+        };
+        for (i, &hit) in hits.iter().enumerate() {
+            let expected = match i {
+                0..=7 => [8.0, 1.0],
+                8..=23 => [6.0, 2.0],
+                _ => [-1.0, 0.0],
+            };
+            assert_eq!(hit, expected, "ray {i}");
+        }
+
+        context.destroy_command_encoder(encoder);
+        for buffer in [
+            vertex_buf,
+            index_buf,
+            transform_buf,
+            instance_buf,
+            scratch_buf,
+            hits_buf,
+        ] {
+            context.destroy_buffer(buffer);
+        }
+        context.destroy_acceleration_structure(blas);
+        context.destroy_acceleration_structure(tlas);
+    }
+}
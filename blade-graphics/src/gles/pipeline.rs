@@ -5,30 +5,139 @@ impl super::Context {
     unsafe fn create_pipeline(
         &self,
         shaders: &[crate::ShaderFunction],
+        overrides: &[(&str, f64)],
         group_layouts: &[&crate::ShaderDataLayout],
         name: &str,
-    ) -> super::PipelineInner {
-        let gl = self.lock();
+    ) -> Result<(super::PipelineInner, [u32; 3]), crate::Error> {
+        crate::shader::check_overrides(name, shaders, overrides)?;
 
-        let program = gl.create_program().unwrap();
-        #[cfg(not(target_arch = "wasm32"))]
-        if !name.is_empty() && gl.supports_debug() {
-            gl.object_label(glow::PROGRAM, std::mem::transmute(program), Some(name));
-        }
-
-        let naga_options = glsl::Options {
+        let mut naga_options = glsl::Options {
             version: glsl::Version::Embedded {
-                version: 300,
+                version: self.limits.shading_language_version,
                 is_webgl: cfg!(target_arch = "wasm32"),
             },
             ..Default::default()
         };
 
+        // Assign the slots of the resources, consistently across the stages.
+        // GLSL ES 3.1 can't change the storage buffer bindings after linking.
+        let mut modules = Vec::with_capacity(shaders.len());
+        let mut slot_counts = [0u32; 3];
+        let mut unknown_count = 0;
+        for &sf in shaders {
+            let resolved = sf.shader.resolve_overrides(overrides)?;
+            let mut module = naga::Module::clone(resolved.module());
+            let (elements, lowered_info) = if super::binding_array::has_binding_arrays(&module) {
+                match super::binding_array::lower(&mut module, resolved.info()) {
+                    Ok((elements, info)) => (elements, Some(info)),
+                    Err(e) => {
+                        log::error!("Pipeline '{}': {}", name, e);
+                        return Err(crate::Error::InvalidDescriptor);
+                    }
+                }
+            } else {
+                Default::default()
+            };
+            for (handle, var) in module.global_variables.iter_mut() {
+                if let naga::TypeInner::BindingArray { .. } = module.types[var.ty].inner {
+                    continue;
+                }
+                let kind = match var.space {
+                    naga::AddressSpace::Handle => match module.types[var.ty].inner {
+                        naga::TypeInner::Image { .. } => 0,
+                        _ => continue,
+                    },
+                    naga::AddressSpace::Storage { .. } => 1,
+                    naga::AddressSpace::Uniform => 2,
+                    _ => continue,
+                };
+                let var_name = var.name.as_deref().unwrap_or_default();
+                let location =
+                    group_layouts
+                        .iter()
+                        .enumerate()
+                        .find_map(|(group_index, layout)| {
+                            layout
+                                .bindings
+                                .iter()
+                                .position(|&(name, _)| name == var_name)
+                                .map(|binding_index| (group_index, binding_index))
+                        });
+                var.binding = Some(match location {
+                    Some((group_index, binding_index)) => {
+                        let proto_binding = group_layouts[group_index].bindings[binding_index].1;
+                        let is_acceleration_structure =
+                            sf.shader.acceleration_structures.contains(&handle);
+                        if is_acceleration_structure
+                            != (proto_binding == crate::ShaderBinding::AccelerationStructure)
+                        {
+                            log::error!(
+                                "Pipeline '{}': mismatched type for binding '{}'",
+                                name,
+                                var_name
+                            );
+                            return Err(crate::Error::InvalidDescriptor);
+                        }
+                        // Elements of the binding arrays go past the bindings of the group
+                        let element = elements.get(&handle).map_or(0, |&element| element);
+                        let binding_count = group_layouts[group_index].bindings.len() as u32;
+                        let binding = naga::ResourceBinding {
+                            group: group_index as u32,
+                            binding: binding_index as u32 + element * binding_count,
+                        };
+                        naga_options
+                            .binding_map
+                            .entry(binding.clone())
+                            .or_insert_with(|| {
+                                slot_counts[kind] += 1;
+                                (slot_counts[kind] - 1) as u8
+                            });
+                        binding
+                    }
+                    // Reported after linking, if the variable is used
+                    None => {
+                        unknown_count += 1;
+                        naga::ResourceBinding {
+                            group: !0,
+                            binding: unknown_count,
+                        }
+                    }
+                });
+            }
+            modules.push((module, lowered_info, elements, resolved));
+        }
+        if slot_counts[0] > self.limits.max_texture_units
+            || slot_counts[1] > self.limits.max_storage_buffers
+        {
+            log::error!(
+                "Pipeline '{}' uses {} textures and {} storage buffers, exceeding the limits",
+                name,
+                slot_counts[0],
+                slot_counts[1]
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+
+        let gl = self.lock();
+        let program = gl.create_program().unwrap();
+        #[cfg(not(target_arch = "wasm32"))]
+        if !name.is_empty() && gl.supports_debug() {
+            gl.object_label(
+                glow::PROGRAM,
+                std::mem::transmute::<glow::NativeProgram, u32>(program),
+                Some(name),
+            );
+        }
+
         let mut baked_shaders = Vec::with_capacity(shaders.len());
+        let mut wg_size = [0; 3];
 
-        for &sf in shaders {
+        for (&sf, (module, lowered_info, elements, resolved)) in shaders.iter().zip(modules) {
             let ep_index = sf.entry_point_index();
-            let ep = &sf.shader.module.entry_points[ep_index];
+            let ep = &module.entry_points[ep_index];
+            if ep.stage == naga::ShaderStage::Compute {
+                wg_size = ep.workgroup_size;
+            }
             let pipeline_options = glsl::PipelineOptions {
                 shader_stage: ep.stage,
                 entry_point: sf.entry_point.to_string(),
@@ -37,8 +146,8 @@ impl super::Context {
             let mut source = String::new();
             let mut writer = glsl::Writer::new(
                 &mut source,
-                &sf.shader.module,
-                &sf.shader.info,
+                &module,
+                lowered_info.as_ref().unwrap_or(resolved.info()),
                 &naga_options,
                 &pipeline_options,
                 Default::default(),
@@ -67,7 +176,7 @@ impl super::Context {
             assert!(compiled_ok, "Compile: {}", msg);
 
             gl.attach_shader(program, shader);
-            baked_shaders.push((shader, reflection));
+            baked_shaders.push((shader, reflection, module, elements));
         }
 
         gl.link_program(program);
@@ -89,7 +198,7 @@ impl super::Context {
 
         let force_uniform_block_assignment = true;
         let mut variables_to_bind = Vec::new();
-        for (sf, &(shader, ref reflection)) in shaders.iter().zip(baked_shaders.iter()) {
+        for &(shader, ref reflection, ref module, ref elements) in baked_shaders.iter() {
             for (glsl_name, mapping) in reflection.texture_mapping.iter() {
                 variables_to_bind.push((glsl_name, mapping.texture));
                 if let Some(handle) = mapping.sampler {
@@ -101,7 +210,8 @@ impl super::Context {
             }
 
             for (glsl_name, var_handle) in variables_to_bind.drain(..) {
-                let var = &sf.shader.module.global_variables[var_handle];
+                let var = &module.global_variables[var_handle];
+                let element = elements.get(&var_handle).map_or(0, |&element| element);
                 let var_name = var.name.as_ref().unwrap().as_str();
                 let (group_index, binding_index) = group_layouts
                     .iter()
@@ -119,28 +229,26 @@ impl super::Context {
 
                 let targets = &mut bind_group_infos[group_index].targets[binding_index];
                 match group_layouts[group_index].bindings[binding_index].1 {
-                    crate::ShaderBinding::Texture | crate::ShaderBinding::Sampler => {
+                    crate::ShaderBinding::Texture
+                    | crate::ShaderBinding::TextureArray { .. }
+                    | crate::ShaderBinding::Sampler => {
                         if let Some(ref location) = gl.get_uniform_location(program, glsl_name) {
                             let mut slots = [0i32];
                             gl.get_uniform_i32(program, location, &mut slots);
-                            targets.push(slots[0] as u32);
-                        }
-                    }
-                    crate::ShaderBinding::Buffer => {
-                        if let Some(index) = gl.get_shader_storage_block_index(program, glsl_name) {
-                            let params = gl.get_program_resource_i32(
-                                program,
-                                glow::SHADER_STORAGE_BLOCK,
-                                index,
-                                &[glow::BUFFER_BINDING],
-                            );
-                            targets.push(params[0] as u32);
+                            targets.push((slots[0] as u32, element));
                         }
                     }
-                    crate::ShaderBinding::TextureArray { .. }
+                    // Acceleration structures are lowered into storage buffers
+                    crate::ShaderBinding::Buffer
                     | crate::ShaderBinding::BufferArray { .. }
                     | crate::ShaderBinding::AccelerationStructure => {
-                        unimplemented!()
+                        if gl
+                            .get_shader_storage_block_index(program, glsl_name)
+                            .is_some()
+                        {
+                            let binding = var.binding.as_ref().unwrap();
+                            targets.push((naga_options.binding_map[binding] as u32, element));
+                        }
                     }
                     crate::ShaderBinding::Plain { size } => {
                         if let Some(index) = gl.get_uniform_block_index(program, glsl_name) {
@@ -168,7 +276,7 @@ impl super::Context {
                                     glow::UNIFORM_BLOCK_BINDING,
                                 ) as u32
                             };
-                            targets.push(slot);
+                            targets.push((slot, 0));
                         }
                     }
                 }
@@ -178,29 +286,84 @@ impl super::Context {
         }
         gl.use_program(None);
 
-        super::PipelineInner {
+        let inner = super::PipelineInner {
             program,
             bind_group_infos,
-        }
+        };
+        Ok((inner, wg_size))
     }
 
-    pub fn create_compute_pipeline(
+    pub fn create_pipeline_cache(&self, _desc: crate::PipelineCacheDesc) -> super::PipelineCache {
+        super::PipelineCache {}
+    }
+
+    pub fn get_pipeline_cache_data(&self, _cache: &super::PipelineCache) -> Vec<u8> {
+        Vec::new()
+    }
+
+    pub fn destroy_pipeline_cache(&self, _cache: super::PipelineCache) {}
+
+    pub fn try_create_compute_pipeline(
         &self,
         desc: crate::ComputePipelineDesc,
-    ) -> super::ComputePipeline {
-        let wg_size = desc.compute.shader.module.entry_points[desc.compute.entry_point_index()]
-            .workgroup_size;
-        let inner = unsafe { self.create_pipeline(&[desc.compute], desc.data_layouts, desc.name) };
-        super::ComputePipeline { inner, wg_size }
+    ) -> Result<super::ComputePipeline, crate::Error> {
+        let (inner, wg_size) = unsafe {
+            self.create_pipeline(
+                &[desc.compute],
+                desc.overrides,
+                desc.data_layouts,
+                desc.name,
+            )
+        }?;
+        Ok(super::ComputePipeline { inner, wg_size })
     }
 
-    pub fn create_render_pipeline(&self, desc: crate::RenderPipelineDesc) -> super::RenderPipeline {
-        let inner = unsafe {
-            self.create_pipeline(&[desc.vertex, desc.fragment], desc.data_layouts, desc.name)
-        };
-        super::RenderPipeline {
+    pub fn try_create_render_pipeline(
+        &self,
+        desc: crate::RenderPipelineDesc,
+    ) -> Result<super::RenderPipeline, crate::Error> {
+        if desc.multisample_state.alpha_to_coverage || desc.multisample_state.sample_mask != !0 {
+            log::warn!("Multisample state is not supported yet");
+        }
+        let (inner, _) = unsafe {
+            self.create_pipeline(
+                &[desc.vertex, desc.fragment],
+                desc.overrides,
+                desc.data_layouts,
+                desc.name,
+            )
+        }?;
+        let mut location = 0;
+        let vertex_buffers = desc
+            .vertex_fetches
+            .iter()
+            .map(|vf| super::VertexBufferInfo {
+                attributes: vf
+                    .layout
+                    .attributes
+                    .iter()
+                    .map(|at| {
+                        location += 1;
+                        super::VertexAttributeInfo {
+                            location: location - 1,
+                            offset: at.offset,
+                            format: at.format,
+                        }
+                    })
+                    .collect(),
+                stride: vf.layout.stride,
+                instanced: vf.instanced,
+            })
+            .collect();
+        Ok(super::RenderPipeline {
             inner,
             topology: desc.primitive.topology,
-        }
+            vertex_buffers,
+            stencil: desc
+                .depth_stencil
+                .as_ref()
+                .map(|ds| ds.stencil.clone())
+                .unwrap_or_default(),
+        })
     }
 }
@@ -1,27 +1,56 @@
+use crate::software_rt;
 use glow::HasContext as _;
-use std::{ptr, slice};
+use std::{mem, ptr, slice};
 
 impl super::Context {
     pub fn get_bottom_level_acceleration_structure_sizes(
         &self,
-        _meshes: &[crate::AccelerationStructureMesh],
+        meshes: &[crate::AccelerationStructureMesh],
     ) -> crate::AccelerationStructureSizes {
-        unimplemented!()
+        software_rt::bottom_level_sizes(meshes)
     }
 
     pub fn get_top_level_acceleration_structure_sizes(
         &self,
-        _instance_count: u32,
+        instance_count: u32,
     ) -> crate::AccelerationStructureSizes {
-        unimplemented!()
+        software_rt::top_level_sizes(instance_count)
     }
 
+    pub fn get_top_level_acceleration_structure_sizes_for(
+        &self,
+        instance_count: u32,
+        bottom_level: &[super::AccelerationStructure],
+    ) -> crate::AccelerationStructureSizes {
+        let sizes = bottom_level
+            .iter()
+            .map(|blas| blas.size)
+            .collect::<Vec<_>>();
+        software_rt::top_level_sizes_with_copies(instance_count, &sizes)
+    }
+
+    /// The instances reference the bottom level structures by their offsets
+    /// in the top level, so they need to be given to the build in the same order.
     pub fn create_acceleration_structure_instance_buffer(
         &self,
-        _instances: &[crate::AccelerationStructureInstance],
-        _bottom_level: &[super::AccelerationStructure],
+        instances: &[crate::AccelerationStructureInstance],
+        bottom_level: &[super::AccelerationStructure],
     ) -> super::Buffer {
-        unimplemented!()
+        let sizes = bottom_level
+            .iter()
+            .map(|blas| blas.size)
+            .collect::<Vec<_>>();
+        let data = software_rt::instance_data(instances, &sizes);
+        let buffer = self.create_buffer(crate::BufferDesc {
+            name: "instance buffer",
+            size: (instances.len() as u64 * software_rt::INSTANCE_DATA_SIZE).max(1),
+            memory: crate::Memory::Upload,
+        });
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), buffer.data, data.len());
+        }
+        self.sync_buffer(buffer);
+        buffer
     }
 }
 
@@ -33,10 +62,10 @@ impl crate::traits::ResourceDevice for super::Context {
     type Sampler = super::Sampler;
     type AccelerationStructure = super::AccelerationStructure;
 
-    fn create_buffer(&self, desc: crate::BufferDesc) -> super::Buffer {
+    fn try_create_buffer(&self, desc: crate::BufferDesc) -> Result<super::Buffer, crate::Error> {
         let gl = self.lock();
 
-        let raw = unsafe { gl.create_buffer() }.unwrap();
+        let raw = unsafe { gl.create_buffer() }.map_err(|_| crate::Error::OutOfMemory)?;
         let mut data = ptr::null_mut();
 
         let mut storage_flags = 0;
@@ -44,7 +73,11 @@ impl crate::traits::ResourceDevice for super::Context {
         let usage = match desc.memory {
             crate::Memory::Device => glow::STATIC_DRAW,
             crate::Memory::Shared => {
-                map_flags = glow::MAP_READ_BIT | glow::MAP_WRITE_BIT | glow::MAP_UNSYNCHRONIZED_BIT;
+                // Unsynchronized mapping is not allowed for reading
+                map_flags = glow::MAP_READ_BIT
+                    | glow::MAP_WRITE_BIT
+                    | glow::MAP_PERSISTENT_BIT
+                    | glow::MAP_COHERENT_BIT;
                 storage_flags = glow::MAP_PERSISTENT_BIT
                     | glow::MAP_COHERENT_BIT
                     | glow::MAP_READ_BIT
@@ -52,7 +85,10 @@ impl crate::traits::ResourceDevice for super::Context {
                 glow::STREAM_READ
             }
             crate::Memory::Upload => {
-                map_flags = glow::MAP_WRITE_BIT | glow::MAP_UNSYNCHRONIZED_BIT;
+                map_flags = glow::MAP_WRITE_BIT
+                    | glow::MAP_UNSYNCHRONIZED_BIT
+                    | glow::MAP_PERSISTENT_BIT
+                    | glow::MAP_COHERENT_BIT;
                 storage_flags =
                     glow::MAP_PERSISTENT_BIT | glow::MAP_COHERENT_BIT | glow::MAP_WRITE_BIT;
                 glow::DYNAMIC_DRAW
@@ -66,6 +102,11 @@ impl crate::traits::ResourceDevice for super::Context {
                 .contains(super::Capabilities::BUFFER_STORAGE)
             {
                 gl.buffer_storage(glow::ARRAY_BUFFER, desc.size as _, None, storage_flags);
+                if gl.get_error() == glow::OUT_OF_MEMORY {
+                    gl.bind_buffer(glow::ARRAY_BUFFER, None);
+                    gl.delete_buffer(raw);
+                    return Err(crate::Error::OutOfMemory);
+                }
                 if map_flags != 0 {
                     data = gl.map_buffer_range(glow::ARRAY_BUFFER, 0, desc.size as _, map_flags);
                 }
@@ -77,14 +118,19 @@ impl crate::traits::ResourceDevice for super::Context {
             gl.bind_buffer(glow::ARRAY_BUFFER, None);
             #[cfg(not(target_arch = "wasm32"))]
             if !desc.name.is_empty() && gl.supports_debug() {
-                gl.object_label(glow::BUFFER, std::mem::transmute(raw), Some(desc.name));
+                gl.object_label(
+                    glow::BUFFER,
+                    mem::transmute::<glow::NativeBuffer, u32>(raw),
+                    Some(desc.name),
+                );
             }
         }
-        super::Buffer {
+        self.resource_counts.buffers.increment();
+        Ok(super::Buffer {
             raw,
             size: desc.size,
             data,
-        }
+        })
     }
 
     fn sync_buffer(&self, buffer: super::Buffer) {
@@ -113,9 +159,11 @@ impl crate::traits::ResourceDevice for super::Context {
                 Vec::from_raw_parts(buffer.data, buffer.size as usize, buffer.size as usize);
             }
         }
+        self.resource_counts.buffers.decrement();
     }
 
-    fn create_texture(&self, desc: crate::TextureDesc) -> super::Texture {
+    fn try_create_texture(&self, desc: crate::TextureDesc) -> Result<super::Texture, crate::Error> {
+        desc.validate(&self.device_limits, self.format_features(desc.format))?;
         let gl = self.lock();
         let format_desc = super::describe_texture_format(desc.format);
 
@@ -123,28 +171,41 @@ impl crate::traits::ResourceDevice for super::Context {
             && desc.dimension == crate::TextureDimension::D2
             && desc.array_layer_count == 1
         {
-            let raw = unsafe { gl.create_renderbuffer().unwrap() };
+            let raw = unsafe { gl.create_renderbuffer() }.map_err(|_| crate::Error::OutOfMemory)?;
             unsafe {
                 gl.bind_renderbuffer(glow::RENDERBUFFER, Some(raw));
-                gl.renderbuffer_storage(
-                    glow::RENDERBUFFER,
-                    format_desc.internal,
-                    desc.size.width as i32,
-                    desc.size.height as i32,
-                );
+                if desc.sample_count > 1 {
+                    gl.renderbuffer_storage_multisample(
+                        glow::RENDERBUFFER,
+                        desc.sample_count as i32,
+                        format_desc.internal,
+                        desc.size.width as i32,
+                        desc.size.height as i32,
+                    );
+                } else {
+                    gl.renderbuffer_storage(
+                        glow::RENDERBUFFER,
+                        format_desc.internal,
+                        desc.size.width as i32,
+                        desc.size.height as i32,
+                    );
+                }
                 gl.bind_renderbuffer(glow::RENDERBUFFER, None);
                 #[cfg(not(target_arch = "wasm32"))]
                 if !desc.name.is_empty() && gl.supports_debug() {
                     gl.object_label(
                         glow::RENDERBUFFER,
-                        std::mem::transmute(raw),
+                        mem::transmute::<glow::NativeRenderbuffer, u32>(raw),
                         Some(desc.name),
                     );
                 }
             }
             super::TextureInner::Renderbuffer { raw }
+        } else if desc.sample_count > 1 && desc.array_layer_count > 1 {
+            log::error!("Multisampled texture arrays are not supported");
+            return Err(crate::Error::InvalidDescriptor);
         } else {
-            let raw = unsafe { gl.create_texture().unwrap() };
+            let raw = unsafe { gl.create_texture() }.map_err(|_| crate::Error::OutOfMemory)?;
             let target = match desc.dimension {
                 crate::TextureDimension::D1 => {
                     if desc.array_layer_count > 1 {
@@ -154,7 +215,9 @@ impl crate::traits::ResourceDevice for super::Context {
                     }
                 }
                 crate::TextureDimension::D2 => {
-                    if desc.array_layer_count > 1 {
+                    if desc.sample_count > 1 {
+                        glow::TEXTURE_2D_MULTISAMPLE
+                    } else if desc.array_layer_count > 1 {
                         glow::TEXTURE_2D_ARRAY
                     } else {
                         glow::TEXTURE_2D
@@ -166,9 +229,11 @@ impl crate::traits::ResourceDevice for super::Context {
             unsafe {
                 gl.bind_texture(target, Some(raw));
                 // Reset default filtering mode. This has to be done before
-                // assigning the storage.
-                gl.tex_parameter_i32(target, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
-                gl.tex_parameter_i32(target, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+                // assigning the storage. Multisampled textures can't be filtered.
+                if desc.sample_count == 1 {
+                    gl.tex_parameter_i32(target, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+                    gl.tex_parameter_i32(target, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+                }
 
                 match desc.dimension {
                     crate::TextureDimension::D3 => {
@@ -181,6 +246,16 @@ impl crate::traits::ResourceDevice for super::Context {
                             desc.size.depth as i32,
                         );
                     }
+                    crate::TextureDimension::D2 if desc.sample_count > 1 => {
+                        gl.tex_storage_2d_multisample(
+                            target,
+                            desc.sample_count as i32,
+                            format_desc.internal,
+                            desc.size.width as i32,
+                            desc.size.height as i32,
+                            true,
+                        );
+                    }
                     crate::TextureDimension::D2 => {
                         gl.tex_storage_2d(
                             target,
@@ -203,18 +278,35 @@ impl crate::traits::ResourceDevice for super::Context {
                 gl.bind_texture(target, None);
                 #[cfg(not(target_arch = "wasm32"))]
                 if !desc.name.is_empty() && gl.supports_debug() {
-                    gl.object_label(glow::TEXTURE, std::mem::transmute(raw), Some(desc.name));
+                    gl.object_label(
+                        glow::TEXTURE,
+                        mem::transmute::<glow::NativeTexture, u32>(raw),
+                        Some(desc.name),
+                    );
                 }
             }
 
             super::TextureInner::Texture { raw, target }
         };
 
-        super::Texture {
+        if unsafe { gl.get_error() } == glow::OUT_OF_MEMORY {
+            match inner {
+                super::TextureInner::Renderbuffer { raw, .. } => unsafe {
+                    gl.delete_renderbuffer(raw);
+                },
+                super::TextureInner::Texture { raw, .. } => unsafe {
+                    gl.delete_texture(raw);
+                },
+            }
+            return Err(crate::Error::OutOfMemory);
+        }
+        self.resource_counts.textures.increment();
+
+        Ok(super::Texture {
             inner,
             target_size: [desc.size.width as u16, desc.size.height as u16],
             format: desc.format,
-        }
+        })
     }
 
     fn destroy_texture(&self, texture: super::Texture) {
@@ -227,20 +319,24 @@ impl crate::traits::ResourceDevice for super::Context {
                 gl.delete_texture(raw);
             },
         }
+        self.resource_counts.textures.decrement();
     }
 
-    fn create_texture_view(&self, desc: crate::TextureViewDesc) -> super::TextureView {
+    fn try_create_texture_view(
+        &self,
+        desc: crate::TextureViewDesc,
+    ) -> Result<super::TextureView, crate::Error> {
         //TODO: actual reinterpretation
-        super::TextureView {
+        Ok(super::TextureView {
             inner: desc.texture.inner,
             target_size: desc.texture.target_size,
-            aspects: desc.format.aspects(),
-        }
+            aspects: desc.format.select_aspects(desc.subresources.aspects),
+        })
     }
 
     fn destroy_texture_view(&self, _view: super::TextureView) {}
 
-    fn create_sampler(&self, desc: crate::SamplerDesc) -> super::Sampler {
+    fn try_create_sampler(&self, desc: crate::SamplerDesc) -> Result<super::Sampler, crate::Error> {
         let gl = self.lock();
 
         let wrap_enums = [
@@ -255,7 +351,7 @@ impl crate::traits::ResourceDevice for super::Context {
             Some(crate::TextureColor::White) => [1.0; 4],
         };
 
-        let raw = unsafe { gl.create_sampler().unwrap() };
+        let raw = unsafe { gl.create_sampler() }.map_err(|_| crate::Error::OutOfMemory)?;
         unsafe {
             gl.sampler_parameter_i32(raw, glow::TEXTURE_MIN_FILTER, min as i32);
             gl.sampler_parameter_i32(raw, glow::TEXTURE_MAG_FILTER, mag as i32);
@@ -293,10 +389,14 @@ impl crate::traits::ResourceDevice for super::Context {
 
             #[cfg(not(target_arch = "wasm32"))]
             if !desc.name.is_empty() && gl.supports_debug() {
-                gl.object_label(glow::SAMPLER, std::mem::transmute(raw), Some(desc.name));
+                gl.object_label(
+                    glow::SAMPLER,
+                    mem::transmute::<glow::NativeSampler, u32>(raw),
+                    Some(desc.name),
+                );
             }
         }
-        super::Sampler { raw }
+        Ok(super::Sampler { raw })
     }
 
     fn destroy_sampler(&self, sampler: super::Sampler) {
@@ -304,18 +404,42 @@ impl crate::traits::ResourceDevice for super::Context {
         unsafe { gl.delete_sampler(sampler.raw) };
     }
 
-    fn create_acceleration_structure(
+    fn try_create_acceleration_structure(
         &self,
-        _desc: crate::AccelerationStructureDesc,
-    ) -> super::AccelerationStructure {
-        unimplemented!()
+        desc: crate::AccelerationStructureDesc,
+    ) -> Result<super::AccelerationStructure, crate::Error> {
+        self.software_rt.init(self);
+        let gl = self.lock();
+        let raw = unsafe { gl.create_buffer() }.map_err(|_| crate::Error::OutOfMemory)?;
+        // The storage is mutable, since the top level grows when built.
+        unsafe {
+            gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, Some(raw));
+            gl.buffer_data_size(
+                glow::SHADER_STORAGE_BUFFER,
+                desc.size as _,
+                glow::STATIC_DRAW,
+            );
+            gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, None);
+            #[cfg(not(target_arch = "wasm32"))]
+            if !desc.name.is_empty() && gl.supports_debug() {
+                gl.object_label(
+                    glow::BUFFER,
+                    mem::transmute::<glow::NativeBuffer, u32>(raw),
+                    Some(desc.name),
+                );
+            }
+        }
+        self.resource_counts.acceleration_structures.increment();
+        Ok(super::AccelerationStructure {
+            raw,
+            size: desc.size,
+        })
     }
 
-    fn destroy_acceleration_structure(
-        &self,
-        _acceleration_structure: super::AccelerationStructure,
-    ) {
-        unimplemented!()
+    fn destroy_acceleration_structure(&self, acceleration_structure: super::AccelerationStructure) {
+        let gl = self.lock();
+        unsafe { gl.delete_buffer(acceleration_structure.raw) };
+        self.resource_counts.acceleration_structures.decrement();
     }
 }
 
@@ -1,5 +1,5 @@
 use glow::HasContext as _;
-use std::cell::Cell;
+use std::{cell::Cell, sync::Arc};
 use wasm_bindgen::JsCast;
 
 //TODO: consider sharing this struct with EGL
@@ -17,6 +17,9 @@ pub struct Context {
     swapchain: Swapchain,
     pub(super) capabilities: super::Capabilities,
     pub(super) limits: super::Limits,
+    pub(super) device_limits: crate::Limits,
+    pub(super) resource_counts: crate::util::ResourceCounts,
+    pub(super) software_rt: Arc<crate::software_rt::SharedPipelines>,
 }
 
 impl Context {
@@ -24,12 +27,20 @@ impl Context {
         Err(crate::NotSupportedError)
     }
 
+    /// WebGL contexts are only created for a canvas, so there is nothing to list.
+    pub unsafe fn enumerate_adapters() -> Vec<crate::AdapterInfo> {
+        Vec::new()
+    }
+
     pub unsafe fn init_windowed<
         I: raw_window_handle::HasRawWindowHandle + raw_window_handle::HasRawDisplayHandle,
     >(
         window: I,
         desc: crate::ContextDesc,
     ) -> Result<Self, crate::NotSupportedError> {
+        if desc.timing {
+            log::warn!("Timing is not supported on WebGL, the timings will be empty");
+        }
         let webgl2 = match window.raw_window_handle() {
             raw_window_handle::RawWindowHandle::Web(handle) => {
                 let canvas: web_sys::HtmlCanvasElement = web_sys::window()
@@ -59,12 +70,38 @@ impl Context {
         };
 
         let glow = glow::Context::from_webgl2_context(webgl2.clone());
-        let capabilities = super::Capabilities::empty();
+        let extensions = glow.supported_extensions();
+        let mut capabilities = super::Capabilities::empty();
+        capabilities.set(
+            super::Capabilities::TEXTURE_BC,
+            extensions.contains("WEBGL_compressed_texture_s3tc")
+                && extensions.contains("EXT_texture_compression_rgtc")
+                && extensions.contains("EXT_texture_compression_bptc"),
+        );
+        capabilities.set(
+            super::Capabilities::TEXTURE_ETC2,
+            extensions.contains("WEBGL_compressed_texture_etc"),
+        );
+        capabilities.set(
+            super::Capabilities::TEXTURE_ASTC,
+            extensions.contains("WEBGL_compressed_texture_astc"),
+        );
+        capabilities.set(
+            super::Capabilities::COLOR_BUFFER_FLOAT,
+            extensions.contains("EXT_color_buffer_float"),
+        );
         let limits = super::Limits {
             uniform_buffer_alignment: unsafe {
                 glow.get_parameter_i32(glow::UNIFORM_BUFFER_OFFSET_ALIGNMENT) as u32
             },
+            max_samples: unsafe { glow.get_parameter_i32(glow::MAX_SAMPLES) as u32 },
+            shading_language_version: 300,
+            max_texture_units: unsafe {
+                glow.get_parameter_i32(glow::MAX_COMBINED_TEXTURE_IMAGE_UNITS) as u32
+            },
+            max_storage_buffers: 0,
         };
+        let device_limits = unsafe { super::query_device_limits(&glow) };
         let swapchain = Swapchain {
             renderbuf: unsafe { glow.create_renderbuffer().unwrap() },
             framebuf: unsafe { glow.create_framebuffer().unwrap() },
@@ -78,11 +115,15 @@ impl Context {
             swapchain,
             capabilities,
             limits,
+            device_limits,
+            resource_counts: Default::default(),
+            software_rt: Default::default(),
         })
     }
 
     pub fn resize(&self, config: crate::SurfaceConfig) -> crate::TextureFormat {
         let sc = &self.swapchain;
+        //TODO: respect `config.color_space`
         let format_desc = super::describe_texture_format(sc.format);
         let gl = &self.glow;
         //Note: this code can be shared with EGL
@@ -23,7 +23,7 @@
     clippy::pattern_type_mismatch,
 )]
 
-pub use naga::{StorageAccess, VectorSize};
+pub use naga::{ScalarKind, StorageAccess, VectorSize};
 pub type Transform = mint::RowMatrix3x4<f32>;
 
 pub const IDENTITY_TRANSFORM: Transform = mint::RowMatrix3x4 {
@@ -48,23 +48,39 @@ pub const IDENTITY_TRANSFORM: Transform = mint::RowMatrix3x4 {
 };
 
 #[cfg_attr(
-    all(not(vulkan), not(gles), any(target_os = "ios", target_os = "macos")),
+    all(
+        not(vulkan),
+        not(gles),
+        not(null),
+        any(target_os = "ios", target_os = "macos")
+    ),
     path = "metal/mod.rs"
 )]
 #[cfg_attr(
     all(
         not(gles),
+        not(null),
         any(vulkan, windows, target_os = "linux", target_os = "android")
     ),
     path = "vulkan/mod.rs"
 )]
-#[cfg_attr(any(gles, target_arch = "wasm32"), path = "gles/mod.rs")]
+#[cfg_attr(
+    all(not(null), any(gles, target_arch = "wasm32")),
+    path = "gles/mod.rs"
+)]
+#[cfg_attr(null, path = "null/mod.rs")]
 mod hal;
+#[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+mod lowering;
+mod readback;
 mod shader;
+#[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+mod software_rt;
 mod traits;
 pub mod util;
 pub mod limits {
-    pub const PLAIN_DATA_SIZE: u32 = 256;
+    /// Maximum size of a `ShaderBinding::Plain`, matching the Metal limit on inline bytes.
+    pub const PLAIN_DATA_SIZE: u32 = 4096;
     pub const RESOURCES_IN_GROUP: u32 = 8;
     pub const STORAGE_BUFFER_ALIGNMENT: u64 = 256;
     pub const ACCELERATION_STRUCTURE_SCRATCH_ALIGNMENT: u64 = 256;
@@ -72,21 +88,188 @@ pub mod limits {
 
 pub use hal::*;
 
-use std::{fmt, num::NonZeroU32};
+use std::{fmt, num::NonZeroU32, time::Duration};
 
 #[derive(Debug)]
 pub struct ContextDesc {
+    /// Enable the API validation, and GPU crash markers of the passes
+    /// on Vulkan, which are reported when the device is lost.
+    /// On Vulkan, this also tracks the names of allocations for `Context::memory_report`.
     pub validation: bool,
     pub capture: bool,
+    /// Measure GPU time spent in each pass.
+    /// Not supported on Metal and WebGL, where the timings stay empty.
+    pub timing: bool,
+    /// Which of the adapters to initialize.
+    pub adapter: AdapterSelector,
+}
+
+/// Choice of the adapter out of the ones listed by `Context::enumerate_adapters`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AdapterSelector {
+    /// Default adapter of the platform.
+    #[default]
+    Any,
+    /// Adapter at the given index of the list.
+    Index(usize),
+    /// The first adapter with a name containing the given string.
+    Name(String),
+    /// Discrete GPU if there is one, or any other adapter.
+    PreferDiscrete,
+    /// Integrated GPU if there is one, or any other adapter.
+    PreferIntegrated,
+    /// CPU implementation if there is one, or any other adapter.
+    PreferSoftware,
+}
+
+impl AdapterSelector {
+    /// Pick the index of the selected adapter in the list.
+    fn select(&self, adapters: &[AdapterInfo]) -> Option<usize> {
+        let first = if adapters.is_empty() { None } else { Some(0) };
+        let prefer = |device_type: DeviceType| {
+            adapters
+                .iter()
+                .position(|info| info.device_type == device_type)
+                .or(first)
+        };
+        match *self {
+            Self::Any => first,
+            Self::Index(index) => Some(index).filter(|&index| index < adapters.len()),
+            Self::Name(ref name) => adapters.iter().position(|info| info.name.contains(name)),
+            Self::PreferDiscrete => prefer(DeviceType::Discrete),
+            Self::PreferIntegrated => prefer(DeviceType::Integrated),
+            Self::PreferSoftware => prefer(DeviceType::Software),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceType {
+    Discrete,
+    Integrated,
+    Virtual,
+    /// Implementation running on the CPU, like lavapipe.
+    Software,
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub name: String,
+    /// PCI vendor ID, if known.
+    pub vendor_id: u32,
+    pub device_type: DeviceType,
+    /// Driver version, encoded in a vendor-specific way.
+    pub driver_version: u32,
+    pub capabilities: Capabilities,
 }
 
 #[derive(Debug)]
 pub struct NotSupportedError;
 
+/// Reason for a failed resource or pipeline creation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Not enough host or device memory.
+    OutOfMemory,
+    /// Format is not supported for the requested usage.
+    UnsupportedFormat,
+    /// Descriptor is invalid or exceeds the device limits.
+    InvalidDescriptor,
+    /// Device is lost and has to be re-created.
+    DeviceLost,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Self::OutOfMemory => "out of memory",
+            Self::UnsupportedFormat => "unsupported format",
+            Self::InvalidDescriptor => "invalid descriptor",
+            Self::DeviceLost => "device lost",
+        };
+        f.write_str(description)
+    }
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Capabilities {
     /// Which shader stages support ray queries
     pub ray_query: ShaderVisibility,
+    /// Bit mask of supported MSAA sample counts, where each bit
+    /// is the sample count itself, i.e. `4` means 4x MSAA is supported.
+    pub sample_count_mask: u32,
+    /// Support for `TextureArray` and `BufferArray` bindings.
+    /// Pipelines using them fail to be created otherwise.
+    pub binding_arrays: bool,
+    /// Ray queries are emulated by traversing acceleration structures
+    /// from storage buffers, which is much slower than the hardware path.
+    pub software_ray_query: bool,
+    /// Support for render bundles.
+    /// Creating a bundle encoder fails otherwise.
+    pub render_bundles: bool,
+}
+
+/// Numeric limits of the device.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum width and height of 1D and 2D textures.
+    pub max_texture_dimension_2d: u32,
+    /// Maximum width, height, and depth of 3D textures.
+    pub max_texture_dimension_3d: u32,
+    pub max_texture_array_layers: u32,
+    /// Maximum size of a workgroup in each dimension.
+    pub max_workgroup_size: [u32; 3],
+    /// Maximum number of invocations in a workgroup.
+    pub max_workgroup_invocations: u32,
+    /// Maximum number of workgroups in each dimension of a dispatch.
+    pub max_workgroup_count: [u32; 3],
+    /// Number of invocations in a subgroup, or zero if it's not known
+    /// ahead of the pipeline creation.
+    pub subgroup_size: u32,
+    /// Nanoseconds per tick of the GPU timestamps, or zero if they are not supported.
+    pub timestamp_period: f32,
+    /// Memory heaps of the device, or empty if they are not known.
+    pub memory_heaps: Vec<MemoryHeap>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryHeap {
+    /// Size in bytes.
+    pub size: u64,
+    /// Heap is local to the device, as opposed to the host memory.
+    pub device_local: bool,
+}
+
+/// Snapshot of the memory used by the context.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryReport {
+    /// Usage of each heap, in the order of `Limits::memory_heaps`.
+    pub heaps: Vec<HeapUsage>,
+    pub buffer_count: usize,
+    pub texture_count: usize,
+    pub acceleration_structure_count: usize,
+    /// Live allocations, only tracked on Vulkan with `ContextDesc::validation`.
+    pub allocations: Vec<AllocationReport>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapUsage {
+    /// Bytes allocated from the heap.
+    pub usage: u64,
+    /// Bytes that can be allocated from the heap without a performance loss,
+    /// or the heap size if the platform doesn't report a budget.
+    pub budget: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllocationReport {
+    /// Name of the resource owning the allocation.
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -197,6 +380,9 @@ pub struct TexturePiece {
     pub mip_level: u32,
     pub array_layer: u32,
     pub origin: [u32; 3],
+    /// Aspects of the texture to copy, where empty means all of them.
+    /// Copies between buffers and depth-stencil textures need a single aspect.
+    pub aspects: TexelAspects,
 }
 
 impl From<Texture> for TexturePiece {
@@ -206,6 +392,7 @@ impl From<Texture> for TexturePiece {
             mip_level: 0,
             array_layer: 0,
             origin: [0; 3],
+            aspects: TexelAspects::empty(),
         }
     }
 }
@@ -214,21 +401,39 @@ impl From<Texture> for TexturePiece {
 #[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
 pub enum TextureFormat {
     // color
+    R8Unorm,
     Rg8Unorm,
     Rg8Snorm,
     Rgba8Unorm,
     Rgba8UnormSrgb,
+    Bgra8Unorm,
     Bgra8UnormSrgb,
     Rgba8Snorm,
+    R16Float,
+    Rg16Float,
     Rgba16Float,
+    Rgba16Unorm,
     R32Float,
     Rg32Float,
     Rgba32Float,
     R32Uint,
     Rg32Uint,
     Rgba32Uint,
+    R32Sint,
+    Rg32Sint,
+    Rgba32Sint,
+    // packed
+    Rgb10a2Unorm,
+    Rg11b10Float,
+    /// Shared 5-bit exponent with 9-bit mantissas, can't be rendered to.
+    Rgb9e5Ufloat,
     // depth and stencil
     Depth32Float,
+    /// Depth with at least 24 bits of precision, and 8 bits of stencil.
+    /// It may be backed by `Depth32FloatStencil8`, and has the same block size.
+    Depth24PlusStencil8,
+    Depth32FloatStencil8,
+    Stencil8,
     // S3TC block compression
     Bc1Unorm,
     Bc1UnormSrgb,
@@ -240,6 +445,24 @@ pub enum TextureFormat {
     Bc4Snorm,
     Bc5Unorm,
     Bc5Snorm,
+    // BPTC block compression
+    /// HDR color without alpha, encoding non-negative half-floats.
+    Bc6hUfloat,
+    Bc6hFloat,
+    Bc7Unorm,
+    Bc7UnormSrgb,
+    // ETC2 block compression, mostly found on mobile
+    Etc2Rgb8Unorm,
+    Etc2Rgb8UnormSrgb,
+    Etc2Rgba8Unorm,
+    Etc2Rgba8UnormSrgb,
+    // ASTC block compression with LDR profile
+    Astc4x4Unorm,
+    Astc4x4UnormSrgb,
+    Astc6x6Unorm,
+    Astc6x6UnormSrgb,
+    Astc8x8Unorm,
+    Astc8x8UnormSrgb,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -335,6 +558,9 @@ pub struct TextureDesc<'a> {
     pub size: Extent,
     pub array_layer_count: u32,
     pub mip_level_count: u32,
+    /// Number of samples per texel. Textures with more than one sample
+    /// can only be used as render targets that get resolved.
+    pub sample_count: u32,
     pub dimension: TextureDimension,
     pub usage: TextureUsage,
 }
@@ -345,6 +571,9 @@ pub struct TextureSubresources {
     pub mip_level_count: Option<NonZeroU32>,
     pub base_array_layer: u32,
     pub array_layer_count: Option<NonZeroU32>,
+    /// Aspects visible through the view, where empty means all of them.
+    /// Sampled views of depth-stencil textures need a single aspect.
+    pub aspects: TexelAspects,
 }
 
 #[derive(Debug)]
@@ -452,7 +681,30 @@ pub struct AccelerationStructureDesc<'a> {
 #[non_exhaustive]
 #[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
 pub enum VertexFormat {
+    F32,
+    F32Vec2,
     F32Vec3,
+    F32Vec4,
+    U32,
+    U32Vec2,
+    U32Vec3,
+    U32Vec4,
+    I32,
+    I32Vec2,
+    I32Vec3,
+    I32Vec4,
+    /// Four unsigned bytes, normalized to `[0, 1]` floats.
+    U8Vec4Norm,
+    /// Four signed bytes, normalized to `[-1, 1]` floats.
+    I8Vec4Norm,
+    /// Two unsigned shorts, normalized to `[0, 1]` floats.
+    U16Vec2Norm,
+    /// Four unsigned shorts, normalized to `[0, 1]` floats.
+    U16Vec4Norm,
+    /// Two signed shorts, normalized to `[-1, 1]` floats.
+    I16Vec2Norm,
+    /// Four signed shorts, normalized to `[-1, 1]` floats.
+    I16Vec4Norm,
 }
 
 #[derive(Clone, Debug)]
@@ -487,6 +739,15 @@ pub struct AccelerationStructureSizes {
 pub struct Shader {
     module: naga::Module,
     info: naga::valid::ModuleInfo,
+    /// Source with the overrides declared as constants,
+    /// which is empty if there are none.
+    source: String,
+    overrides: Vec<shader::ShaderOverride>,
+    lower_options: shader::LowerOptions,
+    /// Acceleration structures lowered into storage buffers
+    /// for the software ray queries.
+    #[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+    acceleration_structures: Vec<naga::Handle<naga::GlobalVariable>>,
 }
 
 #[derive(Clone, Copy)]
@@ -557,6 +818,15 @@ impl ShaderDataLayout {
     pub const EMPTY: &'static Self = &Self {
         bindings: Vec::new(),
     };
+
+    pub fn has_binding_arrays(&self) -> bool {
+        self.bindings.iter().any(|&(_, binding)| {
+            matches!(
+                binding,
+                ShaderBinding::TextureArray { .. } | ShaderBinding::BufferArray { .. }
+            )
+        })
+    }
 }
 
 pub trait ShaderData {
@@ -564,30 +834,120 @@ pub trait ShaderData {
     fn fill(&self, context: PipelineContext);
 }
 
+/// Type of a host structure member, as compared against the shader.
+#[derive(Clone, Copy, Debug)]
+pub enum ShaderMemberType {
+    /// Scalar, vector, matrix, or an array of them, by the kind of the scalars.
+    Plain(ScalarKind),
+    /// Nested structure, which is checked recursively.
+    Struct(fn() -> ShaderStructLayout),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderStructMember {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+    pub ty: ShaderMemberType,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderStructLayout {
+    pub name: &'static str,
+    pub size: u32,
+    pub members: Vec<ShaderStructMember>,
+}
+
+/// Host `#[repr(C)]` structure that has a counterpart in the shaders.
+/// See `Shader::check_struct_layout`.
+pub trait ShaderStruct {
+    fn layout() -> ShaderStructLayout;
+}
+
+pub trait HasShaderMemberType {
+    const MEMBER_TYPE: ShaderMemberType;
+}
+impl HasShaderMemberType for f32 {
+    const MEMBER_TYPE: ShaderMemberType = ShaderMemberType::Plain(ScalarKind::Float);
+}
+impl HasShaderMemberType for f64 {
+    const MEMBER_TYPE: ShaderMemberType = ShaderMemberType::Plain(ScalarKind::Float);
+}
+impl HasShaderMemberType for i32 {
+    const MEMBER_TYPE: ShaderMemberType = ShaderMemberType::Plain(ScalarKind::Sint);
+}
+impl HasShaderMemberType for u32 {
+    const MEMBER_TYPE: ShaderMemberType = ShaderMemberType::Plain(ScalarKind::Uint);
+}
+impl<T: HasShaderMemberType, const N: usize> HasShaderMemberType for [T; N] {
+    const MEMBER_TYPE: ShaderMemberType = T::MEMBER_TYPE;
+}
+impl<T: ShaderStruct> HasShaderMemberType for T {
+    const MEMBER_TYPE: ShaderMemberType = ShaderMemberType::Struct(T::layout);
+}
+
 pub struct ShaderDesc<'a> {
     pub source: &'a str,
 }
 
+/// Kind of work recorded by a command encoder, which determines the queue
+/// it is submitted to. Backends without dedicated queues use a single one.
+///
+/// Host-visible buffers, and textures without `COPY` or `STORAGE` usage,
+/// can only be accessed by the encoders of a single command type.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub enum CommandType {
+    /// Only transfer passes.
     Transfer,
+    /// Transfer, acceleration structure, and compute passes.
     Compute,
+    /// All kinds of passes, as well as presentation.
     #[default]
     General,
 }
 
 pub struct CommandEncoderDesc<'a> {
     pub name: &'a str,
+    pub command_type: CommandType,
     /// Number of buffers that this encoder needs to keep alive.
     /// For example, one buffer is being run on GPU while the
     /// other is being actively encoded, which makes 2.
     pub buffer_count: u32,
 }
 
+/// GPU time spent in each labeled pass of a command buffer.
+pub type Timings = Vec<(String, Duration)>;
+
+/// Texture region copied into a host-visible staging buffer,
+/// which becomes readable once the associated submission is done.
+///
+/// The staging buffer is released by `finish` or a successful `try_finish`.
+#[derive(Debug)]
+pub struct ReadbackRequest {
+    buffer: Buffer,
+    format: TextureFormat,
+    aspects: TexelAspects,
+    extent: Extent,
+    bytes_per_row: u32,
+}
+
+/// Describes a cache of compiled pipelines that can be persisted across runs.
+#[derive(Debug)]
+pub struct PipelineCacheDesc<'a> {
+    pub name: &'a str,
+    /// Data previously returned by `get_pipeline_cache_data`.
+    /// It's ignored if produced by a different device or driver.
+    pub initial_data: Option<&'a [u8]>,
+}
+
 pub struct ComputePipelineDesc<'a> {
     pub name: &'a str,
     pub data_layouts: &'a [&'a ShaderDataLayout],
     pub compute: ShaderFunction<'a>,
+    /// Values of the `override` declarations in the shaders, by name or `@id`.
+    pub overrides: &'a [(&'a str, f64)],
+    /// Cache to look up and store the compiled pipeline in.
+    pub cache: Option<&'a PipelineCache>,
 }
 
 /// Primitive type the input mesh is composed of.
@@ -651,6 +1011,27 @@ pub struct PrimitiveState {
     pub wireframe: bool,
 }
 
+/// Describes the multi-sampling state in a render pipeline.
+#[derive(Clone, Debug)]
+pub struct MultisampleState {
+    /// Number of samples per pixel. Has to match the sample count of the targets.
+    pub sample_count: u32,
+    /// Bit mask of the samples to be written.
+    pub sample_mask: u64,
+    /// If true, the alpha channel of the first target is used as the coverage mask.
+    pub alpha_to_coverage: bool,
+}
+
+impl Default for MultisampleState {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage: false,
+        }
+    }
+}
+
 /// Operation to perform on the stencil value.
 #[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
 pub enum StencilOperation {
@@ -898,6 +1279,33 @@ impl Default for ColorWrites {
     }
 }
 
+/// Attribute of a vertex, fetched from a vertex buffer.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct VertexAttribute {
+    /// Byte offset of the attribute within the vertex.
+    pub offset: u32,
+    pub format: VertexFormat,
+}
+
+/// Layout of the vertices in a vertex buffer.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+    /// Distance in bytes between consecutive vertices.
+    pub stride: u32,
+}
+
+/// Describes a vertex buffer fetched by a render pipeline.
+///
+/// The attributes of all the fetches are assigned to the shader
+/// locations sequentially, starting from `@location(0)`.
+#[derive(Clone, Copy, Debug)]
+pub struct VertexFetchState<'a> {
+    pub layout: &'a VertexLayout,
+    /// If true, the buffer is advanced per instance instead of per vertex.
+    pub instanced: bool,
+}
+
 /// Describes the color state of a render pipeline.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ColorTargetState {
@@ -923,10 +1331,16 @@ pub struct RenderPipelineDesc<'a> {
     pub name: &'a str,
     pub data_layouts: &'a [&'a ShaderDataLayout],
     pub vertex: ShaderFunction<'a>,
+    pub vertex_fetches: &'a [VertexFetchState<'a>],
     pub primitive: PrimitiveState,
     pub depth_stencil: Option<DepthStencilState>,
     pub fragment: ShaderFunction<'a>,
     pub color_targets: &'a [ColorTargetState],
+    pub multisample_state: MultisampleState,
+    /// Values of the `override` declarations in the shaders, by name or `@id`.
+    pub overrides: &'a [(&'a str, f64)],
+    /// Cache to look up and store the compiled pipeline in.
+    pub cache: Option<&'a PipelineCache>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -956,11 +1370,40 @@ pub struct RenderTargetSet<'a> {
     pub depth_stencil: Option<RenderTarget>,
 }
 
+/// Describes the render passes a bundle can be executed in.
+///
+/// The formats and the sample count have to match the render targets,
+/// and the size has to match their extent.
+#[derive(Debug)]
+pub struct RenderBundleDesc<'a> {
+    pub name: &'a str,
+    pub color_formats: &'a [TextureFormat],
+    pub depth_stencil_format: Option<TextureFormat>,
+    pub sample_count: u32,
+    pub size: Extent,
+}
+
+/// Color space of the values written to the surface.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum ColorSpace {
+    /// Linear values, which get encoded into sRGB on write.
+    #[default]
+    Linear,
+    /// Values that are already encoded into sRGB.
+    Srgb,
+    /// HDR10 values, encoded with the PQ curve in BT.2020 primaries,
+    /// and written into 10 bits per channel.
+    Hdr10,
+}
+
 #[derive(Debug)]
 pub struct SurfaceConfig {
     pub size: Extent,
     pub usage: TextureUsage,
     pub frame_count: u32,
+    /// Preferred color space. The surface falls back to a supported one
+    /// otherwise, which can be deduced from the returned format.
+    pub color_space: ColorSpace,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -969,6 +1412,27 @@ pub enum IndexType {
     U32,
 }
 
+/// Arguments of a single indirect draw, as laid out in the indirect buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct DrawIndirectArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+/// Arguments of a single indexed indirect draw, as laid out in the indirect buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ScissorRect {
     pub x: u32,
@@ -0,0 +1,133 @@
+//! Helpers for the passes rewriting the naga modules of the shaders.
+
+use naga::{Expression as E, Handle, Statement as S};
+use std::{collections::HashMap, mem};
+
+/// Visit the blocks nested in a statement.
+fn nested_blocks_mut(statement: &mut S, f: &mut impl FnMut(&mut naga::Block)) {
+    match *statement {
+        S::Block(ref mut inner) => f(inner),
+        S::If {
+            ref mut accept,
+            ref mut reject,
+            ..
+        } => {
+            f(accept);
+            f(reject);
+        }
+        S::Switch { ref mut cases, .. } => {
+            for case in cases.iter_mut() {
+                f(&mut case.body);
+            }
+        }
+        S::Loop {
+            ref mut body,
+            ref mut continuing,
+            ..
+        } => {
+            f(body);
+            f(continuing);
+        }
+        _ => {}
+    }
+}
+
+/// Visit the statements of a block mutably, including the nested blocks.
+pub fn for_each_statement_mut(block: &mut naga::Block, f: &mut impl FnMut(&mut S)) {
+    for statement in block.iter_mut() {
+        f(statement);
+        nested_blocks_mut(statement, &mut |inner| for_each_statement_mut(inner, f));
+    }
+}
+
+/// Rewrite the statements of a block, including the nested blocks.
+///
+/// Emitted expressions that have been replaced by call results get their calls
+/// inserted in place, with the given arguments, while the ones that are no longer
+/// emitted are dropped from the ranges. Other statements go through `rewrite`.
+pub fn rewrite_block(
+    block: &mut naga::Block,
+    expressions: &naga::Arena<E>,
+    call_arguments: &HashMap<Handle<E>, Vec<Handle<E>>>,
+    rewrite: &mut impl FnMut(&mut S),
+) {
+    let old_block = mem::take(block);
+    for (statement, &span) in old_block.span_iter() {
+        let range = match *statement {
+            S::Emit(ref range) => range.clone(),
+            _ => {
+                let mut statement = statement.clone();
+                rewrite(&mut statement);
+                nested_blocks_mut(&mut statement, &mut |inner| {
+                    rewrite_block(inner, expressions, call_arguments, rewrite)
+                });
+                block.push(statement, span);
+                continue;
+            }
+        };
+        let mut pending = None;
+        for handle in range {
+            let call = match expressions[handle] {
+                E::CallResult(function) => Some(S::Call {
+                    function,
+                    arguments: call_arguments[&handle].clone(),
+                    result: Some(handle),
+                }),
+                ref expr if expr.needs_pre_emit() => None,
+                _ => {
+                    pending = Some((pending.map_or(handle, |(first, _)| first), handle));
+                    continue;
+                }
+            };
+            if let Some((first, last)) = pending.take() {
+                block.push(S::Emit(naga::Range::new_from_bounds(first, last)), span);
+            }
+            if let Some(call) = call {
+                block.push(call, span);
+            }
+        }
+        if let Some((first, last)) = pending {
+            block.push(S::Emit(naga::Range::new_from_bounds(first, last)), span);
+        }
+    }
+}
+
+/// Move the selected functions to the start of the arena, keeping the order otherwise,
+/// so that they can be called by the rest.
+pub fn move_functions_first(
+    module: &mut naga::Module,
+    selected: impl Fn(Handle<naga::Function>) -> bool,
+) {
+    let old_functions = mem::take(&mut module.functions);
+    let mut map = HashMap::new();
+    for pass in [true, false] {
+        for (handle, fun) in old_functions.iter() {
+            if selected(handle) == pass {
+                let span = old_functions.get_span(handle);
+                map.insert(handle, module.functions.append(fun.clone(), span));
+            }
+        }
+    }
+
+    let remap = |fun: &mut naga::Function| {
+        for (_, expr) in fun.expressions.iter_mut() {
+            if let E::CallResult(ref mut function) = *expr {
+                *function = map[function];
+            }
+        }
+        for_each_statement_mut(&mut fun.body, &mut |statement| {
+            if let S::Call {
+                ref mut function, ..
+            } = *statement
+            {
+                *function = map[function];
+            }
+        });
+    };
+    for (_, fun) in module.functions.iter_mut() {
+        remap(fun);
+    }
+    for ep in module.entry_points.iter_mut() {
+        remap(&mut ep.function);
+    }
+}
@@ -1,4 +1,10 @@
-use std::{marker::PhantomData, mem};
+use objc::{class, msg_send, runtime::Object, sel, sel_impl};
+use std::{marker::PhantomData, mem, slice, time::Duration};
+
+/// Sample index that is skipped, `MTLCounterDontSample`.
+const COUNTER_DONT_SAMPLE: u64 = !0;
+/// Value of the samples that failed, `MTLCounterErrorValue`.
+const COUNTER_ERROR_VALUE: u64 = !0;
 
 impl<T: bytemuck::Pod> crate::ShaderBindable for T {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
@@ -32,8 +38,22 @@ impl crate::ShaderBindable for super::TextureView {
     }
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::TextureArray<N> {
-    fn bind_to(&self, _ctx: &mut super::PipelineContext, _index: u32) {
-        unimplemented!()
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        let textures = self
+            .data
+            .iter()
+            .map(|view| view.as_ref())
+            .collect::<Vec<_>>();
+        let resources = textures
+            .iter()
+            .map(|&texture| -> &metal::ResourceRef { texture })
+            .collect::<Vec<_>>();
+        ctx.bind_arguments(
+            index,
+            &resources,
+            metal::MTLResourceUsage::Read,
+            |encoder| encoder.set_textures(0, &textures),
+        );
     }
 }
 impl crate::ShaderBindable for super::Sampler {
@@ -68,8 +88,25 @@ impl crate::ShaderBindable for crate::BufferPiece {
     }
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::BufferArray<N> {
-    fn bind_to(&self, _ctx: &mut super::PipelineContext, _index: u32) {
-        unimplemented!()
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        let buffers = self
+            .data
+            .iter()
+            .map(|piece| piece.buffer.as_ref())
+            .collect::<Vec<_>>();
+        let offsets = self
+            .data
+            .iter()
+            .map(|piece| piece.offset)
+            .collect::<Vec<_>>();
+        let resources = buffers
+            .iter()
+            .map(|&buffer| -> &metal::ResourceRef { buffer })
+            .collect::<Vec<_>>();
+        let usage = metal::MTLResourceUsage::Read | metal::MTLResourceUsage::Write;
+        ctx.bind_arguments(index, &resources, usage, |encoder| {
+            encoder.set_buffers(0, &buffers, &offsets)
+        });
     }
 }
 impl crate::ShaderBindable for crate::AccelerationStructure {
@@ -88,9 +125,107 @@ impl crate::ShaderBindable for crate::AccelerationStructure {
     }
 }
 
+impl super::PipelineContext<'_> {
+    /// Bind an argument buffer encoded with the given binding array,
+    /// and make the resources of the array resident.
+    fn bind_arguments(
+        &mut self,
+        index: u32,
+        resources: &[&metal::ResourceRef],
+        usage: metal::MTLResourceUsage,
+        encode: impl FnOnce(&metal::ArgumentEncoderRef),
+    ) {
+        let argument_encoder: &metal::ArgumentEncoderRef =
+            match self.argument_encoders[index as usize] {
+                Some(ref encoder) => encoder,
+                // not used by any of the shaders
+                None => return,
+            };
+        let slot = self.targets[index as usize] as u64;
+        // The command buffer retains the argument buffer until it's done.
+        let buffer = self.device.new_buffer(
+            argument_encoder.encoded_length().max(1),
+            metal::MTLResourceOptions::StorageModeShared,
+        );
+        argument_encoder.set_argument_buffer(&buffer, 0);
+        encode(argument_encoder);
+
+        if let Some(encoder) = self.vs_encoder {
+            encoder.set_vertex_buffer(slot, Some(&buffer), 0);
+            encoder.use_resources(resources, usage, metal::MTLRenderStages::Vertex);
+        }
+        if let Some(encoder) = self.fs_encoder {
+            encoder.set_fragment_buffer(slot, Some(&buffer), 0);
+            encoder.use_resources(resources, usage, metal::MTLRenderStages::Fragment);
+        }
+        if let Some(encoder) = self.cs_encoder {
+            encoder.set_buffer(slot, Some(&buffer), 0);
+            encoder.use_resources(resources, usage);
+        }
+    }
+}
+
+impl super::TimingData {
+    /// Add a pass to be timed, and return the index of its first sample.
+    fn add_pass(&mut self, label: &str) -> Option<u64> {
+        if self.pass_names.len() == super::MAX_TIMED_PASSES {
+            return None;
+        }
+        self.pass_names.push(label.to_string());
+        Some(2 * (self.pass_names.len() as u64 - 1))
+    }
+
+    /// Read the timestamps of the passes, once the submission is done.
+    fn resolve(&mut self, device: &metal::DeviceRef, timings: &mut crate::Timings) {
+        let (cpu_start, gpu_start) = self.start_timestamps;
+        let (mut cpu_end, mut gpu_end) = (0, 0);
+        device.sample_timestamps(&mut cpu_end, &mut gpu_end);
+        // The CPU timestamps are in nanoseconds
+        let period = if gpu_end > gpu_start {
+            cpu_end.saturating_sub(cpu_start) as f64 / (gpu_end - gpu_start) as f64
+        } else {
+            1.0
+        };
+
+        let range = metal::NSRange::new(0, 2 * self.pass_names.len() as u64);
+        let timestamps = objc::rc::autoreleasepool(|| unsafe {
+            let sample_buffer: &metal::CounterSampleBufferRef = &self.sample_buffer;
+            let data: *mut Object = msg_send![sample_buffer, resolveCounterRange: range];
+            if data.is_null() {
+                return Vec::new();
+            }
+            let bytes: *const u64 = msg_send![data, bytes];
+            let length: u64 = msg_send![data, length];
+            slice::from_raw_parts(bytes, length as usize / mem::size_of::<u64>()).to_vec()
+        });
+        if timestamps.is_empty() {
+            log::warn!("Unable to resolve the timestamps");
+        }
+        for (name, pair) in self.pass_names.drain(..).zip(timestamps.chunks(2)) {
+            if let [start, end] = *pair {
+                if start != COUNTER_ERROR_VALUE && end != COUNTER_ERROR_VALUE {
+                    let diff = end.saturating_sub(start) as f64 * period;
+                    timings.push((name, Duration::from_nanos(diff as u64)));
+                }
+            }
+        }
+    }
+}
+
 impl super::CommandEncoder {
     pub fn start(&mut self) {
         let queue = self.queue.lock().unwrap();
+        if let Some(ref mut timing) = self.timing {
+            self.timings.clear();
+            if let Some(cmd_buf) = timing.submission.take() {
+                cmd_buf.wait_until_completed();
+                timing.resolve(queue.device(), &mut self.timings);
+            }
+            timing.pass_names.clear();
+            let (mut cpu, mut gpu) = (0, 0);
+            queue.device().sample_timestamps(&mut cpu, &mut gpu);
+            timing.start_timestamps = (cpu, gpu);
+        }
         self.raw = Some(objc::rc::autoreleasepool(|| {
             let cmd_buf = queue.new_command_buffer();
             if !self.name.is_empty() {
@@ -106,13 +241,49 @@ impl super::CommandEncoder {
         self.raw.as_mut().unwrap().present_drawable(&frame.drawable);
     }
 
+    /// Wait for the work of another encoder before executing this one.
+    /// All encoders share a single queue, so their work is already ordered.
+    pub fn wait_for(&mut self, _sp: &super::SyncPoint) {}
+
+    /// Return the timings of the passes executed by the previous submission,
+    /// which `start` waits for. The acceleration structure passes aren't timed.
+    pub fn timings(&self) -> &crate::Timings {
+        &self.timings
+    }
+
     pub fn transfer(&mut self) -> super::TransferCommandEncoder {
+        self.transfer_labeled("transfer")
+    }
+
+    /// Same as `transfer`, but the pass is labeled for debugging and timing.
+    pub fn transfer_labeled(&mut self, label: &str) -> super::TransferCommandEncoder {
+        let sample_index = self
+            .timing
+            .as_mut()
+            .and_then(|timing| timing.add_pass(label));
         let raw = objc::rc::autoreleasepool(|| {
-            self.raw
-                .as_mut()
-                .unwrap()
-                .new_blit_command_encoder()
-                .to_owned()
+            let cmd_buf = self.raw.as_mut().unwrap();
+            let encoder = match (sample_index, self.timing.as_ref()) {
+                // metal-rs doesn't expose the blit pass descriptors
+                (Some(index), Some(timing)) => unsafe {
+                    let sample_buffer: &metal::CounterSampleBufferRef = &timing.sample_buffer;
+                    let descriptor: *mut Object =
+                        msg_send![class!(MTLBlitPassDescriptor), blitPassDescriptor];
+                    let attachments: *mut Object = msg_send![descriptor, sampleBufferAttachments];
+                    let attachment: *mut Object =
+                        msg_send![attachments, objectAtIndexedSubscript: 0u64];
+                    let () = msg_send![attachment, setSampleBuffer: sample_buffer];
+                    let () = msg_send![attachment, setStartOfEncoderSampleIndex: index];
+                    let () = msg_send![attachment, setEndOfEncoderSampleIndex: index + 1];
+                    let cmd_buf: &metal::CommandBufferRef = cmd_buf;
+                    let encoder: &metal::BlitCommandEncoderRef =
+                        msg_send![cmd_buf, blitCommandEncoderWithDescriptor: descriptor];
+                    encoder
+                },
+                _ => cmd_buf.new_blit_command_encoder(),
+            };
+            encoder.set_label(label);
+            encoder.to_owned()
         });
         super::TransferCommandEncoder {
             raw,
@@ -121,12 +292,22 @@ impl super::CommandEncoder {
     }
 
     pub fn acceleration_structure(&mut self) -> super::AccelerationStructureCommandEncoder {
+        self.acceleration_structure_labeled("acceleration structure")
+    }
+
+    /// Same as `acceleration_structure`, but the pass is labeled for debugging and timing.
+    pub fn acceleration_structure_labeled(
+        &mut self,
+        label: &str,
+    ) -> super::AccelerationStructureCommandEncoder {
         let raw = objc::rc::autoreleasepool(|| {
-            self.raw
+            let encoder = self
+                .raw
                 .as_mut()
                 .unwrap()
-                .new_acceleration_structure_command_encoder()
-                .to_owned()
+                .new_acceleration_structure_command_encoder();
+            encoder.set_label(label);
+            encoder.to_owned()
         });
         super::AccelerationStructureCommandEncoder {
             raw,
@@ -135,12 +316,30 @@ impl super::CommandEncoder {
     }
 
     pub fn compute(&mut self) -> super::ComputeCommandEncoder {
+        self.compute_labeled("compute")
+    }
+
+    /// Same as `compute`, but the pass is labeled for debugging and timing.
+    pub fn compute_labeled(&mut self, label: &str) -> super::ComputeCommandEncoder {
+        let sample_index = self
+            .timing
+            .as_mut()
+            .and_then(|timing| timing.add_pass(label));
         let raw = objc::rc::autoreleasepool(|| {
-            self.raw
-                .as_mut()
-                .unwrap()
-                .new_compute_command_encoder()
-                .to_owned()
+            let cmd_buf = self.raw.as_mut().unwrap();
+            let encoder = match (sample_index, self.timing.as_ref()) {
+                (Some(index), Some(timing)) => {
+                    let descriptor = metal::ComputePassDescriptor::new();
+                    let attachment = descriptor.sample_buffer_attachments().object_at(0).unwrap();
+                    attachment.set_sample_buffer(&timing.sample_buffer);
+                    attachment.set_start_of_encoder_sample_index(index);
+                    attachment.set_end_of_encoder_sample_index(index + 1);
+                    cmd_buf.compute_command_encoder_with_descriptor(descriptor)
+                }
+                _ => cmd_buf.new_compute_command_encoder(),
+            };
+            encoder.set_label(label);
+            encoder.to_owned()
         });
         super::ComputeCommandEncoder {
             raw,
@@ -149,9 +348,31 @@ impl super::CommandEncoder {
     }
 
     pub fn render(&mut self, targets: crate::RenderTargetSet) -> super::RenderCommandEncoder {
+        self.render_labeled("render", targets)
+    }
+
+    /// Same as `render`, but the pass is labeled for debugging and timing.
+    pub fn render_labeled(
+        &mut self,
+        label: &str,
+        targets: crate::RenderTargetSet,
+    ) -> super::RenderCommandEncoder {
+        let sample_index = self
+            .timing
+            .as_mut()
+            .and_then(|timing| timing.add_pass(label));
         let raw = objc::rc::autoreleasepool(|| {
             let descriptor = metal::RenderPassDescriptor::new();
 
+            if let (Some(index), Some(timing)) = (sample_index, self.timing.as_ref()) {
+                let attachment = descriptor.sample_buffer_attachments().object_at(0).unwrap();
+                attachment.set_sample_buffer(&timing.sample_buffer);
+                attachment.set_start_of_vertex_sample_index(index);
+                attachment.set_end_of_vertex_sample_index(COUNTER_DONT_SAMPLE);
+                attachment.set_start_of_fragment_sample_index(COUNTER_DONT_SAMPLE);
+                attachment.set_end_of_fragment_sample_index(index + 1);
+            }
+
             for (i, rt) in targets.colors.iter().enumerate() {
                 let at_descriptor = descriptor.color_attachments().object_at(i as u64).unwrap();
                 at_descriptor.set_texture(Some(rt.view.as_ref()));
@@ -180,36 +401,30 @@ impl super::CommandEncoder {
             }
 
             if let Some(ref rt) = targets.depth_stencil {
-                let at_descriptor = descriptor.depth_attachment().unwrap();
-                at_descriptor.set_texture(Some(rt.view.as_ref()));
-                let load_action = match rt.init_op {
-                    crate::InitOp::Load => metal::MTLLoadAction::Load,
-                    crate::InitOp::Clear(color) => {
-                        let clear_depth = match color {
-                            crate::TextureColor::TransparentBlack
-                            | crate::TextureColor::OpaqueBlack => 0.0,
-                            crate::TextureColor::White => 1.0,
-                        };
-                        at_descriptor.set_clear_depth(clear_depth);
-                        metal::MTLLoadAction::Clear
-                    }
-                };
-                let store_action = match rt.finish_op {
-                    crate::FinishOp::Store | crate::FinishOp::Ignore => {
-                        metal::MTLStoreAction::Store
-                    }
-                    crate::FinishOp::Discard => metal::MTLStoreAction::DontCare,
-                    crate::FinishOp::ResolveTo(_) => panic!("Can't resolve depth texture"),
+                let aspects = super::map_pixel_format_aspects(rt.view.as_ref().pixel_format());
+                let is_white = match rt.init_op {
+                    crate::InitOp::Clear(crate::TextureColor::White) => true,
+                    crate::InitOp::Clear(_) | crate::InitOp::Load => false,
                 };
-                at_descriptor.set_load_action(load_action);
-                at_descriptor.set_store_action(store_action);
+                if aspects.contains(crate::TexelAspects::DEPTH) {
+                    let at_descriptor = descriptor.depth_attachment().unwrap();
+                    set_depth_stencil_attachment(at_descriptor, rt);
+                    at_descriptor.set_clear_depth(if is_white { 1.0 } else { 0.0 });
+                }
+                if aspects.contains(crate::TexelAspects::STENCIL) {
+                    let at_descriptor = descriptor.stencil_attachment().unwrap();
+                    set_depth_stencil_attachment(at_descriptor, rt);
+                    at_descriptor.set_clear_stencil(if is_white { !0 } else { 0 });
+                }
             }
 
-            self.raw
+            let encoder = self
+                .raw
                 .as_mut()
                 .unwrap()
-                .new_render_command_encoder(descriptor)
-                .to_owned()
+                .new_render_command_encoder(descriptor);
+            encoder.set_label(label);
+            encoder.to_owned()
         });
 
         super::RenderCommandEncoder {
@@ -219,6 +434,78 @@ impl super::CommandEncoder {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::CommandEncoder {
+    fn push_debug_group(&mut self, label: &str) {
+        self.raw.as_mut().unwrap().push_debug_group(label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.raw.as_mut().unwrap().pop_debug_group();
+    }
+
+    // Command buffers don't have signposts, so the marker is an empty group.
+    fn insert_debug_marker(&mut self, label: &str) {
+        let cmd_buf = self.raw.as_mut().unwrap();
+        cmd_buf.push_debug_group(label);
+        cmd_buf.pop_debug_group();
+    }
+}
+
+fn set_depth_stencil_attachment(
+    at_descriptor: &metal::RenderPassAttachmentDescriptorRef,
+    rt: &crate::RenderTarget,
+) {
+    at_descriptor.set_texture(Some(rt.view.as_ref()));
+    let load_action = match rt.init_op {
+        crate::InitOp::Load => metal::MTLLoadAction::Load,
+        crate::InitOp::Clear(_) => metal::MTLLoadAction::Clear,
+    };
+    let store_action = match rt.finish_op {
+        crate::FinishOp::Store | crate::FinishOp::Ignore => metal::MTLStoreAction::Store,
+        crate::FinishOp::Discard => metal::MTLStoreAction::DontCare,
+        crate::FinishOp::ResolveTo(ref view) => {
+            at_descriptor.set_resolve_texture(Some(view.as_ref()));
+            metal::MTLStoreAction::MultisampleResolve
+        }
+    };
+    at_descriptor.set_load_action(load_action);
+    at_descriptor.set_store_action(store_action);
+}
+
+/// Return the blit option for copying the selected aspects of a texture.
+fn map_blit_option(piece: &crate::TexturePiece) -> metal::MTLBlitOption {
+    let texture = piece.texture.as_ref();
+    let format_aspects = super::map_pixel_format_aspects(texture.pixel_format());
+    let aspects = if piece.aspects.is_empty() {
+        format_aspects
+    } else {
+        format_aspects & piece.aspects
+    };
+    if aspects == format_aspects {
+        metal::MTLBlitOption::empty()
+    } else if aspects == crate::TexelAspects::DEPTH {
+        metal::MTLBlitOption::DepthFromDepthStencil
+    } else {
+        metal::MTLBlitOption::StencilFromDepthStencil
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::TransferCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.raw.push_debug_group(label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.raw.pop_debug_group();
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.raw.insert_debug_signpost(label);
+    }
+}
+
 #[hidden_trait::expose]
 impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
     fn fill_buffer(&mut self, dst: crate::BufferPiece, size: u64, value: u8) {
@@ -279,7 +566,7 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
             dst.array_layer as u64,
             dst.mip_level as u64,
             map_origin(&dst.origin),
-            metal::MTLBlitOption::empty(),
+            map_blit_option(&dst),
         );
     }
 
@@ -300,7 +587,7 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
             dst.offset,
             bytes_per_row as u64,
             0,
-            metal::MTLBlitOption::empty(),
+            map_blit_option(&src),
         );
     }
 }
@@ -311,6 +598,21 @@ impl Drop for super::TransferCommandEncoder<'_> {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::AccelerationStructureCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.raw.push_debug_group(label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.raw.pop_debug_group();
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.raw.insert_debug_signpost(label);
+    }
+}
+
 #[hidden_trait::expose]
 impl crate::traits::AccelerationStructureEncoder
     for super::AccelerationStructureCommandEncoder<'_>
@@ -368,6 +670,21 @@ impl Drop for super::AccelerationStructureCommandEncoder<'_> {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::ComputeCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.raw.push_debug_group(label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.raw.pop_debug_group();
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.raw.insert_debug_signpost(label);
+    }
+}
+
 impl super::ComputeCommandEncoder<'_> {
     pub fn with<'p>(
         &'p mut self,
@@ -387,6 +704,7 @@ impl super::ComputeCommandEncoder<'_> {
 
         super::ComputePipelineContext {
             encoder: &mut self.raw,
+            device: &pipeline.layout.device,
             bind_groups: &pipeline.layout.bind_groups,
             wg_size: pipeline.wg_size,
         }
@@ -399,6 +717,21 @@ impl Drop for super::ComputeCommandEncoder<'_> {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::RenderCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.raw.push_debug_group(label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.raw.pop_debug_group();
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.raw.insert_debug_signpost(label);
+    }
+}
+
 impl super::RenderCommandEncoder<'_> {
     pub fn set_scissor_rect(&mut self, rect: &crate::ScissorRect) {
         let scissor = metal::MTLScissorRect {
@@ -444,9 +777,41 @@ impl super::RenderCommandEncoder<'_> {
         super::RenderPipelineContext {
             encoder: &mut self.raw,
             primitive_type: pipeline.primitive_type,
+            device: &pipeline.layout.device,
             bind_groups: &pipeline.layout.bind_groups,
         }
     }
+
+    pub fn execute_bundle(&mut self, bundle: &super::RenderBundle) {
+        match *bundle {}
+    }
+}
+
+impl super::Context {
+    pub fn try_create_render_bundle_encoder(
+        &self,
+        desc: crate::RenderBundleDesc,
+    ) -> Result<super::RenderBundleEncoder, crate::Error> {
+        log::error!("Render bundle '{}' is not supported on Metal", desc.name);
+        Err(crate::Error::InvalidDescriptor)
+    }
+
+    pub fn destroy_render_bundle(&self, bundle: super::RenderBundle) {
+        match bundle {}
+    }
+}
+
+impl super::RenderBundleEncoder {
+    pub fn with<'p>(
+        &'p mut self,
+        _pipeline: &'p super::RenderPipeline,
+    ) -> super::RenderPipelineContext<'p> {
+        match *self {}
+    }
+
+    pub fn finish(self) -> super::RenderBundle {
+        match self {}
+    }
 }
 
 impl Drop for super::RenderCommandEncoder<'_> {
@@ -461,6 +826,7 @@ impl crate::traits::PipelineEncoder for super::ComputePipelineContext<'_> {
         let info = &self.bind_groups[group as usize];
 
         data.fill(super::PipelineContext {
+            device: self.device,
             cs_encoder: if info.visibility.contains(crate::ShaderVisibility::COMPUTE) {
                 Some(self.encoder.as_ref())
             } else {
@@ -469,6 +835,7 @@ impl crate::traits::PipelineEncoder for super::ComputePipelineContext<'_> {
             vs_encoder: None,
             fs_encoder: None,
             targets: &info.targets,
+            argument_encoders: &info.argument_encoders,
         });
     }
 }
@@ -483,6 +850,14 @@ impl crate::traits::ComputePipelineEncoder for super::ComputePipelineContext<'_>
         };
         self.encoder.dispatch_thread_groups(raw_count, self.wg_size);
     }
+
+    fn dispatch_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.encoder.dispatch_thread_groups_indirect(
+            indirect_buf.buffer.as_ref(),
+            indirect_buf.offset,
+            self.wg_size,
+        );
+    }
 }
 
 impl Drop for super::ComputePipelineContext<'_> {
@@ -497,6 +872,7 @@ impl crate::traits::PipelineEncoder for super::RenderPipelineContext<'_> {
         let info = &self.bind_groups[group as usize];
 
         data.fill(super::PipelineContext {
+            device: self.device,
             cs_encoder: None,
             vs_encoder: if info.visibility.contains(crate::ShaderVisibility::VERTEX) {
                 Some(self.encoder.as_ref())
@@ -509,6 +885,7 @@ impl crate::traits::PipelineEncoder for super::RenderPipelineContext<'_> {
                 None
             },
             targets: &info.targets,
+            argument_encoders: &info.argument_encoders,
         });
     }
 }
@@ -525,6 +902,18 @@ impl crate::traits::RenderPipelineEncoder for super::RenderPipelineContext<'_> {
         self.encoder.set_scissor_rect(scissor);
     }
 
+    fn set_stencil_reference(&mut self, reference: u32) {
+        self.encoder.set_stencil_reference_value(reference);
+    }
+
+    fn bind_vertex_buffer(&mut self, index: u32, vertex_buf: crate::BufferPiece) {
+        self.encoder.set_vertex_buffer(
+            super::vertex_buffer_slot(index),
+            Some(vertex_buf.buffer.as_ref()),
+            vertex_buf.offset,
+        );
+    }
+
     fn draw(
         &mut self,
         first_vertex: u32,
@@ -619,6 +1008,45 @@ impl crate::traits::RenderPipelineEncoder for super::RenderPipelineContext<'_> {
             indirect_buf.offset,
         );
     }
+
+    //Note: Metal has no multi-draw, and the count buffer is ignored.
+    fn multi_draw_indirect(
+        &mut self,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        _count_buf: Option<crate::BufferPiece>,
+    ) {
+        let stride = mem::size_of::<crate::DrawIndirectArgs>() as u64;
+        for i in 0..max_draw_count as u64 {
+            self.encoder.draw_primitives_indirect(
+                self.primitive_type,
+                indirect_buf.buffer.as_ref(),
+                indirect_buf.offset + i * stride,
+            );
+        }
+    }
+
+    fn multi_draw_indexed_indirect(
+        &mut self,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        _count_buf: Option<crate::BufferPiece>,
+    ) {
+        let raw_index_type = super::map_index_type(index_type);
+        let stride = mem::size_of::<crate::DrawIndexedIndirectArgs>() as u64;
+        for i in 0..max_draw_count as u64 {
+            self.encoder.draw_indexed_primitives_indirect(
+                self.primitive_type,
+                raw_index_type,
+                index_buf.buffer.as_ref(),
+                index_buf.offset,
+                indirect_buf.buffer.as_ref(),
+                indirect_buf.offset + i * stride,
+            );
+        }
+    }
 }
 
 impl Drop for super::RenderPipelineContext<'_> {
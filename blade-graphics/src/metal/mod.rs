@@ -42,6 +42,8 @@ impl Frame {
 
 struct DeviceInfo {
     language_version: metal::MTLLanguageVersion,
+    /// Counters of the GPU timestamps, if timing is requested and supported.
+    timestamp_counter_set: Option<metal::CounterSet>,
 }
 
 pub struct Context {
@@ -50,6 +52,7 @@ pub struct Context {
     surface: Option<Mutex<Surface>>,
     capture: Option<metal::CaptureManager>,
     info: DeviceInfo,
+    resource_counts: crate::util::ResourceCounts,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq)]
@@ -172,21 +175,41 @@ pub struct SyncPoint {
     cmd_buf: metal::CommandBuffer,
 }
 
+/// Number of passes that can be timed in a command buffer.
+const MAX_TIMED_PASSES: usize = 64;
+
+/// Timestamps sampled at the start and the end of each pass.
+#[derive(Debug)]
+struct TimingData {
+    sample_buffer: metal::CounterSampleBuffer,
+    pass_names: Vec<String>,
+    /// CPU and GPU timestamps sampled when the commands are started,
+    /// which relate the GPU ticks to nanoseconds.
+    start_timestamps: (u64, u64),
+    /// Submitted command buffer, which the timestamps are read after.
+    submission: Option<metal::CommandBuffer>,
+}
+
 #[derive(Debug)]
 pub struct CommandEncoder {
     raw: Option<metal::CommandBuffer>,
     name: String,
     queue: Arc<Mutex<metal::CommandQueue>>,
+    timing: Option<TimingData>,
+    timings: crate::Timings,
 }
 
 #[derive(Debug)]
 struct BindGroupInfo {
     visibility: crate::ShaderVisibility,
     targets: Box<[u32]>,
+    /// Encoders of the argument buffers for binding arrays, if used by the shaders.
+    argument_encoders: Box<[Option<metal::ArgumentEncoder>]>,
 }
 
 #[derive(Debug)]
 struct PipelineLayout {
+    device: metal::Device,
     bind_groups: Box<[BindGroupInfo]>,
     sizes_buffer_slot: Option<u32>,
 }
@@ -211,6 +234,10 @@ impl ComputePipeline {
     }
 }
 
+//TODO: use binary archives
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct PipelineCache {}
+
 #[derive(Debug)]
 pub struct RenderPipeline {
     raw: metal::RenderPipelineState,
@@ -252,18 +279,27 @@ pub struct RenderCommandEncoder<'a> {
     phantom: PhantomData<&'a CommandEncoder>,
 }
 
+//TODO: implement with indirect command buffers
+/// Render bundles are not supported, see `Capabilities::render_bundles`.
+pub enum RenderBundleEncoder {}
+
+#[derive(Debug)]
+pub enum RenderBundle {}
+
 pub struct PipelineContext<'a> {
-    //raw: metal::ArgumentEncoderRef,
+    device: &'a metal::DeviceRef,
     cs_encoder: Option<&'a metal::ComputeCommandEncoderRef>,
     vs_encoder: Option<&'a metal::RenderCommandEncoderRef>,
     fs_encoder: Option<&'a metal::RenderCommandEncoderRef>,
     targets: &'a [u32],
+    argument_encoders: &'a [Option<metal::ArgumentEncoder>],
 }
 
 #[derive(Debug)]
 pub struct ComputePipelineContext<'a> {
     encoder: &'a mut metal::ComputeCommandEncoder,
     wg_size: metal::MTLSize,
+    device: &'a metal::DeviceRef,
     bind_groups: &'a [BindGroupInfo],
 }
 
@@ -271,6 +307,7 @@ pub struct ComputePipelineContext<'a> {
 pub struct RenderPipelineContext<'a> {
     encoder: &'a mut metal::RenderCommandEncoder,
     primitive_type: metal::MTLPrimitiveType,
+    device: &'a metal::DeviceRef,
     bind_groups: &'a [BindGroupInfo],
 }
 
@@ -278,20 +315,36 @@ fn map_texture_format(format: crate::TextureFormat) -> metal::MTLPixelFormat {
     use crate::TextureFormat as Tf;
     use metal::MTLPixelFormat::*;
     match format {
+        Tf::R8Unorm => R8Unorm,
         Tf::Rg8Unorm => RG8Unorm,
         Tf::Rg8Snorm => RG8Snorm,
         Tf::Rgba8Unorm => RGBA8Unorm,
         Tf::Rgba8UnormSrgb => RGBA8Unorm_sRGB,
+        Tf::Bgra8Unorm => BGRA8Unorm,
         Tf::Bgra8UnormSrgb => BGRA8Unorm_sRGB,
         Tf::Rgba8Snorm => RGBA8Snorm,
+        Tf::R16Float => R16Float,
+        Tf::Rg16Float => RG16Float,
         Tf::Rgba16Float => RGBA16Float,
+        Tf::Rgba16Unorm => RGBA16Unorm,
         Tf::R32Float => R32Float,
         Tf::Rg32Float => RG32Float,
         Tf::Rgba32Float => RGBA32Float,
         Tf::R32Uint => R32Uint,
         Tf::Rg32Uint => RG32Uint,
         Tf::Rgba32Uint => RGBA32Uint,
+        Tf::R32Sint => R32Sint,
+        Tf::Rg32Sint => RG32Sint,
+        Tf::Rgba32Sint => RGBA32Sint,
+        Tf::Rgb10a2Unorm => RGB10A2Unorm,
+        Tf::Rg11b10Float => RG11B10Float,
+        Tf::Rgb9e5Ufloat => RGB9E5Float,
         Tf::Depth32Float => Depth32Float,
+        // 24-bit depth is not available on Apple silicon,
+        // and the block size of `Depth24PlusStencil8` matches this format.
+        Tf::Depth24PlusStencil8 => Depth32Float_Stencil8,
+        Tf::Depth32FloatStencil8 => Depth32Float_Stencil8,
+        Tf::Stencil8 => Stencil8,
         Tf::Bc1Unorm => BC1_RGBA,
         Tf::Bc1UnormSrgb => BC1_RGBA_sRGB,
         Tf::Bc2Unorm => BC2_RGBA,
@@ -302,6 +355,32 @@ fn map_texture_format(format: crate::TextureFormat) -> metal::MTLPixelFormat {
         Tf::Bc4Snorm => BC4_RSnorm,
         Tf::Bc5Unorm => BC5_RGUnorm,
         Tf::Bc5Snorm => BC5_RGSnorm,
+        Tf::Bc6hUfloat => BC6H_RGBUfloat,
+        Tf::Bc6hFloat => BC6H_RGBFloat,
+        Tf::Bc7Unorm => BC7_RGBAUnorm,
+        Tf::Bc7UnormSrgb => BC7_RGBAUnorm_sRGB,
+        Tf::Etc2Rgb8Unorm => ETC2_RGB8,
+        Tf::Etc2Rgb8UnormSrgb => ETC2_RGB8_sRGB,
+        Tf::Etc2Rgba8Unorm => EAC_RGBA8,
+        Tf::Etc2Rgba8UnormSrgb => EAC_RGBA8_sRGB,
+        Tf::Astc4x4Unorm => ASTC_4x4_LDR,
+        Tf::Astc4x4UnormSrgb => ASTC_4x4_sRGB,
+        Tf::Astc6x6Unorm => ASTC_6x6_LDR,
+        Tf::Astc6x6UnormSrgb => ASTC_6x6_sRGB,
+        Tf::Astc8x8Unorm => ASTC_8x8_LDR,
+        Tf::Astc8x8UnormSrgb => ASTC_8x8_sRGB,
+    }
+}
+
+fn map_pixel_format_aspects(format: metal::MTLPixelFormat) -> crate::TexelAspects {
+    use metal::MTLPixelFormat as Pf;
+    match format {
+        Pf::Depth16Unorm | Pf::Depth32Float => crate::TexelAspects::DEPTH,
+        Pf::Depth24Unorm_Stencil8 | Pf::Depth32Float_Stencil8 => {
+            crate::TexelAspects::DEPTH | crate::TexelAspects::STENCIL
+        }
+        Pf::Stencil8 | Pf::X24_Stencil8 | Pf::X32_Stencil8 => crate::TexelAspects::STENCIL,
+        _ => crate::TexelAspects::COLOR,
     }
 }
 
@@ -328,18 +407,107 @@ fn map_index_type(ty: crate::IndexType) -> metal::MTLIndexType {
 }
 
 fn map_attribute_format(format: crate::VertexFormat) -> metal::MTLAttributeFormat {
+    use crate::VertexFormat as Vf;
+    use metal::MTLAttributeFormat as Af;
     match format {
-        crate::VertexFormat::F32Vec3 => metal::MTLAttributeFormat::Float3,
+        Vf::F32 => Af::Float,
+        Vf::F32Vec2 => Af::Float2,
+        Vf::F32Vec3 => Af::Float3,
+        Vf::F32Vec4 => Af::Float4,
+        Vf::U32 => Af::UInt,
+        Vf::U32Vec2 => Af::UInt2,
+        Vf::U32Vec3 => Af::UInt3,
+        Vf::U32Vec4 => Af::UInt4,
+        Vf::I32 => Af::Int,
+        Vf::I32Vec2 => Af::Int2,
+        Vf::I32Vec3 => Af::Int3,
+        Vf::I32Vec4 => Af::Int4,
+        Vf::U8Vec4Norm => Af::UChar4Normalized,
+        Vf::I8Vec4Norm => Af::Char4Normalized,
+        Vf::U16Vec2Norm => Af::UShort2Normalized,
+        Vf::U16Vec4Norm => Af::UShort4Normalized,
+        Vf::I16Vec2Norm => Af::Short2Normalized,
+        Vf::I16Vec4Norm => Af::Short4Normalized,
     }
 }
 
+fn map_vertex_format(format: crate::VertexFormat) -> metal::MTLVertexFormat {
+    use crate::VertexFormat as Vf;
+    use metal::MTLVertexFormat as Mvf;
+    match format {
+        Vf::F32 => Mvf::Float,
+        Vf::F32Vec2 => Mvf::Float2,
+        Vf::F32Vec3 => Mvf::Float3,
+        Vf::F32Vec4 => Mvf::Float4,
+        Vf::U32 => Mvf::UInt,
+        Vf::U32Vec2 => Mvf::UInt2,
+        Vf::U32Vec3 => Mvf::UInt3,
+        Vf::U32Vec4 => Mvf::UInt4,
+        Vf::I32 => Mvf::Int,
+        Vf::I32Vec2 => Mvf::Int2,
+        Vf::I32Vec3 => Mvf::Int3,
+        Vf::I32Vec4 => Mvf::Int4,
+        Vf::U8Vec4Norm => Mvf::UChar4Normalized,
+        Vf::I8Vec4Norm => Mvf::Char4Normalized,
+        Vf::U16Vec2Norm => Mvf::UShort2Normalized,
+        Vf::U16Vec4Norm => Mvf::UShort4Normalized,
+        Vf::I16Vec2Norm => Mvf::Short2Normalized,
+        Vf::I16Vec4Norm => Mvf::Short4Normalized,
+    }
+}
+
+/// Vertex buffers are bound to the buffer slots from the end of the
+/// argument table, so that they don't collide with the shader resources.
+fn vertex_buffer_slot(index: u32) -> u64 {
+    const LAST_BUFFER_SLOT: u64 = 30;
+    LAST_BUFFER_SLOT - index as u64
+}
+
 impl Context {
     pub unsafe fn init(desc: super::ContextDesc) -> Result<Self, super::NotSupportedError> {
         if desc.validation {
             std::env::set_var("METAL_DEVICE_WRAPPER_TYPE", "1");
         }
-        let device = metal::Device::system_default().ok_or(super::NotSupportedError)?;
+        let device = match desc.adapter {
+            super::AdapterSelector::Any => {
+                metal::Device::system_default().ok_or(super::NotSupportedError)?
+            }
+            ref selector => {
+                let mut devices = metal::Device::all();
+                let adapter_infos = devices
+                    .iter()
+                    .map(|device| adapter_info(device))
+                    .collect::<Vec<_>>();
+                let index = selector
+                    .select(&adapter_infos)
+                    .ok_or(super::NotSupportedError)?;
+                devices.swap_remove(index)
+            }
+        };
         let queue = device.new_command_queue();
+        let timestamp_counter_set = if desc.timing {
+            let counter_set = device
+                .counter_sets()
+                .into_iter()
+                .find(|counter_set| counter_set.name() == "timestamp");
+            match counter_set {
+                Some(counter_set)
+                    if device.supports_counter_sampling(
+                        metal::MTLCounterSamplingPoint::AtStageBoundary,
+                    ) =>
+                {
+                    Some(counter_set)
+                }
+                _ => {
+                    log::warn!(
+                        "Timestamps are not supported by the device, the timings will be empty"
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
         let capture = if desc.capture {
             objc::rc::autoreleasepool(|| {
@@ -362,7 +530,9 @@ impl Context {
             info: DeviceInfo {
                 //TODO: determine based on OS version
                 language_version: metal::MTLLanguageVersion::V2_4,
+                timestamp_counter_set,
             },
+            resource_counts: Default::default(),
         })
     }
 
@@ -390,20 +560,114 @@ impl Context {
         Ok(context)
     }
 
+    /// List the adapters suitable for initializing the context.
+    pub unsafe fn enumerate_adapters() -> Vec<crate::AdapterInfo> {
+        metal::Device::all()
+            .iter()
+            .map(|device| adapter_info(device))
+            .collect()
+    }
+
     pub fn capabilities(&self) -> crate::Capabilities {
         let device = self.device.lock().unwrap();
-        crate::Capabilities {
-            ray_query: if device.supports_family(metal::MTLGPUFamily::Apple6) {
-                crate::ShaderVisibility::all()
-            } else if device.supports_family(metal::MTLGPUFamily::Mac2)
-                || device.supports_family(metal::MTLGPUFamily::Metal3)
+        make_capabilities(&device)
+    }
+
+    pub fn limits(&self) -> crate::Limits {
+        let device = self.device.lock().unwrap();
+        let max_group = device.max_threads_per_threadgroup();
+        crate::Limits {
+            max_texture_dimension_2d: if device.supports_family(metal::MTLGPUFamily::Apple3)
+                || device.supports_family(metal::MTLGPUFamily::Mac2)
             {
-                crate::ShaderVisibility::COMPUTE
+                16384
             } else {
-                crate::ShaderVisibility::empty()
+                8192
             },
+            max_texture_dimension_3d: 2048,
+            max_texture_array_layers: 2048,
+            max_workgroup_size: [
+                max_group.width as u32,
+                max_group.height as u32,
+                max_group.depth as u32,
+            ],
+            max_workgroup_invocations: 1024,
+            // Metal doesn't limit the number of threadgroups in a dispatch.
+            max_workgroup_count: [!0; 3],
+            // The SIMD group width is only known per pipeline.
+            subgroup_size: 0,
+            // Raw GPU timestamps are not exposed.
+            timestamp_period: 0.0,
+            memory_heaps: vec![crate::MemoryHeap {
+                size: device.recommended_max_working_set_size(),
+                device_local: true,
+            }],
+        }
+    }
+
+    /// Return the usages supported by textures of the given format.
+    pub fn format_features(&self, format: crate::TextureFormat) -> crate::TextureUsage {
+        use crate::util::Compression;
+        let device = self.device.lock().unwrap();
+        let supported = match format.compression() {
+            Compression::None => true,
+            Compression::Bc => device.supports_family(metal::MTLGPUFamily::Mac2),
+            Compression::Etc2 | Compression::Astc => {
+                device.supports_family(metal::MTLGPUFamily::Apple2)
+            }
+        };
+        if supported {
+            format.base_features()
+        } else {
+            crate::TextureUsage::empty()
         }
     }
+
+    pub fn memory_report(&self) -> crate::MemoryReport {
+        let device = self.device.lock().unwrap();
+        self.resource_counts.report(vec![crate::HeapUsage {
+            usage: device.current_allocated_size(),
+            budget: device.recommended_max_working_set_size(),
+        }])
+    }
+}
+
+fn make_capabilities(device: &metal::DeviceRef) -> crate::Capabilities {
+    crate::Capabilities {
+        ray_query: if device.supports_family(metal::MTLGPUFamily::Apple6) {
+            crate::ShaderVisibility::all()
+        } else if device.supports_family(metal::MTLGPUFamily::Mac2)
+            || device.supports_family(metal::MTLGPUFamily::Metal3)
+        {
+            crate::ShaderVisibility::COMPUTE
+        } else {
+            crate::ShaderVisibility::empty()
+        },
+        sample_count_mask: (0..7)
+            .map(|shift| 1u32 << shift)
+            .filter(|&count| device.supports_texture_sample_count(count as u64))
+            .fold(0, |mask, count| mask | count),
+        binding_arrays: device.argument_buffers_support() == metal::MTLArgumentBuffersTier::Tier2,
+        software_ray_query: false,
+        render_bundles: false,
+    }
+}
+
+fn adapter_info(device: &metal::DeviceRef) -> crate::AdapterInfo {
+    crate::AdapterInfo {
+        name: device.name().to_string(),
+        // Metal doesn't expose the PCI vendor or the driver version.
+        vendor_id: 0,
+        // Apple GPUs are always integrated.
+        device_type: if device.is_low_power() || device.supports_family(metal::MTLGPUFamily::Apple1)
+        {
+            crate::DeviceType::Integrated
+        } else {
+            crate::DeviceType::Discrete
+        },
+        driver_version: 0,
+        capabilities: make_capabilities(device),
+    }
 }
 
 #[hidden_trait::expose]
@@ -412,10 +676,40 @@ impl crate::traits::CommandDevice for Context {
     type SyncPoint = SyncPoint;
 
     fn create_command_encoder(&self, desc: super::CommandEncoderDesc) -> CommandEncoder {
+        let timing = self
+            .info
+            .timestamp_counter_set
+            .as_ref()
+            .and_then(|counter_set| {
+                let descriptor = metal::CounterSampleBufferDescriptor::new();
+                descriptor.set_counter_set(counter_set);
+                descriptor.set_sample_count(2 * MAX_TIMED_PASSES as u64);
+                descriptor.set_storage_mode(metal::MTLStorageMode::Shared);
+                descriptor.set_label(desc.name);
+                let result = self
+                    .device
+                    .lock()
+                    .unwrap()
+                    .new_counter_sample_buffer_with_descriptor(&descriptor);
+                match result {
+                    Ok(sample_buffer) => Some(TimingData {
+                        sample_buffer,
+                        pass_names: Vec::new(),
+                        start_timestamps: (0, 0),
+                        submission: None,
+                    }),
+                    Err(err) => {
+                        log::warn!("Unable to create the timestamp buffer: {}", err);
+                        None
+                    }
+                }
+            });
         CommandEncoder {
             raw: None,
             name: desc.name.to_string(),
             queue: Arc::clone(&self.queue),
+            timing,
+            timings: Vec::new(),
         }
     }
 
@@ -424,6 +718,9 @@ impl crate::traits::CommandDevice for Context {
     fn submit(&self, encoder: &mut CommandEncoder) -> SyncPoint {
         let cmd_buf = encoder.raw.take().unwrap();
         cmd_buf.commit();
+        if let Some(ref mut timing) = encoder.timing {
+            timing.submission = Some(cmd_buf.clone());
+        }
         SyncPoint { cmd_buf }
     }
 
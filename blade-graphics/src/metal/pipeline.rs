@@ -1,4 +1,5 @@
 use naga::back::msl;
+use std::fmt::Write as _;
 
 fn map_blend_factor(factor: crate::BlendFactor) -> metal::MTLBlendFactor {
     use crate::BlendFactor as Bf;
@@ -110,6 +111,128 @@ bitflags::bitflags! {
     }
 }
 
+/// Find the closing bracket, given the text after the opening one.
+fn find_closing_bracket(text: &str, open: u8, close: u8) -> usize {
+    let mut depth = 1;
+    for (i, b) in text.bytes().enumerate() {
+        if b == open {
+            depth += 1;
+        } else if b == close {
+            depth -= 1;
+            if depth == 0 {
+                return i;
+            }
+        }
+    }
+    panic!("Unbalanced brackets in the generated MSL");
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Turn a binding array into an argument buffer, which naga doesn't do.
+///
+/// Naga expects textures of an array in consecutive slots, and buffers
+/// of an array packed together in a single buffer. Both are replaced with
+/// a structure holding the array, in the constant address space.
+fn patch_binding_array(source: &str, name: &str, slot: u32) -> String {
+    const ARRAY_PREFIX: &str = "metal::array<";
+    let struct_name = format!("{name}_Arguments");
+    let mut patched = String::with_capacity(source.len());
+    let mut struct_decl = String::new();
+    let mut first_decl = None;
+    let mut is_buffer = false;
+    let mut rest = source;
+    while let Some(pos) = rest.find(ARRAY_PREFIX) {
+        let inner_start = pos + ARRAY_PREFIX.len();
+        let inner_end = inner_start + find_closing_bracket(&rest[inner_start..], b'<', b'>');
+        let suffix = rest[inner_end + 1..]
+            .trim_start_matches(" const")
+            .trim_start_matches('&');
+        let after_name = match suffix.strip_prefix(' ').and_then(|s| s.strip_prefix(name)) {
+            Some(after) if !after.starts_with(|c: char| is_ident_byte(c as u8)) => after,
+            _ => {
+                patched.push_str(&rest[..inner_start]);
+                rest = &rest[inner_start..];
+                continue;
+            }
+        };
+
+        let mut head = &rest[..pos];
+        if let Some(stripped) = head.strip_suffix("device ") {
+            head = stripped;
+            is_buffer = true;
+        }
+        patched.push_str(head);
+        first_decl.get_or_insert(patched.len());
+        write!(patched, "constant {struct_name}& {name}").unwrap();
+        if struct_decl.is_empty() {
+            let (element, count) = rest[inner_start..inner_end].rsplit_once(", ").unwrap();
+            let pointer = if is_buffer { "*" } else { "" };
+            let space = if is_buffer { "device " } else { "" };
+            struct_decl = format!(
+                "struct {struct_name} {{\n    {ARRAY_PREFIX}{space}{element}{pointer}, {count}> inner;\n}};\n"
+            );
+        }
+
+        rest = after_name;
+        // entry point arguments are followed by the slot attribute
+        if rest.starts_with(" [[") {
+            let end = rest.find("]]").unwrap() + 2;
+            write!(patched, " [[buffer({slot})]]").unwrap();
+            rest = &rest[end..];
+        }
+    }
+    patched.push_str(rest);
+
+    // The structure goes right before the first function using it.
+    let first_decl = first_decl.expect("Binding array is not found in the generated MSL");
+    let mut depth = 0;
+    let mut params_start = 0;
+    for (i, b) in patched[..first_decl].bytes().enumerate().rev() {
+        if b == b')' {
+            depth += 1;
+        } else if b == b'(' {
+            if depth == 0 {
+                params_start = i;
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let function_start = patched[..params_start].rfind('\n').map_or(0, |p| p + 1);
+    patched.insert_str(function_start, &struct_decl);
+
+    // Elements are accessed through the structure, and buffers through pointers.
+    let access = format!("{name}[");
+    let mut output = String::with_capacity(patched.len());
+    let mut rest = patched.as_str();
+    while let Some(pos) = rest.find(&access) {
+        let index_start = pos + access.len();
+        if rest[..pos]
+            .bytes()
+            .last()
+            .map_or(false, |b| is_ident_byte(b) || b == b'.')
+        {
+            output.push_str(&rest[..index_start]);
+            rest = &rest[index_start..];
+            continue;
+        }
+        let index_end = index_start + find_closing_bracket(&rest[index_start..], b'[', b']');
+        let index = &rest[index_start..index_end];
+        output.push_str(&rest[..pos]);
+        if is_buffer {
+            write!(output, "(*{name}.inner[{index}])").unwrap();
+        } else {
+            write!(output, "{name}.inner[{index}]").unwrap();
+        }
+        rest = &rest[index_end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
 fn _align_to(offset: u32, alignment: u32) -> u32 {
     let remain = offset % alignment;
     if remain != 0 {
@@ -120,7 +243,7 @@ fn _align_to(offset: u32, alignment: u32) -> u32 {
 }
 
 impl super::PipelineLayout {
-    fn new(bind_group_layouts: &[&crate::ShaderDataLayout]) -> Self {
+    fn new(device: &metal::DeviceRef, bind_group_layouts: &[&crate::ShaderDataLayout]) -> Self {
         let mut bind_group_infos = Vec::with_capacity(bind_group_layouts.len());
         let mut unsized_buffer_count = 0;
         let mut num_textures = 0u32;
@@ -143,8 +266,12 @@ impl super::PipelineLayout {
                         num_buffers += 1;
                         num_buffers - 1
                     }
+                    // argument buffer
                     crate::ShaderBinding::TextureArray { .. }
-                    | crate::ShaderBinding::BufferArray { .. } => unimplemented!(),
+                    | crate::ShaderBinding::BufferArray { .. } => {
+                        num_buffers += 1;
+                        num_buffers - 1
+                    }
                     crate::ShaderBinding::AccelerationStructure => {
                         num_buffers += 1;
                         num_buffers - 1
@@ -159,10 +286,12 @@ impl super::PipelineLayout {
             bind_group_infos.push(super::BindGroupInfo {
                 visibility: crate::ShaderVisibility::empty(),
                 targets: targets.into_boxed_slice(),
+                argument_encoders: layout.bindings.iter().map(|_| None).collect(),
             });
         }
 
         super::PipelineLayout {
+            device: device.to_owned(),
             bind_groups: bind_group_infos.into_boxed_slice(),
             sizes_buffer_slot: if unsized_buffer_count != 0 {
                 Some(num_buffers)
@@ -177,21 +306,25 @@ impl super::Context {
     fn load_shader(
         &self,
         sf: crate::ShaderFunction,
+        overrides: &[(&str, f64)],
         bind_group_layouts: &[&crate::ShaderDataLayout],
         layout: &mut super::PipelineLayout,
         flags: ShaderFlags,
-    ) -> CompiledShader {
+    ) -> Result<CompiledShader, crate::Error> {
         let mut naga_resources = msl::EntryPointResources::default();
         if let Some(slot) = layout.sizes_buffer_slot {
             naga_resources.sizes_buffer = Some(slot as _);
         }
 
         let ep_index = sf.entry_point_index();
-        let ep_info = sf.shader.info.get_entry_point(ep_index);
-        let naga_stage = sf.shader.module.entry_points[ep_index].stage;
-        let mut module = sf.shader.module.clone();
+        let resolved = sf.shader.resolve_overrides(overrides)?;
+        let ep_info = resolved.info().get_entry_point(ep_index);
+        let naga_stage = resolved.module().entry_points[ep_index].stage;
+        let mut module = resolved.module().clone();
         let mut layouter = naga::proc::Layouter::default();
         layouter.update(module.to_ctx()).unwrap();
+        // name, slot, group, and binding of each binding array
+        let mut binding_arrays = Vec::new();
 
         for (handle, var) in module.global_variables.iter_mut() {
             if ep_info[handle].is_empty() {
@@ -247,6 +380,37 @@ impl super::Context {
                                 ..Default::default()
                             },
                         ),
+                        naga::TypeInner::BindingArray { base, size: _ } => {
+                            let count = match proto_binding {
+                                crate::ShaderBinding::TextureArray { count }
+                                | crate::ShaderBinding::BufferArray { count } => count,
+                                _ => 0,
+                            };
+                            binding_arrays.push((
+                                var_name.clone(),
+                                resource_index,
+                                group_index,
+                                binding_index,
+                            ));
+                            match module.types[base].inner {
+                                naga::TypeInner::Image { .. } => (
+                                    crate::ShaderBinding::TextureArray { count },
+                                    msl::BindTarget {
+                                        texture: Some(resource_index as _),
+                                        binding_array_size: Some(count),
+                                        ..Default::default()
+                                    },
+                                ),
+                                _ => (
+                                    crate::ShaderBinding::BufferArray { count },
+                                    msl::BindTarget {
+                                        buffer: Some(resource_index as _),
+                                        binding_array_size: Some(count),
+                                        ..Default::default()
+                                    },
+                                ),
+                            }
+                        }
                         _ => {
                             let type_layout = &layouter[var.ty];
                             let expected_proto = if access.is_empty() {
@@ -303,8 +467,11 @@ impl super::Context {
         let pipeline_options = msl::PipelineOptions {
             allow_and_force_point_size: flags.contains(ShaderFlags::ALLOW_POINT_SIZE),
         };
-        let (source, info) =
-            msl::write_string(&module, &sf.shader.info, &naga_options, &pipeline_options).unwrap();
+        let (mut source, info) =
+            msl::write_string(&module, resolved.info(), &naga_options, &pipeline_options).unwrap();
+        for &(ref name, slot, _, _) in binding_arrays.iter() {
+            source = patch_binding_array(&source, name, slot);
+        }
 
         log::debug!(
             "Naga generated shader for entry point '{}' and stage {:?}\n{}",
@@ -336,29 +503,57 @@ impl super::Context {
         };
 
         let function = library.get_function(name, None).unwrap();
+        for &(_, slot, group_index, binding_index) in binding_arrays.iter() {
+            let argument_encoders = &mut layout.bind_groups[group_index].argument_encoders;
+            if argument_encoders[binding_index].is_none() {
+                argument_encoders[binding_index] = Some(function.new_argument_encoder(slot as _));
+            }
+        }
 
-        CompiledShader {
+        Ok(CompiledShader {
             library,
             function,
             wg_size,
-        }
+        })
     }
 
-    pub fn create_compute_pipeline(
+    pub fn create_pipeline_cache(&self, _desc: crate::PipelineCacheDesc) -> super::PipelineCache {
+        super::PipelineCache {}
+    }
+
+    pub fn get_pipeline_cache_data(&self, _cache: &super::PipelineCache) -> Vec<u8> {
+        Vec::new()
+    }
+
+    pub fn destroy_pipeline_cache(&self, _cache: super::PipelineCache) {}
+
+    pub fn try_create_compute_pipeline(
         &self,
         desc: crate::ComputePipelineDesc,
-    ) -> super::ComputePipeline {
-        let mut layout = super::PipelineLayout::new(desc.data_layouts);
+    ) -> Result<super::ComputePipeline, crate::Error> {
+        crate::shader::check_overrides(desc.name, &[desc.compute], desc.overrides)?;
+        if desc
+            .data_layouts
+            .iter()
+            .any(|layout| layout.has_binding_arrays())
+            && !self.capabilities().binding_arrays
+        {
+            log::error!("Pipeline '{}' uses unsupported binding arrays", desc.name);
+            return Err(crate::Error::InvalidDescriptor);
+        }
+        let mut layout =
+            super::PipelineLayout::new(&self.device.lock().unwrap(), desc.data_layouts);
 
         objc::rc::autoreleasepool(|| {
             let descriptor = metal::ComputePipelineDescriptor::new();
 
             let cs = self.load_shader(
                 desc.compute,
+                desc.overrides,
                 desc.data_layouts,
                 &mut layout,
                 ShaderFlags::empty(),
-            );
+            )?;
             descriptor.set_compute_function(Some(&cs.function));
 
             if !desc.name.is_empty() {
@@ -370,20 +565,37 @@ impl super::Context {
                 .lock()
                 .unwrap()
                 .new_compute_pipeline_state(&descriptor)
-                .unwrap();
+                .map_err(|e| {
+                    log::error!("Compute pipeline '{}' creation failed: {}", desc.name, e);
+                    crate::Error::InvalidDescriptor
+                })?;
 
-            super::ComputePipeline {
+            Ok(super::ComputePipeline {
                 raw,
                 name: desc.name.to_string(),
                 lib: cs.library,
                 layout,
                 wg_size: cs.wg_size,
-            }
+            })
         })
     }
 
-    pub fn create_render_pipeline(&self, desc: crate::RenderPipelineDesc) -> super::RenderPipeline {
-        let mut layout = super::PipelineLayout::new(desc.data_layouts);
+    pub fn try_create_render_pipeline(
+        &self,
+        desc: crate::RenderPipelineDesc,
+    ) -> Result<super::RenderPipeline, crate::Error> {
+        crate::shader::check_overrides(desc.name, &[desc.vertex, desc.fragment], desc.overrides)?;
+        if desc
+            .data_layouts
+            .iter()
+            .any(|layout| layout.has_binding_arrays())
+            && !self.capabilities().binding_arrays
+        {
+            log::error!("Pipeline '{}' uses unsupported binding arrays", desc.name);
+            return Err(crate::Error::InvalidDescriptor);
+        }
+        let mut layout =
+            super::PipelineLayout::new(&self.device.lock().unwrap(), desc.data_layouts);
 
         let triangle_fill_mode = match desc.primitive.wireframe {
             false => metal::MTLTriangleFillMode::Fill,
@@ -418,22 +630,49 @@ impl super::Context {
 
             let vs = self.load_shader(
                 desc.vertex,
+                desc.overrides,
                 desc.data_layouts,
                 &mut layout,
                 match primitive_class {
                     metal::MTLPrimitiveTopologyClass::Point => ShaderFlags::ALLOW_POINT_SIZE,
                     _ => ShaderFlags::empty(),
                 },
-            );
+            )?;
             descriptor.set_vertex_function(Some(&vs.function));
 
+            if !desc.vertex_fetches.is_empty() {
+                let vertex_descriptor = metal::VertexDescriptor::new();
+                let mut location = 0;
+                for (i, vf) in desc.vertex_fetches.iter().enumerate() {
+                    let buffer_slot = super::vertex_buffer_slot(i as u32);
+                    let layout_descriptor =
+                        vertex_descriptor.layouts().object_at(buffer_slot).unwrap();
+                    layout_descriptor.set_stride(vf.layout.stride as u64);
+                    layout_descriptor.set_step_function(if vf.instanced {
+                        metal::MTLVertexStepFunction::PerInstance
+                    } else {
+                        metal::MTLVertexStepFunction::PerVertex
+                    });
+                    for at in vf.layout.attributes.iter() {
+                        let at_descriptor =
+                            vertex_descriptor.attributes().object_at(location).unwrap();
+                        at_descriptor.set_format(super::map_vertex_format(at.format));
+                        at_descriptor.set_buffer_index(buffer_slot);
+                        at_descriptor.set_offset(at.offset as u64);
+                        location += 1;
+                    }
+                }
+                descriptor.set_vertex_descriptor(Some(vertex_descriptor));
+            }
+
             // Fragment shader
             let fs = self.load_shader(
                 desc.fragment,
+                desc.overrides,
                 desc.data_layouts,
                 &mut layout,
                 ShaderFlags::empty(),
-            );
+            )?;
             descriptor.set_fragment_function(Some(&fs.function));
 
             for (i, ct) in desc.color_targets.iter().enumerate() {
@@ -473,8 +712,13 @@ impl super::Context {
             let depth_stencil = match desc.depth_stencil {
                 Some(ref ds) => {
                     let raw_format = super::map_texture_format(ds.format);
-                    descriptor.set_depth_attachment_pixel_format(raw_format);
-                    //TODO: descriptor.set_stencil_attachment_pixel_format(raw_format);
+                    let aspects = ds.format.aspects();
+                    if aspects.contains(crate::TexelAspects::DEPTH) {
+                        descriptor.set_depth_attachment_pixel_format(raw_format);
+                    }
+                    if aspects.contains(crate::TexelAspects::STENCIL) {
+                        descriptor.set_stencil_attachment_pixel_format(raw_format);
+                    }
 
                     let ds_descriptor = create_depth_stencil_desc(ds);
                     let raw = self
@@ -487,6 +731,12 @@ impl super::Context {
                 None => None,
             };
 
+            descriptor.set_sample_count(desc.multisample_state.sample_count as u64);
+            descriptor.set_alpha_to_coverage_enabled(desc.multisample_state.alpha_to_coverage);
+            if desc.multisample_state.sample_mask != !0 {
+                log::warn!("Sample mask is not supported by Metal pipelines");
+            }
+
             if !desc.name.is_empty() {
                 descriptor.set_label(desc.name);
             }
@@ -496,9 +746,12 @@ impl super::Context {
                 .lock()
                 .unwrap()
                 .new_render_pipeline_state(&descriptor)
-                .unwrap();
+                .map_err(|e| {
+                    log::error!("Render pipeline '{}' creation failed: {}", desc.name, e);
+                    crate::Error::InvalidDescriptor
+                })?;
 
-            super::RenderPipeline {
+            Ok(super::RenderPipeline {
                 raw,
                 name: desc.name.to_string(),
                 vs_lib: vs.library,
@@ -521,7 +774,7 @@ impl super::Context {
                     metal::MTLDepthClipMode::Clip
                 },
                 depth_stencil,
-            }
+            })
         })
     }
 }
@@ -1,5 +1,6 @@
+use metal::foreign_types::ForeignType as _;
 use objc::{msg_send, sel, sel_impl};
-use std::mem;
+use std::{mem, ptr};
 
 fn map_texture_usage(usage: crate::TextureUsage) -> metal::MTLTextureUsage {
     use crate::TextureUsage as Tu;
@@ -83,6 +84,14 @@ impl super::Context {
         }
     }
 
+    pub fn get_top_level_acceleration_structure_sizes_for(
+        &self,
+        instance_count: u32,
+        _bottom_level: &[super::AccelerationStructure],
+    ) -> crate::AccelerationStructureSizes {
+        self.get_top_level_acceleration_structure_sizes(instance_count)
+    }
+
     pub fn get_top_level_acceleration_structure_sizes(
         &self,
         instance_count: u32,
@@ -139,7 +148,7 @@ impl crate::traits::ResourceDevice for super::Context {
     type Sampler = super::Sampler;
     type AccelerationStructure = super::AccelerationStructure;
 
-    fn create_buffer(&self, desc: crate::BufferDesc) -> super::Buffer {
+    fn try_create_buffer(&self, desc: crate::BufferDesc) -> Result<super::Buffer, crate::Error> {
         let options = match desc.memory {
             crate::Memory::Device => metal::MTLResourceOptions::StorageModePrivate,
             crate::Memory::Shared => metal::MTLResourceOptions::StorageModeShared,
@@ -148,14 +157,30 @@ impl crate::traits::ResourceDevice for super::Context {
                     | metal::MTLResourceOptions::CPUCacheModeWriteCombined
             }
         };
-        let raw = objc::rc::autoreleasepool(|| {
-            let raw = self.device.lock().unwrap().new_buffer(desc.size, options);
+        let raw: *mut metal::MTLBuffer = objc::rc::autoreleasepool(|| {
+            let device = self.device.lock().unwrap();
+            if desc.size > device.max_buffer_length() {
+                return ptr::null_mut();
+            }
+            let raw = device.new_buffer(desc.size, options);
+            if raw.as_ptr().is_null() {
+                return ptr::null_mut();
+            }
             if !desc.name.is_empty() {
                 raw.set_label(&desc.name);
             }
             unsafe { msg_send![raw.as_ref(), retain] }
         });
-        super::Buffer { raw }
+        if raw.is_null() {
+            log::error!(
+                "Unable to allocate buffer '{}' of size {}",
+                desc.name,
+                desc.size
+            );
+            return Err(crate::Error::OutOfMemory);
+        }
+        self.resource_counts.buffers.increment();
+        Ok(super::Buffer { raw })
     }
 
     fn sync_buffer(&self, _buffer: super::Buffer) {}
@@ -164,9 +189,11 @@ impl crate::traits::ResourceDevice for super::Context {
         unsafe {
             let () = msg_send![buffer.raw, release];
         }
+        self.resource_counts.buffers.decrement();
     }
 
-    fn create_texture(&self, desc: crate::TextureDesc) -> super::Texture {
+    fn try_create_texture(&self, desc: crate::TextureDesc) -> Result<super::Texture, crate::Error> {
+        desc.validate(&self.limits(), self.format_features(desc.format))?;
         let mtl_format = super::map_texture_format(desc.format);
 
         let mtl_type = match desc.dimension {
@@ -178,7 +205,13 @@ impl crate::traits::ResourceDevice for super::Context {
                 }
             }
             crate::TextureDimension::D2 => {
-                if desc.array_layer_count > 1 {
+                if desc.sample_count > 1 {
+                    if desc.array_layer_count > 1 {
+                        metal::MTLTextureType::D2MultisampleArray
+                    } else {
+                        metal::MTLTextureType::D2Multisample
+                    }
+                } else if desc.array_layer_count > 1 {
                     metal::MTLTextureType::D2Array
                 } else {
                     metal::MTLTextureType::D2
@@ -188,7 +221,7 @@ impl crate::traits::ResourceDevice for super::Context {
         };
         let mtl_usage = map_texture_usage(desc.usage);
 
-        let raw = objc::rc::autoreleasepool(|| {
+        let raw: *mut metal::MTLTexture = objc::rc::autoreleasepool(|| {
             let descriptor = metal::TextureDescriptor::new();
 
             descriptor.set_texture_type(mtl_type);
@@ -197,30 +230,51 @@ impl crate::traits::ResourceDevice for super::Context {
             descriptor.set_depth(desc.size.depth as u64);
             descriptor.set_array_length(desc.array_layer_count as u64);
             descriptor.set_mipmap_level_count(desc.mip_level_count as u64);
+            descriptor.set_sample_count(desc.sample_count as u64);
             descriptor.set_pixel_format(mtl_format);
             descriptor.set_usage(mtl_usage);
             descriptor.set_storage_mode(metal::MTLStorageMode::Private);
 
             let raw = self.device.lock().unwrap().new_texture(&descriptor);
+            if raw.as_ptr().is_null() {
+                return ptr::null_mut();
+            }
             if !desc.name.is_empty() {
                 raw.set_label(desc.name);
             }
 
             unsafe { msg_send![raw.as_ref(), retain] }
         });
+        if raw.is_null() {
+            log::error!("Unable to allocate texture '{}'", desc.name);
+            return Err(crate::Error::OutOfMemory);
+        }
+        self.resource_counts.textures.increment();
 
-        super::Texture { raw }
+        Ok(super::Texture { raw })
     }
 
     fn destroy_texture(&self, texture: super::Texture) {
         unsafe {
             let () = msg_send![texture.raw, release];
         }
+        self.resource_counts.textures.decrement();
     }
 
-    fn create_texture_view(&self, desc: crate::TextureViewDesc) -> super::TextureView {
+    fn try_create_texture_view(
+        &self,
+        desc: crate::TextureViewDesc,
+    ) -> Result<super::TextureView, crate::Error> {
         let texture = desc.texture.as_ref();
-        let mtl_format = super::map_texture_format(desc.format);
+        let aspects = desc.format.select_aspects(desc.subresources.aspects);
+        let mtl_format = if aspects == crate::TexelAspects::STENCIL
+            && desc.format.aspects().contains(crate::TexelAspects::DEPTH)
+        {
+            // Stencil of a combined texture is sampled through a separate format
+            metal::MTLPixelFormat::X32_Stencil8
+        } else {
+            super::map_texture_format(desc.format)
+        };
         let mtl_type = map_view_dimension(desc.dimension);
         let mip_level_count = match desc.subresources.mip_level_count {
             Some(count) => count.get() as u64,
@@ -249,7 +303,7 @@ impl crate::traits::ResourceDevice for super::Context {
             }
             unsafe { msg_send![raw.as_ref(), retain] }
         });
-        super::TextureView { raw }
+        Ok(super::TextureView { raw })
     }
 
     fn destroy_texture_view(&self, view: super::TextureView) {
@@ -258,7 +312,7 @@ impl crate::traits::ResourceDevice for super::Context {
         }
     }
 
-    fn create_sampler(&self, desc: crate::SamplerDesc) -> super::Sampler {
+    fn try_create_sampler(&self, desc: crate::SamplerDesc) -> Result<super::Sampler, crate::Error> {
         let raw = objc::rc::autoreleasepool(|| {
             let descriptor = metal::SamplerDescriptor::new();
 
@@ -300,7 +354,7 @@ impl crate::traits::ResourceDevice for super::Context {
             unsafe { msg_send![raw.as_ref(), retain] }
         });
 
-        super::Sampler { raw }
+        Ok(super::Sampler { raw })
     }
 
     fn destroy_sampler(&self, sampler: super::Sampler) {
@@ -309,27 +363,37 @@ impl crate::traits::ResourceDevice for super::Context {
         }
     }
 
-    fn create_acceleration_structure(
+    fn try_create_acceleration_structure(
         &self,
         desc: crate::AccelerationStructureDesc,
-    ) -> super::AccelerationStructure {
+    ) -> Result<super::AccelerationStructure, crate::Error> {
         let raw = self
             .device
             .lock()
             .unwrap()
             .new_acceleration_structure_with_size(desc.size);
+        if raw.as_ptr().is_null() {
+            log::error!(
+                "Unable to allocate acceleration structure '{}' of size {}",
+                desc.name,
+                desc.size
+            );
+            return Err(crate::Error::OutOfMemory);
+        }
         if !desc.name.is_empty() {
             raw.set_label(desc.name);
         }
+        self.resource_counts.acceleration_structures.increment();
 
-        super::AccelerationStructure {
+        Ok(super::AccelerationStructure {
             raw: unsafe { msg_send![raw.as_ref(), retain] },
-        }
+        })
     }
 
     fn destroy_acceleration_structure(&self, acceleration_structure: super::AccelerationStructure) {
         unsafe {
             let () = msg_send![acceleration_structure.raw, release];
         }
+        self.resource_counts.acceleration_structures.decrement();
     }
 }
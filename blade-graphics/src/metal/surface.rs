@@ -4,11 +4,11 @@ use core_graphics_types::{
 };
 use objc::{
     class, msg_send,
-    runtime::{Object, BOOL, YES},
+    runtime::{Object, BOOL, NO, YES},
     sel, sel_impl,
 };
 
-use std::mem;
+use std::{mem, ptr};
 
 #[cfg(target_os = "macos")]
 #[link(name = "QuartzCore", kind = "framework")]
@@ -17,6 +17,22 @@ extern "C" {
     static kCAGravityTopLeft: *mut Object;
 }
 
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    #[allow(non_upper_case_globals)]
+    static kCGColorSpaceITUR_2100_PQ: *const Object;
+    fn CGColorSpaceCreateWithName(name: *const Object) -> *mut Object;
+    fn CGColorSpaceRelease(space: *mut Object);
+}
+
+fn map_color_space(color_space: crate::ColorSpace) -> crate::TextureFormat {
+    match color_space {
+        crate::ColorSpace::Linear => crate::TextureFormat::Bgra8UnormSrgb,
+        crate::ColorSpace::Srgb => crate::TextureFormat::Bgra8Unorm,
+        crate::ColorSpace::Hdr10 => crate::TextureFormat::Rgb10a2Unorm,
+    }
+}
+
 impl Drop for super::Surface {
     fn drop(&mut self) {
         unsafe {
@@ -68,6 +84,7 @@ impl super::Surface {
     }
 
     fn reconfigure(&mut self, device: &metal::DeviceRef, config: crate::SurfaceConfig) {
+        self.format = map_color_space(config.color_space);
         self.render_layer.set_opaque(true);
         self.render_layer.set_device(device);
         self.render_layer
@@ -82,6 +99,20 @@ impl super::Surface {
         ));
         unsafe {
             let () = msg_send![self.render_layer, setDisplaySyncEnabled: true];
+            let is_hdr = config.color_space == crate::ColorSpace::Hdr10;
+            let color_space = if is_hdr {
+                CGColorSpaceCreateWithName(kCGColorSpaceITUR_2100_PQ)
+            } else {
+                ptr::null_mut()
+            };
+            let () = msg_send![self.render_layer, setColorspace: color_space];
+            let () = msg_send![
+                self.render_layer,
+                setWantsExtendedDynamicRangeContent: if is_hdr { YES } else { NO }
+            ];
+            if !color_space.is_null() {
+                CGColorSpaceRelease(color_space);
+            }
         }
     }
 }
@@ -0,0 +1,1032 @@
+use crate::software_rt;
+use std::{mem::size_of, ptr, time::Instant};
+
+impl super::PipelineContext<'_> {
+    fn check(&self, index: u32, binding: crate::ShaderBinding) {
+        let (name, expected) = self.layout.bindings[index as usize];
+        assert_eq!(binding, expected, "Mismatched binding for '{}'", name);
+    }
+}
+
+impl<T: bytemuck::Pod> crate::ShaderBindable for T {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(
+            index,
+            crate::ShaderBinding::Plain {
+                size: size_of::<T>() as u32,
+            },
+        );
+    }
+}
+impl crate::ShaderBindable for super::TextureView {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::Texture);
+    }
+}
+impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::TextureArray<N> {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::TextureArray { count: N });
+    }
+}
+impl crate::ShaderBindable for super::Sampler {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::Sampler);
+    }
+}
+impl crate::ShaderBindable for crate::BufferPiece {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::Buffer);
+        assert!(
+            self.offset <= self.buffer.size,
+            "Buffer offset {} is out of bounds",
+            self.offset
+        );
+    }
+}
+impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::BufferArray<N> {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::BufferArray { count: N });
+    }
+}
+impl crate::ShaderBindable for super::AccelerationStructure {
+    fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.check(index, crate::ShaderBinding::AccelerationStructure);
+    }
+}
+
+impl super::CommandEncoder {
+    pub fn start(&mut self) {
+        self.commands.clear();
+        self.timed_passes.clear();
+    }
+
+    pub fn init_texture(&mut self, _texture: super::Texture) {}
+
+    pub fn present(&mut self, _frame: super::Frame) {
+        self.check_command_type("present", &[crate::CommandType::General]);
+    }
+
+    /// Wait for the work of another encoder before executing this one.
+    /// Commands are executed on submission, so they are always ordered.
+    pub fn wait_for(&mut self, _sp: &super::SyncPoint) {}
+
+    /// Return the timings of the passes executed by the last submission.
+    pub fn timings(&self) -> &crate::Timings {
+        &self.timings
+    }
+
+    fn check_command_type(&self, label: &str, command_types: &[crate::CommandType]) {
+        assert!(
+            command_types.contains(&self.command_type),
+            "Pass '{}' is not supported by {:?} encoder '{}'",
+            label,
+            self.command_type,
+            self.name
+        );
+    }
+
+    fn begin_pass(&mut self, label: &str) {
+        if self.timing {
+            self.timed_passes
+                .push((label.to_string(), self.commands.len()));
+        }
+    }
+
+    fn check_debug_groups(&self) {
+        let mut depth = 0usize;
+        for command in self.commands.iter() {
+            match *command {
+                super::Command::PushDebugGroup(_) => depth += 1,
+                super::Command::PopDebugGroup => {
+                    assert!(
+                        depth != 0,
+                        "Debug group is popped without being pushed in '{}'",
+                        self.name
+                    );
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(depth, 0, "Debug groups are left open in '{}'", self.name);
+    }
+
+    pub(super) fn execute(&mut self) {
+        self.check_debug_groups();
+        let pass_ends = self
+            .timed_passes
+            .iter()
+            .skip(1)
+            .map(|&(_, begin)| begin)
+            .chain(Some(self.commands.len()));
+        self.timings.clear();
+        let mut next = 0;
+        for (&(ref label, begin), end) in self.timed_passes.iter().zip(pass_ends) {
+            for command in self.commands[next..begin].iter() {
+                unsafe { command.execute() };
+            }
+            let instant = Instant::now();
+            for command in self.commands[begin..end].iter() {
+                unsafe { command.execute() };
+            }
+            self.timings.push((label.clone(), instant.elapsed()));
+            next = end;
+        }
+        for command in self.commands[next..].iter() {
+            unsafe { command.execute() };
+        }
+    }
+
+    pub fn transfer(&mut self) -> super::PassEncoder<'_, ()> {
+        self.transfer_labeled("transfer")
+    }
+
+    /// Same as `transfer`, but the pass is labeled for debugging and timing.
+    pub fn transfer_labeled(&mut self, label: &str) -> super::PassEncoder<'_, ()> {
+        self.begin_pass(label);
+        super::PassEncoder {
+            commands: &mut self.commands,
+            sample_count: 1,
+            pipeline: Default::default(),
+            software_rt: &self.software_rt,
+        }
+    }
+
+    pub fn acceleration_structure(&mut self) -> super::PassEncoder<'_, ()> {
+        self.acceleration_structure_labeled("acceleration structure")
+    }
+
+    /// Same as `acceleration_structure`, but the pass is labeled for debugging and timing.
+    pub fn acceleration_structure_labeled(&mut self, label: &str) -> super::PassEncoder<'_, ()> {
+        self.check_command_type(
+            label,
+            &[crate::CommandType::Compute, crate::CommandType::General],
+        );
+        self.begin_pass(label);
+        super::PassEncoder {
+            commands: &mut self.commands,
+            sample_count: 1,
+            pipeline: Default::default(),
+            software_rt: &self.software_rt,
+        }
+    }
+
+    pub fn compute(&mut self) -> super::PassEncoder<'_, super::ComputePipeline> {
+        self.compute_labeled("compute")
+    }
+
+    /// Same as `compute`, but the pass is labeled for debugging and timing.
+    pub fn compute_labeled(
+        &mut self,
+        label: &str,
+    ) -> super::PassEncoder<'_, super::ComputePipeline> {
+        self.check_command_type(
+            label,
+            &[crate::CommandType::Compute, crate::CommandType::General],
+        );
+        self.begin_pass(label);
+        super::PassEncoder {
+            commands: &mut self.commands,
+            sample_count: 1,
+            pipeline: Default::default(),
+            software_rt: &self.software_rt,
+        }
+    }
+
+    pub fn render(
+        &mut self,
+        targets: crate::RenderTargetSet,
+    ) -> super::PassEncoder<'_, super::RenderPipeline> {
+        self.render_labeled("render", targets)
+    }
+
+    /// Same as `render`, but the pass is labeled for debugging and timing.
+    pub fn render_labeled(
+        &mut self,
+        label: &str,
+        targets: crate::RenderTargetSet,
+    ) -> super::PassEncoder<'_, super::RenderPipeline> {
+        self.check_command_type(label, &[crate::CommandType::General]);
+        let mut target_size = None;
+        for rt in targets.colors.iter().chain(targets.depth_stencil.as_ref()) {
+            assert!(
+                !rt.view.texture.data.is_null(),
+                "Render target is not initialized"
+            );
+            assert!(
+                *target_size.get_or_insert(rt.view.target_size) == rt.view.target_size,
+                "Render target sizes don't match"
+            );
+        }
+        for rt in targets.colors.iter() {
+            assert!(
+                rt.view.aspects.contains(crate::TexelAspects::COLOR),
+                "Color target has a depth-stencil format"
+            );
+        }
+        if let Some(ref rt) = targets.depth_stencil {
+            assert!(
+                !rt.view.aspects.contains(crate::TexelAspects::COLOR),
+                "Depth-stencil target has a color format"
+            );
+        }
+        let mut sample_count = None;
+        for rt in targets.colors.iter().chain(targets.depth_stencil.as_ref()) {
+            let texture = &rt.view.texture;
+            assert_eq!(
+                *sample_count.get_or_insert(texture.sample_count),
+                texture.sample_count,
+                "Render target sample counts don't match"
+            );
+            if let crate::FinishOp::ResolveTo(ref resolve_view) = rt.finish_op {
+                assert!(
+                    texture.sample_count > 1,
+                    "Resolving a render target that is not multisampled"
+                );
+                assert_eq!(
+                    resolve_view.texture.sample_count, 1,
+                    "Resolve target is multisampled"
+                );
+                assert_eq!(
+                    resolve_view.target_size, rt.view.target_size,
+                    "Resolve target size doesn't match"
+                );
+                assert_eq!(
+                    resolve_view.texture.format, texture.format,
+                    "Resolve target format doesn't match"
+                );
+            }
+        }
+        self.begin_pass(label);
+        super::PassEncoder {
+            commands: &mut self.commands,
+            sample_count: sample_count.unwrap_or(1),
+            pipeline: Default::default(),
+            software_rt: &self.software_rt,
+        }
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::CommandEncoder {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, ()> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, super::ComputePipeline> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::PassEncoder<'_, super::RenderPipeline> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::PushDebugGroup(label.to_string()));
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.commands.push(super::Command::PopDebugGroup);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.commands
+            .push(super::Command::InsertDebugMarker(label.to_string()));
+    }
+}
+
+impl super::PassEncoder<'_, super::ComputePipeline> {
+    pub fn with<'b>(
+        &'b mut self,
+        pipeline: &'b super::ComputePipeline,
+    ) -> super::PipelineEncoder<'b> {
+        super::PipelineEncoder {
+            commands: self.commands,
+            group_layouts: &pipeline.layout.group_layouts,
+            topology: crate::PrimitiveTopology::default(),
+            vertex_fetch_count: 0,
+        }
+    }
+}
+
+impl super::PassEncoder<'_, super::RenderPipeline> {
+    pub fn with<'b>(
+        &'b mut self,
+        pipeline: &'b super::RenderPipeline,
+    ) -> super::PipelineEncoder<'b> {
+        assert_eq!(
+            pipeline.sample_count, self.sample_count,
+            "Pipeline sample count doesn't match the render targets"
+        );
+        super::PipelineEncoder {
+            commands: self.commands,
+            group_layouts: &pipeline.layout.group_layouts,
+            topology: pipeline.topology,
+            vertex_fetch_count: pipeline.vertex_fetch_count,
+        }
+    }
+
+    pub fn execute_bundle(&mut self, bundle: &super::RenderBundle) {
+        assert_eq!(
+            bundle.sample_count, self.sample_count,
+            "Bundle sample count doesn't match the render targets"
+        );
+        self.commands.extend_from_slice(&bundle.commands);
+    }
+}
+
+impl super::Context {
+    pub fn try_create_render_bundle_encoder(
+        &self,
+        desc: crate::RenderBundleDesc,
+    ) -> Result<super::RenderBundleEncoder, crate::Error> {
+        if !desc
+            .color_formats
+            .iter()
+            .all(|format| format.aspects().contains(crate::TexelAspects::COLOR))
+        {
+            log::error!(
+                "Bundle '{}' has a color target with a depth-stencil format",
+                desc.name
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+        Ok(super::RenderBundleEncoder {
+            commands: Vec::new(),
+            sample_count: desc.sample_count,
+        })
+    }
+
+    pub fn destroy_render_bundle(&self, _bundle: super::RenderBundle) {}
+}
+
+impl super::RenderBundleEncoder {
+    pub fn with<'b>(
+        &'b mut self,
+        pipeline: &'b super::RenderPipeline,
+    ) -> super::PipelineEncoder<'b> {
+        assert_eq!(
+            pipeline.sample_count, self.sample_count,
+            "Pipeline sample count doesn't match the bundle"
+        );
+        super::PipelineEncoder {
+            commands: &mut self.commands,
+            group_layouts: &pipeline.layout.group_layouts,
+            topology: pipeline.topology,
+            vertex_fetch_count: pipeline.vertex_fetch_count,
+        }
+    }
+
+    pub fn finish(self) -> super::RenderBundle {
+        super::RenderBundle {
+            commands: self.commands,
+            sample_count: self.sample_count,
+        }
+    }
+}
+
+/// Size of the mesh transform data, a row-major 3x4 matrix.
+const TRANSFORM_SIZE: u64 = size_of::<crate::Transform>() as u64;
+
+/// Number of bytes used by the vertices of a mesh.
+fn vertex_data_size(mesh: &crate::AccelerationStructureMesh) -> u64 {
+    let (_, size) = software_rt::position_format(mesh.vertex_format);
+    mesh.vertex_count.saturating_sub(1) as u64 * mesh.vertex_stride as u64 + size
+}
+
+/// Number of bytes used by the indices of a mesh.
+fn index_data_size(mesh: &crate::AccelerationStructureMesh) -> u64 {
+    software_rt::index_size(mesh) as u64 * mesh.triangle_count as u64 * 3
+}
+
+fn check_buffer_range(piece: &crate::BufferPiece, size: u64) {
+    assert!(
+        piece.offset + size <= piece.buffer.size,
+        "Range {}..{} is out of buffer bounds {}",
+        piece.offset,
+        piece.offset + size,
+        piece.buffer.size,
+    );
+}
+
+fn check_texture_range(piece: &crate::TexturePiece, size: &crate::Extent) {
+    let texture = &piece.texture;
+    assert_eq!(
+        texture.sample_count, 1,
+        "Multisampled textures can't be copied"
+    );
+    assert!(
+        !texture.format.select_aspects(piece.aspects).is_empty(),
+        "Copied aspects {:?} are not in the format {:?}",
+        piece.aspects,
+        texture.format
+    );
+    assert!(
+        piece.mip_level < texture.mip_level_count,
+        "Mip level {} is out of bounds",
+        piece.mip_level
+    );
+    assert!(
+        piece.array_layer < texture.array_layer_count,
+        "Array layer {} is out of bounds",
+        piece.array_layer
+    );
+    let extent = texture.size.at_mip_level(piece.mip_level);
+    assert!(
+        piece.origin[0] + size.width <= extent.width
+            && piece.origin[1] + size.height <= extent.height
+            && piece.origin[2] + size.depth <= extent.depth,
+        "Region {:?} of size {} is out of texture bounds {}",
+        piece.origin,
+        size,
+        extent
+    );
+}
+
+fn check_buffer_copy_aspects(piece: &crate::TexturePiece) {
+    let aspects = piece.texture.format.select_aspects(piece.aspects);
+    assert_eq!(
+        aspects.bits().count_ones(),
+        1,
+        "Buffer copies of {:?} need a single aspect",
+        piece.texture.format
+    );
+}
+
+/// Memory layout of a copied region.
+struct CopyLayout {
+    offset: usize,
+    row_pitch: usize,
+    slice_pitch: usize,
+    /// Distance between blocks, which is larger than the block size
+    /// when copying a single aspect of a depth-stencil texture.
+    block_pitch: usize,
+}
+
+struct CopyRegion {
+    aspects: crate::TexelAspects,
+    block_size: usize,
+    row_size: usize,
+    rows: usize,
+    slices: usize,
+}
+
+impl CopyRegion {
+    fn new(piece: &crate::TexturePiece, size: &crate::Extent) -> Self {
+        let format = piece.texture.format;
+        let aspects = format.select_aspects(piece.aspects);
+        let block_info = format.aspect_block_info(aspects);
+        let (bw, bh) = (
+            block_info.dimensions.0 as u32,
+            block_info.dimensions.1 as u32,
+        );
+        Self {
+            aspects,
+            block_size: block_info.size as usize,
+            row_size: ((size.width + bw - 1) / bw) as usize * block_info.size as usize,
+            rows: ((size.height + bh - 1) / bh) as usize,
+            slices: size.depth as usize,
+        }
+    }
+
+    fn buffer_layout(&self, piece: &crate::BufferPiece, bytes_per_row: u32) -> CopyLayout {
+        assert!(
+            bytes_per_row as usize >= self.row_size,
+            "Bytes per row {} is less than the row size {}",
+            bytes_per_row,
+            self.row_size
+        );
+        let layout = CopyLayout {
+            offset: piece.offset as usize,
+            row_pitch: bytes_per_row as usize,
+            slice_pitch: bytes_per_row as usize * self.rows,
+            block_pitch: self.block_size,
+        };
+        check_buffer_range(piece, self.extent_in(&layout) as u64);
+        layout
+    }
+
+    fn texture_layout(&self, piece: &crate::TexturePiece) -> CopyLayout {
+        let texture = &piece.texture;
+        let block_info = texture.format.block_info();
+        let (row_pitch, slice_pitch) = texture.pitches(piece.mip_level);
+        let block_x = (piece.origin[0] / block_info.dimensions.0 as u32) as usize;
+        let block_y = (piece.origin[1] / block_info.dimensions.1 as u32) as usize;
+        // Stencil is stored in the last byte of a depth-stencil texel
+        let aspect_offset = if self.aspects == crate::TexelAspects::STENCIL {
+            block_info.size as usize - 1
+        } else {
+            0
+        };
+        CopyLayout {
+            offset: texture.subresource_offset(piece.mip_level, piece.array_layer)
+                + piece.origin[2] as usize * slice_pitch
+                + block_y * row_pitch
+                + block_x * block_info.size as usize
+                + aspect_offset,
+            row_pitch,
+            slice_pitch,
+            block_pitch: block_info.size as usize,
+        }
+    }
+
+    /// Return the number of bytes spanned by the region in the given layout.
+    fn extent_in(&self, layout: &CopyLayout) -> usize {
+        if self.slices == 0 || self.rows == 0 {
+            return 0;
+        }
+        (self.slices - 1) * layout.slice_pitch + (self.rows - 1) * layout.row_pitch + self.row_size
+    }
+
+    unsafe fn copy(
+        &self,
+        src: *const u8,
+        src_layout: &CopyLayout,
+        dst: *mut u8,
+        dst_layout: &CopyLayout,
+    ) {
+        for slice in 0..self.slices {
+            for row in 0..self.rows {
+                let src_offset =
+                    src_layout.offset + slice * src_layout.slice_pitch + row * src_layout.row_pitch;
+                let dst_offset =
+                    dst_layout.offset + slice * dst_layout.slice_pitch + row * dst_layout.row_pitch;
+                if src_layout.block_pitch == self.block_size
+                    && dst_layout.block_pitch == self.block_size
+                {
+                    ptr::copy(src.add(src_offset), dst.add(dst_offset), self.row_size);
+                    continue;
+                }
+                for block in 0..self.row_size / self.block_size {
+                    ptr::copy(
+                        src.add(src_offset + block * src_layout.block_pitch),
+                        dst.add(dst_offset + block * dst_layout.block_pitch),
+                        self.block_size,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl super::Command {
+    pub(super) unsafe fn execute(&self) {
+        match *self {
+            Self::FillBuffer { dst, size, value } => {
+                ptr::write_bytes(dst.data(), value, size as usize);
+            }
+            Self::CopyBufferToBuffer { src, dst, size } => {
+                ptr::copy(src.data(), dst.data(), size as usize);
+            }
+            Self::CopyTextureToTexture {
+                ref src,
+                ref dst,
+                ref size,
+            } => {
+                let region = CopyRegion::new(src, size);
+                region.copy(
+                    src.texture.data,
+                    &region.texture_layout(src),
+                    dst.texture.data,
+                    &region.texture_layout(dst),
+                );
+            }
+            Self::CopyBufferToTexture {
+                ref src,
+                bytes_per_row,
+                ref dst,
+                ref size,
+            } => {
+                let region = CopyRegion::new(dst, size);
+                region.copy(
+                    src.buffer.data,
+                    &region.buffer_layout(src, bytes_per_row),
+                    dst.texture.data,
+                    &region.texture_layout(dst),
+                );
+            }
+            Self::CopyTextureToBuffer {
+                ref src,
+                ref dst,
+                bytes_per_row,
+                ref size,
+            } => {
+                let region = CopyRegion::new(src, size);
+                region.copy(
+                    src.texture.data,
+                    &region.texture_layout(src),
+                    dst.buffer.data,
+                    &region.buffer_layout(dst, bytes_per_row),
+                );
+            }
+            Self::Dispatch(groups) => {
+                log::debug!("Skipping dispatch of {:?} groups", groups);
+            }
+            Self::DispatchIndirect { indirect_buf } => {
+                let groups = ptr::read_unaligned(indirect_buf.data() as *const [u32; 3]);
+                log::debug!("Skipping indirect dispatch of {:?} groups", groups);
+            }
+            Self::Draw {
+                topology,
+                vertex_count,
+                instance_count,
+            } => {
+                log::debug!(
+                    "Skipping draw of {} {:?} vertices in {} instances",
+                    vertex_count,
+                    topology,
+                    instance_count
+                );
+            }
+            Self::DrawIndirect {
+                topology,
+                ref indirect_buf,
+                max_draw_count,
+                count_buf,
+            } => {
+                let draw_count = match count_buf {
+                    Some(piece) => ptr::read_unaligned(piece.data() as *const u32),
+                    None => max_draw_count,
+                };
+                log::debug!(
+                    "Skipping {} indirect draws of {:?} from {:?}",
+                    draw_count.min(max_draw_count),
+                    topology,
+                    indirect_buf
+                );
+            }
+            Self::PushDebugGroup(ref label) => {
+                log::trace!("Debug group '{}'", label);
+            }
+            Self::PopDebugGroup => {}
+            Self::InsertDebugMarker(ref label) => {
+                log::trace!("Debug marker '{}'", label);
+            }
+        }
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::TransferEncoder for super::PassEncoder<'_, ()> {
+    fn fill_buffer(&mut self, dst: crate::BufferPiece, size: u64, value: u8) {
+        check_buffer_range(&dst, size);
+        self.commands
+            .push(super::Command::FillBuffer { dst, size, value });
+    }
+
+    fn copy_buffer_to_buffer(
+        &mut self,
+        src: crate::BufferPiece,
+        dst: crate::BufferPiece,
+        size: u64,
+    ) {
+        check_buffer_range(&src, size);
+        check_buffer_range(&dst, size);
+        self.commands
+            .push(super::Command::CopyBufferToBuffer { src, dst, size });
+    }
+
+    fn copy_texture_to_texture(
+        &mut self,
+        src: crate::TexturePiece,
+        dst: crate::TexturePiece,
+        size: crate::Extent,
+    ) {
+        check_texture_range(&src, &size);
+        check_texture_range(&dst, &size);
+        assert_eq!(
+            src.texture.format.block_info().size,
+            dst.texture.format.block_info().size,
+            "Texture formats are not copy-compatible"
+        );
+        assert_eq!(
+            src.texture.format.select_aspects(src.aspects),
+            dst.texture.format.select_aspects(dst.aspects),
+            "Copied texture aspects don't match"
+        );
+        self.commands
+            .push(super::Command::CopyTextureToTexture { src, dst, size });
+    }
+
+    fn copy_buffer_to_texture(
+        &mut self,
+        src: crate::BufferPiece,
+        bytes_per_row: u32,
+        dst: crate::TexturePiece,
+        size: crate::Extent,
+    ) {
+        check_texture_range(&dst, &size);
+        check_buffer_copy_aspects(&dst);
+        let _ = CopyRegion::new(&dst, &size).buffer_layout(&src, bytes_per_row);
+        self.commands.push(super::Command::CopyBufferToTexture {
+            src,
+            bytes_per_row,
+            dst,
+            size,
+        });
+    }
+
+    fn copy_texture_to_buffer(
+        &mut self,
+        src: crate::TexturePiece,
+        dst: crate::BufferPiece,
+        bytes_per_row: u32,
+        size: crate::Extent,
+    ) {
+        check_texture_range(&src, &size);
+        check_buffer_copy_aspects(&src);
+        let _ = CopyRegion::new(&src, &size).buffer_layout(&dst, bytes_per_row);
+        self.commands.push(super::Command::CopyTextureToBuffer {
+            src,
+            dst,
+            bytes_per_row,
+            size,
+        });
+    }
+}
+
+impl super::AccelerationStructure {
+    fn piece(&self) -> crate::BufferPiece {
+        super::Buffer {
+            data: self.data,
+            size: self.size,
+        }
+        .at(0)
+    }
+}
+
+impl software_rt::BuildEncoder for super::PassEncoder<'_, ()> {
+    fn has_memory(piece: &crate::BufferPiece) -> bool {
+        !piece.buffer.data.is_null()
+    }
+
+    fn dispatch<D: crate::ShaderData>(
+        &mut self,
+        pipeline: &super::ComputePipeline,
+        data: &D,
+        groups: [u32; 3],
+    ) {
+        let mut pc = super::PipelineEncoder {
+            commands: self.commands,
+            group_layouts: &pipeline.layout.group_layouts,
+            topology: crate::PrimitiveTopology::default(),
+            vertex_fetch_count: 0,
+        };
+        pc.bind(0, data);
+        pc.dispatch(groups);
+    }
+
+    // Nothing is traversed, so the bottom level copies,
+    // which don't fit into the top level storage, are skipped.
+    fn copy_buffer(&mut self, src: crate::BufferPiece, _dst: crate::BufferPiece, size: u64) {
+        check_buffer_range(&src, size);
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::AccelerationStructureEncoder for super::PassEncoder<'_, ()> {
+    fn build_bottom_level(
+        &mut self,
+        acceleration_structure: super::AccelerationStructure,
+        meshes: &[crate::AccelerationStructureMesh],
+        scratch_data: crate::BufferPiece,
+    ) {
+        let required = software_rt::bottom_level_sizes(meshes);
+        assert!(acceleration_structure.size >= required.data);
+        check_buffer_range(&scratch_data, required.scratch);
+        for mesh in meshes {
+            check_buffer_range(&mesh.vertex_data, vertex_data_size(mesh));
+            check_buffer_range(&mesh.index_data, index_data_size(mesh));
+            if !mesh.transform_data.buffer.data.is_null() {
+                check_buffer_range(&mesh.transform_data, TRANSFORM_SIZE);
+            }
+        }
+        let pipelines = self.software_rt.get();
+        software_rt::build_bottom_level(
+            self,
+            &pipelines,
+            meshes,
+            acceleration_structure.piece(),
+            scratch_data,
+        );
+    }
+
+    fn build_top_level(
+        &mut self,
+        acceleration_structure: super::AccelerationStructure,
+        bottom_level: &[super::AccelerationStructure],
+        instance_count: u32,
+        instance_data: crate::BufferPiece,
+        scratch_data: crate::BufferPiece,
+    ) {
+        assert!(!bottom_level.is_empty() || instance_count == 0);
+        check_buffer_range(
+            &instance_data,
+            instance_count as u64 * software_rt::INSTANCE_DATA_SIZE,
+        );
+        let required = software_rt::top_level_sizes(instance_count);
+        assert!(acceleration_structure.size >= required.data);
+        check_buffer_range(&scratch_data, required.scratch);
+        let bottom_level = bottom_level
+            .iter()
+            .map(|blas| (blas.piece(), blas.size))
+            .collect::<Vec<_>>();
+        let sizes = bottom_level
+            .iter()
+            .map(|&(_, size)| size)
+            .collect::<Vec<_>>();
+        log::debug!(
+            "Building {:?} of {} bytes with {} instances",
+            acceleration_structure,
+            software_rt::top_level_size(instance_count, &sizes),
+            instance_count
+        );
+        let pipelines = self.software_rt.get();
+        software_rt::build_top_level(
+            self,
+            &pipelines,
+            &bottom_level,
+            instance_count,
+            instance_data,
+            acceleration_structure.piece(),
+            scratch_data,
+        );
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::PipelineEncoder for super::PipelineEncoder<'_> {
+    fn bind<D: crate::ShaderData>(&mut self, group: u32, data: &D) {
+        let layout = &self.group_layouts[group as usize];
+        assert_eq!(
+            D::layout(),
+            *layout,
+            "Data layout of group {} doesn't match the pipeline",
+            group
+        );
+        data.fill(super::PipelineContext { layout });
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::ComputePipelineEncoder for super::PipelineEncoder<'_> {
+    fn dispatch(&mut self, groups: [u32; 3]) {
+        self.commands.push(super::Command::Dispatch(groups));
+    }
+
+    fn dispatch_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        assert_eq!(
+            indirect_buf.offset % 4,
+            0,
+            "Indirect buffer offset {} is not aligned",
+            indirect_buf.offset
+        );
+        check_buffer_range(&indirect_buf, 12);
+        self.commands
+            .push(super::Command::DispatchIndirect { indirect_buf });
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_> {
+    fn set_scissor_rect(&mut self, _rect: &crate::ScissorRect) {}
+
+    fn set_stencil_reference(&mut self, _reference: u32) {}
+
+    fn bind_vertex_buffer(&mut self, index: u32, vertex_buf: crate::BufferPiece) {
+        assert!(
+            index < self.vertex_fetch_count,
+            "Vertex buffer {} is not fetched by the pipeline",
+            index
+        );
+        check_buffer_range(&vertex_buf, 0);
+    }
+
+    fn draw(
+        &mut self,
+        _first_vertex: u32,
+        vertex_count: u32,
+        _first_instance: u32,
+        instance_count: u32,
+    ) {
+        self.commands.push(super::Command::Draw {
+            topology: self.topology,
+            vertex_count,
+            instance_count,
+        });
+    }
+
+    fn draw_indexed(
+        &mut self,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        index_count: u32,
+        _base_vertex: i32,
+        _start_instance: u32,
+        instance_count: u32,
+    ) {
+        let index_size = match index_type {
+            crate::IndexType::U16 => 2,
+            crate::IndexType::U32 => 4,
+        };
+        check_buffer_range(&index_buf, index_count as u64 * index_size);
+        self.commands.push(super::Command::Draw {
+            topology: self.topology,
+            vertex_count: index_count,
+            instance_count,
+        });
+    }
+
+    fn draw_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.multi_draw_indirect(indirect_buf, 1, None);
+    }
+
+    fn draw_indexed_indirect(
+        &mut self,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
+    ) {
+        self.multi_draw_indexed_indirect(index_buf, index_type, indirect_buf, 1, None);
+    }
+
+    fn multi_draw_indirect(
+        &mut self,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<crate::BufferPiece>,
+    ) {
+        let stride = size_of::<crate::DrawIndirectArgs>() as u64;
+        check_buffer_range(&indirect_buf, max_draw_count as u64 * stride);
+        if let Some(ref count_buf) = count_buf {
+            check_buffer_range(count_buf, 4);
+        }
+        self.commands.push(super::Command::DrawIndirect {
+            topology: self.topology,
+            indirect_buf,
+            max_draw_count,
+            count_buf,
+        });
+    }
+
+    fn multi_draw_indexed_indirect(
+        &mut self,
+        _index_buf: crate::BufferPiece,
+        _index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<crate::BufferPiece>,
+    ) {
+        let stride = size_of::<crate::DrawIndexedIndirectArgs>() as u64;
+        check_buffer_range(&indirect_buf, max_draw_count as u64 * stride);
+        if let Some(ref count_buf) = count_buf {
+            check_buffer_range(count_buf, 4);
+        }
+        self.commands.push(super::Command::DrawIndirect {
+            topology: self.topology,
+            indirect_buf,
+            max_draw_count,
+            count_buf,
+        });
+    }
+}
@@ -0,0 +1,442 @@
+//! Headless backend that keeps all resources in host memory.
+//!
+//! Transfer operations are executed on the CPU at submission time,
+//! while compute and render work is only validated and recorded.
+
+use std::{
+    marker::PhantomData,
+    ptr,
+    sync::{Arc, Mutex},
+};
+
+mod command;
+mod pipeline;
+mod resource;
+
+const SAMPLE_COUNT_MASK: u32 = 0x1F;
+
+pub struct Context {
+    last_progress: Mutex<u64>,
+    timing: bool,
+    resource_counts: crate::util::ResourceCounts,
+    software_rt: Arc<crate::software_rt::SharedPipelines>,
+    /// Host texture standing in for the surface, recreated on every resize.
+    frame: Mutex<Texture>,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct Buffer {
+    data: *mut u8,
+    size: u64,
+}
+
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            size: 0,
+        }
+    }
+}
+
+impl Buffer {
+    pub fn data(&self) -> *mut u8 {
+        self.data
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct Texture {
+    data: *mut u8,
+    format: crate::TextureFormat,
+    size: crate::Extent,
+    array_layer_count: u32,
+    mip_level_count: u32,
+    sample_count: u32,
+}
+
+unsafe impl Send for Texture {}
+unsafe impl Sync for Texture {}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            format: crate::TextureFormat::Rgba8Unorm,
+            size: crate::Extent::default(),
+            array_layer_count: 0,
+            mip_level_count: 0,
+            sample_count: 1,
+        }
+    }
+}
+
+impl Texture {
+    /// Return the row pitch and the slice pitch of a mip level, in bytes.
+    fn pitches(&self, mip_level: u32) -> (usize, usize) {
+        let block_info = self.format.block_info();
+        let extent = self.size.at_mip_level(mip_level);
+        let (bw, bh) = (
+            block_info.dimensions.0 as u32,
+            block_info.dimensions.1 as u32,
+        );
+        let row_pitch = ((extent.width + bw - 1) / bw) as usize * block_info.size as usize;
+        let rows = ((extent.height + bh - 1) / bh) as usize;
+        (row_pitch, row_pitch * rows)
+    }
+
+    fn subresource_size(&self, mip_level: u32) -> usize {
+        let (_, slice_pitch) = self.pitches(mip_level);
+        slice_pitch * self.size.at_mip_level(mip_level).depth as usize
+    }
+
+    /// Return the offset of a subresource in the texture memory.
+    /// Mip levels are laid out one after another, each containing all the array layers.
+    fn subresource_offset(&self, mip_level: u32, array_layer: u32) -> usize {
+        let mut offset = 0;
+        for level in 0..mip_level {
+            offset += self.subresource_size(level) * self.array_layer_count as usize;
+        }
+        offset + self.subresource_size(mip_level) * array_layer as usize
+    }
+
+    fn total_size(&self) -> usize {
+        self.subresource_offset(self.mip_level_count, 0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq)]
+pub struct TextureView {
+    texture: Texture,
+    target_size: [u16; 2],
+    aspects: crate::TexelAspects,
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq)]
+pub struct Sampler {}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct AccelerationStructure {
+    data: *mut u8,
+    size: u64,
+}
+
+unsafe impl Send for AccelerationStructure {}
+unsafe impl Sync for AccelerationStructure {}
+
+impl Default for AccelerationStructure {
+    fn default() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            size: 0,
+        }
+    }
+}
+
+struct PipelineLayout {
+    group_layouts: Box<[crate::ShaderDataLayout]>,
+}
+
+pub struct ComputePipeline {
+    layout: PipelineLayout,
+    wg_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    pub fn get_workgroup_size(&self) -> [u32; 3] {
+        self.wg_size
+    }
+}
+
+/// Pipelines are not compiled, so there is nothing to cache.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct PipelineCache {}
+
+pub struct RenderPipeline {
+    layout: PipelineLayout,
+    topology: crate::PrimitiveTopology,
+    sample_count: u32,
+    vertex_fetch_count: u32,
+}
+
+pub struct Frame {
+    texture: Texture,
+}
+
+impl Frame {
+    pub fn texture(&self) -> Texture {
+        self.texture
+    }
+
+    pub fn texture_view(&self) -> TextureView {
+        TextureView {
+            texture: self.texture,
+            target_size: [
+                self.texture.size.width as u16,
+                self.texture.size.height as u16,
+            ],
+            aspects: crate::TexelAspects::COLOR,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Command {
+    FillBuffer {
+        dst: crate::BufferPiece,
+        size: u64,
+        value: u8,
+    },
+    CopyBufferToBuffer {
+        src: crate::BufferPiece,
+        dst: crate::BufferPiece,
+        size: u64,
+    },
+    CopyTextureToTexture {
+        src: crate::TexturePiece,
+        dst: crate::TexturePiece,
+        size: crate::Extent,
+    },
+    CopyBufferToTexture {
+        src: crate::BufferPiece,
+        bytes_per_row: u32,
+        dst: crate::TexturePiece,
+        size: crate::Extent,
+    },
+    CopyTextureToBuffer {
+        src: crate::TexturePiece,
+        dst: crate::BufferPiece,
+        bytes_per_row: u32,
+        size: crate::Extent,
+    },
+    Dispatch([u32; 3]),
+    DispatchIndirect {
+        indirect_buf: crate::BufferPiece,
+    },
+    Draw {
+        topology: crate::PrimitiveTopology,
+        vertex_count: u32,
+        instance_count: u32,
+    },
+    DrawIndirect {
+        topology: crate::PrimitiveTopology,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<crate::BufferPiece>,
+    },
+    PushDebugGroup(String),
+    PopDebugGroup,
+    InsertDebugMarker(String),
+}
+
+pub struct CommandEncoder {
+    name: String,
+    command_type: crate::CommandType,
+    commands: Vec<Command>,
+    timing: bool,
+    /// Labels of the timed passes, with the index of their first command.
+    timed_passes: Vec<(String, usize)>,
+    timings: crate::Timings,
+    software_rt: Arc<crate::software_rt::SharedPipelines>,
+}
+
+pub struct PassEncoder<'a, P> {
+    commands: &'a mut Vec<Command>,
+    sample_count: u32,
+    pipeline: PhantomData<P>,
+    software_rt: &'a crate::software_rt::SharedPipelines,
+}
+
+pub type ComputeCommandEncoder<'a> = PassEncoder<'a, ComputePipeline>;
+pub type RenderCommandEncoder<'a> = PassEncoder<'a, RenderPipeline>;
+
+pub struct PipelineEncoder<'a> {
+    commands: &'a mut Vec<Command>,
+    group_layouts: &'a [crate::ShaderDataLayout],
+    topology: crate::PrimitiveTopology,
+    vertex_fetch_count: u32,
+}
+
+pub struct RenderBundleEncoder {
+    commands: Vec<Command>,
+    sample_count: u32,
+}
+
+#[derive(Debug)]
+pub struct RenderBundle {
+    commands: Vec<Command>,
+    sample_count: u32,
+}
+
+pub struct PipelineContext<'a> {
+    layout: &'a crate::ShaderDataLayout,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncPoint {
+    progress: u64,
+}
+
+impl Context {
+    pub unsafe fn init(desc: crate::ContextDesc) -> Result<Self, crate::NotSupportedError> {
+        desc.adapter
+            .select(&[adapter_info()])
+            .ok_or(crate::NotSupportedError)?;
+        Ok(Self {
+            last_progress: Mutex::new(0),
+            timing: desc.timing,
+            resource_counts: Default::default(),
+            software_rt: Default::default(),
+            frame: Mutex::new(Texture::default()),
+        })
+    }
+
+    pub unsafe fn init_windowed<
+        I: raw_window_handle::HasRawWindowHandle + raw_window_handle::HasRawDisplayHandle,
+    >(
+        _window: &I,
+        _desc: crate::ContextDesc,
+    ) -> Result<Self, crate::NotSupportedError> {
+        Err(crate::NotSupportedError)
+    }
+
+    /// List the adapters suitable for initializing the context,
+    /// which is the only null one.
+    pub unsafe fn enumerate_adapters() -> Vec<crate::AdapterInfo> {
+        vec![adapter_info()]
+    }
+
+    pub fn capabilities(&self) -> crate::Capabilities {
+        make_capabilities()
+    }
+
+    pub fn limits(&self) -> crate::Limits {
+        make_limits()
+    }
+
+    /// Return the usages supported by textures of the given format.
+    pub fn format_features(&self, format: crate::TextureFormat) -> crate::TextureUsage {
+        format.base_features()
+    }
+
+    pub fn memory_report(&self) -> crate::MemoryReport {
+        self.resource_counts.report(Vec::new())
+    }
+
+    /// Allocate a host texture for the frames, since there is no surface to present to.
+    pub fn resize(&self, config: crate::SurfaceConfig) -> crate::TextureFormat {
+        let format = match config.color_space {
+            crate::ColorSpace::Linear => crate::TextureFormat::Rgba8UnormSrgb,
+            crate::ColorSpace::Srgb => crate::TextureFormat::Rgba8Unorm,
+            crate::ColorSpace::Hdr10 => crate::TextureFormat::Rgb10a2Unorm,
+        };
+        let mut frame = self.frame.lock().unwrap();
+        let old = std::mem::take(&mut *frame);
+        unsafe { resource::free(old.data, old.total_size()) };
+        *frame = Texture {
+            data: ptr::null_mut(),
+            format,
+            size: config.size,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+        };
+        match resource::allocate(frame.total_size() as u64) {
+            Ok(data) => frame.data = data,
+            Err(_) => {
+                log::error!("Unable to allocate the frame of {:?}", config.size);
+                *frame = Texture::default();
+            }
+        }
+        format
+    }
+
+    /// Return the frame texture, which is empty until the first resize.
+    pub fn acquire_frame(&self) -> Frame {
+        Frame {
+            texture: *self.frame.lock().unwrap(),
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        let frame = self.frame.get_mut().unwrap();
+        unsafe { resource::free(frame.data, frame.total_size()) };
+    }
+}
+
+fn make_capabilities() -> crate::Capabilities {
+    crate::Capabilities {
+        // Shaders are never executed, so any of them is accepted.
+        ray_query: crate::ShaderVisibility::all(),
+        sample_count_mask: SAMPLE_COUNT_MASK,
+        binding_arrays: true,
+        // Follow the software path, so that it's validated.
+        software_ray_query: true,
+        render_bundles: true,
+    }
+}
+
+fn make_limits() -> crate::Limits {
+    crate::Limits {
+        max_texture_dimension_2d: 16384,
+        max_texture_dimension_3d: 2048,
+        max_texture_array_layers: 2048,
+        max_workgroup_size: [1024, 1024, 64],
+        max_workgroup_invocations: 1024,
+        max_workgroup_count: [65535; 3],
+        subgroup_size: 0,
+        timestamp_period: 0.0,
+        // All the resources live in the host memory.
+        memory_heaps: Vec::new(),
+    }
+}
+
+fn adapter_info() -> crate::AdapterInfo {
+    crate::AdapterInfo {
+        name: "Null".to_string(),
+        vendor_id: 0,
+        device_type: crate::DeviceType::Other,
+        driver_version: 0,
+        capabilities: make_capabilities(),
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::CommandDevice for Context {
+    type CommandEncoder = CommandEncoder;
+    type SyncPoint = SyncPoint;
+
+    fn create_command_encoder(&self, desc: super::CommandEncoderDesc) -> CommandEncoder {
+        CommandEncoder {
+            name: desc.name.to_string(),
+            command_type: desc.command_type,
+            commands: Vec::new(),
+            timing: self.timing,
+            timed_passes: Vec::new(),
+            timings: Vec::new(),
+            software_rt: Arc::clone(&self.software_rt),
+        }
+    }
+
+    fn destroy_command_encoder(&self, _command_encoder: CommandEncoder) {}
+
+    fn submit(&self, encoder: &mut CommandEncoder) -> SyncPoint {
+        log::trace!("Submitting '{}'", encoder.name);
+        encoder.execute();
+        let mut last_progress = self.last_progress.lock().unwrap();
+        *last_progress += 1;
+        SyncPoint {
+            progress: *last_progress,
+        }
+    }
+
+    fn wait_for(&self, sp: &SyncPoint, _timeout_ms: u32) -> bool {
+        // Everything is executed at submission time.
+        sp.progress <= *self.last_progress.lock().unwrap()
+    }
+}
@@ -0,0 +1,221 @@
+fn check_shader_bindings(sf: crate::ShaderFunction, group_layouts: &[&crate::ShaderDataLayout]) {
+    let ep_index = sf.entry_point_index();
+    let ep_info = sf.shader.info.get_entry_point(ep_index);
+    let module = &sf.shader.module;
+
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(module.to_ctx()).unwrap();
+
+    for (handle, var) in module.global_variables.iter() {
+        if ep_info[handle].is_empty() {
+            continue;
+        }
+        let var_access = match var.space {
+            naga::AddressSpace::Storage { access } => access,
+            naga::AddressSpace::Uniform | naga::AddressSpace::Handle => {
+                naga::StorageAccess::empty()
+            }
+            _ => continue,
+        };
+
+        assert_eq!(var.binding, None);
+        let var_name = var.name.as_ref().unwrap();
+        let proto_binding = group_layouts
+            .iter()
+            .find_map(|layout| {
+                layout
+                    .bindings
+                    .iter()
+                    .find(|&&(name, _)| name == var_name)
+                    .map(|&(_, binding)| binding)
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "Unable to resolve binding for '{}' in the entry point '{}'",
+                    var_name, sf.entry_point,
+                )
+            });
+
+        let expected_proto = match module.types[var.ty].inner {
+            _ if sf.shader.acceleration_structures.contains(&handle) => {
+                crate::ShaderBinding::AccelerationStructure
+            }
+            naga::TypeInner::Image { .. } => crate::ShaderBinding::Texture,
+            naga::TypeInner::Sampler { .. } => crate::ShaderBinding::Sampler,
+            naga::TypeInner::AccelerationStructure => crate::ShaderBinding::AccelerationStructure,
+            naga::TypeInner::BindingArray { base, size: _ } => {
+                let count = match proto_binding {
+                    crate::ShaderBinding::TextureArray { count } => count,
+                    crate::ShaderBinding::BufferArray { count } => count,
+                    _ => 0,
+                };
+                match module.types[base].inner {
+                    naga::TypeInner::Image { .. } => crate::ShaderBinding::TextureArray { count },
+                    naga::TypeInner::Struct { .. } => crate::ShaderBinding::BufferArray { count },
+                    ref other => panic!("Unsupported binding array for {:?}", other),
+                }
+            }
+            _ => {
+                if var_access.is_empty() {
+                    let size = layouter[var.ty].size;
+                    assert!(
+                        size <= crate::limits::PLAIN_DATA_SIZE,
+                        "Plain data of '{}' is too large: {} bytes",
+                        var_name,
+                        size
+                    );
+                    crate::ShaderBinding::Plain { size }
+                } else {
+                    crate::ShaderBinding::Buffer
+                }
+            }
+        };
+        assert_eq!(
+            proto_binding, expected_proto,
+            "Mismatched type for binding '{}'",
+            var_name
+        );
+    }
+}
+
+/// Return the scalar kind seen by the shader, and the size in bytes.
+fn describe_vertex_format(format: crate::VertexFormat) -> (naga::ScalarKind, u32) {
+    use crate::VertexFormat as Vf;
+    use naga::ScalarKind as Sk;
+    match format {
+        Vf::F32 => (Sk::Float, 4),
+        Vf::F32Vec2 => (Sk::Float, 8),
+        Vf::F32Vec3 => (Sk::Float, 12),
+        Vf::F32Vec4 => (Sk::Float, 16),
+        Vf::U32 => (Sk::Uint, 4),
+        Vf::U32Vec2 => (Sk::Uint, 8),
+        Vf::U32Vec3 => (Sk::Uint, 12),
+        Vf::U32Vec4 => (Sk::Uint, 16),
+        Vf::I32 => (Sk::Sint, 4),
+        Vf::I32Vec2 => (Sk::Sint, 8),
+        Vf::I32Vec3 => (Sk::Sint, 12),
+        Vf::I32Vec4 => (Sk::Sint, 16),
+        Vf::U8Vec4Norm | Vf::I8Vec4Norm => (Sk::Float, 4),
+        Vf::U16Vec2Norm | Vf::I16Vec2Norm => (Sk::Float, 4),
+        Vf::U16Vec4Norm | Vf::I16Vec4Norm => (Sk::Float, 8),
+    }
+}
+
+fn check_vertex_inputs(sf: crate::ShaderFunction, fetches: &[crate::VertexFetchState]) {
+    for fetch in fetches {
+        for at in fetch.layout.attributes.iter() {
+            let (_, size) = describe_vertex_format(at.format);
+            assert!(
+                at.offset + size <= fetch.layout.stride,
+                "Vertex attribute at offset {} doesn't fit into the stride {}",
+                at.offset,
+                fetch.layout.stride
+            );
+        }
+    }
+    let attributes = fetches
+        .iter()
+        .flat_map(|fetch| fetch.layout.attributes.iter())
+        .collect::<Vec<_>>();
+
+    let module = &sf.shader.module;
+    let ep = &module.entry_points[sf.entry_point_index()];
+    let mut inputs = Vec::new();
+    for arg in ep.function.arguments.iter() {
+        match module.types[arg.ty].inner {
+            naga::TypeInner::Struct { ref members, .. } => {
+                inputs.extend(members.iter().map(|m| (m.binding.as_ref(), m.ty)));
+            }
+            _ => inputs.push((arg.binding.as_ref(), arg.ty)),
+        }
+    }
+
+    for (binding, ty) in inputs {
+        let location = match binding {
+            Some(&naga::Binding::Location { location, .. }) => location,
+            _ => continue,
+        };
+        let attribute = attributes.get(location as usize).unwrap_or_else(|| {
+            panic!(
+                "Vertex attribute for location {} is missing in the entry point '{}'",
+                location, sf.entry_point
+            )
+        });
+        let shader_kind = match module.types[ty].inner {
+            naga::TypeInner::Scalar { kind, .. } | naga::TypeInner::Vector { kind, .. } => kind,
+            ref other => panic!("Unexpected vertex input type {:?}", other),
+        };
+        let (kind, _) = describe_vertex_format(attribute.format);
+        assert_eq!(
+            kind, shader_kind,
+            "Mismatched vertex format for location {}",
+            location
+        );
+    }
+}
+
+impl super::Context {
+    fn create_pipeline_layout(
+        &self,
+        shaders: &[crate::ShaderFunction],
+        group_layouts: &[&crate::ShaderDataLayout],
+    ) -> super::PipelineLayout {
+        for &sf in shaders {
+            check_shader_bindings(sf, group_layouts);
+        }
+        super::PipelineLayout {
+            group_layouts: group_layouts.iter().map(|&layout| layout.clone()).collect(),
+        }
+    }
+
+    pub fn create_pipeline_cache(&self, _desc: crate::PipelineCacheDesc) -> super::PipelineCache {
+        super::PipelineCache {}
+    }
+
+    pub fn get_pipeline_cache_data(&self, _cache: &super::PipelineCache) -> Vec<u8> {
+        Vec::new()
+    }
+
+    pub fn destroy_pipeline_cache(&self, _cache: super::PipelineCache) {}
+
+    pub fn try_create_compute_pipeline(
+        &self,
+        desc: crate::ComputePipelineDesc,
+    ) -> Result<super::ComputePipeline, crate::Error> {
+        crate::shader::check_overrides(desc.name, &[desc.compute], desc.overrides)?;
+        let ep_index = desc.compute.entry_point_index();
+        let resolved = desc.compute.shader.resolve_overrides(desc.overrides)?;
+        let wg_size = resolved.module().entry_points[ep_index].workgroup_size;
+        let layout = self.create_pipeline_layout(&[desc.compute], desc.data_layouts);
+        log::debug!("Created compute pipeline '{}'", desc.name);
+        Ok(super::ComputePipeline { layout, wg_size })
+    }
+
+    pub fn try_create_render_pipeline(
+        &self,
+        desc: crate::RenderPipelineDesc,
+    ) -> Result<super::RenderPipeline, crate::Error> {
+        let sample_count = desc.multisample_state.sample_count;
+        if sample_count & super::SAMPLE_COUNT_MASK == 0 {
+            log::error!(
+                "Render pipeline '{}' has unsupported sample count {}",
+                desc.name,
+                sample_count
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+        crate::shader::check_overrides(desc.name, &[desc.vertex, desc.fragment], desc.overrides)?;
+        for sf in [desc.vertex, desc.fragment] {
+            sf.shader.resolve_overrides(desc.overrides)?;
+        }
+        check_vertex_inputs(desc.vertex, desc.vertex_fetches);
+        let layout = self.create_pipeline_layout(&[desc.vertex, desc.fragment], desc.data_layouts);
+        log::debug!("Created render pipeline '{}'", desc.name);
+        Ok(super::RenderPipeline {
+            layout,
+            topology: desc.primitive.topology,
+            sample_count,
+            vertex_fetch_count: desc.vertex_fetches.len() as u32,
+        })
+    }
+}
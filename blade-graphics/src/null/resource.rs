@@ -0,0 +1,200 @@
+use crate::software_rt;
+use std::ptr;
+
+pub(super) fn allocate(size: u64) -> Result<*mut u8, crate::Error> {
+    let size = usize::try_from(size).map_err(|_| crate::Error::OutOfMemory)?;
+    let mut data = Vec::new();
+    data.try_reserve_exact(size)
+        .map_err(|_| crate::Error::OutOfMemory)?;
+    data.resize(size, 0u8);
+    Ok(Box::leak(data.into_boxed_slice()).as_mut_ptr())
+}
+
+pub(super) unsafe fn free(data: *mut u8, size: usize) {
+    if !data.is_null() {
+        drop(Vec::from_raw_parts(data, size, size));
+    }
+}
+
+impl super::Context {
+    pub fn get_bottom_level_acceleration_structure_sizes(
+        &self,
+        meshes: &[crate::AccelerationStructureMesh],
+    ) -> crate::AccelerationStructureSizes {
+        software_rt::bottom_level_sizes(meshes)
+    }
+
+    pub fn get_top_level_acceleration_structure_sizes(
+        &self,
+        instance_count: u32,
+    ) -> crate::AccelerationStructureSizes {
+        software_rt::top_level_sizes(instance_count)
+    }
+
+    pub fn get_top_level_acceleration_structure_sizes_for(
+        &self,
+        instance_count: u32,
+        bottom_level: &[super::AccelerationStructure],
+    ) -> crate::AccelerationStructureSizes {
+        let sizes = bottom_level
+            .iter()
+            .map(|blas| blas.size)
+            .collect::<Vec<_>>();
+        software_rt::top_level_sizes_with_copies(instance_count, &sizes)
+    }
+
+    pub fn create_acceleration_structure_instance_buffer(
+        &self,
+        instances: &[crate::AccelerationStructureInstance],
+        bottom_level: &[super::AccelerationStructure],
+    ) -> super::Buffer {
+        let sizes = bottom_level
+            .iter()
+            .map(|blas| blas.size)
+            .collect::<Vec<_>>();
+        let data = software_rt::instance_data(instances, &sizes);
+        let buffer = self.create_buffer(crate::BufferDesc {
+            name: "instances",
+            size: instances.len() as u64 * software_rt::INSTANCE_DATA_SIZE,
+            memory: crate::Memory::Shared,
+        });
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), buffer.data, data.len());
+        }
+        buffer
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::ResourceDevice for super::Context {
+    type Buffer = super::Buffer;
+    type Texture = super::Texture;
+    type TextureView = super::TextureView;
+    type Sampler = super::Sampler;
+    type AccelerationStructure = super::AccelerationStructure;
+
+    fn try_create_buffer(&self, desc: crate::BufferDesc) -> Result<super::Buffer, crate::Error> {
+        log::trace!("Creating buffer '{}' of size {}", desc.name, desc.size);
+        let data = allocate(desc.size)?;
+        self.resource_counts.buffers.increment();
+        Ok(super::Buffer {
+            data,
+            size: desc.size,
+        })
+    }
+
+    fn sync_buffer(&self, _buffer: super::Buffer) {}
+
+    fn destroy_buffer(&self, buffer: super::Buffer) {
+        unsafe { free(buffer.data, buffer.size as usize) };
+        self.resource_counts.buffers.decrement();
+    }
+
+    fn try_create_texture(&self, desc: crate::TextureDesc) -> Result<super::Texture, crate::Error> {
+        desc.validate(&super::make_limits(), self.format_features(desc.format))?;
+        if desc.sample_count & super::SAMPLE_COUNT_MASK == 0 {
+            return Err(crate::Error::InvalidDescriptor);
+        }
+
+        let mut texture = super::Texture {
+            data: ptr::null_mut(),
+            format: desc.format,
+            size: desc.size,
+            array_layer_count: desc.array_layer_count,
+            mip_level_count: desc.mip_level_count,
+            sample_count: desc.sample_count,
+        };
+        texture.data = allocate(texture.total_size() as u64)?;
+        self.resource_counts.textures.increment();
+        Ok(texture)
+    }
+
+    fn destroy_texture(&self, texture: super::Texture) {
+        unsafe { free(texture.data, texture.total_size()) };
+        self.resource_counts.textures.decrement();
+    }
+
+    fn try_create_texture_view(
+        &self,
+        desc: crate::TextureViewDesc,
+    ) -> Result<super::TextureView, crate::Error> {
+        let texture = desc.texture;
+        let subresources = desc.subresources;
+        let mip_level_end = subresources
+            .mip_level_count
+            .map_or(texture.mip_level_count, |c| {
+                subresources.base_mip_level + c.get()
+            });
+        let array_layer_end = subresources
+            .array_layer_count
+            .map_or(texture.array_layer_count, |c| {
+                subresources.base_array_layer + c.get()
+            });
+        if subresources.base_mip_level >= mip_level_end
+            || subresources.base_array_layer >= array_layer_end
+            || mip_level_end > texture.mip_level_count
+            || array_layer_end > texture.array_layer_count
+        {
+            log::error!("View '{}' subresources are out of bounds", desc.name);
+            return Err(crate::Error::InvalidDescriptor);
+        }
+        if desc.format.block_info().size != texture.format.block_info().size {
+            log::error!(
+                "View '{}' format is incompatible with the texture",
+                desc.name
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+
+        let aspects = desc.format.select_aspects(subresources.aspects);
+        if aspects.is_empty() {
+            log::error!(
+                "View '{}' aspects {:?} are not in the format",
+                desc.name,
+                subresources.aspects
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+
+        let target_size = texture.size.at_mip_level(subresources.base_mip_level);
+        Ok(super::TextureView {
+            texture,
+            target_size: [target_size.width as _, target_size.height as _],
+            aspects,
+        })
+    }
+
+    fn destroy_texture_view(&self, _view: super::TextureView) {}
+
+    fn try_create_sampler(
+        &self,
+        _desc: crate::SamplerDesc,
+    ) -> Result<super::Sampler, crate::Error> {
+        Ok(super::Sampler {})
+    }
+
+    fn destroy_sampler(&self, _sampler: super::Sampler) {}
+
+    fn try_create_acceleration_structure(
+        &self,
+        desc: crate::AccelerationStructureDesc,
+    ) -> Result<super::AccelerationStructure, crate::Error> {
+        self.software_rt.init(self);
+        let data = allocate(desc.size)?;
+        self.resource_counts.acceleration_structures.increment();
+        Ok(super::AccelerationStructure {
+            data,
+            size: desc.size,
+        })
+    }
+
+    fn destroy_acceleration_structure(&self, acceleration_structure: super::AccelerationStructure) {
+        unsafe {
+            free(
+                acceleration_structure.data,
+                acceleration_structure.size as usize,
+            )
+        };
+        self.resource_counts.acceleration_structures.decrement();
+    }
+}
@@ -0,0 +1,139 @@
+use std::ptr;
+
+//Note: backends differ in how they expect the buffer rows of texture copies
+// to be aligned, so the staging rows are padded to satisfy all of them.
+const ROW_PITCH_ALIGNMENT: u32 = 256;
+
+/// Return the size of a tightly packed row of texel blocks, and the number of rows.
+fn block_layout(
+    format: super::TextureFormat,
+    aspects: super::TexelAspects,
+    extent: super::Extent,
+) -> (u32, u32) {
+    let block_info = format.aspect_block_info(aspects);
+    let block_width = block_info.dimensions.0 as u32;
+    let block_height = block_info.dimensions.1 as u32;
+    let row_size = (extent.width + block_width - 1) / block_width * block_info.size as u32;
+    let row_count = (extent.height + block_height - 1) / block_height;
+    (row_size, row_count)
+}
+
+impl super::Context {
+    /// Record a copy of the texture region into a staging buffer.
+    ///
+    /// The texels can be read once the encoder is submitted,
+    /// using the returned request together with the obtained sync point.
+    pub fn request_readback(
+        &self,
+        encoder: &mut super::CommandEncoder,
+        src: super::TexturePiece,
+        format: super::TextureFormat,
+        extent: super::Extent,
+    ) -> super::ReadbackRequest {
+        let (row_size, row_count) = block_layout(format, src.aspects, extent);
+        let bytes_per_row =
+            (row_size + ROW_PITCH_ALIGNMENT - 1) / ROW_PITCH_ALIGNMENT * ROW_PITCH_ALIGNMENT;
+        let slice_size = bytes_per_row as u64 * row_count as u64;
+        let buffer = self.create_buffer(super::BufferDesc {
+            name: "readback",
+            size: slice_size * extent.depth as u64,
+            memory: super::Memory::Shared,
+        });
+
+        // Copy slice by slice, since not all backends support the image pitch
+        let mut transfer = encoder.transfer_labeled("readback");
+        for slice in 0..extent.depth {
+            transfer.copy_texture_to_buffer(
+                super::TexturePiece {
+                    origin: [src.origin[0], src.origin[1], src.origin[2] + slice],
+                    ..src
+                },
+                buffer.at(slice as u64 * slice_size),
+                bytes_per_row,
+                super::Extent { depth: 1, ..extent },
+            );
+        }
+
+        super::ReadbackRequest {
+            buffer,
+            format,
+            aspects: src.aspects,
+            extent,
+            bytes_per_row,
+        }
+    }
+
+    /// Read the texture region back to the host, blocking until it's done.
+    ///
+    /// See `ReadbackRequest::finish` for the layout of the result.
+    pub fn read_texture(
+        &self,
+        src: super::TexturePiece,
+        format: super::TextureFormat,
+        extent: super::Extent,
+    ) -> Vec<u8> {
+        let mut encoder = self.create_command_encoder(super::CommandEncoderDesc {
+            name: "readback",
+            command_type: super::CommandType::General,
+            buffer_count: 1,
+        });
+        encoder.start();
+        let request = self.request_readback(&mut encoder, src, format, extent);
+        let sync_point = self.submit(&mut encoder);
+        let data = request.finish(self, &sync_point);
+        self.destroy_command_encoder(encoder);
+        data
+    }
+}
+
+impl super::ReadbackRequest {
+    pub fn format(&self) -> super::TextureFormat {
+        self.format
+    }
+
+    pub fn extent(&self) -> super::Extent {
+        self.extent
+    }
+
+    /// Return the texels if the submission has already finished,
+    /// or the request itself otherwise.
+    pub fn try_finish(
+        self,
+        context: &super::Context,
+        sync_point: &super::SyncPoint,
+    ) -> Result<Vec<u8>, Self> {
+        if context.wait_for(sync_point, 0) {
+            Ok(self.unpack(context))
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Wait for the submission to finish and return the texels.
+    ///
+    /// Rows of texel blocks, as described by `TextureFormat::aspect_block_info`,
+    /// are tightly packed, and depth slices follow each other.
+    pub fn finish(self, context: &super::Context, sync_point: &super::SyncPoint) -> Vec<u8> {
+        let done = context.wait_for(sync_point, !0);
+        assert!(done, "Readback submission is not finished");
+        self.unpack(context)
+    }
+
+    fn unpack(self, context: &super::Context) -> Vec<u8> {
+        let (row_size, row_count) = block_layout(self.format, self.aspects, self.extent);
+        let total_rows = (row_count * self.extent.depth) as usize;
+        let mut data = vec![0u8; row_size as usize * total_rows];
+        let base = self.buffer.data();
+        for row in 0..total_rows {
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    base.add(row * self.bytes_per_row as usize),
+                    data.as_mut_ptr().add(row * row_size as usize),
+                    row_size as usize,
+                );
+            }
+        }
+        context.destroy_buffer(self.buffer);
+        data
+    }
+}
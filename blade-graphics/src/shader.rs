@@ -1,3 +1,223 @@
+use std::ops::Range;
+
+/// Constant that can be overridden at pipeline creation.
+///
+/// Naga doesn't parse `override` declarations yet, so they are declared
+/// as constants, and the shader is lowered again with the values of each
+/// pipeline. This keeps them immutable, and usable in any constant
+/// expression, such as the workgroup and array sizes.
+pub(super) struct ShaderOverride {
+    name: String,
+    /// Numeric identifier from the `@id` attribute.
+    id: Option<u16>,
+    /// Range of the value in the source with the constant declarations.
+    value: Range<usize>,
+    kind: naga::ScalarKind,
+}
+
+impl ShaderOverride {
+    /// Check if the override is set by a pipeline value,
+    /// which refers to it by name or by the identifier.
+    fn matches(&self, key: &str) -> bool {
+        self.name == key || (self.id.is_some() && key.parse().ok() == self.id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Ident,
+    Symbol(u8),
+    Other,
+}
+
+/// Split the source into identifiers and symbols, skipping the whitespace and comments.
+fn tokenize(source: &str) -> Vec<(Token, Range<usize>)> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if bytes[i..].starts_with(b"/*") {
+            let mut depth = 0;
+            while i < bytes.len() {
+                if bytes[i..].starts_with(b"/*") {
+                    depth += 1;
+                    i += 2;
+                } else if bytes[i..].starts_with(b"*/") {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident, start..i));
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            tokens.push((Token::Other, start..i));
+        } else {
+            i += 1;
+            tokens.push((Token::Symbol(c), start..i));
+        }
+    }
+    tokens
+}
+
+struct OverrideDecl<'a> {
+    name: &'a str,
+    id: Option<u16>,
+    value: Range<usize>,
+}
+
+/// Rewrite the `override` declarations into constants, returning the new source
+/// and the declarations, with the ranges of their values in it.
+fn declare_overrides(source: &str) -> Result<(String, Vec<OverrideDecl<'_>>), String> {
+    let tokens = tokenize(source);
+    let text = |index: usize| &source[tokens[index].1.clone()];
+    let is_token = |index: usize, token: Token| tokens.get(index).map(|t| t.0) == Some(token);
+    let is_ident = |index: usize, name: &str| is_token(index, Token::Ident) && text(index) == name;
+    // Source of the tokens in the range, unless it's empty.
+    let span = |range: Range<usize>| {
+        if range.is_empty() {
+            None
+        } else {
+            Some(&source[tokens[range.start].1.start..tokens[range.end - 1].1.end])
+        }
+    };
+
+    let mut expanded = String::with_capacity(source.len());
+    let mut offset = 0;
+    let mut decls = Vec::new();
+    let mut depth = 0;
+    for (index, &(token, ref range)) in tokens.iter().enumerate() {
+        match token {
+            Token::Symbol(b'{') => depth += 1,
+            Token::Symbol(b'}') => depth -= 1,
+            Token::Ident if depth == 0 && text(index) == "override" => {
+                let mut start = range.start;
+                let mut id = None;
+                if index >= 5
+                    && is_token(index - 1, Token::Symbol(b')'))
+                    && is_token(index - 3, Token::Symbol(b'('))
+                    && is_ident(index - 4, "id")
+                    && is_token(index - 5, Token::Symbol(b'@'))
+                {
+                    start = tokens[index - 5].1.start;
+                    let value = text(index - 2).trim_end_matches(['i', 'u']);
+                    id = Some(
+                        value
+                            .parse::<u16>()
+                            .map_err(|_| format!("Override identifier '{value}' is invalid"))?,
+                    );
+                }
+                if !is_token(index + 1, Token::Ident) {
+                    return Err("Override declaration is missing a name".to_string());
+                }
+                let name = text(index + 1);
+                let end = (index + 2..tokens.len())
+                    .find(|&i| is_token(i, Token::Symbol(b';')))
+                    .ok_or_else(|| format!("Override '{name}' is missing a semicolon"))?;
+                let assign = (index + 2..end).find(|&i| is_token(i, Token::Symbol(b'=')));
+                let ty = if is_token(index + 2, Token::Symbol(b':')) {
+                    let ty = span(index + 3..assign.unwrap_or(end))
+                        .ok_or_else(|| format!("Override '{name}' is missing a type"))?;
+                    Some(ty)
+                } else {
+                    None
+                };
+                let default = match assign {
+                    Some(i) => Some(
+                        span(i + 1..end)
+                            .ok_or_else(|| format!("Override '{name}' is missing a value"))?,
+                    ),
+                    None => None,
+                };
+                expanded += &source[offset..start];
+                expanded += "const ";
+                expanded += name;
+                if let Some(ty) = ty {
+                    expanded += ": ";
+                    expanded += ty;
+                }
+                expanded += " = ";
+                let value_start = expanded.len();
+                // Overrides without a default are zero unless set by the pipeline
+                match (default, ty) {
+                    (Some(value), _) => expanded += value,
+                    (None, Some(ty)) => {
+                        expanded += ty;
+                        expanded += "()";
+                    }
+                    (None, None) => {
+                        return Err(format!("Override '{name}' needs a type or a value"));
+                    }
+                }
+                decls.push(OverrideDecl {
+                    name,
+                    id,
+                    value: value_start..expanded.len(),
+                });
+                offset = tokens[end].1.start;
+            }
+            _ => {}
+        }
+    }
+    expanded += &source[offset..];
+    Ok((expanded, decls))
+}
+
+/// Source of a constant value for an override of the given kind.
+fn override_value(kind: naga::ScalarKind, value: f64) -> Option<String> {
+    let is_integer = value.fract() == 0.0;
+    Some(match kind {
+        naga::ScalarKind::Bool => (value != 0.0).to_string(),
+        naga::ScalarKind::Sint
+            if is_integer && value >= i32::MIN as f64 && value <= i32::MAX as f64 =>
+        {
+            match value as i32 {
+                // The literal of the magnitude doesn't fit
+                i32::MIN => "(-2147483647i - 1i)".to_string(),
+                v if v < 0 => format!("-{}i", -v),
+                v => format!("{}i", v),
+            }
+        }
+        naga::ScalarKind::Uint if is_integer && value >= 0.0 && value <= u32::MAX as f64 => {
+            format!("{}u", value as u32)
+        }
+        naga::ScalarKind::Float if (value as f32).is_finite() => format!("{:?}f", value as f32),
+        _ => return None,
+    })
+}
+
+/// Replace the source ranges with new text.
+fn apply_replacements(source: &str, mut replacements: Vec<(Range<usize>, String)>) -> String {
+    replacements.sort_by_key(|replacement| replacement.0.start);
+    let mut expanded = String::with_capacity(source.len());
+    let mut offset = 0;
+    for (range, text) in replacements {
+        expanded += &source[offset..range.start];
+        expanded += &text;
+        offset = range.end;
+    }
+    expanded += &source[offset..];
+    expanded
+}
+
 impl From<naga::ShaderStage> for super::ShaderVisibility {
     fn from(stage: naga::ShaderStage) -> Self {
         match stage {
@@ -8,34 +228,115 @@ impl From<naga::ShaderStage> for super::ShaderVisibility {
     }
 }
 
+/// Settings to lower the shaders with, which are kept for
+/// lowering them again with the override values.
+#[derive(Clone, Copy)]
+pub(super) struct LowerOptions {
+    #[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+    software_ray_query: bool,
+    validation_caps: naga::valid::Capabilities,
+}
+
+/// Module lowered from the shader source, its validation info,
+/// and the acceleration structures turned into storage buffers.
+type LoweredModule = (
+    naga::Module,
+    naga::valid::ModuleInfo,
+    Vec<naga::Handle<naga::GlobalVariable>>,
+);
+
+fn lower(source: &str, options: LowerOptions) -> Result<LoweredModule, &'static str> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| {
+        e.emit_to_stderr_with_path(source, "");
+        "compilation failed"
+    })?;
+    #[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+    let (module, acceleration_structures) =
+        if options.software_ray_query && crate::software_rt::has_ray_queries(&module) {
+            crate::software_rt::lower_ray_queries(source, &module).map_err(|e| {
+                log::error!("Ray query lowering: {}", e);
+                "ray query lowering failed"
+            })?
+        } else {
+            (module, Vec::new())
+        };
+    #[cfg(not(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos")))))]
+    let acceleration_structures = Vec::new();
+
+    // Bindings are set up at pipeline creation, ignore here
+    let flags = naga::valid::ValidationFlags::all() ^ naga::valid::ValidationFlags::BINDINGS;
+    let info = naga::valid::Validator::new(flags, options.validation_caps)
+        .validate(&module)
+        .map_err(|e| {
+            crate::util::emit_annotated_error(&e, "", source);
+            crate::util::print_err(&e);
+            "validation failed"
+        })?;
+    Ok((module, info, acceleration_structures))
+}
+
 impl super::Context {
     pub fn try_create_shader(
         &self,
         desc: super::ShaderDesc,
     ) -> Result<super::Shader, &'static str> {
-        let module = naga::front::wgsl::parse_str(desc.source).map_err(|e| {
-            e.emit_to_stderr_with_path(desc.source, "");
-            "compilation failed"
-        })?;
-
         let device_caps = self.capabilities();
-
-        // Bindings are set up at pipeline creation, ignore here
-        let flags = naga::valid::ValidationFlags::all() ^ naga::valid::ValidationFlags::BINDINGS;
-        let mut caps = naga::valid::Capabilities::empty();
-        caps.set(
+        let mut validation_caps = naga::valid::Capabilities::empty();
+        validation_caps.set(
             naga::valid::Capabilities::RAY_QUERY | naga::valid::Capabilities::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
             !device_caps.ray_query.is_empty(),
         );
-        let info = naga::valid::Validator::new(flags, caps)
-            .validate(&module)
-            .map_err(|e| {
-                crate::util::emit_annotated_error(&e, "", desc.source);
-                crate::util::print_err(&e);
-                "validation failed"
-            })?;
+        let options = LowerOptions {
+            #[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+            software_ray_query: device_caps.software_ray_query,
+            validation_caps,
+        };
+        let (source, decls) = declare_overrides(desc.source).map_err(|e| {
+            log::error!("Shader overrides: {}", e);
+            "override declaration failed"
+        })?;
+        let lowered = lower(&source, options)?;
 
-        Ok(super::Shader { module, info })
+        let mut overrides = Vec::with_capacity(decls.len());
+        for decl in decls {
+            let (_, constant) = lowered
+                .0
+                .constants
+                .iter()
+                .find(|&(_, c)| c.name.as_deref() == Some(decl.name))
+                .ok_or("override is not found")?;
+            let kind = match lowered.0.types[constant.ty].inner {
+                naga::TypeInner::Scalar { kind, width }
+                    if kind == naga::ScalarKind::Bool || width == 4 =>
+                {
+                    kind
+                }
+                ref other => {
+                    log::error!("Override '{}' has unsupported type {:?}", decl.name, other);
+                    return Err("override type is not supported");
+                }
+            };
+            overrides.push(ShaderOverride {
+                name: decl.name.to_string(),
+                id: decl.id,
+                value: decl.value,
+                kind,
+            });
+        }
+
+        Ok(super::Shader {
+            module: lowered.0,
+            info: lowered.1,
+            source: if overrides.is_empty() {
+                String::new()
+            } else {
+                source
+            },
+            overrides,
+            lower_options: options,
+            #[cfg(any(vulkan, gles, null, not(any(target_os = "ios", target_os = "macos"))))]
+            acceleration_structures: lowered.2,
+        })
     }
 
     pub fn create_shader(&self, desc: super::ShaderDesc) -> super::Shader {
@@ -43,7 +344,87 @@ impl super::Context {
     }
 }
 
+/// Check that every override is declared by one of the pipeline shaders.
+pub(super) fn check_overrides(
+    pipeline_name: &str,
+    functions: &[super::ShaderFunction],
+    overrides: &[(&str, f64)],
+) -> Result<(), super::Error> {
+    for &(name, _) in overrides {
+        if !functions
+            .iter()
+            .any(|sf| sf.shader.overrides.iter().any(|so| so.matches(name)))
+        {
+            log::error!(
+                "Override '{}' of pipeline '{}' is not declared in the shaders",
+                name,
+                pipeline_name
+            );
+            return Err(super::Error::InvalidDescriptor);
+        }
+    }
+    Ok(())
+}
+
+/// Module of a shader with the override values of a pipeline.
+pub(super) struct ResolvedModule<'a> {
+    shader: &'a super::Shader,
+    lowered: Option<LoweredModule>,
+}
+
+impl ResolvedModule<'_> {
+    pub(super) fn module(&self) -> &naga::Module {
+        self.lowered.as_ref().map_or(&self.shader.module, |l| &l.0)
+    }
+
+    #[cfg_attr(null, allow(dead_code))]
+    pub(super) fn info(&self) -> &naga::valid::ModuleInfo {
+        self.lowered.as_ref().map_or(&self.shader.info, |l| &l.1)
+    }
+}
+
 impl super::Shader {
+    /// Lower the shader with the override values, unless none of them are
+    /// declared by this shader. The later values of the same override win.
+    pub(super) fn resolve_overrides(
+        &self,
+        overrides: &[(&str, f64)],
+    ) -> Result<ResolvedModule<'_>, super::Error> {
+        let mut replacements = Vec::new();
+        for so in self.overrides.iter() {
+            let value = match overrides.iter().rev().find(|&&(key, _)| so.matches(key)) {
+                Some(&(_, value)) => value,
+                None => continue,
+            };
+            let text = override_value(so.kind, value).ok_or_else(|| {
+                log::error!(
+                    "Value {} of override '{}' doesn't fit {:?}",
+                    value,
+                    so.name,
+                    so.kind
+                );
+                super::Error::InvalidDescriptor
+            })?;
+            replacements.push((so.value.clone(), text));
+        }
+        if replacements.is_empty() {
+            return Ok(ResolvedModule {
+                shader: self,
+                lowered: None,
+            });
+        }
+
+        let source = apply_replacements(&self.source, replacements);
+        let lowered = lower(&source, self.lower_options).map_err(|e| {
+            log::error!("Shader with the override values: {}", e);
+            super::Error::InvalidDescriptor
+        })?;
+        Ok(ResolvedModule {
+            shader: self,
+            lowered: Some(lowered),
+        })
+    }
+
     pub fn at<'a>(&'a self, entry_point: &'a str) -> super::ShaderFunction<'a> {
         super::ShaderFunction {
             shader: self,
@@ -51,7 +432,7 @@ impl super::Shader {
         }
     }
 
-    pub fn get_struct_size(&self, struct_name: &str) -> u32 {
+    fn find_struct(&self, struct_name: &str) -> (&[naga::StructMember], u32) {
         match self
             .module
             .types
@@ -59,13 +440,17 @@ impl super::Shader {
             .find(|&(_, ty)| ty.name.as_deref() == Some(struct_name))
         {
             Some((_, ty)) => match ty.inner {
-                naga::TypeInner::Struct { members: _, span } => span,
+                naga::TypeInner::Struct { ref members, span } => (members, span),
                 _ => panic!("Type '{struct_name}' is not a struct in the shader"),
             },
             None => panic!("Struct '{struct_name}' is not found in the shader"),
         }
     }
 
+    pub fn get_struct_size(&self, struct_name: &str) -> u32 {
+        self.find_struct(struct_name).1
+    }
+
     pub fn check_struct_size<T>(&self) {
         use std::{any::type_name, mem::size_of};
         let name = type_name::<T>().rsplit("::").next().unwrap();
@@ -75,4 +460,70 @@ impl super::Shader {
             "Host struct '{name}' size doesn't match the shader"
         );
     }
+
+    /// Check the offsets and types of all the members of a host structure
+    /// against the structure of the same name in the shader.
+    /// Host members that the shader doesn't have are considered padding.
+    pub fn check_struct_layout<T: super::ShaderStruct>(&self) {
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(self.module.to_ctx()).unwrap();
+        self.check_struct(&layouter, &T::layout());
+    }
+
+    fn check_struct(&self, layouter: &naga::proc::Layouter, layout: &super::ShaderStructLayout) {
+        let name = layout.name;
+        let (members, span) = self.find_struct(name);
+        assert_eq!(
+            layout.size, span,
+            "Host struct '{name}' size doesn't match the shader"
+        );
+        for member in members {
+            let member_name = member.name.as_deref().unwrap_or_default();
+            let host = match layout.members.iter().find(|m| m.name == member_name) {
+                Some(host) => host,
+                None => panic!("Member '{name}::{member_name}' is missing on the host"),
+            };
+            assert_eq!(
+                host.offset, member.offset,
+                "Member '{name}::{member_name}' offset doesn't match the shader"
+            );
+            assert_eq!(
+                host.size, layouter[member.ty].size,
+                "Member '{name}::{member_name}' size doesn't match the shader"
+            );
+            match host.ty {
+                super::ShaderMemberType::Plain(kind) => {
+                    assert_eq!(
+                        Some(kind),
+                        scalar_kind(&self.module.types, member.ty),
+                        "Member '{name}::{member_name}' type doesn't match the shader"
+                    );
+                }
+                super::ShaderMemberType::Struct(nested_layout) => {
+                    let nested = nested_layout();
+                    assert_eq!(
+                        Some(nested.name),
+                        self.module.types[member.ty].name.as_deref(),
+                        "Member '{name}::{member_name}' type doesn't match the shader"
+                    );
+                    self.check_struct(layouter, &nested);
+                }
+            }
+        }
+    }
+}
+
+/// Kind of the scalars that make up a type, unless it's opaque or a structure.
+fn scalar_kind(
+    types: &naga::UniqueArena<naga::Type>,
+    ty: naga::Handle<naga::Type>,
+) -> Option<naga::ScalarKind> {
+    match types[ty].inner {
+        naga::TypeInner::Scalar { kind, .. }
+        | naga::TypeInner::Vector { kind, .. }
+        | naga::TypeInner::Atomic { kind, .. } => Some(kind),
+        naga::TypeInner::Matrix { .. } => Some(naga::ScalarKind::Float),
+        naga::TypeInner::Array { base, .. } => scalar_kind(types, base),
+        _ => None,
+    }
 }
@@ -0,0 +1,332 @@
+// Building of the acceleration structures traversed by `query.wgsl`,
+// with the layout described in `mod.rs`. The primitives are sorted along
+// a Morton curve and grouped into the leaves of a complete binary tree,
+// which is then completed level by level, from the bottom.
+//
+// Scratch vectors, with `n` primitives of `s` vectors each:
+//   - bounds of the primitive centers: 2 vectors
+//   - primitives, in the original order: `n * s` vectors
+//   - bounds of the primitives: `2 * n` vectors
+//   - sort keys, `(code, primitive)`, padded to a power of two
+
+const LEAF_SIZE: u32 = 4u;
+const TRIANGLE_SIZE: u32 = 3u;
+const INSTANCE_SIZE: u32 = 7u;
+const GROUP_SIZE: u32 = 64u;
+const REDUCE_SIZE: u32 = 256u;
+const NO_TRANSFORM: u32 = 0xFFFFFFFFu;
+const NO_KEY: u32 = 0xFFFFFFFFu;
+const BIG: f32 = 3.4028235e38;
+
+struct MeshParams {
+    // All in 4-byte words, except for the index offset
+    vertex_offset: u32,
+    vertex_stride: u32,
+    vertex_components: u32,
+    index_offset: u32,
+    index_size: u32,
+    transform_offset: u32,
+    triangle_count: u32,
+    first_primitive: u32,
+    primitive_count: u32,
+    geometry_index: u32,
+    is_opaque: u32,
+    pad: u32,
+}
+
+struct BuildParams {
+    primitive_count: u32,
+    primitive_size: u32,
+    leaf_count: u32,
+    sort_size: u32,
+    sort_block: u32,
+    sort_distance: u32,
+    level_first: u32,
+    level_count: u32,
+    // Start of the bottom level copies, in the top level structure
+    copies_start: u32,
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
+}
+
+var<storage, read> vertices: array<u32>;
+var<storage, read> indices: array<u32>;
+var<storage, read> transforms: array<u32>;
+var<storage, read> instances: array<vec4<u32>>;
+var<storage, read_write> scratch: array<vec4<u32>>;
+var<storage, read_write> data: array<vec4<u32>>;
+var<uniform> mesh: MeshParams;
+var<uniform> build: BuildParams;
+
+// Large dispatches are split across the second dimension.
+fn item_index(global_id: vec3<u32>, group_count: vec3<u32>) -> u32 {
+    return global_id.x + global_id.y * group_count.x * GROUP_SIZE;
+}
+
+fn bounds_start(primitive_count: u32, primitive_size: u32) -> u32 {
+    return 2u + primitive_count * primitive_size;
+}
+
+fn keys_start() -> u32 {
+    return bounds_start(build.primitive_count, build.primitive_size) + 2u * build.primitive_count;
+}
+
+fn node_count() -> u32 {
+    return 2u * build.leaf_count - 1u;
+}
+
+fn to_vector(xyz: vec3<f32>, w: u32) -> vec4<u32> {
+    return vec4<u32>(bitcast<vec3<u32>>(xyz), w);
+}
+
+fn to_point(v: vec4<u32>) -> vec3<f32> {
+    return bitcast<vec3<f32>>(v.xyz);
+}
+
+fn fetch_index(position: u32) -> u32 {
+    switch mesh.index_size {
+        case 2u: {
+            let byte = mesh.index_offset + position * 2u;
+            return (indices[byte / 4u] >> ((byte & 2u) * 8u)) & 0xFFFFu;
+        }
+        case 4u: {
+            return indices[mesh.index_offset / 4u + position];
+        }
+        default: {
+            return position;
+        }
+    }
+}
+
+fn load_transform_row(row: u32) -> vec4<f32> {
+    let offset = mesh.transform_offset + row * 4u;
+    return bitcast<vec4<f32>>(vec4<u32>(
+        transforms[offset],
+        transforms[offset + 1u],
+        transforms[offset + 2u],
+        transforms[offset + 3u],
+    ));
+}
+
+fn load_position(index: u32) -> vec3<f32> {
+    let offset = mesh.vertex_offset + index * mesh.vertex_stride;
+    var position = vec3<f32>(bitcast<f32>(vertices[offset]), bitcast<f32>(vertices[offset + 1u]), 0.0);
+    if (mesh.vertex_components >= 3u) {
+        position.z = bitcast<f32>(vertices[offset + 2u]);
+    }
+    if (mesh.transform_offset != NO_TRANSFORM) {
+        let transform = mat3x4<f32>(load_transform_row(0u), load_transform_row(1u), load_transform_row(2u));
+        position = vec4<f32>(position, 1.0) * transform;
+    }
+    return position;
+}
+
+@compute @workgroup_size(64)
+fn prepare_triangles(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let triangle = item_index(global_id, group_count);
+    if (triangle >= mesh.triangle_count) {
+        return;
+    }
+    var positions: array<vec3<f32>, 3>;
+    var lo = vec3<f32>(BIG);
+    var hi = vec3<f32>(-BIG);
+    for (var corner = 0u; corner < 3u; corner += 1u) {
+        let position = load_position(fetch_index(triangle * 3u + corner));
+        positions[corner] = position;
+        lo = min(lo, position);
+        hi = max(hi, position);
+    }
+    let primitive = mesh.first_primitive + triangle;
+    let base = 2u + primitive * TRIANGLE_SIZE;
+    scratch[base] = to_vector(positions[0], triangle);
+    scratch[base + 1u] = to_vector(positions[1], mesh.geometry_index);
+    scratch[base + 2u] = to_vector(positions[2], mesh.is_opaque);
+    let bounds = bounds_start(mesh.primitive_count, TRIANGLE_SIZE) + primitive * 2u;
+    scratch[bounds] = to_vector(lo, 0u);
+    scratch[bounds + 1u] = to_vector(hi, 0u);
+}
+
+// Instances are given by the records of `instance_data`:
+// 3 rows of the transform, and `(custom index, mask, bottom level offset, 0)`.
+@compute @workgroup_size(64)
+fn prepare_instances(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let instance = item_index(global_id, group_count);
+    if (instance >= build.primitive_count) {
+        return;
+    }
+    let r0 = bitcast<vec4<f32>>(instances[instance * 4u]);
+    let r1 = bitcast<vec4<f32>>(instances[instance * 4u + 1u]);
+    let r2 = bitcast<vec4<f32>>(instances[instance * 4u + 2u]);
+    let info = instances[instance * 4u + 3u];
+    let object_to_world = mat3x4<f32>(r0, r1, r2);
+
+    // The columns of the inverse are the cross products of the rows.
+    let bc = cross(r1.xyz, r2.xyz);
+    let ca = cross(r2.xyz, r0.xyz);
+    let ab = cross(r0.xyz, r1.xyz);
+    let det = dot(r0.xyz, bc);
+    let inverse = mat3x3<f32>(bc, ca, ab) * (1.0 / det);
+    let translation = -(inverse * vec3<f32>(r0.w, r1.w, r2.w));
+    let inverse_rows = transpose(inverse);
+
+    let blas = build.copies_start + info.z;
+    let header = data[blas];
+    var mask = info.y & 0xFFu;
+    var lo = vec3<f32>(BIG);
+    var hi = vec3<f32>(-BIG);
+    // Empty structures and degenerate transforms can't be hit,
+    // so the instance is left with empty bounds.
+    if (header.y == 0u || !(abs(det) > 0.0 && abs(det) <= BIG)) {
+        mask = 0u;
+    } else {
+        let root_min = to_point(data[blas + 1u]);
+        let root_max = to_point(data[blas + 2u]);
+        for (var corner = 0u; corner < 8u; corner += 1u) {
+            let select_max = (vec3<u32>(corner) & vec3<u32>(1u, 2u, 4u)) != vec3<u32>(0u);
+            let point = vec4<f32>(select(root_min, root_max, select_max), 1.0) * object_to_world;
+            lo = min(lo, point);
+            hi = max(hi, point);
+        }
+    }
+
+    let base = 2u + instance * INSTANCE_SIZE;
+    scratch[base] = bitcast<vec4<u32>>(r0);
+    scratch[base + 1u] = bitcast<vec4<u32>>(r1);
+    scratch[base + 2u] = bitcast<vec4<u32>>(r2);
+    scratch[base + 3u] = to_vector(inverse_rows[0], bitcast<u32>(translation.x));
+    scratch[base + 4u] = to_vector(inverse_rows[1], bitcast<u32>(translation.y));
+    scratch[base + 5u] = to_vector(inverse_rows[2], bitcast<u32>(translation.z));
+    scratch[base + 6u] = vec4<u32>(info.x & 0xFFFFFFu, mask, blas, instance);
+    let bounds = bounds_start(build.primitive_count, INSTANCE_SIZE) + instance * 2u;
+    scratch[bounds] = to_vector(lo, 0u);
+    scratch[bounds + 1u] = to_vector(hi, 0u);
+}
+
+var<workgroup> reduce_min: array<vec3<f32>, REDUCE_SIZE>;
+var<workgroup> reduce_max: array<vec3<f32>, REDUCE_SIZE>;
+
+// Dispatched as a single workgroup.
+@compute @workgroup_size(256)
+fn center_bounds(@builtin(local_invocation_index) local_index: u32) {
+    let bounds = bounds_start(build.primitive_count, build.primitive_size);
+    var lo = vec3<f32>(BIG);
+    var hi = vec3<f32>(-BIG);
+    for (var i = local_index; i < build.primitive_count; i += REDUCE_SIZE) {
+        let primitive_min = to_point(scratch[bounds + 2u * i]);
+        let primitive_max = to_point(scratch[bounds + 2u * i + 1u]);
+        if (all(primitive_min <= primitive_max)) {
+            let center = 0.5 * (primitive_min + primitive_max);
+            lo = min(lo, center);
+            hi = max(hi, center);
+        }
+    }
+    reduce_min[local_index] = lo;
+    reduce_max[local_index] = hi;
+    for (var stride = REDUCE_SIZE / 2u; stride > 0u; stride = stride / 2u) {
+        workgroupBarrier();
+        if (local_index < stride) {
+            reduce_min[local_index] = min(reduce_min[local_index], reduce_min[local_index + stride]);
+            reduce_max[local_index] = max(reduce_max[local_index], reduce_max[local_index + stride]);
+        }
+    }
+    if (local_index == 0u) {
+        scratch[0] = to_vector(reduce_min[0], 0u);
+        scratch[1] = to_vector(reduce_max[0], 0u);
+    }
+}
+
+// Spread the lower 10 bits, leaving 2 zero bits between each.
+fn expand_bits(value: u32) -> u32 {
+    var x = value & 0x3FFu;
+    x = (x | (x << 16u)) & 0x030000FFu;
+    x = (x | (x << 8u)) & 0x0300F00Fu;
+    x = (x | (x << 4u)) & 0x030C30C3u;
+    x = (x | (x << 2u)) & 0x09249249u;
+    return x;
+}
+
+@compute @workgroup_size(64)
+fn compute_keys(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let index = item_index(global_id, group_count);
+    if (index >= build.sort_size) {
+        return;
+    }
+    // The padding goes after all the primitives.
+    var code = NO_KEY;
+    if (index < build.primitive_count) {
+        let bounds = bounds_start(build.primitive_count, build.primitive_size) + 2u * index;
+        let center = 0.5 * (to_point(scratch[bounds]) + to_point(scratch[bounds + 1u]));
+        let scene_min = to_point(scratch[0]);
+        let extent = max(to_point(scratch[1]) - scene_min, vec3<f32>(1e-30));
+        let cell = vec3<u32>(clamp((center - scene_min) / extent, vec3<f32>(0.0), vec3<f32>(1.0)) * 1023.0);
+        code = (expand_bits(cell.x) << 2u) | (expand_bits(cell.y) << 1u) | expand_bits(cell.z);
+    }
+    scratch[keys_start() + index] = vec4<u32>(code, index, 0u, 0u);
+}
+
+// One step of the bitonic sort, comparing the keys at the given distance
+// within the blocks of the given size, which alternate the direction.
+@compute @workgroup_size(64)
+fn sort_keys(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let pair = item_index(global_id, group_count);
+    if (pair >= build.sort_size / 2u) {
+        return;
+    }
+    let distance = build.sort_distance;
+    let first = 2u * distance * (pair / distance) + pair % distance;
+    let keys = keys_start();
+    let a = scratch[keys + first];
+    let b = scratch[keys + first + distance];
+    let ascending = (first & build.sort_block) == 0u;
+    if ((a.x > b.x) == ascending && a.x != b.x) {
+        scratch[keys + first] = b;
+        scratch[keys + first + distance] = a;
+    }
+}
+
+@compute @workgroup_size(64)
+fn build_leaves(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let leaf = item_index(global_id, group_count);
+    if (leaf >= build.leaf_count) {
+        return;
+    }
+    if (leaf == 0u) {
+        data[0] = vec4<u32>(node_count(), build.primitive_count, build.leaf_count, 0u);
+    }
+    let size = build.primitive_size;
+    let first = leaf * LEAF_SIZE;
+    let count = min(build.primitive_count - min(first, build.primitive_count), LEAF_SIZE);
+    let primitives = 1u + 2u * node_count();
+    let bounds = bounds_start(build.primitive_count, size);
+    let keys = keys_start();
+    var lo = vec3<f32>(BIG);
+    var hi = vec3<f32>(-BIG);
+    for (var i = 0u; i < count; i += 1u) {
+        let sorted = first + i;
+        let primitive = scratch[keys + sorted].y;
+        for (var j = 0u; j < size; j += 1u) {
+            data[primitives + sorted * size + j] = scratch[2u + primitive * size + j];
+        }
+        lo = min(lo, to_point(scratch[bounds + 2u * primitive]));
+        hi = max(hi, to_point(scratch[bounds + 2u * primitive + 1u]));
+    }
+    let node = build.leaf_count - 1u + leaf;
+    data[1u + 2u * node] = to_vector(lo, primitives + first * size);
+    data[2u + 2u * node] = to_vector(hi, count);
+}
+
+@compute @workgroup_size(64)
+fn build_nodes(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) group_count: vec3<u32>) {
+    let index = item_index(global_id, group_count);
+    if (index >= build.level_count) {
+        return;
+    }
+    let node = build.level_first + index;
+    let left = 1u + 2u * (2u * node + 1u);
+    let right = left + 2u;
+    let lo = min(to_point(data[left]), to_point(data[right]));
+    let hi = max(to_point(data[left + 1u]), to_point(data[right + 1u]));
+    data[1u + 2u * node] = to_vector(lo, 0u);
+    data[2u + 2u * node] = to_vector(hi, 0u);
+}
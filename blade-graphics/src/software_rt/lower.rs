@@ -0,0 +1,280 @@
+//! Lowering of the ray queries into the traversal of `query.wgsl`.
+//!
+//! The shader is parsed again together with the traversal functions.
+//! The acceleration structures become storage buffers of vectors, known by
+//! their index, and the ray queries become structures that the built-ins
+//! pass to the corresponding traversal functions.
+
+use crate::lowering;
+use naga::{Expression as E, Handle, Statement as S};
+use std::collections::{HashMap, HashSet};
+
+const QUERY_SOURCE: &str = include_str!("query.wgsl");
+
+pub(crate) fn has_ray_queries(module: &naga::Module) -> bool {
+    module.types.iter().any(|(_, ty)| {
+        matches!(
+            ty.inner,
+            naga::TypeInner::AccelerationStructure | naga::TypeInner::RayQuery
+        )
+    })
+}
+
+/// Functions and types of the traversal.
+struct Traversal {
+    ray_query: Handle<naga::Type>,
+    initialize: Handle<naga::Function>,
+    proceed: Handle<naga::Function>,
+    get_committed_intersection: Handle<naga::Function>,
+}
+
+/// Lower the ray queries of a parsed shader, given its source.
+/// Returns the new module and the acceleration structure globals,
+/// in the order of their indices.
+pub(crate) fn lower(
+    source: &str,
+    shader_module: &naga::Module,
+) -> Result<(naga::Module, Vec<Handle<naga::GlobalVariable>>), String> {
+    // The traversal goes after the shader, so that the locations are unchanged.
+    let full_source = format!("{}\n{}", source, QUERY_SOURCE);
+    let mut module_with_traversal =
+        naga::front::wgsl::parse_str(&full_source).map_err(|e| e.emit_to_string(&full_source))?;
+    let module = &mut module_with_traversal;
+    let shader_functions = shader_module
+        .functions
+        .iter()
+        .map(|(_, fun)| fun.name.as_deref())
+        .collect::<HashSet<_>>();
+    let traversal_functions = module
+        .functions
+        .iter()
+        .filter(|&(_, fun)| !shader_functions.contains(&fun.name.as_deref()))
+        .map(|(handle, _)| handle)
+        .collect::<HashSet<_>>();
+    let find_function = |name: &str| {
+        module
+            .functions
+            .iter()
+            .find(|&(_, fun)| fun.name.as_deref() == Some(name))
+            .map(|(handle, _)| handle)
+            .ok_or_else(|| format!("Ray query function '{}' is not found", name))
+    };
+    let traversal = Traversal {
+        ray_query: module
+            .types
+            .iter()
+            .find(|&(_, ty)| ty.name.as_deref() == Some("BladeRayQuery"))
+            .map(|(handle, _)| handle)
+            .ok_or("Ray query structure is not found")?,
+        initialize: find_function("blade_ray_query_initialize")?,
+        proceed: find_function("blade_ray_query_proceed")?,
+        get_committed_intersection: find_function("blade_ray_query_get_committed_intersection")?,
+    };
+    let loader = find_function("blade_load_acceleration_structure")?;
+
+    let vector_ty = module.types.insert(
+        naga::Type {
+            name: None,
+            inner: naga::TypeInner::Vector {
+                size: naga::VectorSize::Quad,
+                kind: naga::ScalarKind::Float,
+                width: 4,
+            },
+        },
+        naga::Span::UNDEFINED,
+    );
+    let storage_ty = module.types.insert(
+        naga::Type {
+            name: None,
+            inner: naga::TypeInner::Array {
+                base: vector_ty,
+                size: naga::ArraySize::Dynamic,
+                stride: 16,
+            },
+        },
+        naga::Span::UNDEFINED,
+    );
+    let mut acceleration_structures = Vec::new();
+    for (handle, var) in module.global_variables.iter_mut() {
+        if let naga::TypeInner::AccelerationStructure = module.types[var.ty].inner {
+            var.ty = storage_ty;
+            var.space = naga::AddressSpace::Storage {
+                access: naga::StorageAccess::LOAD,
+            };
+            acceleration_structures.push(handle);
+        }
+    }
+    if !acceleration_structures.is_empty() {
+        let fun = &mut module.functions[loader];
+        fun.expressions = naga::Arena::new();
+        fun.named_expressions.clear();
+        fun.body = naga::Block::new();
+        fill_loader(fun, &acceleration_structures);
+    }
+
+    for (handle, fun) in module.functions.iter_mut() {
+        if !traversal_functions.contains(&handle) {
+            lower_function(fun, &mut module.types, &traversal, &acceleration_structures)?;
+        }
+    }
+    for ep in module.entry_points.iter_mut() {
+        lower_function(
+            &mut ep.function,
+            &mut module.types,
+            &traversal,
+            &acceleration_structures,
+        )?;
+    }
+    lowering::move_functions_first(module, |handle| traversal_functions.contains(&handle));
+    // Drop the ray query types, which the backends would still declare.
+    // Global variables are kept, so their handles are unchanged.
+    naga::compact::compact(module);
+
+    Ok((module_with_traversal, acceleration_structures))
+}
+
+/// Switch over the acceleration structures in the loader,
+/// returning the vector at the offset.
+fn fill_loader(fun: &mut naga::Function, globals: &[Handle<naga::GlobalVariable>]) {
+    let index = fun
+        .expressions
+        .append(E::FunctionArgument(0), naga::Span::UNDEFINED);
+    let offset = fun
+        .expressions
+        .append(E::FunctionArgument(1), naga::Span::UNDEFINED);
+    let cases = globals
+        .iter()
+        .enumerate()
+        .map(|(position, &var)| {
+            let base = fun
+                .expressions
+                .append(E::GlobalVariable(var), naga::Span::UNDEFINED);
+            let pointer = fun.expressions.append(
+                E::Access {
+                    base,
+                    index: offset,
+                },
+                naga::Span::UNDEFINED,
+            );
+            let value = fun
+                .expressions
+                .append(E::Load { pointer }, naga::Span::UNDEFINED);
+            let mut body = naga::Block::new();
+            body.push(
+                S::Emit(naga::Range::new_from_bounds(pointer, value)),
+                naga::Span::UNDEFINED,
+            );
+            body.push(S::Return { value: Some(value) }, naga::Span::UNDEFINED);
+            naga::SwitchCase {
+                value: if position + 1 == globals.len() {
+                    naga::SwitchValue::Default
+                } else {
+                    naga::SwitchValue::U32(position as u32)
+                },
+                body,
+                fall_through: false,
+            }
+        })
+        .collect();
+    fun.body.push(
+        S::Switch {
+            selector: index,
+            cases,
+        },
+        naga::Span::UNDEFINED,
+    );
+}
+
+fn lower_function(
+    fun: &mut naga::Function,
+    types: &mut naga::UniqueArena<naga::Type>,
+    traversal: &Traversal,
+    acceleration_structures: &[Handle<naga::GlobalVariable>],
+) -> Result<(), String> {
+    for (_, var) in fun.local_variables.iter_mut() {
+        if let naga::TypeInner::RayQuery = types[var.ty].inner {
+            var.ty = traversal.ray_query;
+        }
+    }
+    for argument in fun.arguments.iter_mut() {
+        if let naga::TypeInner::Pointer { base, space } = types[argument.ty].inner {
+            if let naga::TypeInner::RayQuery = types[base].inner {
+                let inner = naga::TypeInner::Pointer {
+                    base: traversal.ray_query,
+                    space,
+                };
+                argument.ty = types.insert(naga::Type { name: None, inner }, naga::Span::UNDEFINED);
+            }
+        }
+    }
+
+    // Acceleration structures are passed to the initialization by index
+    let mut indices = HashMap::new();
+    let mut call_arguments = HashMap::new();
+    let mut new_expressions = Vec::new();
+    for (handle, expr) in fun.expressions.iter_mut() {
+        match *expr {
+            E::GlobalVariable(var) => {
+                if let Some(position) = acceleration_structures.iter().position(|&h| h == var) {
+                    new_expressions.push((handle, position as u32));
+                }
+            }
+            E::RayQueryProceedResult => *expr = E::CallResult(traversal.proceed),
+            E::RayQueryGetIntersection {
+                query,
+                committed: true,
+            } => {
+                *expr = E::CallResult(traversal.get_committed_intersection);
+                call_arguments.insert(handle, vec![query]);
+            }
+            E::RayQueryGetIntersection {
+                committed: false, ..
+            } => return Err("Candidate intersections are not supported".to_string()),
+            _ => {}
+        }
+    }
+    for (handle, position) in new_expressions {
+        let literal = E::Literal(naga::Literal::U32(position));
+        let index = fun.expressions.append(literal, naga::Span::UNDEFINED);
+        indices.insert(handle, index);
+    }
+
+    let mut error = None;
+    lowering::rewrite_block(
+        &mut fun.body,
+        &fun.expressions,
+        &call_arguments,
+        &mut |statement| {
+            let (query, ray_query_fun) = match *statement {
+                S::RayQuery { query, ref fun } => (query, fun),
+                _ => return,
+            };
+            *statement = match *ray_query_fun {
+                naga::RayQueryFunction::Initialize {
+                    acceleration_structure,
+                    descriptor,
+                } => match indices.get(&acceleration_structure) {
+                    Some(&index) => S::Call {
+                        function: traversal.initialize,
+                        arguments: vec![query, index, descriptor],
+                        result: None,
+                    },
+                    None => {
+                        error = Some("Ray queries need to be initialized with an acceleration structure global");
+                        return;
+                    }
+                },
+                naga::RayQueryFunction::Proceed { result } => S::Call {
+                    function: traversal.proceed,
+                    arguments: vec![query],
+                    result: Some(result),
+                },
+                naga::RayQueryFunction::Terminate => {
+                    error = Some("Ray query termination is not supported");
+                    return;
+                }
+            };
+        },
+    );
+    error.map_or(Ok(()), |e| Err(e.to_string()))
+}
@@ -0,0 +1,491 @@
+//! Acceleration structures for the backends without hardware ray queries.
+//!
+//! The structures are built by compute shaders (see `build.wgsl`) into flat arrays
+//! of 4-component vectors, which the lowered shaders traverse from a storage buffer
+//! (see `query.wgsl`). All indices are in vectors, relative to the start of the structure:
+//!   - header: number of nodes, number of primitives, number of leaves
+//!   - nodes of a complete binary tree, 2 vectors each: `(min, first primitive)`, `(max, primitive count)`.
+//!     The children of node `i` are `2i + 1` and `2i + 2`, and the leaves are the last nodes.
+//!   - primitives: triangles for the bottom level, instances for the top level
+//!   - top level only: copies of the bottom level structures, in the order given to the build
+mod lower;
+
+pub(crate) use lower::{has_ray_queries, lower as lower_ray_queries};
+
+use std::{
+    mem::size_of,
+    sync::{Arc, Mutex},
+};
+
+const BUILD_SOURCE: &str = include_str!("build.wgsl");
+
+pub(crate) type Vector = [u32; 4];
+
+pub(crate) const VECTOR_SIZE: u64 = size_of::<Vector>() as u64;
+/// Size of an instance record, see `instance_data`.
+pub(crate) const INSTANCE_DATA_SIZE: u64 = size_of::<InstanceRecord>() as u64;
+
+const LEAF_SIZE: u32 = 4;
+const TRIANGLE_SIZE: u32 = 3;
+const INSTANCE_SIZE: u32 = 7;
+const GROUP_SIZE: u32 = 64;
+const MAX_GROUPS: u32 = 1 << 15;
+const NO_TRANSFORM: u32 = !0;
+
+type InstanceRecord = [Vector; 4];
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Zeroable, bytemuck::Pod)]
+struct MeshParams {
+    vertex_offset: u32,
+    vertex_stride: u32,
+    vertex_components: u32,
+    index_offset: u32,
+    index_size: u32,
+    transform_offset: u32,
+    triangle_count: u32,
+    first_primitive: u32,
+    primitive_count: u32,
+    geometry_index: u32,
+    is_opaque: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Zeroable, bytemuck::Pod)]
+struct BuildParams {
+    primitive_count: u32,
+    primitive_size: u32,
+    leaf_count: u32,
+    sort_size: u32,
+    sort_block: u32,
+    sort_distance: u32,
+    level_first: u32,
+    level_count: u32,
+    copies_start: u32,
+    pad: [u32; 3],
+}
+
+impl BuildParams {
+    fn new(primitive_count: u32, primitive_size: u32) -> Self {
+        let leaf_count = ((primitive_count + LEAF_SIZE - 1) / LEAF_SIZE).max(1);
+        Self {
+            primitive_count,
+            primitive_size,
+            leaf_count: leaf_count.next_power_of_two(),
+            sort_size: primitive_count.next_power_of_two(),
+            ..Default::default()
+        }
+    }
+
+    fn data_len(&self) -> u64 {
+        let node_count = 2 * self.leaf_count as u64 - 1;
+        1 + 2 * node_count + self.primitive_count as u64 * self.primitive_size as u64
+    }
+
+    fn scratch_len(&self) -> u64 {
+        let primitive_count = self.primitive_count as u64;
+        2 + primitive_count * (self.primitive_size as u64 + 2) + self.sort_size as u64
+    }
+
+    fn sizes(&self) -> crate::AccelerationStructureSizes {
+        crate::AccelerationStructureSizes {
+            data: self.data_len() * VECTOR_SIZE,
+            scratch: self.scratch_len() * VECTOR_SIZE,
+        }
+    }
+}
+
+pub(crate) fn bottom_level_sizes(
+    meshes: &[crate::AccelerationStructureMesh],
+) -> crate::AccelerationStructureSizes {
+    let triangle_count = meshes.iter().map(|mesh| mesh.triangle_count).sum();
+    BuildParams::new(triangle_count, TRIANGLE_SIZE).sizes()
+}
+
+/// The copies of the bottom level structures are not included,
+/// so the top level storage is expected to grow when built.
+pub(crate) fn top_level_sizes(instance_count: u32) -> crate::AccelerationStructureSizes {
+    BuildParams::new(instance_count, INSTANCE_SIZE).sizes()
+}
+
+/// Sizes of a top level structure with the copies of the bottom level structures.
+pub(crate) fn top_level_sizes_with_copies(
+    instance_count: u32,
+    bottom_level_sizes: &[u64],
+) -> crate::AccelerationStructureSizes {
+    crate::AccelerationStructureSizes {
+        data: top_level_size(instance_count, bottom_level_sizes),
+        ..top_level_sizes(instance_count)
+    }
+}
+
+/// Number of vectors taken by the copy of a bottom level structure of the given size.
+fn copy_len(size: u64) -> u64 {
+    (size + VECTOR_SIZE - 1) / VECTOR_SIZE
+}
+
+/// Size in bytes taken by the copy of a bottom level structure of the given size.
+pub(crate) fn copy_size(size: u64) -> u64 {
+    copy_len(size) * VECTOR_SIZE
+}
+
+/// Size of a top level structure with the copies of the bottom level structures.
+pub(crate) fn top_level_size(instance_count: u32, bottom_level_sizes: &[u64]) -> u64 {
+    let copies_len = bottom_level_sizes
+        .iter()
+        .map(|&size| copy_len(size))
+        .sum::<u64>();
+    (BuildParams::new(instance_count, INSTANCE_SIZE).data_len() + copies_len) * VECTOR_SIZE
+}
+
+/// Encode the instance records: 3 rows of the transform, and
+/// `(custom index, mask, bottom level offset, 0)`, where the offset
+/// is in the copies of the bottom level structures, in the given order.
+pub(crate) fn instance_data(
+    instances: &[crate::AccelerationStructureInstance],
+    bottom_level_sizes: &[u64],
+) -> Vec<u8> {
+    let mut offsets = Vec::with_capacity(bottom_level_sizes.len());
+    let mut offset = 0;
+    for &size in bottom_level_sizes {
+        offsets.push(offset as u32);
+        offset += copy_len(size);
+    }
+    let records = instances
+        .iter()
+        .map(|instance| {
+            let index = instance.acceleration_structure_index as usize;
+            assert!(
+                index < offsets.len(),
+                "Instance references an out of bounds acceleration structure {}",
+                index
+            );
+            let transform = &instance.transform;
+            let rows: [[f32; 4]; 3] = [transform.x.into(), transform.y.into(), transform.z.into()];
+            let [r0, r1, r2] = rows.map(|row| row.map(f32::to_bits));
+            [
+                r0,
+                r1,
+                r2,
+                [instance.custom_index, instance.mask, offsets[index], 0],
+            ]
+        })
+        .collect::<Vec<InstanceRecord>>();
+    bytemuck::cast_slice(&records).to_vec()
+}
+
+pub(crate) fn index_size(mesh: &crate::AccelerationStructureMesh) -> u32 {
+    match mesh.index_type {
+        Some(crate::IndexType::U16) => 2,
+        Some(crate::IndexType::U32) => 4,
+        None => 0,
+    }
+}
+
+/// Number of position components, and the size in bytes.
+pub(crate) fn position_format(format: crate::VertexFormat) -> (u32, u64) {
+    match format {
+        crate::VertexFormat::F32Vec2 => (2, 8),
+        crate::VertexFormat::F32Vec3 => (3, 12),
+        crate::VertexFormat::F32Vec4 => (3, 16),
+        other => panic!(
+            "Unsupported vertex format {:?} for acceleration structures",
+            other
+        ),
+    }
+}
+
+/// Implement `ShaderData` for the build data, the same way as `blade-macros` does.
+macro_rules! shader_data {
+    ($name:ident { $($field:ident: $ty:ty = $index:literal,)* }) => {
+        #[derive(Clone, Copy)]
+        struct $name {
+            $($field: $ty,)*
+        }
+        impl crate::ShaderData for $name {
+            fn layout() -> crate::ShaderDataLayout {
+                crate::ShaderDataLayout {
+                    bindings: vec![$((stringify!($field), <$ty as crate::HasShaderBinding>::TYPE)),*],
+                }
+            }
+            fn fill(&self, mut ctx: crate::PipelineContext) {
+                use crate::ShaderBindable as _;
+                $(self.$field.bind_to(&mut ctx, $index);)*
+            }
+        }
+    };
+}
+
+shader_data!(MeshData {
+    vertices: crate::BufferPiece = 0,
+    indices: crate::BufferPiece = 1,
+    transforms: crate::BufferPiece = 2,
+    scratch: crate::BufferPiece = 3,
+    mesh: MeshParams = 4,
+});
+shader_data!(InstanceData {
+    instances: crate::BufferPiece = 0,
+    data: crate::BufferPiece = 1,
+    scratch: crate::BufferPiece = 2,
+    build: BuildParams = 3,
+});
+shader_data!(ScratchData {
+    scratch: crate::BufferPiece = 0,
+    build: BuildParams = 1,
+});
+shader_data!(LeafData {
+    scratch: crate::BufferPiece = 0,
+    data: crate::BufferPiece = 1,
+    build: BuildParams = 2,
+});
+shader_data!(NodeData {
+    data: crate::BufferPiece = 0,
+    build: BuildParams = 1,
+});
+
+/// Compute pipelines of the build.
+pub(crate) struct Pipelines {
+    prepare_triangles: crate::ComputePipeline,
+    prepare_instances: crate::ComputePipeline,
+    center_bounds: crate::ComputePipeline,
+    compute_keys: crate::ComputePipeline,
+    sort_keys: crate::ComputePipeline,
+    build_leaves: crate::ComputePipeline,
+    build_nodes: crate::ComputePipeline,
+}
+
+impl Pipelines {
+    fn new(context: &crate::Context) -> Self {
+        let shader = context.create_shader(crate::ShaderDesc {
+            source: BUILD_SOURCE,
+        });
+        let create = |entry_point: &str, layout: &crate::ShaderDataLayout| {
+            context.create_compute_pipeline(crate::ComputePipelineDesc {
+                name: entry_point,
+                data_layouts: &[layout],
+                compute: shader.at(entry_point),
+                overrides: &[],
+                cache: None,
+            })
+        };
+        let scratch_layout = <ScratchData as crate::ShaderData>::layout();
+        Self {
+            prepare_triangles: create(
+                "prepare_triangles",
+                &<MeshData as crate::ShaderData>::layout(),
+            ),
+            prepare_instances: create(
+                "prepare_instances",
+                &<InstanceData as crate::ShaderData>::layout(),
+            ),
+            center_bounds: create("center_bounds", &scratch_layout),
+            compute_keys: create("compute_keys", &scratch_layout),
+            sort_keys: create("sort_keys", &scratch_layout),
+            build_leaves: create("build_leaves", &<LeafData as crate::ShaderData>::layout()),
+            build_nodes: create("build_nodes", &<NodeData as crate::ShaderData>::layout()),
+        }
+    }
+}
+
+/// Build pipelines, created along with the first acceleration structure.
+#[derive(Default)]
+pub(crate) struct SharedPipelines {
+    pipelines: Mutex<Option<Arc<Pipelines>>>,
+}
+
+impl SharedPipelines {
+    pub fn init(&self, context: &crate::Context) {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if pipelines.is_none() {
+            *pipelines = Some(Arc::new(Pipelines::new(context)));
+        }
+    }
+
+    pub fn get(&self) -> Arc<Pipelines> {
+        let pipelines = self.pipelines.lock().unwrap();
+        Arc::clone(
+            pipelines
+                .as_ref()
+                .expect("Acceleration structures are built before being created"),
+        )
+    }
+}
+
+/// Recording of the build commands by a backend.
+pub(crate) trait BuildEncoder {
+    /// Check if the buffer piece is backed by memory, or is a placeholder.
+    fn has_memory(piece: &crate::BufferPiece) -> bool;
+    /// Dispatch a compute pipeline, with the storage writes being visible
+    /// to the following commands.
+    fn dispatch<D: crate::ShaderData>(
+        &mut self,
+        pipeline: &crate::ComputePipeline,
+        data: &D,
+        groups: [u32; 3],
+    );
+    fn copy_buffer(&mut self, src: crate::BufferPiece, dst: crate::BufferPiece, size: u64);
+}
+
+/// Workgroups covering the items, splitting them across the second dimension.
+fn groups(count: u32) -> [u32; 3] {
+    let total = (count + GROUP_SIZE - 1) / GROUP_SIZE;
+    let x = total.min(MAX_GROUPS);
+    [x, (total + x - 1) / x, 1]
+}
+
+/// Storage buffers are bound from the start, to avoid the offset alignment,
+/// and the offsets are given to the shaders instead.
+fn whole(piece: crate::BufferPiece) -> crate::BufferPiece {
+    piece.buffer.at(0)
+}
+
+pub(crate) fn build_bottom_level<E: BuildEncoder>(
+    encoder: &mut E,
+    pipelines: &Pipelines,
+    meshes: &[crate::AccelerationStructureMesh],
+    dst: crate::BufferPiece,
+    scratch: crate::BufferPiece,
+) {
+    let triangle_count = meshes.iter().map(|mesh| mesh.triangle_count).sum();
+    let params = BuildParams::new(triangle_count, TRIANGLE_SIZE);
+    let mut first_primitive = 0;
+    for (geometry_index, mesh) in meshes.iter().enumerate() {
+        let (vertex_components, _) = position_format(mesh.vertex_format);
+        let index_size = index_size(mesh);
+        let has_transform = E::has_memory(&mesh.transform_data);
+        assert!(
+            mesh.vertex_data.offset % 4 == 0 && mesh.vertex_stride % 4 == 0,
+            "Vertex data of acceleration structures needs to be 4-byte aligned"
+        );
+        assert_eq!(mesh.index_data.offset % index_size.max(1) as u64, 0);
+        assert!(!has_transform || mesh.transform_data.offset % 4 == 0);
+        let data = MeshData {
+            vertices: whole(mesh.vertex_data),
+            // Missing buffers are never read, but something needs to be bound.
+            indices: whole(if index_size != 0 {
+                mesh.index_data
+            } else {
+                mesh.vertex_data
+            }),
+            transforms: whole(if has_transform {
+                mesh.transform_data
+            } else {
+                mesh.vertex_data
+            }),
+            scratch,
+            mesh: MeshParams {
+                vertex_offset: (mesh.vertex_data.offset / 4) as u32,
+                vertex_stride: mesh.vertex_stride / 4,
+                vertex_components,
+                index_offset: mesh.index_data.offset as u32,
+                index_size,
+                transform_offset: if has_transform {
+                    (mesh.transform_data.offset / 4) as u32
+                } else {
+                    NO_TRANSFORM
+                },
+                triangle_count: mesh.triangle_count,
+                first_primitive,
+                primitive_count: triangle_count,
+                geometry_index: geometry_index as u32,
+                is_opaque: mesh.is_opaque as u32,
+                pad: 0,
+            },
+        };
+        if mesh.triangle_count != 0 {
+            encoder.dispatch(
+                &pipelines.prepare_triangles,
+                &data,
+                groups(mesh.triangle_count),
+            );
+        }
+        first_primitive += mesh.triangle_count;
+    }
+    build_tree(encoder, pipelines, params, dst, scratch);
+}
+
+/// The instance data is expected to be made by `instance_data`,
+/// with the same bottom level structures, given with their sizes.
+pub(crate) fn build_top_level<E: BuildEncoder>(
+    encoder: &mut E,
+    pipelines: &Pipelines,
+    bottom_level: &[(crate::BufferPiece, u64)],
+    instance_count: u32,
+    instance_data: crate::BufferPiece,
+    dst: crate::BufferPiece,
+    scratch: crate::BufferPiece,
+) {
+    let mut params = BuildParams::new(instance_count, INSTANCE_SIZE);
+    params.copies_start = params.data_len() as u32;
+    let mut offset = dst.offset + params.data_len() * VECTOR_SIZE;
+    for &(blas, size) in bottom_level {
+        encoder.copy_buffer(blas, dst.buffer.at(offset), size);
+        offset += copy_size(size);
+    }
+    if instance_count != 0 {
+        let data = InstanceData {
+            instances: instance_data,
+            data: dst,
+            scratch,
+            build: params,
+        };
+        encoder.dispatch(&pipelines.prepare_instances, &data, groups(instance_count));
+    }
+    build_tree(encoder, pipelines, params, dst, scratch);
+}
+
+/// Sort the prepared primitives, and build the tree over them.
+fn build_tree<E: BuildEncoder>(
+    encoder: &mut E,
+    pipelines: &Pipelines,
+    params: BuildParams,
+    dst: crate::BufferPiece,
+    scratch: crate::BufferPiece,
+) {
+    if params.primitive_count != 0 {
+        let data = ScratchData {
+            scratch,
+            build: params,
+        };
+        encoder.dispatch(&pipelines.center_bounds, &data, [1, 1, 1]);
+        encoder.dispatch(&pipelines.compute_keys, &data, groups(params.sort_size));
+        let mut block = 2;
+        while block <= params.sort_size {
+            let mut distance = block / 2;
+            while distance != 0 {
+                let data = ScratchData {
+                    scratch,
+                    build: BuildParams {
+                        sort_block: block,
+                        sort_distance: distance,
+                        ..params
+                    },
+                };
+                encoder.dispatch(&pipelines.sort_keys, &data, groups(params.sort_size / 2));
+                distance /= 2;
+            }
+            block *= 2;
+        }
+    }
+
+    let data = LeafData {
+        scratch,
+        data: dst,
+        build: params,
+    };
+    encoder.dispatch(&pipelines.build_leaves, &data, groups(params.leaf_count));
+    let mut level_count = params.leaf_count / 2;
+    while level_count != 0 {
+        let data = NodeData {
+            data: dst,
+            build: BuildParams {
+                level_first: level_count - 1,
+                level_count,
+                ..params
+            },
+        };
+        encoder.dispatch(&pipelines.build_nodes, &data, groups(level_count));
+        level_count /= 2;
+    }
+}
@@ -0,0 +1,219 @@
+// Ray queries traversing the acceleration structures built by `software_rt`.
+// Parsed together with the shaders using them, which are then lowered
+// to call these functions instead of the ray query built-ins (see `lower.rs`).
+
+const BLADE_RAY_FLAG_FORCE_OPAQUE: u32 = 0x01u;
+const BLADE_RAY_FLAG_FORCE_NO_OPAQUE: u32 = 0x02u;
+const BLADE_RAY_FLAG_TERMINATE_ON_FIRST_HIT: u32 = 0x04u;
+const BLADE_RAY_FLAG_CULL_BACK_FACING: u32 = 0x10u;
+const BLADE_RAY_FLAG_CULL_FRONT_FACING: u32 = 0x20u;
+const BLADE_RAY_FLAG_CULL_OPAQUE: u32 = 0x40u;
+const BLADE_RAY_QUERY_INTERSECTION_NONE: u32 = 0u;
+const BLADE_RAY_QUERY_INTERSECTION_TRIANGLE: u32 = 1u;
+const BLADE_TRIANGLE_SIZE: u32 = 3u;
+const BLADE_INSTANCE_SIZE: u32 = 7u;
+
+struct BladeRayQuery {
+    acceleration_structure: u32,
+    desc: RayDesc,
+    committed: RayIntersection,
+}
+
+// Read a vector of an acceleration structure, which is given by its index.
+// The body is generated by the lowering, switching over the lowered globals.
+fn blade_load_acceleration_structure(index: u32, offset: u32) -> vec4<f32> {
+    return vec4<f32>(0.0);
+}
+
+fn blade_ray_query_initialize(rq: ptr<function, BladeRayQuery>, acceleration_structure: u32, desc: RayDesc) {
+    (*rq).acceleration_structure = acceleration_structure;
+    (*rq).desc = desc;
+    // The other members of the intersection are only defined for the hits.
+    (*rq).committed.kind = BLADE_RAY_QUERY_INTERSECTION_NONE;
+    (*rq).committed.t = desc.tmax;
+}
+
+// Rays parallel to a slab are either inside of it for any distance, or never,
+// which also avoids computing `0 * inf` for the rays along the box faces.
+// Nodes without primitives have inverted, empty boxes.
+fn blade_ray_hits_box(origin: vec3<f32>, dir: vec3<f32>, inv_dir: vec3<f32>, min_corner: vec3<f32>, max_corner: vec3<f32>, t_min: f32, t_max: f32) -> bool {
+    let parallel = abs(dir) < vec3<f32>(1e-30);
+    if (any(min_corner > max_corner) || any(parallel & (origin < min_corner | origin > max_corner))) {
+        return false;
+    }
+    let t0 = (min_corner - origin) * inv_dir;
+    let t1 = (max_corner - origin) * inv_dir;
+    let t_lo = select(min(t0, t1), vec3<f32>(t_min), parallel);
+    let t_hi = select(max(t0, t1), vec3<f32>(t_max), parallel);
+    let t_near = max(max(t_lo.x, t_lo.y), max(t_lo.z, t_min));
+    let t_far = min(min(t_hi.x, t_hi.y), min(t_hi.z, t_max));
+    return t_near <= t_far;
+}
+
+// Returns true if the triangle becomes the committed intersection.
+fn blade_intersect_triangle(rq: ptr<function, BladeRayQuery>, triangle: u32, instance: u32, origin: vec3<f32>, dir: vec3<f32>) -> bool {
+    let acs = (*rq).acceleration_structure;
+    let flags = (*rq).desc.flags;
+    let a = blade_load_acceleration_structure(acs, triangle);
+    let b = blade_load_acceleration_structure(acs, triangle + 1u);
+    let c = blade_load_acceleration_structure(acs, triangle + 2u);
+
+    var is_opaque = bitcast<u32>(c.w) != 0u;
+    if ((flags & BLADE_RAY_FLAG_FORCE_OPAQUE) != 0u) {
+        is_opaque = true;
+    }
+    if ((flags & BLADE_RAY_FLAG_FORCE_NO_OPAQUE) != 0u) {
+        is_opaque = false;
+    }
+    // The traversal runs to completion, so non-opaque candidates are never confirmed.
+    if (!is_opaque || (flags & BLADE_RAY_FLAG_CULL_OPAQUE) != 0u) {
+        return false;
+    }
+
+    let e1 = b.xyz - a.xyz;
+    let e2 = c.xyz - a.xyz;
+    let p = cross(dir, e2);
+    let det = dot(e1, p);
+    if (det == 0.0) {
+        return false;
+    }
+    let front_face = det < 0.0;
+    let cull_flag = select(BLADE_RAY_FLAG_CULL_BACK_FACING, BLADE_RAY_FLAG_CULL_FRONT_FACING, front_face);
+    if ((flags & cull_flag) != 0u) {
+        return false;
+    }
+    let s = origin - a.xyz;
+    let q = cross(s, e1);
+    let u = dot(s, p) / det;
+    let v = dot(dir, q) / det;
+    let t = dot(e2, q) / det;
+    if (u < 0.0 || v < 0.0 || u + v > 1.0 || t < (*rq).desc.tmin || t >= (*rq).committed.t) {
+        return false;
+    }
+
+    let info = bitcast<vec4<u32>>(blade_load_acceleration_structure(acs, instance + 6u));
+    let object_to_world = mat3x4<f32>(
+        blade_load_acceleration_structure(acs, instance),
+        blade_load_acceleration_structure(acs, instance + 1u),
+        blade_load_acceleration_structure(acs, instance + 2u),
+    );
+    let world_to_object = mat3x4<f32>(
+        blade_load_acceleration_structure(acs, instance + 3u),
+        blade_load_acceleration_structure(acs, instance + 4u),
+        blade_load_acceleration_structure(acs, instance + 5u),
+    );
+    (*rq).committed.kind = BLADE_RAY_QUERY_INTERSECTION_TRIANGLE;
+    (*rq).committed.t = t;
+    (*rq).committed.instance_custom_index = info.x;
+    (*rq).committed.instance_id = info.w;
+    (*rq).committed.sbt_record_offset = 0u;
+    (*rq).committed.geometry_index = bitcast<u32>(b.w);
+    (*rq).committed.primitive_index = bitcast<u32>(a.w);
+    (*rq).committed.barycentrics = vec2<f32>(u, v);
+    (*rq).committed.front_face = front_face;
+    (*rq).committed.object_to_world = transpose(object_to_world);
+    (*rq).committed.world_to_object = transpose(world_to_object);
+    return true;
+}
+
+// Traverse the bottom level structure of an instance,
+// returns true if any of its triangles got committed.
+fn blade_intersect_instance(rq: ptr<function, BladeRayQuery>, instance: u32) -> bool {
+    let acs = (*rq).acceleration_structure;
+    let info = bitcast<vec4<u32>>(blade_load_acceleration_structure(acs, instance + 6u));
+    if ((info.y & (*rq).desc.cull_mask) == 0u) {
+        return false;
+    }
+    let world_to_object = mat3x4<f32>(
+        blade_load_acceleration_structure(acs, instance + 3u),
+        blade_load_acceleration_structure(acs, instance + 4u),
+        blade_load_acceleration_structure(acs, instance + 5u),
+    );
+    let origin = vec4<f32>((*rq).desc.origin, 1.0) * world_to_object;
+    let dir = vec4<f32>((*rq).desc.dir, 0.0) * world_to_object;
+    let inv_dir = 1.0 / dir;
+    let terminate = ((*rq).desc.flags & BLADE_RAY_FLAG_TERMINATE_ON_FIRST_HIT) != 0u;
+
+    let base = info.z;
+    let leaf_start = bitcast<vec4<u32>>(blade_load_acceleration_structure(acs, base)).z - 1u;
+    var stack: array<u32, 32>;
+    var depth = 0u;
+    var node = 0u;
+    var is_committed = false;
+    loop {
+        let lo = blade_load_acceleration_structure(acs, base + 1u + 2u * node);
+        let hi = blade_load_acceleration_structure(acs, base + 2u + 2u * node);
+        if (blade_ray_hits_box(origin, dir, inv_dir, lo.xyz, hi.xyz, (*rq).desc.tmin, (*rq).committed.t)) {
+            if (node < leaf_start) {
+                stack[depth] = 2u * node + 2u;
+                depth += 1u;
+                node = 2u * node + 1u;
+                continue;
+            }
+            let first = base + bitcast<u32>(lo.w);
+            let count = bitcast<u32>(hi.w);
+            for (var i = 0u; i < count; i += 1u) {
+                if (blade_intersect_triangle(rq, first + i * BLADE_TRIANGLE_SIZE, instance, origin, dir)) {
+                    is_committed = true;
+                    if (terminate) {
+                        return true;
+                    }
+                }
+            }
+        }
+        if (depth == 0u) {
+            break;
+        }
+        depth -= 1u;
+        node = stack[depth];
+    }
+    return is_committed;
+}
+
+// Run the traversal to completion, so there are never any candidates left.
+fn blade_ray_query_proceed(rq: ptr<function, BladeRayQuery>) -> bool {
+    let acs = (*rq).acceleration_structure;
+    let header = bitcast<vec4<u32>>(blade_load_acceleration_structure(acs, 0u));
+    // Structures that were never built have no leaves.
+    if (header.z == 0u) {
+        return false;
+    }
+    let leaf_start = header.z - 1u;
+    let origin = (*rq).desc.origin;
+    let dir = (*rq).desc.dir;
+    let inv_dir = 1.0 / dir;
+    let terminate = ((*rq).desc.flags & BLADE_RAY_FLAG_TERMINATE_ON_FIRST_HIT) != 0u;
+
+    var stack: array<u32, 32>;
+    var depth = 0u;
+    var node = 0u;
+    loop {
+        let lo = blade_load_acceleration_structure(acs, 1u + 2u * node);
+        let hi = blade_load_acceleration_structure(acs, 2u + 2u * node);
+        if (blade_ray_hits_box(origin, dir, inv_dir, lo.xyz, hi.xyz, (*rq).desc.tmin, (*rq).committed.t)) {
+            if (node < leaf_start) {
+                stack[depth] = 2u * node + 2u;
+                depth += 1u;
+                node = 2u * node + 1u;
+                continue;
+            }
+            let first = bitcast<u32>(lo.w);
+            let count = bitcast<u32>(hi.w);
+            for (var i = 0u; i < count; i += 1u) {
+                if (blade_intersect_instance(rq, first + i * BLADE_INSTANCE_SIZE) && terminate) {
+                    return false;
+                }
+            }
+        }
+        if (depth == 0u) {
+            break;
+        }
+        depth -= 1u;
+        node = stack[depth];
+    }
+    return false;
+}
+
+fn blade_ray_query_get_committed_intersection(rq: ptr<function, BladeRayQuery>) -> RayIntersection {
+    return (*rq).committed;
+}
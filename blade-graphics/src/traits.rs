@@ -6,19 +6,22 @@ pub trait ResourceDevice {
     type Sampler: Send + Sync + Clone + Copy + Debug + Hash + PartialEq;
     type AccelerationStructure: Send + Sync + Clone + Copy + Debug + Hash + PartialEq;
 
-    fn create_buffer(&self, desc: super::BufferDesc) -> Self::Buffer;
+    fn try_create_buffer(&self, desc: super::BufferDesc) -> Result<Self::Buffer, super::Error>;
     fn sync_buffer(&self, buffer: Self::Buffer);
     fn destroy_buffer(&self, buffer: Self::Buffer);
-    fn create_texture(&self, desc: super::TextureDesc) -> Self::Texture;
+    fn try_create_texture(&self, desc: super::TextureDesc) -> Result<Self::Texture, super::Error>;
     fn destroy_texture(&self, texture: Self::Texture);
-    fn create_texture_view(&self, desc: super::TextureViewDesc) -> Self::TextureView;
+    fn try_create_texture_view(
+        &self,
+        desc: super::TextureViewDesc,
+    ) -> Result<Self::TextureView, super::Error>;
     fn destroy_texture_view(&self, view: Self::TextureView);
-    fn create_sampler(&self, desc: super::SamplerDesc) -> Self::Sampler;
+    fn try_create_sampler(&self, desc: super::SamplerDesc) -> Result<Self::Sampler, super::Error>;
     fn destroy_sampler(&self, sampler: Self::Sampler);
-    fn create_acceleration_structure(
+    fn try_create_acceleration_structure(
         &self,
         desc: super::AccelerationStructureDesc,
-    ) -> Self::AccelerationStructure;
+    ) -> Result<Self::AccelerationStructure, super::Error>;
     fn destroy_acceleration_structure(&self, acceleration_structure: Self::AccelerationStructure);
 }
 
@@ -32,6 +35,14 @@ pub trait CommandDevice {
     fn wait_for(&self, sp: &Self::SyncPoint, timeout_ms: u32) -> bool;
 }
 
+pub trait DebugEncoder {
+    /// Open a labeled group of the following commands, shown by graphics debuggers.
+    /// A group pushed on a pass encoder has to be popped before the pass ends.
+    fn push_debug_group(&mut self, label: &str);
+    fn pop_debug_group(&mut self);
+    fn insert_debug_marker(&mut self, label: &str);
+}
+
 pub trait TransferEncoder {
     fn fill_buffer(&mut self, dst: super::BufferPiece, size: u64, value: u8);
     fn copy_buffer_to_buffer(
@@ -88,11 +99,16 @@ pub trait PipelineEncoder {
 
 pub trait ComputePipelineEncoder: PipelineEncoder {
     fn dispatch(&mut self, groups: [u32; 3]);
+    /// Dispatch with the group counts read from the buffer,
+    /// laid out as 3 consecutive `u32` values.
+    fn dispatch_indirect(&mut self, indirect_buf: super::BufferPiece);
 }
 
 pub trait RenderPipelineEncoder: PipelineEncoder {
     //TODO: reconsider exposing this here
     fn set_scissor_rect(&mut self, rect: &super::ScissorRect);
+    fn set_stencil_reference(&mut self, reference: u32);
+    fn bind_vertex_buffer(&mut self, index: u32, vertex_buf: super::BufferPiece);
     fn draw(
         &mut self,
         first_vertex: u32,
@@ -116,4 +132,26 @@ pub trait RenderPipelineEncoder: PipelineEncoder {
         index_type: crate::IndexType,
         indirect_buf: super::BufferPiece,
     );
+    /// Issue up to `max_draw_count` draws with `DrawIndirectArgs` tightly packed
+    /// in the indirect buffer. If `count_buf` is provided, the number of draws
+    /// is limited by the `u32` value it points to.
+    ///
+    /// Backends that can't read the count on GPU issue all of the draws,
+    /// so the unused arguments are expected to have zero instances.
+    fn multi_draw_indirect(
+        &mut self,
+        indirect_buf: super::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<super::BufferPiece>,
+    );
+    /// Indexed version of `multi_draw_indirect`, with `DrawIndexedIndirectArgs`
+    /// in the indirect buffer.
+    fn multi_draw_indexed_indirect(
+        &mut self,
+        index_buf: super::BufferPiece,
+        index_type: super::IndexType,
+        indirect_buf: super::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<super::BufferPiece>,
+    );
 }
@@ -6,7 +6,10 @@ use codespan_reporting::{
         termcolor::{ColorChoice, StandardStream},
     },
 };
-use std::error::Error;
+use std::{
+    error::Error,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 pub fn print_err(error: &dyn Error) {
     eprint!("{}", error);
@@ -41,7 +44,81 @@ pub fn emit_annotated_error<E: Error>(ann_err: &naga::WithSpan<E>, filename: &st
     term::emit(&mut writer.lock(), &config, &files, &diagnostic).expect("cannot write error");
 }
 
+/// Family of block compression used by a texture format.
+#[cfg(any(
+    gles,
+    null,
+    target_arch = "wasm32",
+    all(not(vulkan), any(target_os = "ios", target_os = "macos"))
+))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Bc,
+    Etc2,
+    Astc,
+}
+
 impl super::TextureFormat {
+    #[cfg(any(
+        gles,
+        null,
+        target_arch = "wasm32",
+        all(not(vulkan), any(target_os = "ios", target_os = "macos"))
+    ))]
+    pub(crate) fn compression(&self) -> Compression {
+        match *self {
+            Self::Bc1Unorm
+            | Self::Bc1UnormSrgb
+            | Self::Bc2Unorm
+            | Self::Bc2UnormSrgb
+            | Self::Bc3Unorm
+            | Self::Bc3UnormSrgb
+            | Self::Bc4Unorm
+            | Self::Bc4Snorm
+            | Self::Bc5Unorm
+            | Self::Bc5Snorm
+            | Self::Bc6hUfloat
+            | Self::Bc6hFloat
+            | Self::Bc7Unorm
+            | Self::Bc7UnormSrgb => Compression::Bc,
+            Self::Etc2Rgb8Unorm
+            | Self::Etc2Rgb8UnormSrgb
+            | Self::Etc2Rgba8Unorm
+            | Self::Etc2Rgba8UnormSrgb => Compression::Etc2,
+            Self::Astc4x4Unorm
+            | Self::Astc4x4UnormSrgb
+            | Self::Astc6x6Unorm
+            | Self::Astc6x6UnormSrgb
+            | Self::Astc8x8Unorm
+            | Self::Astc8x8UnormSrgb => Compression::Astc,
+            _ => Compression::None,
+        }
+    }
+
+    /// Return the usages the format has on any device supporting
+    /// its compression family, which backends can narrow down further.
+    #[cfg(any(
+        gles,
+        null,
+        target_arch = "wasm32",
+        all(not(vulkan), any(target_os = "ios", target_os = "macos"))
+    ))]
+    pub(crate) fn base_features(&self) -> super::TextureUsage {
+        use super::TextureUsage as Tu;
+        if self.compression() != Compression::None {
+            Tu::COPY | Tu::RESOURCE
+        } else if !self.aspects().contains(super::TexelAspects::COLOR) {
+            Tu::COPY | Tu::RESOURCE | Tu::TARGET
+        } else {
+            match *self {
+                Self::Rgb9e5Ufloat => Tu::COPY | Tu::RESOURCE,
+                Self::Rgba8UnormSrgb | Self::Bgra8UnormSrgb => Tu::COPY | Tu::RESOURCE | Tu::TARGET,
+                _ => Tu::all(),
+            }
+        }
+    }
+
     pub fn block_info(&self) -> super::TexelBlockInfo {
         fn uncompressed(size: u8) -> super::TexelBlockInfo {
             super::TexelBlockInfo {
@@ -55,21 +132,42 @@ impl super::TextureFormat {
                 size,
             }
         }
+        fn cx_astc(dim: u8) -> super::TexelBlockInfo {
+            super::TexelBlockInfo {
+                dimensions: (dim, dim),
+                size: 16,
+            }
+        }
         match *self {
+            Self::R8Unorm => uncompressed(1),
             Self::Rg8Unorm => uncompressed(2),
             Self::Rg8Snorm => uncompressed(2),
             Self::Rgba8Unorm => uncompressed(4),
             Self::Rgba8UnormSrgb => uncompressed(4),
+            Self::Bgra8Unorm => uncompressed(4),
             Self::Bgra8UnormSrgb => uncompressed(4),
             Self::Rgba8Snorm => uncompressed(4),
+            Self::R16Float => uncompressed(2),
+            Self::Rg16Float => uncompressed(4),
             Self::Rgba16Float => uncompressed(8),
+            Self::Rgba16Unorm => uncompressed(8),
             Self::R32Float => uncompressed(4),
             Self::Rg32Float => uncompressed(8),
             Self::Rgba32Float => uncompressed(16),
             Self::R32Uint => uncompressed(4),
             Self::Rg32Uint => uncompressed(8),
             Self::Rgba32Uint => uncompressed(16),
+            Self::R32Sint => uncompressed(4),
+            Self::Rg32Sint => uncompressed(8),
+            Self::Rgba32Sint => uncompressed(16),
+            Self::Rgb10a2Unorm => uncompressed(4),
+            Self::Rg11b10Float => uncompressed(4),
+            Self::Rgb9e5Ufloat => uncompressed(4),
             Self::Depth32Float => uncompressed(4),
+            // may be backed by `Depth32FloatStencil8`, so sized like it
+            Self::Depth24PlusStencil8 => uncompressed(8),
+            Self::Depth32FloatStencil8 => uncompressed(8),
+            Self::Stencil8 => uncompressed(1),
             Self::Bc1Unorm => cx_bc(8),
             Self::Bc1UnormSrgb => cx_bc(8),
             Self::Bc2Unorm => cx_bc(16),
@@ -80,15 +178,98 @@ impl super::TextureFormat {
             Self::Bc4Snorm => cx_bc(8),
             Self::Bc5Unorm => cx_bc(16),
             Self::Bc5Snorm => cx_bc(16),
+            Self::Bc6hUfloat => cx_bc(16),
+            Self::Bc6hFloat => cx_bc(16),
+            Self::Bc7Unorm => cx_bc(16),
+            Self::Bc7UnormSrgb => cx_bc(16),
+            Self::Etc2Rgb8Unorm => cx_bc(8),
+            Self::Etc2Rgb8UnormSrgb => cx_bc(8),
+            Self::Etc2Rgba8Unorm => cx_bc(16),
+            Self::Etc2Rgba8UnormSrgb => cx_bc(16),
+            Self::Astc4x4Unorm => cx_astc(4),
+            Self::Astc4x4UnormSrgb => cx_astc(4),
+            Self::Astc6x6Unorm => cx_astc(6),
+            Self::Astc6x6UnormSrgb => cx_astc(6),
+            Self::Astc8x8Unorm => cx_astc(8),
+            Self::Astc8x8UnormSrgb => cx_astc(8),
         }
     }
 
     pub fn aspects(&self) -> super::TexelAspects {
         match *self {
             Self::Depth32Float => super::TexelAspects::DEPTH,
+            Self::Depth24PlusStencil8 | Self::Depth32FloatStencil8 => {
+                super::TexelAspects::DEPTH | super::TexelAspects::STENCIL
+            }
+            Self::Stencil8 => super::TexelAspects::STENCIL,
             _ => super::TexelAspects::COLOR,
         }
     }
+
+    /// Return the aspects of the format included in the selection,
+    /// where an empty selection includes all of them.
+    pub fn select_aspects(&self, selection: super::TexelAspects) -> super::TexelAspects {
+        if selection.is_empty() {
+            self.aspects()
+        } else {
+            self.aspects() & selection
+        }
+    }
+
+    /// Return the block info of the selected aspects, as laid out in buffer copies.
+    pub fn aspect_block_info(&self, aspects: super::TexelAspects) -> super::TexelBlockInfo {
+        let aspects = self.select_aspects(aspects);
+        if aspects == self.aspects() {
+            self.block_info()
+        } else if aspects == super::TexelAspects::DEPTH {
+            super::TexelBlockInfo {
+                dimensions: (1, 1),
+                size: 4,
+            }
+        } else {
+            super::TexelBlockInfo {
+                dimensions: (1, 1),
+                size: 1,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicUsize);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Number of live resources, for the backends that don't track them otherwise.
+#[derive(Debug, Default)]
+pub(crate) struct ResourceCounts {
+    pub buffers: Counter,
+    pub textures: Counter,
+    pub acceleration_structures: Counter,
+}
+
+impl ResourceCounts {
+    pub fn report(&self, heaps: Vec<super::HeapUsage>) -> super::MemoryReport {
+        super::MemoryReport {
+            heaps,
+            buffer_count: self.buffers.get(),
+            texture_count: self.textures.get(),
+            acceleration_structure_count: self.acceleration_structures.get(),
+            allocations: Vec::new(),
+        }
+    }
 }
 
 impl super::ComputePipeline {
@@ -102,3 +283,112 @@ impl super::ComputePipeline {
         ]
     }
 }
+
+impl super::TextureDesc<'_> {
+    /// Check the descriptor against the device limits
+    /// and the usages supported by its format.
+    pub(crate) fn validate(
+        &self,
+        limits: &super::Limits,
+        format_features: super::TextureUsage,
+    ) -> Result<(), super::Error> {
+        let size = self.size;
+        if size.width == 0 || size.height == 0 || size.depth == 0 {
+            log::error!("Texture '{}' has zero size", self.name);
+            return Err(super::Error::InvalidDescriptor);
+        }
+        if self.mip_level_count == 0 || self.array_layer_count == 0 {
+            log::error!("Texture '{}' has no mips or layers", self.name);
+            return Err(super::Error::InvalidDescriptor);
+        }
+        if self.dimension != super::TextureDimension::D3 && size.depth != 1 {
+            log::error!("Texture '{}' is not 3D but has depth", self.name);
+            return Err(super::Error::InvalidDescriptor);
+        }
+        if !self.sample_count.is_power_of_two() {
+            log::error!(
+                "Texture '{}' has invalid sample count {}",
+                self.name,
+                self.sample_count
+            );
+            return Err(super::Error::InvalidDescriptor);
+        }
+        if self.sample_count > 1
+            && (self.dimension != super::TextureDimension::D2 || self.mip_level_count != 1)
+        {
+            log::error!(
+                "Multisampled texture '{}' has to be 2D without mips",
+                self.name
+            );
+            return Err(super::Error::InvalidDescriptor);
+        }
+        let (max_dimension, max_depth) = match self.dimension {
+            super::TextureDimension::D3 => (
+                limits.max_texture_dimension_3d,
+                limits.max_texture_dimension_3d,
+            ),
+            _ => (limits.max_texture_dimension_2d, 1),
+        };
+        if size.width > max_dimension
+            || size.height > max_dimension
+            || size.depth > max_depth
+            || self.array_layer_count > limits.max_texture_array_layers
+        {
+            log::error!("Texture '{}' exceeds the device limits", self.name);
+            return Err(super::Error::InvalidDescriptor);
+        }
+        if !format_features.contains(self.usage) {
+            log::error!(
+                "Texture '{}' format {:?} doesn't support {:?}",
+                self.name,
+                self.format,
+                self.usage - format_features
+            );
+            return Err(super::Error::UnsupportedFormat);
+        }
+        Ok(())
+    }
+}
+
+impl super::Context {
+    pub fn create_buffer(&self, desc: super::BufferDesc) -> super::Buffer {
+        self.try_create_buffer(desc).unwrap()
+    }
+
+    pub fn create_texture(&self, desc: super::TextureDesc) -> super::Texture {
+        self.try_create_texture(desc).unwrap()
+    }
+
+    pub fn create_texture_view(&self, desc: super::TextureViewDesc) -> super::TextureView {
+        self.try_create_texture_view(desc).unwrap()
+    }
+
+    pub fn create_sampler(&self, desc: super::SamplerDesc) -> super::Sampler {
+        self.try_create_sampler(desc).unwrap()
+    }
+
+    pub fn create_acceleration_structure(
+        &self,
+        desc: super::AccelerationStructureDesc,
+    ) -> super::AccelerationStructure {
+        self.try_create_acceleration_structure(desc).unwrap()
+    }
+
+    pub fn create_compute_pipeline(
+        &self,
+        desc: super::ComputePipelineDesc,
+    ) -> super::ComputePipeline {
+        self.try_create_compute_pipeline(desc).unwrap()
+    }
+
+    pub fn create_render_pipeline(&self, desc: super::RenderPipelineDesc) -> super::RenderPipeline {
+        self.try_create_render_pipeline(desc).unwrap()
+    }
+
+    pub fn create_render_bundle_encoder(
+        &self,
+        desc: super::RenderBundleDesc,
+    ) -> super::RenderBundleEncoder {
+        self.try_create_render_bundle_encoder(desc).unwrap()
+    }
+}
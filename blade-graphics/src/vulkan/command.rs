@@ -1,5 +1,20 @@
-use ash::vk;
-use std::str;
+use crate::software_rt;
+use ash::vk::{self, Handle as _};
+use std::{borrow::Cow, ffi, mem::size_of, ptr, str, time::Duration};
+
+/// Return the last marker that all the work has passed through,
+/// tagged with the ID of its encoder.
+fn last_checkpoint(fns: &vk::NvDeviceDiagnosticCheckpointsFn, queue: vk::Queue) -> u64 {
+    let mut count = 0;
+    unsafe { (fns.get_queue_checkpoint_data_nv)(queue, &mut count, ptr::null_mut()) };
+    let mut checkpoints = vec![vk::CheckpointDataNV::default(); count as usize];
+    unsafe { (fns.get_queue_checkpoint_data_nv)(queue, &mut count, checkpoints.as_mut_ptr()) };
+    checkpoints
+        .iter()
+        .take(count as usize)
+        .find(|cp| cp.stage.contains(vk::PipelineStageFlags::BOTTOM_OF_PIPE))
+        .map_or(0, |cp| cp.p_checkpoint_marker as usize as u64)
+}
 
 impl super::CrashHandler {
     fn add_marker(&mut self, marker: &str) -> u32 {
@@ -7,7 +22,7 @@ impl super::CrashHandler {
             self.raw_string[self.next_offset] = b'|';
             self.next_offset += 1;
         }
-        let len = marker.as_bytes().len().min(self.raw_string.len());
+        let len = marker.len().min(self.raw_string.len());
         if self.next_offset + len > self.raw_string.len() {
             self.next_offset = 0;
         }
@@ -18,12 +33,35 @@ impl super::CrashHandler {
         start as u32 | (end << 16) as u32
     }
 
-    pub(super) fn extract(&self, id: u32) -> (&str, &str) {
+    /// Checkpoint of the marker, tagged with the encoder ID if the pointers can hold it.
+    fn checkpoint(&self, id: u32) -> usize {
+        if cfg!(target_pointer_width = "64") {
+            ((self.id as u64) << 32 | id as u64) as usize
+        } else {
+            id as usize
+        }
+    }
+
+    /// Return the marker of the checkpoint, if it belongs to this encoder.
+    fn checkpoint_marker(&self, checkpoint: u64) -> Option<u32> {
+        if cfg!(target_pointer_width = "64") && checkpoint >> 32 != self.id as u64 {
+            None
+        } else {
+            Some(checkpoint as u32)
+        }
+    }
+
+    /// Return the history and the marker text, if the ID is in range.
+    /// Markers may be cut in the middle of a character, so the text is lossy.
+    pub(super) fn extract(&self, id: u32) -> Option<(Cow<'_, str>, Cow<'_, str>)> {
         let start = id as usize & 0xFFFF;
         let end = (id >> 16) as usize;
-        let history = str::from_utf8(&self.raw_string[..start]).unwrap_or_default();
-        let marker = str::from_utf8(&self.raw_string[start..end]).unwrap();
-        (history, marker)
+        if start > end || end > self.raw_string.len() {
+            return None;
+        }
+        let history = String::from_utf8_lossy(&self.raw_string[..start]);
+        let marker = String::from_utf8_lossy(&self.raw_string[start..end]);
+        Some((history, marker))
     }
 }
 
@@ -39,6 +77,11 @@ impl super::PipelineContext<'_> {
         };
     }
 
+    #[inline]
+    fn track<H: vk::Handle>(&mut self, index: u32, handle: H) {
+        self.resources.push((index, handle.as_raw()));
+    }
+
     #[inline]
     fn write_array<I: Iterator>(&mut self, index: u32, iter: I) {
         let base_offset = self.template_offsets[index as usize];
@@ -55,8 +98,11 @@ impl<T: bytemuck::Pod> crate::ShaderBindable for T {
         ctx.write(index, *self);
     }
 }
+// Only the render targets are transitioned out of `ImageLayout::GENERAL`,
+// and they are released back to it when the next pass begins.
 impl crate::ShaderBindable for super::TextureView {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.track(index, self.image);
         ctx.write(
             index,
             vk::DescriptorImageInfo {
@@ -69,6 +115,9 @@ impl crate::ShaderBindable for super::TextureView {
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::TextureArray<N> {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        for view in self.data.iter() {
+            ctx.track(index, view.image);
+        }
         ctx.write_array(
             index,
             self.data.iter().map(|view| vk::DescriptorImageInfo {
@@ -93,6 +142,7 @@ impl crate::ShaderBindable for super::Sampler {
 }
 impl crate::ShaderBindable for crate::BufferPiece {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        ctx.track(index, self.buffer.raw);
         ctx.write(
             index,
             vk::DescriptorBufferInfo {
@@ -105,6 +155,9 @@ impl crate::ShaderBindable for crate::BufferPiece {
 }
 impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::BufferArray<N> {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
+        for piece in self.data.iter() {
+            ctx.track(index, piece.buffer.raw);
+        }
         ctx.write_array(
             index,
             self.data.iter().map(|piece| vk::DescriptorBufferInfo {
@@ -117,7 +170,20 @@ impl<'a, const N: crate::ResourceIndex> crate::ShaderBindable for &'a crate::Buf
 }
 impl crate::ShaderBindable for super::AccelerationStructure {
     fn bind_to(&self, ctx: &mut super::PipelineContext, index: u32) {
-        ctx.write(index, self.raw);
+        ctx.track(index, self.buffer);
+        if self.raw == vk::AccelerationStructureKHR::null() {
+            // The software ray queries read the structure from a storage buffer
+            ctx.write(
+                index,
+                vk::DescriptorBufferInfo {
+                    buffer: self.buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                },
+            );
+        } else {
+            ctx.write(index, self.raw);
+        }
     }
 }
 
@@ -146,22 +212,213 @@ fn make_buffer_image_copy(
     texture: &crate::TexturePiece,
     size: &crate::Extent,
 ) -> vk::BufferImageCopy {
-    let block_info = texture.texture.format.block_info();
+    let aspects = texture.texture.format.select_aspects(texture.aspects);
+    let block_info = texture.texture.format.aspect_block_info(aspects);
     vk::BufferImageCopy {
         buffer_offset: buffer.offset,
         buffer_row_length: block_info.dimensions.0 as u32
             * (bytes_per_row / block_info.size as u32),
         buffer_image_height: 0,
-        image_subresource: texture.subresource_layers(texture.texture.format.aspects()),
+        image_subresource: texture.subresource_layers(aspects),
         image_offset: map_origin(&texture.origin),
         image_extent: super::map_extent_3d(size),
     }
 }
 
+#[derive(Clone, Copy)]
+enum PassKind {
+    Transfer,
+    AccelerationStructure,
+    Compute,
+    Render,
+}
+
+/// Return the stages of a pass, with all the accesses they may do.
+fn pass_usage(kind: PassKind, ray_tracing: bool) -> super::Usage {
+    use vk::{AccessFlags as Af, PipelineStageFlags as Psf};
+    let (stages, mut access) = match kind {
+        PassKind::Transfer => (Psf::TRANSFER, Af::TRANSFER_READ | Af::TRANSFER_WRITE),
+        PassKind::AccelerationStructure if ray_tracing => (
+            Psf::ACCELERATION_STRUCTURE_BUILD_KHR,
+            Af::ACCELERATION_STRUCTURE_READ_KHR
+                | Af::ACCELERATION_STRUCTURE_WRITE_KHR
+                | Af::SHADER_READ,
+        ),
+        // built by compute shaders, see `software_rt`
+        PassKind::AccelerationStructure => (
+            Psf::COMPUTE_SHADER | Psf::TRANSFER,
+            Af::UNIFORM_READ
+                | Af::SHADER_READ
+                | Af::SHADER_WRITE
+                | Af::TRANSFER_READ
+                | Af::TRANSFER_WRITE,
+        ),
+        PassKind::Compute => (
+            Psf::DRAW_INDIRECT | Psf::COMPUTE_SHADER,
+            Af::INDIRECT_COMMAND_READ | Af::UNIFORM_READ | Af::SHADER_READ | Af::SHADER_WRITE,
+        ),
+        PassKind::Render => (
+            Psf::DRAW_INDIRECT
+                | Psf::VERTEX_INPUT
+                | Psf::VERTEX_SHADER
+                | Psf::FRAGMENT_SHADER
+                | Psf::EARLY_FRAGMENT_TESTS
+                | Psf::LATE_FRAGMENT_TESTS
+                | Psf::COLOR_ATTACHMENT_OUTPUT,
+            Af::INDIRECT_COMMAND_READ
+                | Af::INDEX_READ
+                | Af::VERTEX_ATTRIBUTE_READ
+                | Af::UNIFORM_READ
+                | Af::SHADER_READ
+                | Af::SHADER_WRITE
+                | Af::COLOR_ATTACHMENT_READ
+                | Af::COLOR_ATTACHMENT_WRITE
+                | Af::DEPTH_STENCIL_ATTACHMENT_READ
+                | Af::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+    };
+    if ray_tracing {
+        match kind {
+            // ray queries read the acceleration structures
+            PassKind::Compute | PassKind::Render => {
+                access |= Af::ACCELERATION_STRUCTURE_READ_KHR;
+            }
+            PassKind::Transfer | PassKind::AccelerationStructure => {}
+        }
+    }
+    super::Usage { stages, access }
+}
+
+impl super::Tracker {
+    /// Check if the accesses conflict with the ones since the last barrier.
+    fn has_hazard(&self, accesses: &[(u64, bool)]) -> bool {
+        self.untracked
+            || accesses.iter().any(|&(handle, write)| {
+                self.writes.contains(&handle) || (write && self.reads.contains(&handle))
+            })
+    }
+
+    fn record(&mut self, accesses: &[(u64, bool)]) {
+        for &(handle, write) in accesses {
+            if write {
+                self.writes.insert(handle);
+            } else {
+                self.reads.insert(handle);
+            }
+        }
+    }
+
+    /// Wait for the conflicting accesses, then record the new ones
+    /// done by the commands of the given usage.
+    fn access(
+        &mut self,
+        device: &super::Device,
+        raw: vk::CommandBuffer,
+        dst: super::Usage,
+        write_access: vk::AccessFlags,
+        accesses: &[(u64, bool)],
+    ) {
+        if self.has_hazard(accesses) {
+            self.barrier(device, raw, dst, Vec::new());
+        }
+        self.usage.stages |= dst.stages;
+        if accesses.iter().any(|&(_, write)| write) {
+            self.usage.access |= write_access;
+        }
+        self.record(accesses);
+    }
+
+    /// Synchronize the commands recorded since the last barrier
+    /// with the following commands of the given usage.
+    fn barrier(
+        &mut self,
+        device: &super::Device,
+        raw: vk::CommandBuffer,
+        dst: super::Usage,
+        mut image_barriers: Vec<vk::ImageMemoryBarrier>,
+    ) {
+        if self.usage.stages.is_empty() && image_barriers.is_empty() {
+            // Previous command buffers are synchronized by their final barrier.
+            return;
+        }
+        let src_stages = if self.usage.stages.is_empty() {
+            vk::PipelineStageFlags::ALL_COMMANDS
+        } else {
+            self.usage.stages
+        };
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(self.usage.access)
+            .dst_access_mask(dst.access)
+            .build();
+        for barrier in image_barriers.iter_mut() {
+            barrier.src_access_mask = self.usage.access;
+            barrier.dst_access_mask = dst.access;
+        }
+        unsafe {
+            device.core.cmd_pipeline_barrier(
+                raw,
+                src_stages,
+                dst.stages,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &image_barriers,
+            );
+        }
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        self.usage = super::Usage::NONE;
+        self.reads.clear();
+        self.writes.clear();
+        self.untracked = false;
+    }
+}
+
+fn attachment_layout(view: &super::TextureView) -> vk::ImageLayout {
+    if view.aspects.contains(crate::TexelAspects::COLOR) {
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    }
+}
+
+fn attachment_range(view: &super::TextureView) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: super::map_aspects(view.aspects),
+        base_mip_level: view.base_mip_level,
+        level_count: 1,
+        base_array_layer: view.base_array_layer,
+        layer_count: view.array_layer_count,
+    }
+}
+
+fn render_area(target_size: [u16; 2]) -> vk::Rect2D {
+    vk::Rect2D {
+        offset: Default::default(),
+        extent: vk::Extent2D {
+            width: target_size[0] as u32,
+            height: target_size[1] as u32,
+        },
+    }
+}
+
+fn viewport(target_size: [u16; 2]) -> vk::Viewport {
+    vk::Viewport {
+        x: 0.0,
+        y: target_size[1] as f32,
+        width: target_size[0] as f32,
+        height: -(target_size[1] as f32),
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
 fn map_render_target(rt: &crate::RenderTarget) -> vk::RenderingAttachmentInfo {
     let mut builder = vk::RenderingAttachmentInfo::builder()
         .image_view(rt.view.raw)
-        .image_layout(vk::ImageLayout::GENERAL)
+        .image_layout(attachment_layout(&rt.view))
         .load_op(vk::AttachmentLoadOp::LOAD);
 
     if let crate::InitOp::Clear(color) = rt.init_op {
@@ -193,32 +450,105 @@ fn map_render_target(rt: &crate::RenderTarget) -> vk::RenderingAttachmentInfo {
         builder = builder.load_op(vk::AttachmentLoadOp::CLEAR).clear_value(cv);
     }
 
+    if let crate::FinishOp::ResolveTo(ref resolve_view) = rt.finish_op {
+        builder = builder
+            .resolve_image_view(resolve_view.raw)
+            .resolve_image_layout(attachment_layout(resolve_view))
+            .resolve_mode(if rt.view.aspects.contains(crate::TexelAspects::COLOR) {
+                vk::ResolveModeFlags::AVERAGE
+            } else {
+                vk::ResolveModeFlags::SAMPLE_ZERO
+            });
+    }
+
     builder.build()
 }
 
+impl super::Device {
+    fn begin_debug_label(&self, raw: vk::CommandBuffer, label: &str) {
+        let name = ffi::CString::new(label).unwrap();
+        let vk_label = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
+        unsafe { self.debug_utils.cmd_begin_debug_utils_label(raw, &vk_label) };
+    }
+
+    fn end_debug_label(&self, raw: vk::CommandBuffer) {
+        unsafe { self.debug_utils.cmd_end_debug_utils_label(raw) };
+    }
+
+    fn insert_debug_label(&self, raw: vk::CommandBuffer, label: &str) {
+        let name = ffi::CString::new(label).unwrap();
+        let vk_label = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
+        unsafe {
+            self.debug_utils
+                .cmd_insert_debug_utils_label(raw, &vk_label)
+        };
+    }
+}
+
 impl super::CommandEncoder {
     pub fn mark(&mut self, marker: &str) {
         if let Some(ref mut ch) = self.crash_handler {
             let id = ch.add_marker(marker);
-            unsafe {
-                (self
-                    .device
-                    .buffer_marker
-                    .as_ref()
-                    .unwrap()
-                    .cmd_write_buffer_marker_amd)(
-                    self.buffers[0].raw,
-                    vk::PipelineStageFlags::ALL_COMMANDS,
-                    ch.marker_buf.raw,
-                    0,
-                    id,
-                );
+            let raw = self.buffers[0].raw;
+            match *self.device.marker_writer.as_ref().unwrap() {
+                super::MarkerWriter::BufferMarker(ref fns) => unsafe {
+                    (fns.cmd_write_buffer_marker_amd)(
+                        raw,
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                        ch.marker_buf.raw,
+                        0,
+                        id,
+                    );
+                },
+                super::MarkerWriter::Checkpoints(ref fns) => unsafe {
+                    let checkpoint = ch.checkpoint(id);
+                    (fns.cmd_set_checkpoint_nv)(raw, checkpoint as *const ffi::c_void);
+                },
+                super::MarkerWriter::FillBuffer => {
+                    // Wait for all the previous work, so that the marker
+                    // is only written once it's done.
+                    let barrier = vk::MemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .build();
+                    unsafe {
+                        self.device.core.cmd_pipeline_barrier(
+                            raw,
+                            vk::PipelineStageFlags::ALL_COMMANDS,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::DependencyFlags::empty(),
+                            &[barrier],
+                            &[],
+                            &[],
+                        );
+                        self.device
+                            .core
+                            .cmd_fill_buffer(raw, ch.marker_buf.raw, 0, 4, id);
+                    }
+                }
             }
         }
     }
 
     pub fn start(&mut self) {
         self.buffers.rotate_left(1);
+        let cmd_buf = &mut self.buffers[0];
+
+        // The buffer can only be reset, and its timestamps read,
+        // once the previous submission of it is done.
+        if let Some((semaphore, value)) = cmd_buf.submission.take() {
+            let semaphores = [semaphore];
+            let semaphore_values = [value];
+            let wait_info = vk::SemaphoreWaitInfoKHR::builder()
+                .semaphores(&semaphores)
+                .values(&semaphore_values);
+            unsafe {
+                self.device
+                    .timeline_semaphore
+                    .wait_semaphores(&wait_info, !0)
+                    .unwrap();
+            }
+        }
 
         let vk_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
@@ -227,48 +557,168 @@ impl super::CommandEncoder {
             self.device
                 .core
                 .reset_descriptor_pool(
-                    self.buffers[0].descriptor_pool,
+                    cmd_buf.descriptor_pool,
                     vk::DescriptorPoolResetFlags::empty(),
                 )
                 .unwrap();
             self.device
                 .core
-                .begin_command_buffer(self.buffers[0].raw, &vk_info)
+                .begin_command_buffer(cmd_buf.raw, &vk_info)
                 .unwrap();
         }
+        if let Some(ref ring) = cmd_buf.uniform_ring {
+            ring.reset();
+        }
+        self.tracker.clear();
+        self.attachments.clear();
+
+        if let Some(ref timing) = self.device.timing {
+            self.timings.clear();
+            if !cmd_buf.timed_pass_names.is_empty() {
+                let count = cmd_buf.timed_pass_names.len() + 1;
+                let mut timestamps = [0u64; super::QUERY_POOL_SIZE as usize];
+                let result = unsafe {
+                    self.device.core.get_query_pool_results(
+                        cmd_buf.query_pool,
+                        0,
+                        count as u32,
+                        &mut timestamps[..count],
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                };
+                match result {
+                    Ok(()) => {
+                        let mut prev = timestamps[0];
+                        for (name, &ts) in cmd_buf
+                            .timed_pass_names
+                            .drain(..)
+                            .zip(timestamps[1..count].iter())
+                        {
+                            let diff = ts.saturating_sub(prev) as f32 * timing.period;
+                            prev = ts;
+                            self.timings.push((name, Duration::from_nanos(diff as u64)));
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Unable to get the timestamps: {:?}", err);
+                        cmd_buf.timed_pass_names.clear();
+                    }
+                }
+            }
+            unsafe {
+                self.device.core.cmd_reset_query_pool(
+                    cmd_buf.raw,
+                    cmd_buf.query_pool,
+                    0,
+                    super::QUERY_POOL_SIZE,
+                );
+            }
+        }
     }
 
     pub(super) fn finish(&mut self) -> vk::CommandBuffer {
-        self.barrier();
+        // Make everything available to the following submissions and the host.
+        let image_barriers = self.release_attachments();
+        let dst = super::Usage {
+            stages: vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+            access: vk::AccessFlags::MEMORY_READ
+                | vk::AccessFlags::MEMORY_WRITE
+                | vk::AccessFlags::HOST_READ
+                | self.device.workarounds.extra_sync_dst_access,
+        };
+        self.barrier(dst, image_barriers);
         self.mark("finish");
-        let raw = self.buffers[0].raw;
-        unsafe { self.device.core.end_command_buffer(raw).unwrap() }
-        raw
-    }
-
-    fn barrier(&mut self) {
-        let wa = &self.device.workarounds;
-        let barrier = vk::MemoryBarrier::builder()
-            .src_access_mask(vk::AccessFlags::MEMORY_WRITE | wa.extra_sync_src_access)
-            .dst_access_mask(
-                vk::AccessFlags::MEMORY_READ
-                    | vk::AccessFlags::MEMORY_WRITE
-                    | wa.extra_sync_dst_access,
-            )
-            .build();
-        unsafe {
-            self.device.core.cmd_pipeline_barrier(
-                self.buffers[0].raw,
-                vk::PipelineStageFlags::ALL_COMMANDS,
-                vk::PipelineStageFlags::ALL_COMMANDS,
-                vk::DependencyFlags::empty(),
-                &[barrier],
-                &[],
-                &[],
-            );
+        let cmd_buf = &mut self.buffers[0];
+        if self.device.timing.is_some() && !cmd_buf.timed_pass_names.is_empty() {
+            let index = cmd_buf.timed_pass_names.len() as u32;
+            unsafe {
+                self.device.core.cmd_write_timestamp(
+                    cmd_buf.raw,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    cmd_buf.query_pool,
+                    index,
+                );
+            }
+        }
+        unsafe { self.device.core.end_command_buffer(cmd_buf.raw).unwrap() }
+        cmd_buf.raw
+    }
+
+    /// Make the next submission wait for a sync point on the GPU,
+    /// which is needed when it comes from a different command type.
+    pub fn wait_for(&mut self, sp: &super::SyncPoint) {
+        self.waits.push(sp.clone());
+    }
+
+    fn check_command_type(&self, label: &str, command_types: &[crate::CommandType]) {
+        assert!(
+            command_types.contains(&self.command_type),
+            "Pass '{}' is not supported by {:?} encoder",
+            label,
+            self.command_type
+        );
+    }
+
+    /// Return the timings of the passes recorded the previous time
+    /// the current command buffer was submitted, which `start` waits for.
+    pub fn timings(&self) -> &crate::Timings {
+        &self.timings
+    }
+
+    fn begin_pass(
+        &mut self,
+        label: &str,
+        kind: PassKind,
+        mut image_barriers: Vec<vk::ImageMemoryBarrier>,
+    ) {
+        image_barriers.extend(self.release_attachments());
+        let usage = pass_usage(kind, self.device.ray_tracing.is_some());
+        // Copies and dispatches wait for their own conflicts, see `Tracker`.
+        let self_synchronized = matches!(kind, PassKind::Transfer | PassKind::Compute);
+        if !self_synchronized || !image_barriers.is_empty() {
+            self.barrier(usage, image_barriers);
+        }
+        // Writes are recorded by the pass itself.
+        self.tracker.usage.stages |= usage.stages;
+        self.mark(label);
+        let cmd_buf = &mut self.buffers[0];
+        // The last timestamp is reserved for the end of the command buffer
+        if self.device.timing.is_some()
+            && cmd_buf.timed_pass_names.len() + 1 < super::QUERY_POOL_SIZE as usize
+        {
+            let index = cmd_buf.timed_pass_names.len() as u32;
+            cmd_buf.timed_pass_names.push(label.to_string());
+            unsafe {
+                self.device.core.cmd_write_timestamp(
+                    cmd_buf.raw,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    cmd_buf.query_pool,
+                    index,
+                );
+            }
         }
     }
 
+    /// Transition the attachments back to `ImageLayout::GENERAL`.
+    fn release_attachments(&mut self) -> Vec<vk::ImageMemoryBarrier> {
+        self.attachments
+            .drain(..)
+            .map(|attachment| {
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(attachment.layout)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .image(attachment.image)
+                    .subresource_range(attachment.range)
+                    .build()
+            })
+            .collect()
+    }
+
+    fn barrier(&mut self, dst: super::Usage, image_barriers: Vec<vk::ImageMemoryBarrier>) {
+        self.tracker
+            .barrier(&self.device, self.buffers[0].raw, dst, image_barriers);
+    }
+
     pub fn init_texture(&mut self, texture: super::Texture) {
         let barrier = vk::ImageMemoryBarrier::builder()
             .old_layout(vk::ImageLayout::UNDEFINED)
@@ -296,14 +746,23 @@ impl super::CommandEncoder {
     }
 
     pub fn present(&mut self, frame: super::Frame) {
+        self.check_command_type("present", &[crate::CommandType::General]);
         assert_eq!(self.present, None);
         self.present = Some(super::Presentation {
             image_index: frame.image_index,
             acquire_semaphore: frame.acquire_semaphore,
         });
 
+        let old_layout = match self
+            .attachments
+            .iter()
+            .position(|attachment| attachment.image == frame.image)
+        {
+            Some(index) => self.attachments.swap_remove(index).layout,
+            None => vk::ImageLayout::GENERAL,
+        };
         let barrier = vk::ImageMemoryBarrier::builder()
-            .old_layout(vk::ImageLayout::GENERAL)
+            .old_layout(old_layout)
             .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
             .image(frame.image)
             .subresource_range(vk::ImageSubresourceRange {
@@ -314,91 +773,158 @@ impl super::CommandEncoder {
                 layer_count: 1,
             })
             .build();
-        unsafe {
-            self.device.core.cmd_pipeline_barrier(
-                self.buffers[0].raw,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                vk::PipelineStageFlags::ALL_COMMANDS,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[barrier],
-            );
-        }
+        let dst = super::Usage {
+            stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            access: vk::AccessFlags::empty(),
+        };
+        self.barrier(dst, vec![barrier]);
     }
 
-    pub fn transfer(&mut self) -> super::TransferCommandEncoder {
-        self.barrier();
-        self.mark("pass/transfer");
+    pub fn transfer(&mut self) -> super::TransferCommandEncoder<'_> {
+        self.transfer_labeled("transfer")
+    }
+
+    /// Same as `transfer`, but the pass is labeled for debugging and timing.
+    pub fn transfer_labeled(&mut self, label: &str) -> super::TransferCommandEncoder<'_> {
+        self.begin_pass(label, PassKind::Transfer, Vec::new());
         super::TransferCommandEncoder {
             raw: self.buffers[0].raw,
             device: &self.device,
+            tracker: &mut self.tracker,
         }
     }
 
-    pub fn acceleration_structure(&mut self) -> super::AccelerationStructureCommandEncoder {
-        self.barrier();
-        self.mark("pass/acc-struct");
+    pub fn acceleration_structure(&mut self) -> super::AccelerationStructureCommandEncoder<'_> {
+        self.acceleration_structure_labeled("acceleration structure")
+    }
+
+    /// Same as `acceleration_structure`, but the pass is labeled for debugging and timing.
+    pub fn acceleration_structure_labeled(
+        &mut self,
+        label: &str,
+    ) -> super::AccelerationStructureCommandEncoder<'_> {
+        self.check_command_type(
+            label,
+            &[crate::CommandType::Compute, crate::CommandType::General],
+        );
+        self.begin_pass(label, PassKind::AccelerationStructure, Vec::new());
+        self.tracker.usage.access |= if self.device.ray_tracing.is_some() {
+            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR
+        } else {
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE
+        };
         super::AccelerationStructureCommandEncoder {
-            raw: self.buffers[0].raw,
+            cmd_buf: &self.buffers[0],
             device: &self.device,
+            update_data: &mut self.update_data,
+            tracker: &mut self.tracker,
         }
     }
 
-    pub fn compute(&mut self) -> super::ComputeCommandEncoder {
-        self.barrier();
-        self.mark("pass/compute");
+    pub fn compute(&mut self) -> super::ComputeCommandEncoder<'_> {
+        self.compute_labeled("compute")
+    }
+
+    /// Same as `compute`, but the pass is labeled for debugging and timing.
+    pub fn compute_labeled(&mut self, label: &str) -> super::ComputeCommandEncoder<'_> {
+        self.check_command_type(
+            label,
+            &[crate::CommandType::Compute, crate::CommandType::General],
+        );
+        self.begin_pass(label, PassKind::Compute, Vec::new());
         super::ComputeCommandEncoder {
-            cmd_buf: self.buffers[0],
+            cmd_buf: &self.buffers[0],
             device: &self.device,
             update_data: &mut self.update_data,
+            tracker: &mut self.tracker,
         }
     }
 
-    pub fn render(&mut self, targets: crate::RenderTargetSet) -> super::RenderCommandEncoder {
-        self.barrier();
-        self.mark("pass/render");
+    pub fn render(&mut self, targets: crate::RenderTargetSet) -> super::RenderCommandEncoder<'_> {
+        self.render_labeled("render", targets)
+    }
+
+    /// Same as `render`, but the pass is labeled for debugging and timing.
+    pub fn render_labeled(
+        &mut self,
+        label: &str,
+        targets: crate::RenderTargetSet,
+    ) -> super::RenderCommandEncoder<'_> {
+        self.check_command_type(label, &[crate::CommandType::General]);
+
+        // Move the targets into the attachment layouts, keeping the ones
+        // that are already there from the previous render passes.
+        let mut image_barriers = Vec::new();
+        let mut attachments = Vec::new();
+        let mut writes = vk::AccessFlags::empty();
+        for rt in targets.colors.iter().chain(targets.depth_stencil.as_ref()) {
+            let mut views = vec![(&rt.view, matches!(rt.init_op, crate::InitOp::Clear(_)))];
+            if let crate::FinishOp::ResolveTo(ref resolve_view) = rt.finish_op {
+                // resolve overwrites the whole target
+                views.push((resolve_view, true));
+                writes |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+            }
+            writes |= if rt.view.aspects.contains(crate::TexelAspects::COLOR) {
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            } else {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            };
+            for (view, discard) in views {
+                let range = attachment_range(view);
+                let layout = attachment_layout(view);
+                let old_layout = match self.attachments.iter().position(|attachment| {
+                    attachment.image == view.image
+                        && attachment.range.base_mip_level == range.base_mip_level
+                        && attachment.range.base_array_layer == range.base_array_layer
+                        && attachment.range.layer_count == range.layer_count
+                }) {
+                    Some(index) => self.attachments.swap_remove(index).layout,
+                    None if discard => vk::ImageLayout::UNDEFINED,
+                    None => vk::ImageLayout::GENERAL,
+                };
+                if old_layout != layout {
+                    image_barriers.push(
+                        vk::ImageMemoryBarrier::builder()
+                            .old_layout(old_layout)
+                            .new_layout(layout)
+                            .image(view.image)
+                            .subresource_range(range)
+                            .build(),
+                    );
+                }
+                attachments.push(super::Attachment {
+                    image: view.image,
+                    range,
+                    layout,
+                });
+            }
+        }
+        self.begin_pass(label, PassKind::Render, image_barriers);
+        self.attachments = attachments;
+        self.tracker.usage.access |= writes;
 
         let mut target_size = [0u16; 2];
         let mut color_attachments = Vec::with_capacity(targets.colors.len());
-        let depth_stencil_attachment;
+        let mut depth_attachment = None;
+        let mut stencil_attachment = None;
         for rt in targets.colors {
             target_size = rt.view.target_size;
             color_attachments.push(map_render_target(rt));
         }
-
-        let mut rendering_info = vk::RenderingInfoKHR::builder()
-            .layer_count(1)
-            .color_attachments(&color_attachments);
         if let Some(rt) = targets.depth_stencil {
             target_size = rt.view.target_size;
-            depth_stencil_attachment = map_render_target(&rt);
+            let depth_stencil_attachment = map_render_target(&rt);
             if rt.view.aspects.contains(crate::TexelAspects::DEPTH) {
-                rendering_info = rendering_info.depth_attachment(&depth_stencil_attachment);
+                depth_attachment = Some(depth_stencil_attachment);
             }
             if rt.view.aspects.contains(crate::TexelAspects::STENCIL) {
-                rendering_info = rendering_info.stencil_attachment(&depth_stencil_attachment);
+                stencil_attachment = Some(depth_stencil_attachment);
             }
         }
 
-        let render_area = vk::Rect2D {
-            offset: Default::default(),
-            extent: vk::Extent2D {
-                width: target_size[0] as u32,
-                height: target_size[1] as u32,
-            },
-        };
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: target_size[1] as f32,
-            width: target_size[0] as f32,
-            height: -(target_size[1] as f32),
-            min_depth: 0.0,
-            max_depth: 1.0,
-        };
-        rendering_info = rendering_info.render_area(render_area);
-
-        let cmd_buf = self.buffers[0];
+        let render_area = render_area(target_size);
+        let viewport = viewport(target_size);
+        let cmd_buf = &self.buffers[0];
         unsafe {
             self.device
                 .core
@@ -406,28 +932,43 @@ impl super::CommandEncoder {
             self.device
                 .core
                 .cmd_set_scissor(cmd_buf.raw, 0, &[render_area]);
-            self.device
-                .dynamic_rendering
-                .cmd_begin_rendering(cmd_buf.raw, &rendering_info);
         };
 
         super::RenderCommandEncoder {
             cmd_buf,
             device: &self.device,
             update_data: &mut self.update_data,
+            tracker: &mut self.tracker,
+            color_attachments,
+            depth_attachment,
+            stencil_attachment,
+            render_area,
+            viewport,
+            scissor: render_area,
+            contents: None,
         }
     }
 
-    pub(super) fn check_gpu_crash<T>(&self, ret: Result<T, vk::Result>) -> T {
+    pub(super) fn check_gpu_crash<T>(&self, ret: Result<T, vk::Result>, queue: vk::Queue) -> T {
         match ret {
             Ok(value) => value,
             Err(vk::Result::ERROR_DEVICE_LOST) => match self.crash_handler {
                 Some(ref ch) => {
-                    let last_id = unsafe { *(ch.marker_buf.data() as *mut u32) };
-                    if last_id != 0 {
-                        let (history, last_marker) = ch.extract(last_id);
-                        log::error!("Last GPU executed marker is '{last_marker}'");
-                        log::info!("Marker history: {}", history);
+                    let last_id = match *self.device.marker_writer.as_ref().unwrap() {
+                        // The queue may have passed the checkpoints of another encoder last
+                        super::MarkerWriter::Checkpoints(ref fns) => {
+                            ch.checkpoint_marker(last_checkpoint(fns, queue))
+                        }
+                        super::MarkerWriter::BufferMarker(_) | super::MarkerWriter::FillBuffer => unsafe {
+                            Some(*(ch.marker_buf.data() as *mut u32))
+                        },
+                    };
+                    match last_id.filter(|&id| id != 0).and_then(|id| ch.extract(id)) {
+                        Some((history, last_marker)) => {
+                            log::error!("Last GPU executed marker is '{last_marker}'");
+                            log::info!("Marker history: {}", history);
+                        }
+                        None => log::error!("No GPU executed marker is known for {}", ch.name),
                     }
                     panic!("GPU has crashed in {}", ch.name);
                 }
@@ -440,9 +981,53 @@ impl super::CommandEncoder {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::CommandEncoder {
+    fn push_debug_group(&mut self, label: &str) {
+        self.device.begin_debug_label(self.buffers[0].raw, label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.device.end_debug_label(self.buffers[0].raw);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.device.insert_debug_label(self.buffers[0].raw, label);
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::TransferCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.device.begin_debug_label(self.raw, label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.device.end_debug_label(self.raw);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.device.insert_debug_label(self.raw, label);
+    }
+}
+
+impl super::TransferCommandEncoder<'_> {
+    fn access(&mut self, accesses: &[(u64, bool)]) {
+        let dst = pass_usage(PassKind::Transfer, self.device.ray_tracing.is_some());
+        self.tracker.access(
+            self.device,
+            self.raw,
+            dst,
+            vk::AccessFlags::TRANSFER_WRITE,
+            accesses,
+        );
+    }
+}
+
 #[hidden_trait::expose]
 impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
     fn fill_buffer(&mut self, dst: crate::BufferPiece, size: u64, value: u8) {
+        self.access(&[(dst.buffer.raw.as_raw(), true)]);
         let value_u32 = (value as u32) * 0x1010101;
         unsafe {
             self.device
@@ -457,6 +1042,10 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
         dst: crate::BufferPiece,
         size: u64,
     ) {
+        self.access(&[
+            (src.buffer.raw.as_raw(), false),
+            (dst.buffer.raw.as_raw(), true),
+        ]);
         let copy = vk::BufferCopy {
             src_offset: src.offset,
             dst_offset: dst.offset,
@@ -475,10 +1064,14 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
         dst: crate::TexturePiece,
         size: crate::Extent,
     ) {
+        self.access(&[
+            (src.texture.raw.as_raw(), false),
+            (dst.texture.raw.as_raw(), true),
+        ]);
         let copy = vk::ImageCopy {
-            src_subresource: src.subresource_layers(crate::TexelAspects::all()),
+            src_subresource: src.subresource_layers(src.texture.format.select_aspects(src.aspects)),
             src_offset: map_origin(&src.origin),
-            dst_subresource: dst.subresource_layers(crate::TexelAspects::all()),
+            dst_subresource: dst.subresource_layers(dst.texture.format.select_aspects(dst.aspects)),
             dst_offset: map_origin(&dst.origin),
             extent: super::map_extent_3d(&size),
         };
@@ -501,6 +1094,10 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
         dst: crate::TexturePiece,
         size: crate::Extent,
     ) {
+        self.access(&[
+            (src.buffer.raw.as_raw(), false),
+            (dst.texture.raw.as_raw(), true),
+        ]);
         let copy = make_buffer_image_copy(&src, bytes_per_row, &dst, &size);
         unsafe {
             self.device.core.cmd_copy_buffer_to_image(
@@ -520,6 +1117,10 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
         bytes_per_row: u32,
         size: crate::Extent,
     ) {
+        self.access(&[
+            (src.texture.raw.as_raw(), false),
+            (dst.buffer.raw.as_raw(), true),
+        ]);
         let copy = make_buffer_image_copy(&dst, bytes_per_row, &src, &size);
         unsafe {
             self.device.core.cmd_copy_image_to_buffer(
@@ -533,6 +1134,93 @@ impl crate::traits::TransferEncoder for super::TransferCommandEncoder<'_> {
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::AccelerationStructureCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.device.begin_debug_label(self.cmd_buf.raw, label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.device.end_debug_label(self.cmd_buf.raw);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.device.insert_debug_label(self.cmd_buf.raw, label);
+    }
+}
+
+impl super::AccelerationStructure {
+    /// Storage of the structure, as seen by the software build.
+    fn piece(&self) -> crate::BufferPiece {
+        super::Buffer {
+            raw: self.buffer,
+            memory_handle: self.memory_handle,
+            mapped_data: ptr::null_mut(),
+        }
+        .at(0)
+    }
+}
+
+impl software_rt::BuildEncoder for super::AccelerationStructureCommandEncoder<'_> {
+    fn has_memory(piece: &crate::BufferPiece) -> bool {
+        piece.buffer.raw != vk::Buffer::null()
+    }
+
+    fn dispatch<D: crate::ShaderData>(
+        &mut self,
+        pipeline: &super::ComputePipeline,
+        data: &D,
+        groups: [u32; 3],
+    ) {
+        let mut pc = super::PipelineEncoder {
+            cmd_buf: self.cmd_buf,
+            layout: &pipeline.layout,
+            bind_point: vk::PipelineBindPoint::COMPUTE,
+            device: self.device,
+            update_data: self.update_data,
+            scissor: None,
+            // the builds wait for everything, and synchronize the steps below
+            tracker: None,
+        }
+        .init(pipeline.raw);
+        pc.bind(0, data);
+        pc.dispatch(groups);
+
+        use vk::{AccessFlags as Af, PipelineStageFlags as Psf};
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(Af::SHADER_WRITE | Af::TRANSFER_WRITE)
+            .dst_access_mask(Af::SHADER_READ | Af::SHADER_WRITE)
+            .build();
+        unsafe {
+            self.device.core.cmd_pipeline_barrier(
+                self.cmd_buf.raw,
+                Psf::COMPUTE_SHADER | Psf::TRANSFER,
+                Psf::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    fn copy_buffer(&mut self, src: crate::BufferPiece, dst: crate::BufferPiece, size: u64) {
+        let copy = vk::BufferCopy {
+            src_offset: src.offset,
+            dst_offset: dst.offset,
+            size,
+        };
+        unsafe {
+            self.device.core.cmd_copy_buffer(
+                self.cmd_buf.raw,
+                src.buffer.raw,
+                dst.buffer.raw,
+                &[copy],
+            )
+        };
+    }
+}
+
 #[hidden_trait::expose]
 impl crate::traits::AccelerationStructureEncoder
     for super::AccelerationStructureCommandEncoder<'_>
@@ -543,6 +1231,27 @@ impl crate::traits::AccelerationStructureEncoder
         meshes: &[crate::AccelerationStructureMesh],
         scratch_data: crate::BufferPiece,
     ) {
+        let mut accesses = vec![
+            (acceleration_structure.buffer.as_raw(), true),
+            (scratch_data.buffer.raw.as_raw(), true),
+        ];
+        for mesh in meshes {
+            accesses.push((mesh.vertex_data.buffer.raw.as_raw(), false));
+            accesses.push((mesh.index_data.buffer.raw.as_raw(), false));
+            accesses.push((mesh.transform_data.buffer.raw.as_raw(), false));
+        }
+        self.tracker.record(&accesses);
+        if let Some(ref software) = self.device.software_rt {
+            let pipelines = software.pipelines.get();
+            software_rt::build_bottom_level(
+                self,
+                &pipelines,
+                meshes,
+                acceleration_structure.piece(),
+                scratch_data,
+            );
+            return;
+        }
         let mut blas_input = self.device.map_acceleration_structure_meshes(meshes);
         blas_input.build_info.dst_acceleration_structure = acceleration_structure.raw;
         let scratch_address = self.device.get_device_address(&scratch_data);
@@ -557,7 +1266,7 @@ impl crate::traits::AccelerationStructureEncoder
         let rt = self.device.ray_tracing.as_ref().unwrap();
         unsafe {
             rt.acceleration_structure.cmd_build_acceleration_structures(
-                self.raw,
+                self.cmd_buf.raw,
                 &[blas_input.build_info],
                 &[&blas_input.build_range_infos],
             );
@@ -567,11 +1276,51 @@ impl crate::traits::AccelerationStructureEncoder
     fn build_top_level(
         &mut self,
         acceleration_structure: super::AccelerationStructure,
-        _bottom_level: &[super::AccelerationStructure],
+        bottom_level: &[super::AccelerationStructure],
         instance_count: u32,
         instance_data: crate::BufferPiece,
         scratch_data: crate::BufferPiece,
     ) {
+        let mut accesses = vec![
+            (acceleration_structure.buffer.as_raw(), true),
+            (scratch_data.buffer.raw.as_raw(), true),
+            (instance_data.buffer.raw.as_raw(), false),
+        ];
+        for blas in bottom_level {
+            accesses.push((blas.buffer.as_raw(), false));
+        }
+        self.tracker.record(&accesses);
+        if let Some(ref software) = self.device.software_rt {
+            let bottom_level = bottom_level
+                .iter()
+                .map(|blas| (blas.piece(), blas.size))
+                .collect::<Vec<_>>();
+            let sizes = bottom_level
+                .iter()
+                .map(|&(_, size)| size)
+                .collect::<Vec<_>>();
+            // The storage can't grow, so the build is skipped if the copies don't fit
+            let required = software_rt::top_level_size(instance_count, &sizes);
+            if required > acceleration_structure.size {
+                log::error!(
+                    "Top level acceleration structure of {} bytes needs {} for the bottom level copies",
+                    acceleration_structure.size,
+                    required
+                );
+                return;
+            }
+            let pipelines = software.pipelines.get();
+            software_rt::build_top_level(
+                self,
+                &pipelines,
+                &bottom_level,
+                instance_count,
+                instance_data,
+                acceleration_structure.piece(),
+                scratch_data,
+            );
+            return;
+        }
         let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR {
             primitive_count: instance_count,
             primitive_offset: 0,
@@ -602,7 +1351,7 @@ impl crate::traits::AccelerationStructureEncoder
         let rt = self.device.ray_tracing.as_ref().unwrap();
         unsafe {
             rt.acceleration_structure.cmd_build_acceleration_structures(
-                self.raw,
+                self.cmd_buf.raw,
                 &[build_info],
                 &[&[build_range_info]],
             );
@@ -610,6 +1359,21 @@ impl crate::traits::AccelerationStructureEncoder
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::ComputeCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.device.begin_debug_label(self.cmd_buf.raw, label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.device.end_debug_label(self.cmd_buf.raw);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.device.insert_debug_label(self.cmd_buf.raw, label);
+    }
+}
+
 impl<'a> super::ComputeCommandEncoder<'a> {
     pub fn with<'b, 'p>(
         &'b mut self,
@@ -621,12 +1385,107 @@ impl<'a> super::ComputeCommandEncoder<'a> {
             bind_point: vk::PipelineBindPoint::COMPUTE,
             device: self.device,
             update_data: self.update_data,
+            scissor: None,
+            tracker: Some(self.tracker),
         }
         .init(pipeline.raw)
     }
 }
 
+#[hidden_trait::expose]
+impl crate::traits::DebugEncoder for super::RenderCommandEncoder<'_> {
+    fn push_debug_group(&mut self, label: &str) {
+        self.device.begin_debug_label(self.cmd_buf.raw, label);
+    }
+
+    fn pop_debug_group(&mut self) {
+        self.device.end_debug_label(self.cmd_buf.raw);
+    }
+
+    fn insert_debug_marker(&mut self, label: &str) {
+        self.device.insert_debug_label(self.cmd_buf.raw, label);
+    }
+}
+
 impl<'a> super::RenderCommandEncoder<'a> {
+    /// Make sure a rendering instance accepting the given contents is active.
+    ///
+    /// Inline commands and bundles can't be mixed within one instance,
+    /// so the pass is split, with the following instances loading
+    /// what the previous ones have stored.
+    fn begin_rendering(&mut self, contents: vk::RenderingFlags) {
+        match self.contents {
+            Some(active) if active == contents => return,
+            Some(_) => {
+                for attachment in self
+                    .color_attachments
+                    .iter_mut()
+                    .chain(self.depth_attachment.as_mut())
+                    .chain(self.stencil_attachment.as_mut())
+                {
+                    attachment.load_op = vk::AttachmentLoadOp::LOAD;
+                }
+                let stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+                let barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .dst_access_mask(
+                        vk::AccessFlags::COLOR_ATTACHMENT_READ
+                            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .build();
+                unsafe {
+                    self.device
+                        .dynamic_rendering
+                        .cmd_end_rendering(self.cmd_buf.raw);
+                    self.device.core.cmd_pipeline_barrier(
+                        self.cmd_buf.raw,
+                        stages,
+                        stages,
+                        vk::DependencyFlags::BY_REGION,
+                        &[barrier],
+                        &[],
+                        &[],
+                    );
+                    // the dynamic state is undefined after executing the bundles
+                    if contents.is_empty() {
+                        self.device
+                            .core
+                            .cmd_set_viewport(self.cmd_buf.raw, 0, &[self.viewport]);
+                        self.device
+                            .core
+                            .cmd_set_scissor(self.cmd_buf.raw, 0, &[self.scissor]);
+                    }
+                }
+            }
+            None => {}
+        }
+
+        let mut rendering_info = vk::RenderingInfoKHR::builder()
+            .flags(contents)
+            .layer_count(1)
+            .render_area(self.render_area)
+            .color_attachments(&self.color_attachments);
+        if let Some(ref attachment) = self.depth_attachment {
+            rendering_info = rendering_info.depth_attachment(attachment);
+        }
+        if let Some(ref attachment) = self.stencil_attachment {
+            rendering_info = rendering_info.stencil_attachment(attachment);
+        }
+        unsafe {
+            self.device
+                .dynamic_rendering
+                .cmd_begin_rendering(self.cmd_buf.raw, &rendering_info);
+        }
+        self.contents = Some(contents);
+    }
+
     pub fn set_scissor_rect(&mut self, rect: &crate::ScissorRect) {
         let vk_scissor = vk::Rect2D {
             offset: vk::Offset2D {
@@ -643,25 +1502,51 @@ impl<'a> super::RenderCommandEncoder<'a> {
                 .core
                 .cmd_set_scissor(self.cmd_buf.raw, 0, &[vk_scissor])
         };
+        self.scissor = vk_scissor;
     }
 
     pub fn with<'b, 'p>(
         &'b mut self,
         pipeline: &'p super::RenderPipeline,
     ) -> super::PipelineEncoder<'b, 'p> {
+        self.begin_rendering(vk::RenderingFlags::empty());
         super::PipelineEncoder {
             cmd_buf: self.cmd_buf,
             layout: &pipeline.layout,
             bind_point: vk::PipelineBindPoint::GRAPHICS,
             device: self.device,
             update_data: self.update_data,
+            scissor: Some(&mut self.scissor),
+            tracker: Some(self.tracker),
         }
         .init(pipeline.raw)
     }
+
+    pub fn execute_bundle(&mut self, bundle: &super::RenderBundle) {
+        assert_eq!(
+            bundle.extent, self.render_area.extent,
+            "Bundle size doesn't match the render targets"
+        );
+        self.begin_rendering(vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS);
+        if bundle.writable {
+            self.tracker.usage.access |= vk::AccessFlags::SHADER_WRITE;
+        }
+        // the resources of the bundle are not known
+        self.tracker.untracked = true;
+        unsafe {
+            self.device
+                .core
+                .cmd_execute_commands(self.cmd_buf.raw, &[bundle.cmd_buf.raw])
+        };
+    }
 }
 
 impl Drop for super::RenderCommandEncoder<'_> {
     fn drop(&mut self) {
+        // the attachments still need to be initialized and stored
+        if self.contents.is_none() {
+            self.begin_rendering(vk::RenderingFlags::empty());
+        }
         unsafe {
             self.device
                 .dynamic_rendering
@@ -670,6 +1555,149 @@ impl Drop for super::RenderCommandEncoder<'_> {
     }
 }
 
+impl super::Context {
+    pub fn try_create_render_bundle_encoder(
+        &self,
+        desc: crate::RenderBundleDesc,
+    ) -> Result<super::RenderBundleEncoder, crate::Error> {
+        //TODO: this number is arbitrary, same as for the command encoders
+        const ROUGH_SET_COUNT: u32 = 1000;
+
+        let pool_info = vk::CommandPoolCreateInfo::builder().queue_family_index(
+            self.queue_families[self.queue_index(&crate::CommandType::General)],
+        );
+        let pool = unsafe {
+            self.device
+                .core
+                .create_command_pool(&pool_info, None)
+                .map_err(super::map_error)?
+        };
+        let cmd_buf_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+        let raw = match unsafe { self.device.core.allocate_command_buffers(&cmd_buf_info) } {
+            Ok(cmd_bufs) => cmd_bufs[0],
+            Err(error) => {
+                unsafe { self.device.core.destroy_command_pool(pool, None) };
+                return Err(super::map_error(error));
+            }
+        };
+        if !desc.name.is_empty() {
+            self.set_object_name(vk::ObjectType::COMMAND_BUFFER, raw, desc.name);
+        }
+
+        let color_formats = desc
+            .color_formats
+            .iter()
+            .map(|&format| self.device.map_texture_format(format))
+            .collect::<Vec<_>>();
+        let mut depth_format = vk::Format::UNDEFINED;
+        let mut stencil_format = vk::Format::UNDEFINED;
+        if let Some(format) = desc.depth_stencil_format {
+            let aspects = format.aspects();
+            if aspects.contains(crate::TexelAspects::DEPTH) {
+                depth_format = self.device.map_texture_format(format);
+            }
+            if aspects.contains(crate::TexelAspects::STENCIL) {
+                stencil_format = self.device.map_texture_format(format);
+            }
+        }
+        let mut inheritance_rendering_info =
+            vk::CommandBufferInheritanceRenderingInfoKHR::builder()
+                .color_attachment_formats(&color_formats)
+                .depth_attachment_format(depth_format)
+                .stencil_attachment_format(stencil_format)
+                .rasterization_samples(vk::SampleCountFlags::from_raw(desc.sample_count));
+        let inheritance_info =
+            vk::CommandBufferInheritanceInfo::builder().push_next(&mut inheritance_rendering_info);
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            )
+            .inheritance_info(&inheritance_info);
+        let target_size = [desc.size.width as u16, desc.size.height as u16];
+        unsafe {
+            self.device
+                .core
+                .begin_command_buffer(raw, &begin_info)
+                .unwrap();
+            self.device
+                .core
+                .cmd_set_viewport(raw, 0, &[viewport(target_size)]);
+            self.device
+                .core
+                .cmd_set_scissor(raw, 0, &[render_area(target_size)]);
+        }
+
+        Ok(super::RenderBundleEncoder {
+            pool,
+            cmd_buf: super::CommandBuffer {
+                raw,
+                descriptor_pool: self.create_descriptor_pool(ROUGH_SET_COUNT),
+                query_pool: vk::QueryPool::null(),
+                timed_pass_names: Vec::new(),
+                uniform_ring: self.create_uniform_ring(),
+                submission: None,
+            },
+            device: self.device.clone(),
+            update_data: Vec::new(),
+            writable: false,
+            extent: render_area(target_size).extent,
+        })
+    }
+
+    pub fn destroy_render_bundle(&self, bundle: super::RenderBundle) {
+        unsafe {
+            self.device
+                .core
+                .free_command_buffers(bundle.pool, &[bundle.cmd_buf.raw]);
+            self.device
+                .core
+                .destroy_descriptor_pool(bundle.cmd_buf.descriptor_pool, None);
+            self.device.core.destroy_command_pool(bundle.pool, None);
+        }
+        if let Some(ref ring) = bundle.cmd_buf.uniform_ring {
+            ring.destroy(&self.device);
+        }
+    }
+}
+
+impl super::RenderBundleEncoder {
+    pub fn with<'b, 'p>(
+        &'b mut self,
+        pipeline: &'p super::RenderPipeline,
+    ) -> super::PipelineEncoder<'b, 'p> {
+        self.writable |= pipeline.layout.writable;
+        super::PipelineEncoder {
+            cmd_buf: &self.cmd_buf,
+            layout: &pipeline.layout,
+            bind_point: vk::PipelineBindPoint::GRAPHICS,
+            device: &self.device,
+            update_data: &mut self.update_data,
+            scissor: None,
+            tracker: None,
+        }
+        .init(pipeline.raw)
+    }
+
+    pub fn finish(self) -> super::RenderBundle {
+        unsafe {
+            self.device
+                .core
+                .end_command_buffer(self.cmd_buf.raw)
+                .unwrap()
+        };
+        super::RenderBundle {
+            pool: self.pool,
+            cmd_buf: self.cmd_buf,
+            writable: self.writable,
+            extent: self.extent,
+        }
+    }
+}
+
 impl super::PipelineEncoder<'_, '_> {
     fn init(self, raw_pipeline: vk::Pipeline) -> Self {
         unsafe {
@@ -679,6 +1707,29 @@ impl super::PipelineEncoder<'_, '_> {
         };
         self
     }
+
+    /// Track the resources of the following commands.
+    /// Barriers are not allowed in the render passes,
+    /// which already waited for everything at the start.
+    fn access(&mut self, accesses: &[(u64, bool)]) {
+        if let Some(ref mut tracker) = self.tracker {
+            if self.bind_point == vk::PipelineBindPoint::COMPUTE {
+                let dst = pass_usage(PassKind::Compute, self.device.ray_tracing.is_some());
+                tracker.access(
+                    self.device,
+                    self.cmd_buf.raw,
+                    dst,
+                    vk::AccessFlags::SHADER_WRITE,
+                    accesses,
+                );
+            } else {
+                if accesses.iter().any(|&(_, write)| write) {
+                    tracker.usage.access |= vk::AccessFlags::SHADER_WRITE;
+                }
+                tracker.record(accesses);
+            }
+        }
+    }
 }
 
 #[hidden_trait::expose]
@@ -687,10 +1738,44 @@ impl crate::traits::PipelineEncoder for super::PipelineEncoder<'_, '_> {
         let dsl = &self.layout.descriptor_set_layouts[group as usize];
         self.update_data.clear();
         self.update_data.resize(dsl.template_size as usize, 0);
+        let mut resources = Vec::new();
         data.fill(super::PipelineContext {
             update_data: self.update_data.as_mut_slice(),
             template_offsets: &dsl.template_offsets,
+            resources: &mut resources,
         });
+        let accesses = resources
+            .iter()
+            .map(|&(index, handle)| (handle, dsl.writable_bindings[index as usize]))
+            .collect::<Vec<_>>();
+
+        for ub in dsl.uniform_buffers.iter() {
+            let ring = self.cmd_buf.uniform_ring.as_ref().unwrap();
+            let (buffer, offset) = ring.allocate(
+                self.device,
+                ub.size as u64,
+                self.device.uniform_buffer_alignment,
+            );
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer: buffer.raw,
+                offset,
+                range: ub.size as u64,
+            };
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    self.update_data.as_ptr().add(ub.data_offset as usize),
+                    buffer.data().add(offset as usize),
+                    ub.size as usize,
+                );
+                ptr::write_unaligned(
+                    self.update_data
+                        .as_mut_ptr()
+                        .add(ub.descriptor_offset as usize)
+                        as *mut vk::DescriptorBufferInfo,
+                    buffer_info,
+                );
+            }
+        }
 
         let descriptor_set_layouts = [dsl.raw];
         let descriptor_set_info = vk::DescriptorSetAllocateInfo::builder()
@@ -716,6 +1801,7 @@ impl crate::traits::PipelineEncoder for super::PipelineEncoder<'_, '_> {
                 &[],
             );
         }
+        self.access(&accesses);
     }
 }
 
@@ -728,6 +1814,17 @@ impl crate::traits::ComputePipelineEncoder for super::PipelineEncoder<'_, '_> {
                 .cmd_dispatch(self.cmd_buf.raw, groups[0], groups[1], groups[2])
         };
     }
+
+    fn dispatch_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.access(&[(indirect_buf.buffer.raw.as_raw(), false)]);
+        unsafe {
+            self.device.core.cmd_dispatch_indirect(
+                self.cmd_buf.raw,
+                indirect_buf.buffer.raw,
+                indirect_buf.offset,
+            )
+        };
+    }
 }
 
 #[hidden_trait::expose]
@@ -748,6 +1845,31 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_, '_> {
                 .core
                 .cmd_set_scissor(self.cmd_buf.raw, 0, &[vk_scissor])
         };
+        if let Some(ref mut scissor) = self.scissor {
+            **scissor = vk_scissor;
+        }
+    }
+
+    fn set_stencil_reference(&mut self, reference: u32) {
+        unsafe {
+            self.device.core.cmd_set_stencil_reference(
+                self.cmd_buf.raw,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                reference,
+            )
+        };
+    }
+
+    fn bind_vertex_buffer(&mut self, index: u32, vertex_buf: crate::BufferPiece) {
+        self.access(&[(vertex_buf.buffer.raw.as_raw(), false)]);
+        unsafe {
+            self.device.core.cmd_bind_vertex_buffers(
+                self.cmd_buf.raw,
+                index,
+                &[vertex_buf.buffer.raw],
+                &[vertex_buf.offset],
+            );
+        }
     }
 
     fn draw(
@@ -777,6 +1899,7 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_, '_> {
         start_instance: u32,
         instance_count: u32,
     ) {
+        self.access(&[(index_buf.buffer.raw.as_raw(), false)]);
         let raw_index_type = super::map_index_type(index_type);
         unsafe {
             self.device.core.cmd_bind_index_buffer(
@@ -797,6 +1920,7 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_, '_> {
     }
 
     fn draw_indirect(&mut self, indirect_buf: crate::BufferPiece) {
+        self.access(&[(indirect_buf.buffer.raw.as_raw(), false)]);
         unsafe {
             self.device.core.cmd_draw_indirect(
                 self.cmd_buf.raw,
@@ -814,6 +1938,10 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_, '_> {
         index_type: crate::IndexType,
         indirect_buf: crate::BufferPiece,
     ) {
+        self.access(&[
+            (index_buf.buffer.raw.as_raw(), false),
+            (indirect_buf.buffer.raw.as_raw(), false),
+        ]);
         let raw_index_type = super::map_index_type(index_type);
         unsafe {
             self.device.core.cmd_bind_index_buffer(
@@ -831,4 +1959,104 @@ impl crate::traits::RenderPipelineEncoder for super::PipelineEncoder<'_, '_> {
             );
         }
     }
+
+    fn multi_draw_indirect(
+        &mut self,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<crate::BufferPiece>,
+    ) {
+        self.access(&[(indirect_buf.buffer.raw.as_raw(), false)]);
+        if let Some(ref count_buf) = count_buf {
+            self.access(&[(count_buf.buffer.raw.as_raw(), false)]);
+        }
+        let stride = size_of::<crate::DrawIndirectArgs>() as u32;
+        unsafe {
+            match (count_buf, self.device.draw_indirect_count.as_ref()) {
+                (Some(count_buf), Some(ext)) => ext.cmd_draw_indirect_count(
+                    self.cmd_buf.raw,
+                    indirect_buf.buffer.raw,
+                    indirect_buf.offset,
+                    count_buf.buffer.raw,
+                    count_buf.offset,
+                    max_draw_count,
+                    stride,
+                ),
+                _ if self.device.multi_draw_indirect => self.device.core.cmd_draw_indirect(
+                    self.cmd_buf.raw,
+                    indirect_buf.buffer.raw,
+                    indirect_buf.offset,
+                    max_draw_count,
+                    stride,
+                ),
+                _ => {
+                    for i in 0..max_draw_count {
+                        self.device.core.cmd_draw_indirect(
+                            self.cmd_buf.raw,
+                            indirect_buf.buffer.raw,
+                            indirect_buf.offset + (i * stride) as u64,
+                            1,
+                            stride,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn multi_draw_indexed_indirect(
+        &mut self,
+        index_buf: crate::BufferPiece,
+        index_type: crate::IndexType,
+        indirect_buf: crate::BufferPiece,
+        max_draw_count: u32,
+        count_buf: Option<crate::BufferPiece>,
+    ) {
+        self.access(&[
+            (index_buf.buffer.raw.as_raw(), false),
+            (indirect_buf.buffer.raw.as_raw(), false),
+        ]);
+        if let Some(ref count_buf) = count_buf {
+            self.access(&[(count_buf.buffer.raw.as_raw(), false)]);
+        }
+        let raw_index_type = super::map_index_type(index_type);
+        let stride = size_of::<crate::DrawIndexedIndirectArgs>() as u32;
+        unsafe {
+            self.device.core.cmd_bind_index_buffer(
+                self.cmd_buf.raw,
+                index_buf.buffer.raw,
+                index_buf.offset,
+                raw_index_type,
+            );
+            match (count_buf, self.device.draw_indirect_count.as_ref()) {
+                (Some(count_buf), Some(ext)) => ext.cmd_draw_indexed_indirect_count(
+                    self.cmd_buf.raw,
+                    indirect_buf.buffer.raw,
+                    indirect_buf.offset,
+                    count_buf.buffer.raw,
+                    count_buf.offset,
+                    max_draw_count,
+                    stride,
+                ),
+                _ if self.device.multi_draw_indirect => self.device.core.cmd_draw_indexed_indirect(
+                    self.cmd_buf.raw,
+                    indirect_buf.buffer.raw,
+                    indirect_buf.offset,
+                    max_draw_count,
+                    stride,
+                ),
+                _ => {
+                    for i in 0..max_draw_count {
+                        self.device.core.cmd_draw_indexed_indirect(
+                            self.cmd_buf.raw,
+                            indirect_buf.buffer.raw,
+                            indirect_buf.offset + (i * stride) as u64,
+                            1,
+                            stride,
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
@@ -3,23 +3,38 @@ use ash::{
     vk,
 };
 use naga::back::spv;
-use std::{ffi, mem, sync::Mutex};
+use std::{
+    ffi, mem,
+    sync::{Arc, Mutex},
+};
 
 const REQUIRED_DEVICE_EXTENSIONS: &[&ffi::CStr] = &[
-    vk::ExtInlineUniformBlockFn::name(),
     vk::KhrTimelineSemaphoreFn::name(),
     vk::KhrDescriptorUpdateTemplateFn::name(),
     vk::KhrDynamicRenderingFn::name(),
 ];
 
+/// Plain data larger than this goes through the uniform ring,
+/// even if the adapter can fit it into an inline uniform block.
+const MAX_INLINE_UNIFORM_BLOCK_SIZE: u32 = 256;
+
 #[derive(Debug)]
 struct AdapterCapabilities {
     api_version: u32,
     properties: vk::PhysicalDeviceProperties,
     layered: bool,
+    inline_uniform_block_size: u32,
+    subgroup_size: u32,
+    descriptor_indexing: bool,
     ray_tracing: bool,
     buffer_marker: bool,
+    checkpoints: bool,
+    memory_budget: bool,
     shader_info: bool,
+    draw_indirect_count: bool,
+    /// Backing format of `Depth24PlusStencil8`.
+    depth24_stencil8: vk::Format,
+    core_features: vk::PhysicalDeviceFeatures,
 }
 
 unsafe fn inspect_adapter(
@@ -55,12 +70,14 @@ unsafe fn inspect_adapter(
         vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
     let mut portability_subset_properties =
         vk::PhysicalDevicePortabilitySubsetPropertiesKHR::default();
+    let mut subgroup_properties = vk::PhysicalDeviceSubgroupProperties::default();
     let mut properties2_khr = vk::PhysicalDeviceProperties2KHR::builder()
         .push_next(&mut inline_uniform_block_properties)
         .push_next(&mut timeline_semaphore_properties)
         .push_next(&mut descriptor_indexing_properties)
         .push_next(&mut acceleration_structure_properties)
-        .push_next(&mut portability_subset_properties);
+        .push_next(&mut portability_subset_properties)
+        .push_next(&mut subgroup_properties);
     instance
         .get_physical_device_properties2
         .get_physical_device_properties2(phd, &mut properties2_khr);
@@ -96,23 +113,26 @@ unsafe fn inspect_adapter(
     instance
         .get_physical_device_properties2
         .get_physical_device_features2(phd, &mut features2_khr);
+    let supported_core_features = features2_khr.features;
 
     let properties = properties2_khr.properties;
     let name = ffi::CStr::from_ptr(properties.device_name.as_ptr());
     log::info!("Adapter {:?}", name);
 
-    if inline_uniform_block_properties.max_inline_uniform_block_size
-        < crate::limits::PLAIN_DATA_SIZE
+    let inline_uniform_block_size = if !supported_extensions
+        .contains(&vk::ExtInlineUniformBlockFn::name())
         || inline_uniform_block_properties.max_descriptor_set_inline_uniform_blocks == 0
         || inline_uniform_block_features.inline_uniform_block == 0
     {
-        log::warn!(
-            "\tRejected for inline uniform blocks. Properties = {:?}, Features = {:?}",
-            inline_uniform_block_properties,
-            inline_uniform_block_features,
+        log::info!(
+            "Inline uniform blocks are not supported, plain data goes through the uniform ring"
         );
-        return None;
-    }
+        0
+    } else {
+        inline_uniform_block_properties
+            .max_inline_uniform_block_size
+            .min(MAX_INLINE_UNIFORM_BLOCK_SIZE)
+    };
 
     if timeline_semaphore_features.timeline_semaphore == 0 {
         log::warn!(
@@ -131,10 +151,10 @@ unsafe fn inspect_adapter(
         return None;
     }
 
-    let ray_tracing = if !supported_extensions.contains(&vk::KhrAccelerationStructureFn::name())
-        || !supported_extensions.contains(&vk::KhrRayQueryFn::name())
+    let descriptor_indexing = if api_version < vk::API_VERSION_1_2
+        && !supported_extensions.contains(&vk::ExtDescriptorIndexingFn::name())
     {
-        log::info!("No ray tracing extensions are supported");
+        log::info!("Descriptor indexing extension is not supported");
         false
     } else if descriptor_indexing_properties.max_per_stage_update_after_bind_resources == vk::FALSE
         || descriptor_indexing_features.descriptor_binding_partially_bound == vk::FALSE
@@ -143,11 +163,23 @@ unsafe fn inspect_adapter(
         || descriptor_indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::FALSE
     {
         log::info!(
-            "No ray tracing because of the descriptor indexing. Properties = {:?}. Features = {:?}",
+            "No binding arrays because of the descriptor indexing. Properties = {:?}. Features = {:?}",
             descriptor_indexing_properties,
             descriptor_indexing_features
         );
         false
+    } else {
+        true
+    };
+
+    let ray_tracing = if !supported_extensions.contains(&vk::KhrAccelerationStructureFn::name())
+        || !supported_extensions.contains(&vk::KhrRayQueryFn::name())
+    {
+        log::info!("No ray tracing extensions are supported");
+        false
+    } else if !descriptor_indexing {
+        log::info!("No ray tracing because of the descriptor indexing");
+        false
     } else if buffer_device_address_features.buffer_device_address == vk::FALSE {
         log::info!(
             "No ray tracing because of the buffer device address. Features = {:?}",
@@ -173,140 +205,268 @@ unsafe fn inspect_adapter(
     };
 
     let buffer_marker = supported_extensions.contains(&vk::AmdBufferMarkerFn::name());
+    let checkpoints = supported_extensions.contains(&vk::NvDeviceDiagnosticCheckpointsFn::name());
+    let memory_budget = supported_extensions.contains(&vk::ExtMemoryBudgetFn::name());
     let shader_info = supported_extensions.contains(&vk::AmdShaderInfoFn::name());
+    let draw_indirect_count = supported_extensions.contains(&vk::KhrDrawIndirectCountFn::name());
+    // Either of the combined depth-stencil formats is guaranteed
+    let depth24_stencil8 = if instance
+        .core
+        .get_physical_device_format_properties(phd, vk::Format::D24_UNORM_S8_UINT)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    {
+        vk::Format::D24_UNORM_S8_UINT
+    } else {
+        log::info!("Depth24PlusStencil8 is backed by D32_SFLOAT_S8_UINT");
+        vk::Format::D32_SFLOAT_S8_UINT
+    };
+
+    // Compressed formats are enabled whenever available, it's up to the user
+    // to pick the ones supported by the platform.
+    let core_features = vk::PhysicalDeviceFeatures {
+        multi_draw_indirect: supported_core_features.multi_draw_indirect,
+        texture_compression_bc: supported_core_features.texture_compression_bc,
+        texture_compression_etc2: supported_core_features.texture_compression_etc2,
+        texture_compression_astc_ldr: supported_core_features.texture_compression_astc_ldr,
+        ..Default::default()
+    };
 
     Some(AdapterCapabilities {
         api_version,
         properties,
         layered: portability_subset_properties.min_vertex_input_binding_stride_alignment != 0,
+        inline_uniform_block_size,
+        subgroup_size: subgroup_properties.subgroup_size,
+        descriptor_indexing,
         ray_tracing,
         buffer_marker,
+        checkpoints,
+        memory_budget,
         shader_info,
+        draw_indirect_count,
+        depth24_stencil8,
+        core_features,
     })
 }
 
-impl super::Context {
-    unsafe fn init_impl(
-        desc: crate::ContextDesc,
-        surface_handles: Option<(
-            raw_window_handle::RawWindowHandle,
-            raw_window_handle::RawDisplayHandle,
-        )>,
-    ) -> Result<Self, crate::NotSupportedError> {
-        let entry = match ash::Entry::load() {
-            Ok(entry) => entry,
-            Err(err) => {
-                log::error!("Missing Vulkan entry points: {:?}", err);
-                return Err(crate::NotSupportedError);
-            }
-        };
-        let driver_api_version = match entry.try_enumerate_instance_version() {
-            // Vulkan 1.1+
-            Ok(Some(version)) => version,
-            Ok(None) => return Err(crate::NotSupportedError),
+/// Create the instance with the extensions needed for the given display.
+unsafe fn create_instance(
+    validation: bool,
+    display_handle: Option<raw_window_handle::RawDisplayHandle>,
+) -> Result<(ash::Entry, super::Instance, u32), crate::NotSupportedError> {
+    let entry = match ash::Entry::load() {
+        Ok(entry) => entry,
+        Err(err) => {
+            log::error!("Missing Vulkan entry points: {:?}", err);
+            return Err(crate::NotSupportedError);
+        }
+    };
+    let driver_api_version = match entry.try_enumerate_instance_version() {
+        // Vulkan 1.1+
+        Ok(Some(version)) => version,
+        Ok(None) => return Err(crate::NotSupportedError),
+        Err(err) => {
+            log::error!("try_enumerate_instance_version: {:?}", err);
+            return Err(crate::NotSupportedError);
+        }
+    };
+
+    let _supported_layers = match entry.enumerate_instance_layer_properties() {
+        Ok(layers) => layers,
+        Err(err) => {
+            log::error!("enumerate_instance_layer_properties: {:?}", err);
+            return Err(crate::NotSupportedError);
+        }
+    };
+
+    let mut layers: Vec<&'static ffi::CStr> = Vec::new();
+    if validation {
+        layers.push(ffi::CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap());
+    }
+
+    let supported_instance_extension_properties =
+        match entry.enumerate_instance_extension_properties(None) {
+            Ok(extensions) => extensions,
             Err(err) => {
-                log::error!("try_enumerate_instance_version: {:?}", err);
+                log::error!("enumerate_instance_extension_properties: {:?}", err);
                 return Err(crate::NotSupportedError);
             }
         };
+    let supported_instance_extensions = supported_instance_extension_properties
+        .iter()
+        .map(|ext_prop| ffi::CStr::from_ptr(ext_prop.extension_name.as_ptr()))
+        .collect::<Vec<_>>();
 
-        let _supported_layers = match entry.enumerate_instance_layer_properties() {
-            Ok(layers) => layers,
-            Err(err) => {
-                log::error!("enumerate_instance_layer_properties: {:?}", err);
+    let core_instance = {
+        let mut create_flags = vk::InstanceCreateFlags::empty();
+
+        let mut instance_extensions = vec![
+            ext::DebugUtils::name(),
+            vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+        ];
+        if let Some(rdh) = display_handle {
+            instance_extensions.extend(
+                ash_window::enumerate_required_extensions(rdh)
+                    .unwrap()
+                    .iter()
+                    .map(|&ptr| ffi::CStr::from_ptr(ptr)),
+            );
+        }
+
+        for inst_ext in instance_extensions.iter() {
+            if !supported_instance_extensions.contains(inst_ext) {
+                log::error!("Instance extension {:?} is not supported", inst_ext);
                 return Err(crate::NotSupportedError);
             }
-        };
-
-        let mut layers: Vec<&'static ffi::CStr> = Vec::new();
-        if desc.validation {
-            layers.push(ffi::CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap());
+        }
+        if supported_instance_extensions.contains(&vk::KhrPortabilityEnumerationFn::name()) {
+            instance_extensions.push(vk::KhrPortabilityEnumerationFn::name());
+            create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+        }
+        if display_handle.is_some()
+            && supported_instance_extensions.contains(&vk::ExtSwapchainColorspaceFn::name())
+        {
+            instance_extensions.push(vk::ExtSwapchainColorspaceFn::name());
         }
 
-        let supported_instance_extension_properties =
-            match entry.enumerate_instance_extension_properties(None) {
-                Ok(extensions) => extensions,
-                Err(err) => {
-                    log::error!("enumerate_instance_extension_properties: {:?}", err);
-                    return Err(crate::NotSupportedError);
-                }
-            };
-        let supported_instance_extensions = supported_instance_extension_properties
+        let app_info = vk::ApplicationInfo::builder()
+            .engine_name(ffi::CStr::from_bytes_with_nul(b"blade\0").unwrap())
+            .engine_version(1)
+            .api_version(vk::HEADER_VERSION_COMPLETE);
+        let str_pointers = layers
             .iter()
-            .map(|ext_prop| ffi::CStr::from_ptr(ext_prop.extension_name.as_ptr()))
+            .chain(instance_extensions.iter())
+            .map(|&s| s.as_ptr())
             .collect::<Vec<_>>();
+        let (layer_strings, extension_strings) = str_pointers.split_at(layers.len());
+        let create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .flags(create_flags)
+            .enabled_layer_names(layer_strings)
+            .enabled_extension_names(extension_strings);
+        entry.create_instance(&create_info, None).unwrap()
+    };
 
-        let core_instance = {
-            let mut create_flags = vk::InstanceCreateFlags::empty();
-
-            let mut instance_extensions = vec![
-                ext::DebugUtils::name(),
-                vk::KhrGetPhysicalDeviceProperties2Fn::name(),
-            ];
-            if let Some((_, rdh)) = surface_handles {
-                instance_extensions.extend(
-                    ash_window::enumerate_required_extensions(rdh)
-                        .unwrap()
-                        .iter()
-                        .map(|&ptr| ffi::CStr::from_ptr(ptr)),
-                );
-            }
-
-            for inst_ext in instance_extensions.iter() {
-                if !supported_instance_extensions.contains(inst_ext) {
-                    log::error!("Instance extension {:?} is not supported", inst_ext);
-                    return Err(crate::NotSupportedError);
-                }
-            }
-            if supported_instance_extensions.contains(&vk::KhrPortabilityEnumerationFn::name()) {
-                instance_extensions.push(vk::KhrPortabilityEnumerationFn::name());
-                create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
-            }
-
-            let app_info = vk::ApplicationInfo::builder()
-                .engine_name(ffi::CStr::from_bytes_with_nul(b"blade\0").unwrap())
-                .engine_version(1)
-                .api_version(vk::HEADER_VERSION_COMPLETE);
-            let str_pointers = layers
-                .iter()
-                .chain(instance_extensions.iter())
-                .map(|&s| s.as_ptr())
-                .collect::<Vec<_>>();
-            let (layer_strings, extension_strings) = str_pointers.split_at(layers.len());
-            let create_info = vk::InstanceCreateInfo::builder()
-                .application_info(&app_info)
-                .flags(create_flags)
-                .enabled_layer_names(layer_strings)
-                .enabled_extension_names(extension_strings);
-            entry.create_instance(&create_info, None).unwrap()
-        };
+    let instance = super::Instance {
+        debug_utils: ext::DebugUtils::new(&entry, &core_instance),
+        get_physical_device_properties2: khr::GetPhysicalDeviceProperties2::new(
+            &entry,
+            &core_instance,
+        ),
+        core: core_instance,
+    };
+    Ok((entry, instance, driver_api_version))
+}
 
-        let instance = super::Instance {
-            debug_utils: ext::DebugUtils::new(&entry, &core_instance),
-            get_physical_device_properties2: khr::GetPhysicalDeviceProperties2::new(
-                &entry,
-                &core_instance,
-            ),
-            core: core_instance,
-        };
+impl super::Context {
+    unsafe fn init_impl(
+        desc: crate::ContextDesc,
+        surface_handles: Option<(
+            raw_window_handle::RawWindowHandle,
+            raw_window_handle::RawDisplayHandle,
+        )>,
+    ) -> Result<Self, crate::NotSupportedError> {
+        let (entry, instance, driver_api_version) =
+            create_instance(desc.validation, surface_handles.map(|(_, rdh)| rdh))?;
 
-        let physical_devices = instance.core.enumerate_physical_devices().unwrap();
-        let (physical_device, capabilities) = physical_devices
+        let mut adapters = instance
+            .core
+            .enumerate_physical_devices()
+            .unwrap()
             .into_iter()
-            .find_map(|phd| {
+            .filter_map(|phd| {
                 inspect_adapter(phd, &instance, driver_api_version).map(|caps| (phd, caps))
             })
+            .collect::<Vec<_>>();
+        let adapter_infos = adapters
+            .iter()
+            .map(|adapter| adapter_info(&adapter.1))
+            .collect::<Vec<_>>();
+        let index = desc
+            .adapter
+            .select(&adapter_infos)
             .ok_or(crate::NotSupportedError)?;
+        log::info!("Selected adapter {:?}", adapter_infos[index].name);
+        let (physical_device, capabilities) = adapters.swap_remove(index);
 
         log::debug!("Adapter {:#?}", capabilities);
-        let queue_family_index = 0; //TODO
+
+        let surface_raw = surface_handles.map(|(rwh, rdh)| {
+            let raw = ash_window::create_surface(&entry, &instance.core, rdh, rwh, None).unwrap();
+            (raw, khr::Surface::new(&entry, &instance.core))
+        });
+
+        let queue_family_properties = instance
+            .core
+            .get_physical_device_queue_family_properties(physical_device);
+        let general_family = queue_family_properties
+            .iter()
+            .enumerate()
+            .position(|(index, props)| {
+                let supports_present = match surface_raw {
+                    Some((raw, ref surface_extension)) => surface_extension
+                        .get_physical_device_surface_support(physical_device, index as u32, raw)
+                        .unwrap_or(false),
+                    None => true,
+                };
+                props
+                    .queue_flags
+                    .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+                    && supports_present
+            })
+            .ok_or_else(|| {
+                log::error!("No queue family can render and present");
+                crate::NotSupportedError
+            })? as u32;
+        // Dedicated families are only used if they can do everything
+        // the encoders of this command type may need.
+        let find_dedicated_family = |flags: vk::QueueFlags, excluded: vk::QueueFlags| {
+            queue_family_properties
+                .iter()
+                .position(|props| {
+                    let granularity = props.min_image_transfer_granularity;
+                    props.queue_flags.contains(flags)
+                        && !props.queue_flags.intersects(excluded)
+                        && (props.timestamp_valid_bits != 0 || !desc.timing)
+                        && granularity.width == 1
+                        && granularity.height == 1
+                        && granularity.depth == 1
+                })
+                .map(|index| index as u32)
+        };
+        let compute_family =
+            find_dedicated_family(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS)
+                .unwrap_or(general_family);
+        let transfer_family = find_dedicated_family(
+            vk::QueueFlags::TRANSFER,
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        )
+        .unwrap_or(compute_family);
+        log::info!(
+            "Queue families: general {}, compute {}, transfer {}",
+            general_family,
+            compute_family,
+            transfer_family
+        );
+        let mut queue_families = vec![general_family];
+        for family in [compute_family, transfer_family] {
+            if !queue_families.contains(&family) {
+                queue_families.push(family);
+            }
+        }
 
         let device_core = {
-            let family_info = vk::DeviceQueueCreateInfo::builder()
-                .queue_family_index(queue_family_index)
-                .queue_priorities(&[1.0])
-                .build();
-            let family_infos = [family_info];
+            let queue_priorities = [1.0];
+            let family_infos = queue_families
+                .iter()
+                .map(|&family| {
+                    vk::DeviceQueueCreateInfo::builder()
+                        .queue_family_index(family)
+                        .queue_priorities(&queue_priorities)
+                        .build()
+                })
+                .collect::<Vec<_>>();
 
             let mut device_extensions = REQUIRED_DEVICE_EXTENSIONS.to_vec();
             if surface_handles.is_some() {
@@ -316,9 +476,11 @@ impl super::Context {
                 log::info!("Enabling Vulkan Portability");
                 device_extensions.push(vk::KhrPortabilitySubsetFn::name());
             }
+            if capabilities.descriptor_indexing && capabilities.api_version < vk::API_VERSION_1_2 {
+                device_extensions.push(vk::ExtDescriptorIndexingFn::name());
+            }
             if capabilities.ray_tracing {
                 if capabilities.api_version < vk::API_VERSION_1_2 {
-                    device_extensions.push(vk::ExtDescriptorIndexingFn::name());
                     device_extensions.push(vk::KhrBufferDeviceAddressFn::name());
                     device_extensions.push(vk::KhrShaderFloatControlsFn::name());
                     device_extensions.push(vk::KhrSpirv14Fn::name());
@@ -330,9 +492,21 @@ impl super::Context {
             if capabilities.buffer_marker {
                 device_extensions.push(vk::AmdBufferMarkerFn::name());
             }
+            if capabilities.checkpoints {
+                device_extensions.push(vk::NvDeviceDiagnosticCheckpointsFn::name());
+            }
+            if capabilities.memory_budget {
+                device_extensions.push(vk::ExtMemoryBudgetFn::name());
+            }
             if capabilities.shader_info {
                 device_extensions.push(vk::AmdShaderInfoFn::name());
             }
+            if capabilities.draw_indirect_count {
+                device_extensions.push(vk::KhrDrawIndirectCountFn::name());
+            }
+            if capabilities.inline_uniform_block_size != 0 {
+                device_extensions.push(vk::ExtInlineUniformBlockFn::name());
+            }
 
             let str_pointers = device_extensions
                 .iter()
@@ -349,20 +523,27 @@ impl super::Context {
             let mut device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&family_infos)
                 .enabled_extension_names(&str_pointers)
-                .push_next(&mut ext_inline_uniform_block)
+                .enabled_features(&capabilities.core_features)
                 .push_next(&mut khr_timeline_semaphore)
                 .push_next(&mut khr_dynamic_rendering);
+            if capabilities.inline_uniform_block_size != 0 {
+                device_create_info = device_create_info.push_next(&mut ext_inline_uniform_block);
+            }
 
             let mut ext_descriptor_indexing;
-            let mut khr_buffer_device_address;
-            let mut khr_acceleration_structure;
-            let mut khr_ray_query;
-            if capabilities.ray_tracing {
+            if capabilities.descriptor_indexing {
                 ext_descriptor_indexing =
                     vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::builder()
                         .shader_storage_buffer_array_non_uniform_indexing(true)
                         .shader_sampled_image_array_non_uniform_indexing(true)
                         .descriptor_binding_partially_bound(true);
+                device_create_info = device_create_info.push_next(&mut ext_descriptor_indexing);
+            }
+
+            let mut khr_buffer_device_address;
+            let mut khr_acceleration_structure;
+            let mut khr_ray_query;
+            if capabilities.ray_tracing {
                 khr_buffer_device_address =
                     vk::PhysicalDeviceBufferDeviceAddressFeaturesKHR::builder()
                         .buffer_device_address(true);
@@ -371,7 +552,6 @@ impl super::Context {
                         .acceleration_structure(true);
                 khr_ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::builder().ray_query(true);
                 device_create_info = device_create_info
-                    .push_next(&mut ext_descriptor_indexing)
                     .push_next(&mut khr_buffer_device_address)
                     .push_next(&mut khr_acceleration_structure)
                     .push_next(&mut khr_ray_query);
@@ -383,56 +563,11 @@ impl super::Context {
                 .unwrap()
         };
 
-        let device = super::Device {
-            timeline_semaphore: khr::TimelineSemaphore::new(&instance.core, &device_core),
-            dynamic_rendering: khr::DynamicRendering::new(&instance.core, &device_core),
-            ray_tracing: if capabilities.ray_tracing {
-                Some(super::RayTracingDevice {
-                    acceleration_structure: khr::AccelerationStructure::new(
-                        &instance.core,
-                        &device_core,
-                    ),
-                })
-            } else {
-                None
-            },
-            buffer_marker: if capabilities.buffer_marker && desc.validation {
-                //TODO: https://github.com/ash-rs/ash/issues/768
-                Some(vk::AmdBufferMarkerFn::load(|name| unsafe {
-                    mem::transmute(
-                        instance
-                            .core
-                            .get_device_proc_addr(device_core.handle(), name.as_ptr()),
-                    )
-                }))
-            } else {
-                None
-            },
-            shader_info: if capabilities.shader_info {
-                Some(vk::AmdShaderInfoFn::load(|name| unsafe {
-                    mem::transmute(
-                        instance
-                            .core
-                            .get_device_proc_addr(device_core.handle(), name.as_ptr()),
-                    )
-                }))
-            } else {
-                None
-            },
-            core: device_core,
-            //TODO: detect GPU family
-            workarounds: super::Workarounds {
-                extra_sync_src_access: vk::AccessFlags::TRANSFER_WRITE,
-                extra_sync_dst_access: vk::AccessFlags::TRANSFER_WRITE
-                    | vk::AccessFlags::TRANSFER_READ
-                    | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-            },
-        };
-
+        let mem_properties = instance
+            .core
+            .get_physical_device_memory_properties(physical_device);
+        let limits = make_limits(&capabilities, &mem_properties);
         let memory_manager = {
-            let mem_properties = instance
-                .core
-                .get_physical_device_memory_properties(physical_device);
             let memory_types =
                 &mem_properties.memory_types[..mem_properties.memory_type_count as usize];
             let limits = &capabilities.properties.limits;
@@ -477,33 +612,142 @@ impl super::Context {
                 allocator: gpu_alloc::GpuAllocator::new(config, properties),
                 slab: slab::Slab::new(),
                 valid_ash_memory_types,
+                memory_type_heaps: memory_types
+                    .iter()
+                    .map(|memory_type| memory_type.heap_index)
+                    .collect(),
+                track_names: desc.validation,
+                budget: capabilities.memory_budget,
             }
         };
 
-        let queue = device.core.get_device_queue(queue_family_index, 0);
-        let last_progress = 0;
-        let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
-            .semaphore_type(vk::SemaphoreType::TIMELINE)
-            .initial_value(last_progress);
-        let timeline_semaphore_create_info =
-            vk::SemaphoreCreateInfo::builder().push_next(&mut timeline_info);
-        let timeline_semaphore = unsafe {
-            device
-                .core
-                .create_semaphore(&timeline_semaphore_create_info, None)
-                .unwrap()
-        };
-        let present_semaphore_create_info = vk::SemaphoreCreateInfo::builder();
-        let present_semaphore = unsafe {
-            device
-                .core
-                .create_semaphore(&present_semaphore_create_info, None)
-                .unwrap()
+        let device = super::Device {
+            timeline_semaphore: khr::TimelineSemaphore::new(&instance.core, &device_core),
+            debug_utils: instance.debug_utils.clone(),
+            dynamic_rendering: khr::DynamicRendering::new(&instance.core, &device_core),
+            draw_indirect_count: if capabilities.draw_indirect_count {
+                Some(khr::DrawIndirectCount::new(&instance.core, &device_core))
+            } else {
+                None
+            },
+            multi_draw_indirect: capabilities.core_features.multi_draw_indirect != vk::FALSE,
+            ray_tracing: if capabilities.ray_tracing {
+                Some(super::RayTracingDevice {
+                    acceleration_structure: khr::AccelerationStructure::new(
+                        &instance.core,
+                        &device_core,
+                    ),
+                })
+            } else {
+                None
+            },
+            software_rt: if capabilities.ray_tracing {
+                None
+            } else {
+                Some(Default::default())
+            },
+            marker_writer: if !desc.validation {
+                None
+            } else if capabilities.buffer_marker {
+                //TODO: https://github.com/ash-rs/ash/issues/768
+                Some(super::MarkerWriter::BufferMarker(
+                    vk::AmdBufferMarkerFn::load(|name| unsafe {
+                        mem::transmute(
+                            instance
+                                .core
+                                .get_device_proc_addr(device_core.handle(), name.as_ptr()),
+                        )
+                    }),
+                ))
+            } else if capabilities.checkpoints {
+                Some(super::MarkerWriter::Checkpoints(
+                    vk::NvDeviceDiagnosticCheckpointsFn::load(|name| unsafe {
+                        mem::transmute(
+                            instance
+                                .core
+                                .get_device_proc_addr(device_core.handle(), name.as_ptr()),
+                        )
+                    }),
+                ))
+            } else {
+                Some(super::MarkerWriter::FillBuffer)
+            },
+            shader_info: if capabilities.shader_info {
+                Some(vk::AmdShaderInfoFn::load(|name| unsafe {
+                    mem::transmute(
+                        instance
+                            .core
+                            .get_device_proc_addr(device_core.handle(), name.as_ptr()),
+                    )
+                }))
+            } else {
+                None
+            },
+            timing: if !desc.timing {
+                None
+            } else if capabilities
+                .properties
+                .limits
+                .timestamp_compute_and_graphics
+                == vk::FALSE
+            {
+                log::warn!("Timestamps are not supported, timing is disabled");
+                None
+            } else {
+                Some(super::TimingDevice {
+                    period: capabilities.properties.limits.timestamp_period,
+                })
+            },
+            memory: Arc::new(Mutex::new(memory_manager)),
+            inline_uniform_block_size: capabilities.inline_uniform_block_size,
+            depth24_stencil8: capabilities.depth24_stencil8,
+            uniform_buffer_alignment: capabilities
+                .properties
+                .limits
+                .min_uniform_buffer_offset_alignment,
+            core: device_core,
+            //TODO: detect GPU family
+            workarounds: super::Workarounds {
+                extra_sync_dst_access: vk::AccessFlags::TRANSFER_WRITE
+                    | vk::AccessFlags::TRANSFER_READ
+                    | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            },
         };
 
-        let surface = surface_handles.map(|(rwh, rdh)| {
+        let queues = queue_families
+            .iter()
+            .map(|&family| {
+                let last_progress = 0;
+                let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
+                    .semaphore_type(vk::SemaphoreType::TIMELINE)
+                    .initial_value(last_progress);
+                let timeline_semaphore_create_info =
+                    vk::SemaphoreCreateInfo::builder().push_next(&mut timeline_info);
+                let timeline_semaphore = unsafe {
+                    device
+                        .core
+                        .create_semaphore(&timeline_semaphore_create_info, None)
+                        .unwrap()
+                };
+                let present_semaphore_create_info = vk::SemaphoreCreateInfo::builder();
+                let present_semaphore = unsafe {
+                    device
+                        .core
+                        .create_semaphore(&present_semaphore_create_info, None)
+                        .unwrap()
+                };
+                Mutex::new(super::Queue {
+                    raw: device.core.get_device_queue(family, 0),
+                    timeline_semaphore,
+                    present_semaphore,
+                    last_progress,
+                })
+            })
+            .collect();
+        let queue_index = |family| queue_families.iter().position(|&f| f == family).unwrap();
+
+        let surface = surface_raw.map(|(raw, surface_extension)| {
             let extension = khr::Swapchain::new(&instance.core, &device.core);
-            let raw = ash_window::create_surface(&entry, &instance.core, rdh, rwh, None).unwrap();
             let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
             let next_semaphore = unsafe {
                 device
@@ -517,6 +761,7 @@ impl super::Context {
                 next_semaphore,
                 swapchain: vk::SwapchainKHR::null(),
                 extension,
+                surface_extension,
             })
         });
 
@@ -526,17 +771,17 @@ impl super::Context {
         }
 
         Ok(super::Context {
-            memory: Mutex::new(memory_manager),
             device,
-            queue_family_index,
-            queue: Mutex::new(super::Queue {
-                raw: queue,
-                timeline_semaphore,
-                present_semaphore,
-                last_progress,
-            }),
+            queues,
+            compute_queue_index: queue_index(compute_family),
+            transfer_queue_index: queue_index(transfer_family),
+            queue_families: queue_families.into_boxed_slice(),
             surface,
-            _physical_device: physical_device,
+            physical_device,
+            sample_count_mask: sample_count_mask(&capabilities.properties.limits),
+            descriptor_indexing: capabilities.descriptor_indexing,
+            limits,
+            resource_counts: Default::default(),
             naga_flags,
             instance,
             _entry: entry,
@@ -547,6 +792,24 @@ impl super::Context {
         Self::init_impl(desc, None)
     }
 
+    /// List the adapters suitable for initializing the context.
+    pub unsafe fn enumerate_adapters() -> Vec<crate::AdapterInfo> {
+        let (_entry, instance, driver_api_version) = match create_instance(false, None) {
+            Ok(created) => created,
+            Err(crate::NotSupportedError) => return Vec::new(),
+        };
+        let adapter_infos = instance
+            .core
+            .enumerate_physical_devices()
+            .unwrap()
+            .into_iter()
+            .filter_map(|phd| inspect_adapter(phd, &instance, driver_api_version))
+            .map(|caps| adapter_info(&caps))
+            .collect();
+        instance.core.destroy_instance(None);
+        adapter_infos
+    }
+
     pub unsafe fn init_windowed<
         I: raw_window_handle::HasRawWindowHandle + raw_window_handle::HasRawDisplayHandle,
     >(
@@ -576,25 +839,178 @@ impl super::Context {
     }
 
     pub fn capabilities(&self) -> crate::Capabilities {
-        crate::Capabilities {
-            ray_query: match self.device.ray_tracing {
-                Some(_) => crate::ShaderVisibility::all(),
-                None => crate::ShaderVisibility::empty(),
-            },
+        make_capabilities(
+            self.device.ray_tracing.is_some(),
+            self.descriptor_indexing,
+            self.sample_count_mask,
+        )
+    }
+
+    pub fn limits(&self) -> crate::Limits {
+        self.limits.clone()
+    }
+
+    /// Return the usages supported by textures of the given format.
+    pub fn format_features(&self, format: crate::TextureFormat) -> crate::TextureUsage {
+        let properties = unsafe {
+            self.instance.core.get_physical_device_format_properties(
+                self.physical_device,
+                self.device.map_texture_format(format),
+            )
+        };
+        let features = properties.optimal_tiling_features;
+        let mut usage = crate::TextureUsage::empty();
+        if features
+            .contains(vk::FormatFeatureFlags::TRANSFER_SRC | vk::FormatFeatureFlags::TRANSFER_DST)
+        {
+            usage |= crate::TextureUsage::COPY;
+        }
+        if features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            usage |= crate::TextureUsage::RESOURCE;
+        }
+        if features.intersects(
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT
+                | vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ) {
+            usage |= crate::TextureUsage::TARGET;
         }
+        if features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE) {
+            usage |= crate::TextureUsage::STORAGE;
+        }
+        usage
     }
 }
 
+fn make_limits(
+    capabilities: &AdapterCapabilities,
+    mem_properties: &vk::PhysicalDeviceMemoryProperties,
+) -> crate::Limits {
+    let limits = &capabilities.properties.limits;
+    crate::Limits {
+        max_texture_dimension_2d: limits.max_image_dimension2_d,
+        max_texture_dimension_3d: limits.max_image_dimension3_d,
+        max_texture_array_layers: limits.max_image_array_layers,
+        max_workgroup_size: limits.max_compute_work_group_size,
+        max_workgroup_invocations: limits.max_compute_work_group_invocations,
+        max_workgroup_count: limits.max_compute_work_group_count,
+        subgroup_size: capabilities.subgroup_size,
+        timestamp_period: if limits.timestamp_compute_and_graphics != vk::FALSE {
+            limits.timestamp_period
+        } else {
+            0.0
+        },
+        memory_heaps: mem_properties.memory_heaps[..mem_properties.memory_heap_count as usize]
+            .iter()
+            .map(|heap| crate::MemoryHeap {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            })
+            .collect(),
+    }
+}
+
+fn make_capabilities(
+    ray_tracing: bool,
+    descriptor_indexing: bool,
+    sample_count_mask: u32,
+) -> crate::Capabilities {
+    crate::Capabilities {
+        // Without the hardware support, the queries read storage buffers
+        ray_query: crate::ShaderVisibility::all(),
+        sample_count_mask,
+        binding_arrays: descriptor_indexing,
+        software_ray_query: !ray_tracing,
+        render_bundles: true,
+    }
+}
+
+fn sample_count_mask(limits: &vk::PhysicalDeviceLimits) -> u32 {
+    (limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts).as_raw()
+}
+
+fn adapter_info(capabilities: &AdapterCapabilities) -> crate::AdapterInfo {
+    let properties = &capabilities.properties;
+    let name = unsafe { ffi::CStr::from_ptr(properties.device_name.as_ptr()) };
+    crate::AdapterInfo {
+        name: name.to_string_lossy().into_owned(),
+        vendor_id: properties.vendor_id,
+        device_type: match properties.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => crate::DeviceType::Discrete,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => crate::DeviceType::Integrated,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => crate::DeviceType::Virtual,
+            vk::PhysicalDeviceType::CPU => crate::DeviceType::Software,
+            _ => crate::DeviceType::Other,
+        },
+        driver_version: properties.driver_version,
+        capabilities: make_capabilities(
+            capabilities.ray_tracing,
+            capabilities.descriptor_indexing,
+            sample_count_mask(&properties.limits),
+        ),
+    }
+}
+
+fn surface_format_candidates(
+    color_space: crate::ColorSpace,
+) -> (&'static [crate::TextureFormat], vk::ColorSpaceKHR) {
+    use crate::TextureFormat as Tf;
+    match color_space {
+        crate::ColorSpace::Linear => (
+            &[Tf::Bgra8UnormSrgb, Tf::Rgba8UnormSrgb],
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        ),
+        crate::ColorSpace::Srgb => (
+            &[Tf::Bgra8Unorm, Tf::Rgba8Unorm],
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        ),
+        crate::ColorSpace::Hdr10 => (&[Tf::Rgb10a2Unorm], vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+    }
+}
+
+/// Pick the format for the color space out of the ones supported by the surface.
+fn select_surface_format(
+    supported: &[vk::SurfaceFormatKHR],
+    color_space: crate::ColorSpace,
+) -> Option<(crate::TextureFormat, vk::ColorSpaceKHR)> {
+    let (candidates, vk_color_space) = surface_format_candidates(color_space);
+    candidates
+        .iter()
+        .find(|&&format| {
+            let vk_format = super::map_texture_format(format);
+            supported
+                .iter()
+                .any(|sf| sf.format == vk_format && sf.color_space == vk_color_space)
+        })
+        .map(|&format| (format, vk_color_space))
+}
+
 impl super::Context {
     pub fn resize(&self, config: crate::SurfaceConfig) -> crate::TextureFormat {
         let mut surface = self.surface.as_ref().unwrap().lock().unwrap();
-        let queue_families = [self.queue_family_index];
-        let format = crate::TextureFormat::Bgra8UnormSrgb;
+        let queue_families = [self.queue_families[0]];
+        let supported_formats = unsafe {
+            surface
+                .surface_extension
+                .get_physical_device_surface_formats(self.physical_device, surface.raw)
+                .unwrap()
+        };
+        let (format, vk_color_space) =
+            select_surface_format(&supported_formats, config.color_space).unwrap_or_else(|| {
+                log::warn!(
+                    "Color space {:?} is not supported by the surface",
+                    config.color_space
+                );
+                select_surface_format(&supported_formats, crate::ColorSpace::Linear).unwrap_or((
+                    crate::TextureFormat::Bgra8UnormSrgb,
+                    vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                ))
+            });
         let vk_format = super::map_texture_format(format);
         let create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surface.raw)
             .min_image_count(config.frame_count)
             .image_format(vk_format)
+            .image_color_space(vk_color_space)
             .image_extent(vk::Extent2D {
                 width: config.size.width,
                 height: config.size.height,
@@ -2,7 +2,16 @@ use ash::{
     extensions::{ext, khr},
     vk,
 };
-use std::{num::NonZeroU32, ptr, sync::Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    num::NonZeroU32,
+    ptr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
 mod command;
 mod init;
@@ -20,27 +29,79 @@ struct RayTracingDevice {
     acceleration_structure: khr::AccelerationStructure,
 }
 
+/// Acceleration structures built by compute shaders,
+/// for the software ray queries without `VK_KHR_ray_query`.
+#[derive(Default)]
+struct SoftwareRayTracing {
+    pipelines: crate::software_rt::SharedPipelines,
+    /// Total size of the live acceleration structures, which the top level
+    /// reserves for the copies of the bottom level ones.
+    total_size: AtomicU64,
+}
+
 #[derive(Clone)]
 struct Workarounds {
-    extra_sync_src_access: vk::AccessFlags,
     extra_sync_dst_access: vk::AccessFlags,
 }
 
+/// Way of writing the crash markers on GPU.
+#[derive(Clone)]
+enum MarkerWriter {
+    /// `VK_AMD_buffer_marker` into the marker buffer.
+    BufferMarker(vk::AmdBufferMarkerFn),
+    /// `VK_NV_device_diagnostic_checkpoints`, queried from the queue.
+    Checkpoints(vk::NvDeviceDiagnosticCheckpointsFn),
+    /// Plain fill of the marker buffer, after all the previous work.
+    FillBuffer,
+}
+
+#[derive(Clone)]
+struct TimingDevice {
+    period: f32,
+}
+
 #[derive(Clone)]
 struct Device {
     core: ash::Device,
     timeline_semaphore: khr::TimelineSemaphore,
+    debug_utils: ext::DebugUtils,
     dynamic_rendering: khr::DynamicRendering,
+    draw_indirect_count: Option<khr::DrawIndirectCount>,
+    multi_draw_indirect: bool,
     ray_tracing: Option<RayTracingDevice>,
-    buffer_marker: Option<vk::AmdBufferMarkerFn>,
+    /// Used instead of the hardware ray tracing, if it's not supported.
+    software_rt: Option<Arc<SoftwareRayTracing>>,
+    /// Only used with validation, since the markers serialize the work.
+    marker_writer: Option<MarkerWriter>,
     shader_info: Option<vk::AmdShaderInfoFn>,
+    timing: Option<TimingDevice>,
+    /// Shared with the command encoders, which grow their uniform rings.
+    memory: Arc<Mutex<MemoryManager>>,
+    /// Plain data up to this size is bound as inline uniform blocks,
+    /// and the rest goes through the uniform ring of the command buffer.
+    inline_uniform_block_size: u32,
+    /// Backing format of `Depth24PlusStencil8`, which has
+    /// 32-bit float depth where 24-bit depth isn't supported.
+    depth24_stencil8: vk::Format,
+    uniform_buffer_alignment: u64,
     workarounds: Workarounds,
 }
 
+struct MemoryBlock {
+    raw: gpu_alloc::MemoryBlock<vk::DeviceMemory>,
+    /// Name of the owning resource, if the names are tracked.
+    name: String,
+}
+
 struct MemoryManager {
     allocator: gpu_alloc::GpuAllocator<vk::DeviceMemory>,
-    slab: slab::Slab<gpu_alloc::MemoryBlock<vk::DeviceMemory>>,
+    slab: slab::Slab<MemoryBlock>,
     valid_ash_memory_types: u32,
+    /// Heap index of each memory type.
+    memory_type_heaps: Vec<u32>,
+    track_names: bool,
+    /// `VK_EXT_memory_budget` is enabled.
+    budget: bool,
 }
 
 struct Queue {
@@ -73,8 +134,12 @@ impl Frame {
     pub fn texture_view(&self) -> TextureView {
         TextureView {
             raw: self.view,
+            image: self.image,
             target_size: self.target_size,
             aspects: crate::TexelAspects::COLOR,
+            base_mip_level: 0,
+            base_array_layer: 0,
+            array_layer_count: 1,
         }
     }
 }
@@ -85,8 +150,15 @@ struct Surface {
     next_semaphore: vk::Semaphore,
     swapchain: vk::SwapchainKHR,
     extension: khr::Swapchain,
+    surface_extension: khr::Surface,
 }
 
+/// Maximum number of timestamps written by a command buffer,
+/// which is one per timed pass and one at the end.
+const QUERY_POOL_SIZE: u32 = 100;
+/// Size of each of the chained buffers of a uniform ring.
+const UNIFORM_RING_SIZE: u64 = 1 << 20;
+
 fn map_timeout(millis: u32) -> u64 {
     if millis == !0 {
         !0
@@ -95,13 +167,38 @@ fn map_timeout(millis: u32) -> u64 {
     }
 }
 
+fn map_error(result: vk::Result) -> crate::Error {
+    match result {
+        vk::Result::ERROR_OUT_OF_HOST_MEMORY
+        | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+        | vk::Result::ERROR_OUT_OF_POOL_MEMORY
+        | vk::Result::ERROR_TOO_MANY_OBJECTS
+        | vk::Result::ERROR_FRAGMENTED_POOL
+        | vk::Result::ERROR_FRAGMENTATION => crate::Error::OutOfMemory,
+        vk::Result::ERROR_FORMAT_NOT_SUPPORTED
+        | vk::Result::ERROR_IMAGE_USAGE_NOT_SUPPORTED_KHR => crate::Error::UnsupportedFormat,
+        vk::Result::ERROR_DEVICE_LOST => crate::Error::DeviceLost,
+        other => {
+            log::error!("Unexpected Vulkan error: {:?}", other);
+            crate::Error::InvalidDescriptor
+        }
+    }
+}
+
 pub struct Context {
-    memory: Mutex<MemoryManager>,
     device: Device,
-    queue_family_index: u32,
-    queue: Mutex<Queue>,
+    /// Queues of distinct families, starting with the general one.
+    queues: Box<[Mutex<Queue>]>,
+    /// Family index of each queue.
+    queue_families: Box<[u32]>,
+    compute_queue_index: usize,
+    transfer_queue_index: usize,
     surface: Option<Mutex<Surface>>,
-    _physical_device: vk::PhysicalDevice,
+    physical_device: vk::PhysicalDevice,
+    sample_count_mask: u32,
+    descriptor_indexing: bool,
+    limits: crate::Limits,
+    resource_counts: crate::util::ResourceCounts,
     naga_flags: naga::back::spv::WriterFlags,
     instance: Instance,
     _entry: ash::Entry,
@@ -162,8 +259,12 @@ impl Default for Texture {
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq)]
 pub struct TextureView {
     raw: vk::ImageView,
+    image: vk::Image,
     target_size: [u16; 2],
     aspects: crate::TexelAspects,
+    base_mip_level: u32,
+    base_array_layer: u32,
+    array_layer_count: u32,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq)]
@@ -173,9 +274,21 @@ pub struct Sampler {
 
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq)]
 pub struct AccelerationStructure {
+    /// Null for the software ray queries, which read the buffer instead.
     raw: vk::AccelerationStructureKHR,
     buffer: vk::Buffer,
     memory_handle: usize,
+    size: u64,
+}
+
+/// Plain data binding that is backed by a uniform buffer.
+#[derive(Debug)]
+struct UniformBufferInfo {
+    /// Offset of the plain data in the update data.
+    data_offset: u32,
+    /// Offset of the buffer descriptor in the update data.
+    descriptor_offset: u32,
+    size: u32,
 }
 
 #[derive(Debug, Default)]
@@ -184,17 +297,24 @@ struct DescriptorSetLayout {
     update_template: vk::DescriptorUpdateTemplate,
     template_size: u32,
     template_offsets: Box<[u32]>,
+    uniform_buffers: Box<[UniformBufferInfo]>,
+    /// Whether the shaders may write to each of the bindings.
+    writable_bindings: Box<[bool]>,
 }
 
 #[derive(Debug)]
 struct PipelineLayout {
     raw: vk::PipelineLayout,
     descriptor_set_layouts: Vec<DescriptorSetLayout>,
+    /// Whether the shaders write to any of the bound resources.
+    writable: bool,
 }
 
 pub struct PipelineContext<'a> {
     update_data: &'a mut [u8],
     template_offsets: &'a [u32],
+    /// Raw handles of the bound buffers and images, with their binding indices.
+    resources: &'a mut Vec<(u32, u64)>,
 }
 
 #[derive(Debug)]
@@ -210,15 +330,104 @@ impl ComputePipeline {
     }
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub struct PipelineCache {
+    raw: vk::PipelineCache,
+}
+
 pub struct RenderPipeline {
     raw: vk::Pipeline,
     layout: PipelineLayout,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Upload buffers for the plain data of a command buffer.
+/// Another buffer is chained when the current one is full,
+/// and all of them are reused after the command buffer is reset.
+#[derive(Debug, Default)]
+struct UniformRing {
+    buffers: RefCell<Vec<Buffer>>,
+    /// Index of the buffer being filled, and the offset in it.
+    position: Cell<(usize, u64)>,
+}
+
+impl UniformRing {
+    fn allocate(&self, device: &Device, size: u64, alignment: u64) -> (Buffer, u64) {
+        let (mut index, offset) = self.position.get();
+        let mut offset = (offset + alignment - 1) & !(alignment - 1);
+        let mut buffers = self.buffers.borrow_mut();
+        if index < buffers.len() && offset + size > UNIFORM_RING_SIZE {
+            index += 1;
+            offset = 0;
+        }
+        if index == buffers.len() {
+            let buffer = device
+                .create_uniform_buffer(UNIFORM_RING_SIZE)
+                .expect("Unable to grow the uniform ring");
+            buffers.push(buffer);
+        }
+        self.position.set((index, offset + size));
+        (buffers[index], offset)
+    }
+
+    fn reset(&self) {
+        self.position.set((0, 0));
+    }
+
+    fn destroy(&self, device: &Device) {
+        for buffer in self.buffers.borrow_mut().drain(..) {
+            device.destroy_uniform_buffer(buffer);
+        }
+    }
+}
+
+#[derive(Debug)]
 struct CommandBuffer {
     raw: vk::CommandBuffer,
     descriptor_pool: vk::DescriptorPool,
+    query_pool: vk::QueryPool,
+    timed_pass_names: Vec<String>,
+    uniform_ring: Option<UniformRing>,
+    /// Timeline semaphore and its value signaled by the last submission.
+    submission: Option<(vk::Semaphore, u64)>,
+}
+
+/// Pipeline stages and memory accesses of the recorded commands.
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    stages: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+}
+
+impl Usage {
+    const NONE: Self = Self {
+        stages: vk::PipelineStageFlags::empty(),
+        access: vk::AccessFlags::empty(),
+    };
+}
+
+/// Resources accessed by the commands recorded since the last barrier.
+///
+/// Copies and dispatches only wait when they access a resource in conflict,
+/// while the render passes and the acceleration structure builds
+/// wait for everything, since their resources are not known upfront.
+#[derive(Debug, Default)]
+struct Tracker {
+    usage: Usage,
+    /// Raw handles of the buffers and images that are read.
+    reads: HashSet<u64>,
+    /// Raw handles of the buffers and images that are written.
+    writes: HashSet<u64>,
+    /// Some of the commands access unknown resources, such as the render bundles.
+    untracked: bool,
+}
+
+/// Render target that is kept in an attachment layout
+/// instead of `ImageLayout::GENERAL` between passes.
+#[derive(Debug)]
+struct Attachment {
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    layout: vk::ImageLayout,
 }
 
 #[derive(Debug, PartialEq)]
@@ -229,90 +438,190 @@ struct Presentation {
 
 struct CrashHandler {
     name: String,
+    /// Tags the checkpoints, which are shared by all the encoders of a queue.
+    id: u32,
     marker_buf: Buffer,
     raw_string: Box<[u8]>,
     next_offset: usize,
 }
 
 pub struct CommandEncoder {
+    command_type: crate::CommandType,
+    queue_index: usize,
+    /// Sync points of other queues to wait for on the next submission.
+    waits: Vec<SyncPoint>,
     pool: vk::CommandPool,
     buffers: Box<[CommandBuffer]>,
     device: Device,
     update_data: Vec<u8>,
+    tracker: Tracker,
+    attachments: Vec<Attachment>,
     present: Option<Presentation>,
     crash_handler: Option<CrashHandler>,
+    timings: crate::Timings,
 }
 pub struct TransferCommandEncoder<'a> {
     raw: vk::CommandBuffer,
     device: &'a Device,
+    tracker: &'a mut Tracker,
 }
 pub struct AccelerationStructureCommandEncoder<'a> {
-    raw: vk::CommandBuffer,
+    cmd_buf: &'a CommandBuffer,
     device: &'a Device,
+    update_data: &'a mut Vec<u8>,
+    tracker: &'a mut Tracker,
 }
 pub struct ComputeCommandEncoder<'a> {
-    cmd_buf: CommandBuffer,
+    cmd_buf: &'a CommandBuffer,
     device: &'a Device,
     update_data: &'a mut Vec<u8>,
+    tracker: &'a mut Tracker,
 }
 pub struct RenderCommandEncoder<'a> {
-    cmd_buf: CommandBuffer,
+    cmd_buf: &'a CommandBuffer,
     device: &'a Device,
     update_data: &'a mut Vec<u8>,
+    tracker: &'a mut Tracker,
+    color_attachments: Vec<vk::RenderingAttachmentInfo>,
+    depth_attachment: Option<vk::RenderingAttachmentInfo>,
+    stencil_attachment: Option<vk::RenderingAttachmentInfo>,
+    render_area: vk::Rect2D,
+    viewport: vk::Viewport,
+    /// Last scissor set, restored when the pass is split.
+    scissor: vk::Rect2D,
+    /// Contents of the active rendering instance, if any.
+    contents: Option<vk::RenderingFlags>,
 }
-pub struct PipelineEncoder<'a, 'p> {
+pub struct RenderBundleEncoder {
+    pool: vk::CommandPool,
+    cmd_buf: CommandBuffer,
+    device: Device,
+    update_data: Vec<u8>,
+    writable: bool,
+    extent: vk::Extent2D,
+}
+/// Render commands recorded into a secondary command buffer.
+#[derive(Debug)]
+pub struct RenderBundle {
+    pool: vk::CommandPool,
     cmd_buf: CommandBuffer,
+    writable: bool,
+    extent: vk::Extent2D,
+}
+pub struct PipelineEncoder<'a, 'p> {
+    cmd_buf: &'a CommandBuffer,
     layout: &'p PipelineLayout,
     bind_point: vk::PipelineBindPoint,
     device: &'a Device,
     update_data: &'a mut Vec<u8>,
+    /// Scissor tracked by the render pass, if any.
+    scissor: Option<&'a mut vk::Rect2D>,
+    /// Accesses of the command encoder, if not recording a bundle.
+    tracker: Option<&'a mut Tracker>,
 }
 
 #[derive(Clone, Debug)]
 pub struct SyncPoint {
+    queue_index: usize,
     progress: u64,
 }
 
-#[hidden_trait::expose]
-impl crate::traits::CommandDevice for Context {
-    type CommandEncoder = CommandEncoder;
-    type SyncPoint = SyncPoint;
+impl Context {
+    fn queue_index(&self, command_type: &crate::CommandType) -> usize {
+        match *command_type {
+            crate::CommandType::Transfer => self.transfer_queue_index,
+            crate::CommandType::Compute => self.compute_queue_index,
+            crate::CommandType::General => 0,
+        }
+    }
 
-    fn create_command_encoder(&self, desc: super::CommandEncoderDesc) -> CommandEncoder {
-        //TODO: these numbers are arbitrary, needs to be replaced by
-        // an abstraction from gpu-alloc, if possible.
-        const ROUGH_SET_COUNT: u32 = 60000;
+    /// Sharing mode and queue families of a resource. Resources that may be
+    /// accessed by several queues are shared without ownership transfers,
+    /// the others stay exclusive to the queue that uses them.
+    fn sharing(&self, shared: bool) -> (vk::SharingMode, &[u32]) {
+        if shared && self.queue_families.len() > 1 {
+            (vk::SharingMode::CONCURRENT, &self.queue_families)
+        } else {
+            (vk::SharingMode::EXCLUSIVE, &[])
+        }
+    }
+
+    fn create_descriptor_pool(&self, set_count: u32) -> vk::DescriptorPool {
         let mut descriptor_sizes = vec![
             vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::INLINE_UNIFORM_BLOCK_EXT,
-                descriptor_count: ROUGH_SET_COUNT * crate::limits::PLAIN_DATA_SIZE,
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: set_count,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: ROUGH_SET_COUNT,
+                descriptor_count: set_count,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::SAMPLED_IMAGE,
-                descriptor_count: 2 * ROUGH_SET_COUNT,
+                descriptor_count: 2 * set_count,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::SAMPLER,
-                descriptor_count: ROUGH_SET_COUNT,
+                descriptor_count: set_count,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_IMAGE,
-                descriptor_count: ROUGH_SET_COUNT,
+                descriptor_count: set_count,
             },
         ];
         if self.device.ray_tracing.is_some() {
             descriptor_sizes.push(vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-                descriptor_count: ROUGH_SET_COUNT,
+                descriptor_count: set_count,
+            });
+        }
+        if self.device.inline_uniform_block_size != 0 {
+            descriptor_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::INLINE_UNIFORM_BLOCK_EXT,
+                descriptor_count: set_count * self.device.inline_uniform_block_size,
             });
         }
 
+        let mut inline_uniform_block_info =
+            vk::DescriptorPoolInlineUniformBlockCreateInfoEXT::builder()
+                .max_inline_uniform_block_bindings(set_count);
+        let mut descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(set_count)
+            .pool_sizes(&descriptor_sizes);
+        if self.device.inline_uniform_block_size != 0 {
+            descriptor_pool_info = descriptor_pool_info.push_next(&mut inline_uniform_block_info);
+        }
+        unsafe {
+            self.device
+                .core
+                .create_descriptor_pool(&descriptor_pool_info, None)
+                .unwrap()
+        }
+    }
+
+    /// Plain data that doesn't fit into inline uniform blocks goes to the ring.
+    fn create_uniform_ring(&self) -> Option<UniformRing> {
+        if self.device.inline_uniform_block_size >= crate::limits::PLAIN_DATA_SIZE {
+            return None;
+        }
+        Some(UniformRing::default())
+    }
+}
+
+#[hidden_trait::expose]
+impl crate::traits::CommandDevice for Context {
+    type CommandEncoder = CommandEncoder;
+    type SyncPoint = SyncPoint;
+
+    fn create_command_encoder(&self, desc: super::CommandEncoderDesc) -> CommandEncoder {
+        //TODO: these numbers are arbitrary, needs to be replaced by
+        // an abstraction from gpu-alloc, if possible.
+        const ROUGH_SET_COUNT: u32 = 60000;
+
+        let queue_index = self.queue_index(&desc.command_type);
         let pool_info = vk::CommandPoolCreateInfo::builder()
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(self.queue_families[queue_index]);
         let pool = unsafe {
             self.device
                 .core
@@ -335,29 +644,36 @@ impl crate::traits::CommandDevice for Context {
                 if !desc.name.is_empty() {
                     self.set_object_name(vk::ObjectType::COMMAND_BUFFER, raw, desc.name);
                 };
-                let mut inline_uniform_block_info =
-                    vk::DescriptorPoolInlineUniformBlockCreateInfoEXT::builder()
-                        .max_inline_uniform_block_bindings(ROUGH_SET_COUNT);
-                let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-                    .max_sets(ROUGH_SET_COUNT)
-                    .pool_sizes(&descriptor_sizes)
-                    .push_next(&mut inline_uniform_block_info);
-                let descriptor_pool = unsafe {
-                    self.device
-                        .core
-                        .create_descriptor_pool(&descriptor_pool_info, None)
-                        .unwrap()
+                let descriptor_pool = self.create_descriptor_pool(ROUGH_SET_COUNT);
+                let query_pool = if self.device.timing.is_some() {
+                    let query_pool_info = vk::QueryPoolCreateInfo::builder()
+                        .query_type(vk::QueryType::TIMESTAMP)
+                        .query_count(QUERY_POOL_SIZE);
+                    unsafe {
+                        self.device
+                            .core
+                            .create_query_pool(&query_pool_info, None)
+                            .unwrap()
+                    }
+                } else {
+                    vk::QueryPool::null()
                 };
                 CommandBuffer {
                     raw,
                     descriptor_pool,
+                    query_pool,
+                    timed_pass_names: Vec::new(),
+                    uniform_ring: self.create_uniform_ring(),
+                    submission: None,
                 }
             })
             .collect();
 
-        let crash_handler = if self.device.buffer_marker.is_some() {
+        let crash_handler = if self.device.marker_writer.is_some() {
+            static NEXT_ID: AtomicU32 = AtomicU32::new(1);
             Some(CrashHandler {
                 name: desc.name.to_string(),
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
                 marker_buf: self.create_buffer(crate::BufferDesc {
                     name: "_marker",
                     size: 4,
@@ -371,12 +687,18 @@ impl crate::traits::CommandDevice for Context {
         };
 
         CommandEncoder {
+            command_type: desc.command_type,
+            queue_index,
+            waits: Vec::new(),
             pool,
             buffers,
             device: self.device.clone(),
             update_data: Vec::new(),
+            tracker: Tracker::default(),
+            attachments: Vec::new(),
             present: None,
             crash_handler,
+            timings: Vec::new(),
         }
     }
 
@@ -390,6 +712,14 @@ impl crate::traits::CommandDevice for Context {
                 self.device
                     .core
                     .destroy_descriptor_pool(cmd_buf.descriptor_pool, None);
+                if cmd_buf.query_pool != vk::QueryPool::null() {
+                    self.device
+                        .core
+                        .destroy_query_pool(cmd_buf.query_pool, None);
+                }
+            }
+            if let Some(ref ring) = cmd_buf.uniform_ring {
+                ring.destroy(&self.device);
             }
         }
         unsafe {
@@ -404,29 +734,36 @@ impl crate::traits::CommandDevice for Context {
 
     fn submit(&self, encoder: &mut CommandEncoder) -> SyncPoint {
         let raw_cmd_buf = encoder.finish();
-        let mut queue = self.queue.lock().unwrap();
+        let mut wait_semaphores = Vec::new();
+        let mut wait_values = Vec::new();
+        for sp in encoder.waits.drain(..) {
+            // submissions to the same queue are already ordered by the barriers
+            if sp.queue_index != encoder.queue_index {
+                let other_queue = self.queues[sp.queue_index].lock().unwrap();
+                wait_semaphores.push(other_queue.timeline_semaphore);
+                wait_values.push(sp.progress);
+            }
+        }
+        if let Some(ref presentation) = encoder.present {
+            wait_semaphores.push(presentation.acquire_semaphore);
+            wait_values.push(0);
+        }
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+
+        let mut queue = self.queues[encoder.queue_index].lock().unwrap();
         queue.last_progress += 1;
         let progress = queue.last_progress;
         let command_buffers = [raw_cmd_buf];
-        let wait_values_all = [0];
-        let mut wait_semaphores_all = [vk::Semaphore::null()];
-        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
         let signal_semaphores_all = [queue.timeline_semaphore, queue.present_semaphore];
         let signal_values_all = [progress, 0];
-        let (num_wait_semaphores, num_signal_sepahores) = match encoder.present {
-            Some(ref presentation) => {
-                wait_semaphores_all[0] = presentation.acquire_semaphore;
-                (1, 2)
-            }
-            None => (0, 1),
-        };
+        let num_signal_sepahores = if encoder.present.is_some() { 2 } else { 1 };
         let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
-            .wait_semaphore_values(&wait_values_all[..num_wait_semaphores])
+            .wait_semaphore_values(&wait_values)
             .signal_semaphore_values(&signal_values_all[..num_signal_sepahores]);
         let vk_info = vk::SubmitInfo::builder()
             .command_buffers(&command_buffers)
-            .wait_semaphores(&wait_semaphores_all[..num_wait_semaphores])
-            .wait_dst_stage_mask(&wait_stages[..num_wait_semaphores])
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
             .signal_semaphores(&signal_semaphores_all[..num_signal_sepahores])
             .push_next(&mut timeline_info);
         let ret = unsafe {
@@ -434,7 +771,8 @@ impl crate::traits::CommandDevice for Context {
                 .core
                 .queue_submit(queue.raw, &[vk_info.build()], vk::Fence::null())
         };
-        encoder.check_gpu_crash(ret);
+        encoder.check_gpu_crash(ret, queue.raw);
+        encoder.buffers[0].submission = Some((queue.timeline_semaphore, progress));
 
         if let Some(presentation) = encoder.present.take() {
             let surface = self.surface.as_ref().unwrap().lock().unwrap();
@@ -446,16 +784,22 @@ impl crate::traits::CommandDevice for Context {
                 .image_indices(&image_indices)
                 .wait_semaphores(&wait_semaphores);
             let ret = unsafe { surface.extension.queue_present(queue.raw, &present_info) };
-            let _ = encoder.check_gpu_crash(ret);
+            let _ = encoder.check_gpu_crash(ret, queue.raw);
         }
 
-        SyncPoint { progress }
+        SyncPoint {
+            queue_index: encoder.queue_index,
+            progress,
+        }
     }
 
     fn wait_for(&self, sp: &SyncPoint, timeout_ms: u32) -> bool {
         //Note: technically we could get away without locking the queue,
         // but also this isn't time-sensitive, so it's fine.
-        let timeline_semaphore = self.queue.lock().unwrap().timeline_semaphore;
+        let timeline_semaphore = self.queues[sp.queue_index]
+            .lock()
+            .unwrap()
+            .timeline_semaphore;
         let semaphores = [timeline_semaphore];
         let semaphore_values = [sp.progress];
         let wait_info = vk::SemaphoreWaitInfoKHR::builder()
@@ -471,23 +815,47 @@ impl crate::traits::CommandDevice for Context {
     }
 }
 
+impl Device {
+    fn map_texture_format(&self, format: crate::TextureFormat) -> vk::Format {
+        match format {
+            crate::TextureFormat::Depth24PlusStencil8 => self.depth24_stencil8,
+            _ => map_texture_format(format),
+        }
+    }
+}
+
+/// Map a texture format, without the device specifics of `Device::map_texture_format`.
 fn map_texture_format(format: crate::TextureFormat) -> vk::Format {
     use crate::TextureFormat as Tf;
     match format {
+        Tf::R8Unorm => vk::Format::R8_UNORM,
         Tf::Rg8Unorm => vk::Format::R8G8_UNORM,
         Tf::Rg8Snorm => vk::Format::R8G8_SNORM,
         Tf::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
         Tf::Rgba8UnormSrgb => vk::Format::R8G8B8A8_SRGB,
+        Tf::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
         Tf::Bgra8UnormSrgb => vk::Format::B8G8R8A8_SRGB,
         Tf::Rgba8Snorm => vk::Format::R8G8B8A8_SNORM,
+        Tf::R16Float => vk::Format::R16_SFLOAT,
+        Tf::Rg16Float => vk::Format::R16G16_SFLOAT,
         Tf::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        Tf::Rgba16Unorm => vk::Format::R16G16B16A16_UNORM,
         Tf::R32Float => vk::Format::R32_SFLOAT,
         Tf::Rg32Float => vk::Format::R32G32_SFLOAT,
         Tf::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
         Tf::R32Uint => vk::Format::R32_UINT,
         Tf::Rg32Uint => vk::Format::R32G32_UINT,
         Tf::Rgba32Uint => vk::Format::R32G32B32A32_UINT,
+        Tf::R32Sint => vk::Format::R32_SINT,
+        Tf::Rg32Sint => vk::Format::R32G32_SINT,
+        Tf::Rgba32Sint => vk::Format::R32G32B32A32_SINT,
+        Tf::Rgb10a2Unorm => vk::Format::A2B10G10R10_UNORM_PACK32,
+        Tf::Rg11b10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+        Tf::Rgb9e5Ufloat => vk::Format::E5B9G9R9_UFLOAT_PACK32,
         Tf::Depth32Float => vk::Format::D32_SFLOAT,
+        Tf::Depth24PlusStencil8 => vk::Format::D24_UNORM_S8_UINT,
+        Tf::Depth32FloatStencil8 => vk::Format::D32_SFLOAT_S8_UINT,
+        Tf::Stencil8 => vk::Format::S8_UINT,
         Tf::Bc1Unorm => vk::Format::BC1_RGBA_SRGB_BLOCK,
         Tf::Bc1UnormSrgb => vk::Format::BC1_RGBA_UNORM_BLOCK,
         Tf::Bc2Unorm => vk::Format::BC2_UNORM_BLOCK,
@@ -498,6 +866,20 @@ fn map_texture_format(format: crate::TextureFormat) -> vk::Format {
         Tf::Bc4Snorm => vk::Format::BC4_SNORM_BLOCK,
         Tf::Bc5Unorm => vk::Format::BC5_UNORM_BLOCK,
         Tf::Bc5Snorm => vk::Format::BC5_SNORM_BLOCK,
+        Tf::Bc6hUfloat => vk::Format::BC6H_UFLOAT_BLOCK,
+        Tf::Bc6hFloat => vk::Format::BC6H_SFLOAT_BLOCK,
+        Tf::Bc7Unorm => vk::Format::BC7_UNORM_BLOCK,
+        Tf::Bc7UnormSrgb => vk::Format::BC7_SRGB_BLOCK,
+        Tf::Etc2Rgb8Unorm => vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+        Tf::Etc2Rgb8UnormSrgb => vk::Format::ETC2_R8G8B8_SRGB_BLOCK,
+        Tf::Etc2Rgba8Unorm => vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+        Tf::Etc2Rgba8UnormSrgb => vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+        Tf::Astc4x4Unorm => vk::Format::ASTC_4X4_UNORM_BLOCK,
+        Tf::Astc4x4UnormSrgb => vk::Format::ASTC_4X4_SRGB_BLOCK,
+        Tf::Astc6x6Unorm => vk::Format::ASTC_6X6_UNORM_BLOCK,
+        Tf::Astc6x6UnormSrgb => vk::Format::ASTC_6X6_SRGB_BLOCK,
+        Tf::Astc8x8Unorm => vk::Format::ASTC_8X8_UNORM_BLOCK,
+        Tf::Astc8x8UnormSrgb => vk::Format::ASTC_8X8_SRGB_BLOCK,
     }
 }
 
@@ -564,7 +946,24 @@ fn map_index_type(index_type: crate::IndexType) -> vk::IndexType {
 fn map_vertex_format(vertex_format: crate::VertexFormat) -> vk::Format {
     use crate::VertexFormat as Vf;
     match vertex_format {
+        Vf::F32 => vk::Format::R32_SFLOAT,
+        Vf::F32Vec2 => vk::Format::R32G32_SFLOAT,
         Vf::F32Vec3 => vk::Format::R32G32B32_SFLOAT,
+        Vf::F32Vec4 => vk::Format::R32G32B32A32_SFLOAT,
+        Vf::U32 => vk::Format::R32_UINT,
+        Vf::U32Vec2 => vk::Format::R32G32_UINT,
+        Vf::U32Vec3 => vk::Format::R32G32B32_UINT,
+        Vf::U32Vec4 => vk::Format::R32G32B32A32_UINT,
+        Vf::I32 => vk::Format::R32_SINT,
+        Vf::I32Vec2 => vk::Format::R32G32_SINT,
+        Vf::I32Vec3 => vk::Format::R32G32B32_SINT,
+        Vf::I32Vec4 => vk::Format::R32G32B32A32_SINT,
+        Vf::U8Vec4Norm => vk::Format::R8G8B8A8_UNORM,
+        Vf::I8Vec4Norm => vk::Format::R8G8B8A8_SNORM,
+        Vf::U16Vec2Norm => vk::Format::R16G16_UNORM,
+        Vf::U16Vec4Norm => vk::Format::R16G16B16A16_UNORM,
+        Vf::I16Vec2Norm => vk::Format::R16G16_SNORM,
+        Vf::I16Vec4Norm => vk::Format::R16G16B16A16_SNORM,
     }
 }
 
@@ -1,6 +1,6 @@
 use ash::vk;
 use naga::back::spv;
-use std::{ffi, mem, ptr, str};
+use std::{ffi, mem::size_of, ptr, str};
 
 const DUMP_PREFIX: Option<&str> = None;
 
@@ -68,15 +68,17 @@ impl super::Context {
     fn load_shader(
         &self,
         sf: crate::ShaderFunction,
+        overrides: &[(&str, f64)],
         naga_options: &spv::Options,
         group_layouts: &[&crate::ShaderDataLayout],
         group_infos: &mut [BindGroupInfo],
-    ) -> CompiledShader {
+    ) -> Result<CompiledShader, crate::Error> {
         let ep_index = sf.entry_point_index();
-        let ep_info = sf.shader.info.get_entry_point(ep_index);
-        let ep = &sf.shader.module.entry_points[ep_index];
+        let resolved = sf.shader.resolve_overrides(overrides)?;
+        let ep_info = resolved.info().get_entry_point(ep_index);
+        let ep = &resolved.module().entry_points[ep_index];
 
-        let mut module = sf.shader.module.clone();
+        let mut module = naga::Module::clone(resolved.module());
         let mut layouter = naga::proc::Layouter::default();
         layouter.update(module.to_ctx()).unwrap();
 
@@ -108,6 +110,10 @@ impl super::Context {
                         binding: binding_index as u32,
                     };
                     let (expected_proto, access) = match module.types[var.ty].inner {
+                        _ if sf.shader.acceleration_structures.contains(&handle) => (
+                            crate::ShaderBinding::AccelerationStructure,
+                            naga::StorageAccess::empty(),
+                        ),
                         naga::TypeInner::Image {
                             class: naga::ImageClass::Storage { access, format: _ },
                             ..
@@ -180,7 +186,7 @@ impl super::Context {
 
         let spv = spv::write_vec(
             &module,
-            &sf.shader.info,
+            resolved.info(),
             naga_options,
             Some(&pipeline_options),
         )
@@ -222,12 +228,12 @@ impl super::Context {
             .name(&entry_point)
             .build();
 
-        CompiledShader {
+        Ok(CompiledShader {
             vk_module,
             _entry_point: entry_point,
             create_info,
-            wg_size: ep.workgroup_size,
-        }
+            wg_size: module.entry_points[ep_index].workgroup_size,
+        })
     }
 
     fn create_descriptor_set_layout(
@@ -243,6 +249,7 @@ impl super::Context {
         let mut template_entries = Vec::with_capacity(layout.bindings.len());
         let mut template_offsets = Vec::with_capacity(layout.bindings.len());
         let mut binding_flags = Vec::with_capacity(layout.bindings.len());
+        let mut uniform_buffers = Vec::new();
         let mut update_offset = 0;
         for (binding_index, (&(_, binding), &access)) in layout
             .bindings
@@ -257,7 +264,7 @@ impl super::Context {
                     } else {
                         vk::DescriptorType::STORAGE_IMAGE
                     },
-                    mem::size_of::<vk::DescriptorImageInfo>(),
+                    size_of::<vk::DescriptorImageInfo>(),
                     1u32,
                     vk::DescriptorBindingFlags::empty(),
                 ),
@@ -267,40 +274,76 @@ impl super::Context {
                     } else {
                         vk::DescriptorType::STORAGE_IMAGE
                     },
-                    mem::size_of::<vk::DescriptorImageInfo>(),
+                    size_of::<vk::DescriptorImageInfo>(),
                     count,
                     vk::DescriptorBindingFlags::PARTIALLY_BOUND,
                 ),
                 crate::ShaderBinding::Sampler => (
                     vk::DescriptorType::SAMPLER,
-                    mem::size_of::<vk::DescriptorImageInfo>(),
+                    size_of::<vk::DescriptorImageInfo>(),
                     1u32,
                     vk::DescriptorBindingFlags::empty(),
                 ),
                 crate::ShaderBinding::Buffer => (
                     vk::DescriptorType::STORAGE_BUFFER,
-                    mem::size_of::<vk::DescriptorBufferInfo>(),
+                    size_of::<vk::DescriptorBufferInfo>(),
                     1u32,
                     vk::DescriptorBindingFlags::empty(),
                 ),
                 crate::ShaderBinding::BufferArray { count } => (
                     vk::DescriptorType::STORAGE_BUFFER,
-                    mem::size_of::<vk::DescriptorBufferInfo>(),
+                    size_of::<vk::DescriptorBufferInfo>(),
                     count,
                     vk::DescriptorBindingFlags::PARTIALLY_BOUND,
                 ),
+                crate::ShaderBinding::AccelerationStructure
+                    if self.device.ray_tracing.is_some() =>
+                {
+                    (
+                        vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                        size_of::<vk::AccelerationStructureKHR>(),
+                        1u32,
+                        vk::DescriptorBindingFlags::empty(),
+                    )
+                }
+                // read from a storage buffer by the software ray queries
                 crate::ShaderBinding::AccelerationStructure => (
-                    vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-                    mem::size_of::<vk::AccelerationStructureKHR>(),
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    size_of::<vk::DescriptorBufferInfo>(),
                     1u32,
                     vk::DescriptorBindingFlags::empty(),
                 ),
-                crate::ShaderBinding::Plain { size } => (
-                    vk::DescriptorType::INLINE_UNIFORM_BLOCK_EXT,
-                    1,
-                    size,
-                    vk::DescriptorBindingFlags::empty(),
-                ),
+                crate::ShaderBinding::Plain { size } => {
+                    assert!(
+                        size <= crate::limits::PLAIN_DATA_SIZE,
+                        "Plain data of size {} is too large",
+                        size
+                    );
+                    if size <= self.device.inline_uniform_block_size {
+                        (
+                            vk::DescriptorType::INLINE_UNIFORM_BLOCK_EXT,
+                            1,
+                            size,
+                            vk::DescriptorBindingFlags::empty(),
+                        )
+                    } else {
+                        // the data offset is assigned after all the descriptors
+                        uniform_buffers.push((
+                            binding_index,
+                            super::UniformBufferInfo {
+                                data_offset: 0,
+                                descriptor_offset: update_offset as u32,
+                                size,
+                            },
+                        ));
+                        (
+                            vk::DescriptorType::UNIFORM_BUFFER,
+                            size_of::<vk::DescriptorBufferInfo>(),
+                            1u32,
+                            vk::DescriptorBindingFlags::empty(),
+                        )
+                    }
+                }
             };
             vk_bindings.push(vk::DescriptorSetLayoutBinding {
                 binding: binding_index as u32,
@@ -322,11 +365,22 @@ impl super::Context {
             update_offset += descriptor_size * descriptor_count as usize;
         }
 
+        // Plain data of the uniform buffers is stored past the descriptors,
+        // and then copied into the uniform ring when binding.
+        for &mut (binding_index, ref mut ub) in uniform_buffers.iter_mut() {
+            ub.data_offset = update_offset as u32;
+            template_offsets[binding_index] = ub.data_offset;
+            update_offset += ub.size as usize;
+        }
+
         let mut binding_flags_info =
             vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&binding_flags);
-        let set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&vk_bindings)
-            .push_next(&mut binding_flags_info);
+        let mut set_layout_info =
+            vk::DescriptorSetLayoutCreateInfo::builder().bindings(&vk_bindings);
+        // binding flags are a part of descriptor indexing
+        if self.descriptor_indexing {
+            set_layout_info = set_layout_info.push_next(&mut binding_flags_info);
+        }
         let raw = unsafe {
             self.device
                 .core
@@ -350,6 +404,12 @@ impl super::Context {
             update_template,
             template_size: update_offset as u32,
             template_offsets: template_offsets.into_boxed_slice(),
+            uniform_buffers: uniform_buffers.into_iter().map(|(_, ub)| ub).collect(),
+            writable_bindings: info
+                .binding_access
+                .iter()
+                .map(|access| access.contains(naga::StorageAccess::STORE))
+                .collect(),
         }
     }
 
@@ -377,13 +437,73 @@ impl super::Context {
         super::PipelineLayout {
             raw,
             descriptor_set_layouts,
+            writable: group_infos.iter().any(|info| {
+                info.binding_access
+                    .iter()
+                    .any(|access| access.contains(naga::StorageAccess::STORE))
+            }),
+        }
+    }
+
+    fn destroy_pipeline_layout(&self, layout: super::PipelineLayout) {
+        unsafe {
+            self.device.core.destroy_pipeline_layout(layout.raw, None);
+            for dsl in layout.descriptor_set_layouts {
+                self.device
+                    .core
+                    .destroy_descriptor_update_template(dsl.update_template, None);
+                self.device
+                    .core
+                    .destroy_descriptor_set_layout(dsl.raw, None);
+            }
+        }
+    }
+
+    pub fn create_pipeline_cache(&self, desc: crate::PipelineCacheDesc) -> super::PipelineCache {
+        let initial_data = desc.initial_data.unwrap_or_default();
+        let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(initial_data);
+        let raw = match unsafe { self.device.core.create_pipeline_cache(&create_info, None) } {
+            Ok(raw) => raw,
+            Err(error) if !initial_data.is_empty() => {
+                log::warn!("Pipeline cache '{}' data is rejected: {}", desc.name, error);
+                let empty_info = vk::PipelineCacheCreateInfo::default();
+                unsafe {
+                    self.device
+                        .core
+                        .create_pipeline_cache(&empty_info, None)
+                        .unwrap()
+                }
+            }
+            Err(error) => panic!("Unable to create pipeline cache '{}': {}", desc.name, error),
+        };
+        if !desc.name.is_empty() {
+            self.set_object_name(vk::ObjectType::PIPELINE_CACHE, raw, desc.name);
         }
+        super::PipelineCache { raw }
+    }
+
+    /// Serialize the pipeline cache contents, to be restored on the next run.
+    pub fn get_pipeline_cache_data(&self, cache: &super::PipelineCache) -> Vec<u8> {
+        unsafe { self.device.core.get_pipeline_cache_data(cache.raw) }.unwrap()
     }
 
-    pub fn create_compute_pipeline(
+    pub fn destroy_pipeline_cache(&self, cache: super::PipelineCache) {
+        unsafe { self.device.core.destroy_pipeline_cache(cache.raw, None) };
+    }
+
+    pub fn try_create_compute_pipeline(
         &self,
         desc: crate::ComputePipelineDesc,
-    ) -> super::ComputePipeline {
+    ) -> Result<super::ComputePipeline, crate::Error> {
+        if desc
+            .data_layouts
+            .iter()
+            .any(|layout| layout.has_binding_arrays())
+            && !self.descriptor_indexing
+        {
+            log::error!("Pipeline '{}' uses unsupported binding arrays", desc.name);
+            return Err(crate::Error::InvalidDescriptor);
+        }
         let mut group_infos = desc
             .data_layouts
             .iter()
@@ -392,7 +512,14 @@ impl super::Context {
             .collect::<Vec<_>>();
 
         let options = self.make_spv_options(desc.data_layouts);
-        let cs = self.load_shader(desc.compute, &options, desc.data_layouts, &mut group_infos);
+        crate::shader::check_overrides(desc.name, &[desc.compute], desc.overrides)?;
+        let cs = self.load_shader(
+            desc.compute,
+            desc.overrides,
+            &options,
+            desc.data_layouts,
+            &mut group_infos,
+        )?;
 
         let layout = self.create_pipeline_layout(desc.data_layouts, &group_infos);
 
@@ -401,18 +528,25 @@ impl super::Context {
             .stage(cs.create_info)
             .build();
 
-        let mut raw_vec = unsafe {
-            self.device
-                .core
-                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
-                .unwrap()
+        let result = unsafe {
+            self.device.core.create_compute_pipelines(
+                desc.cache
+                    .map_or(vk::PipelineCache::null(), |cache| cache.raw),
+                &[create_info],
+                None,
+            )
         };
-        let raw = raw_vec.pop().unwrap();
-
         unsafe { self.device.core.destroy_shader_module(cs.vk_module, None) };
+        let raw = match result {
+            Ok(mut raw_vec) => raw_vec.pop().unwrap(),
+            Err((_, error)) => {
+                self.destroy_pipeline_layout(layout);
+                return Err(super::map_error(error));
+            }
+        };
 
         if let Some(ref ext) = self.device.shader_info {
-            let mut data_size = mem::size_of::<vk::ShaderStatisticsInfoAMD>();
+            let mut data_size = size_of::<vk::ShaderStatisticsInfoAMD>();
             let mut statistics = vk::ShaderStatisticsInfoAMD::default();
             if unsafe {
                 #[allow(trivial_casts)]
@@ -439,14 +573,26 @@ impl super::Context {
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::PIPELINE, raw, desc.name);
         }
-        super::ComputePipeline {
+        Ok(super::ComputePipeline {
             raw,
             layout,
             wg_size: cs.wg_size,
-        }
+        })
     }
 
-    pub fn create_render_pipeline(&self, desc: crate::RenderPipelineDesc) -> super::RenderPipeline {
+    pub fn try_create_render_pipeline(
+        &self,
+        desc: crate::RenderPipelineDesc,
+    ) -> Result<super::RenderPipeline, crate::Error> {
+        if desc
+            .data_layouts
+            .iter()
+            .any(|layout| layout.has_binding_arrays())
+            && !self.descriptor_indexing
+        {
+            log::error!("Pipeline '{}' uses unsupported binding arrays", desc.name);
+            return Err(crate::Error::InvalidDescriptor);
+        }
         let mut group_infos = desc
             .data_layouts
             .iter()
@@ -455,13 +601,56 @@ impl super::Context {
             .collect::<Vec<_>>();
 
         let options = self.make_spv_options(desc.data_layouts);
-        let vs = self.load_shader(desc.vertex, &options, desc.data_layouts, &mut group_infos);
-        let fs = self.load_shader(desc.fragment, &options, desc.data_layouts, &mut group_infos);
+        crate::shader::check_overrides(desc.name, &[desc.vertex, desc.fragment], desc.overrides)?;
+        let vs = self.load_shader(
+            desc.vertex,
+            desc.overrides,
+            &options,
+            desc.data_layouts,
+            &mut group_infos,
+        )?;
+        let fs = match self.load_shader(
+            desc.fragment,
+            desc.overrides,
+            &options,
+            desc.data_layouts,
+            &mut group_infos,
+        ) {
+            Ok(fs) => fs,
+            Err(e) => {
+                unsafe { self.device.core.destroy_shader_module(vs.vk_module, None) };
+                return Err(e);
+            }
+        };
 
         let layout = self.create_pipeline_layout(desc.data_layouts, &group_infos);
 
         let stages = [vs.create_info, fs.create_info];
-        let vk_vertex_input = vk::PipelineVertexInputStateCreateInfo::builder().build();
+        let mut vertex_buffers = Vec::with_capacity(desc.vertex_fetches.len());
+        let mut vertex_attributes = Vec::new();
+        for (i, vf) in desc.vertex_fetches.iter().enumerate() {
+            vertex_buffers.push(vk::VertexInputBindingDescription {
+                binding: i as u32,
+                stride: vf.layout.stride,
+                input_rate: if vf.instanced {
+                    vk::VertexInputRate::INSTANCE
+                } else {
+                    vk::VertexInputRate::VERTEX
+                },
+            });
+            for at in vf.layout.attributes.iter() {
+                vertex_attributes.push(vk::VertexInputAttributeDescription {
+                    location: vertex_attributes.len() as u32,
+                    binding: i as u32,
+                    format: super::map_vertex_format(at.format),
+                    offset: at.offset,
+                });
+            }
+        }
+        let vk_vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&vertex_buffers)
+            .vertex_attribute_descriptions(&vertex_attributes)
+            .build();
         let (raw_topology, supports_restart) = map_primitive_topology(desc.primitive.topology);
         let vk_input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(raw_topology)
@@ -499,16 +688,25 @@ impl super::Context {
             .viewport_count(1)
             .build();
 
-        let vk_sample_mask = [1u32, 0];
+        let ms = &desc.multisample_state;
+        let vk_sample_mask = [ms.sample_mask as u32, (ms.sample_mask >> 32) as u32];
         let vk_multisample = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+            .rasterization_samples(vk::SampleCountFlags::from_raw(ms.sample_count))
+            .alpha_to_coverage_enable(ms.alpha_to_coverage)
             .sample_mask(&vk_sample_mask)
             .build();
 
-        let mut ds_format = vk::Format::UNDEFINED;
+        let mut depth_format = vk::Format::UNDEFINED;
+        let mut stencil_format = vk::Format::UNDEFINED;
         let mut vk_depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder();
         if let Some(ref ds) = desc.depth_stencil {
-            ds_format = super::map_texture_format(ds.format);
+            let aspects = ds.format.aspects();
+            if aspects.contains(crate::TexelAspects::DEPTH) {
+                depth_format = self.device.map_texture_format(ds.format);
+            }
+            if aspects.contains(crate::TexelAspects::STENCIL) {
+                stencil_format = self.device.map_texture_format(ds.format);
+            }
 
             if ds.depth_write_enabled || ds.depth_compare != crate::CompareFunction::Always {
                 vk_depth_stencil = vk_depth_stencil
@@ -553,7 +751,7 @@ impl super::Context {
                     .dst_alpha_blend_factor(alpha_dst);
             }
 
-            color_formats.push(super::map_texture_format(ct.format));
+            color_formats.push(self.device.map_texture_format(ct.format));
             vk_attachments.push(vk_attachment.build());
         }
         let vk_color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
@@ -562,8 +760,8 @@ impl super::Context {
 
         let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
             .color_attachment_formats(&color_formats)
-            .depth_attachment_format(ds_format)
-            .stencil_attachment_format(ds_format)
+            .depth_attachment_format(depth_format)
+            .stencil_attachment_format(stencil_format)
             .build();
 
         let create_info = vk::GraphicsPipelineCreateInfo::builder()
@@ -580,21 +778,28 @@ impl super::Context {
             .push_next(&mut rendering_info)
             .build();
 
-        let mut raw_vec = unsafe {
-            self.device
-                .core
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
-                .unwrap()
+        let result = unsafe {
+            self.device.core.create_graphics_pipelines(
+                desc.cache
+                    .map_or(vk::PipelineCache::null(), |cache| cache.raw),
+                &[create_info],
+                None,
+            )
         };
-        let raw = raw_vec.pop().unwrap();
-
         unsafe { self.device.core.destroy_shader_module(vs.vk_module, None) };
         unsafe { self.device.core.destroy_shader_module(fs.vk_module, None) };
+        let raw = match result {
+            Ok(mut raw_vec) => raw_vec.pop().unwrap(),
+            Err((_, error)) => {
+                self.destroy_pipeline_layout(layout);
+                return Err(super::map_error(error));
+            }
+        };
 
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::PIPELINE, raw, desc.name);
         }
-        super::RenderPipeline { raw, layout }
+        Ok(super::RenderPipeline { raw, layout })
     }
 }
 
@@ -1,6 +1,7 @@
+use crate::software_rt;
 use ash::vk;
 use gpu_alloc_ash::AshMemoryDevice;
-use std::{mem, ptr};
+use std::{mem, ptr, sync::atomic::Ordering};
 
 struct Allocation {
     memory: vk::DeviceMemory,
@@ -9,14 +10,15 @@ struct Allocation {
     handle: usize,
 }
 
-impl super::Context {
+impl super::Device {
     fn allocate_memory(
         &self,
         requirements: vk::MemoryRequirements,
         memory: crate::Memory,
-    ) -> Allocation {
+        name: &str,
+    ) -> Result<Allocation, crate::Error> {
         let mut manager = self.memory.lock().unwrap();
-        let device_address_usage = if self.device.ray_tracing.is_some() {
+        let device_address_usage = if self.ray_tracing.is_some() {
             gpu_alloc::UsageFlags::DEVICE_ADDRESS
         } else {
             gpu_alloc::UsageFlags::empty()
@@ -41,7 +43,7 @@ impl super::Context {
             manager
                 .allocator
                 .alloc(
-                    AshMemoryDevice::wrap(&self.device.core),
+                    AshMemoryDevice::wrap(&self.core),
                     gpu_alloc::Request {
                         size: requirements.size,
                         align_mask: requirements.alignment - 1,
@@ -49,28 +51,52 @@ impl super::Context {
                         memory_types,
                     },
                 )
-                .unwrap()
+                .map_err(|e| {
+                    log::error!("Memory allocation failed: {}", e);
+                    match e {
+                        gpu_alloc::AllocationError::NoCompatibleMemoryTypes => {
+                            crate::Error::InvalidDescriptor
+                        }
+                        _ => crate::Error::OutOfMemory,
+                    }
+                })?
         };
 
         let data = match memory {
             crate::Memory::Device => ptr::null_mut(),
-            crate::Memory::Shared | crate::Memory::Upload => unsafe {
-                block
-                    .map(
-                        AshMemoryDevice::wrap(&self.device.core),
+            crate::Memory::Shared | crate::Memory::Upload => {
+                let result = unsafe {
+                    block.map(
+                        AshMemoryDevice::wrap(&self.core),
                         0,
                         requirements.size as usize,
                     )
-                    .unwrap()
-                    .as_ptr()
-            },
+                };
+                match result {
+                    Ok(data) => data.as_ptr(),
+                    Err(e) => {
+                        log::error!("Memory mapping failed: {}", e);
+                        unsafe {
+                            manager
+                                .allocator
+                                .dealloc(AshMemoryDevice::wrap(&self.core), block);
+                        }
+                        return Err(crate::Error::OutOfMemory);
+                    }
+                }
+            }
         };
-        Allocation {
+        let name = if manager.track_names {
+            name.to_string()
+        } else {
+            String::new()
+        };
+        Ok(Allocation {
             memory: *block.memory(),
             offset: block.offset(),
             data,
-            handle: manager.slab.insert(block),
-        }
+            handle: manager.slab.insert(super::MemoryBlock { raw: block, name }),
+        })
     }
 
     fn free_memory(&self, handle: usize) {
@@ -79,8 +105,89 @@ impl super::Context {
         unsafe {
             manager
                 .allocator
-                .dealloc(AshMemoryDevice::wrap(&self.device.core), block);
+                .dealloc(AshMemoryDevice::wrap(&self.core), block.raw);
+        }
+    }
+
+    /// Create a buffer for the uniform ring, only used by the queue of its command buffer.
+    pub(super) fn create_uniform_buffer(&self, size: u64) -> Result<super::Buffer, crate::Error> {
+        let vk_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let raw = unsafe { self.core.create_buffer(&vk_info, None) }.map_err(super::map_error)?;
+        let requirements = unsafe { self.core.get_buffer_memory_requirements(raw) };
+        let allocation =
+            match self.allocate_memory(requirements, crate::Memory::Upload, "_uniform_ring") {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    unsafe { self.core.destroy_buffer(raw, None) };
+                    return Err(e);
+                }
+            };
+        log::info!("Creating uniform ring buffer {:?} of size {}", raw, size);
+        if let Err(e) = unsafe {
+            self.core
+                .bind_buffer_memory(raw, allocation.memory, allocation.offset)
+        } {
+            unsafe { self.core.destroy_buffer(raw, None) };
+            self.free_memory(allocation.handle);
+            return Err(super::map_error(e));
+        }
+        Ok(super::Buffer {
+            raw,
+            memory_handle: allocation.handle,
+            mapped_data: allocation.data,
+        })
+    }
+
+    pub(super) fn destroy_uniform_buffer(&self, buffer: super::Buffer) {
+        unsafe { self.core.destroy_buffer(buffer.raw, None) };
+        self.free_memory(buffer.memory_handle);
+    }
+}
+
+impl super::Context {
+    pub fn memory_report(&self) -> crate::MemoryReport {
+        let manager = self.device.memory.lock().unwrap();
+        let mut report = self.resource_counts.report(
+            self.limits
+                .memory_heaps
+                .iter()
+                .map(|heap| crate::HeapUsage {
+                    usage: 0,
+                    budget: heap.size,
+                })
+                .collect(),
+        );
+        for (_, block) in manager.slab.iter() {
+            let heap_index = manager.memory_type_heaps[block.raw.memory_type() as usize];
+            report.heaps[heap_index as usize].usage += block.raw.size();
+            if manager.track_names {
+                report.allocations.push(crate::AllocationReport {
+                    name: block.name.clone(),
+                    size: block.raw.size(),
+                });
+            }
+        }
+
+        if manager.budget {
+            // The driver knows about all the allocations of the process,
+            // including the unused space of the memory blocks.
+            let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+            let mut properties2 =
+                vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget_properties);
+            unsafe {
+                self.instance
+                    .core
+                    .get_physical_device_memory_properties2(self.physical_device, &mut properties2);
+            }
+            for (i, heap) in report.heaps.iter_mut().enumerate() {
+                heap.usage = budget_properties.heap_usage[i];
+                heap.budget = budget_properties.heap_budget[i];
+            }
         }
+        report
     }
 
     //TODO: move these into `ResourceDevice` trait when ready
@@ -88,6 +195,9 @@ impl super::Context {
         &self,
         meshes: &[crate::AccelerationStructureMesh],
     ) -> crate::AccelerationStructureSizes {
+        if self.device.software_rt.is_some() {
+            return software_rt::bottom_level_sizes(meshes);
+        }
         let blas_input = self.device.map_acceleration_structure_meshes(meshes);
         let rt = self.device.ray_tracing.as_ref().unwrap();
         let sizes_raw = unsafe {
@@ -104,10 +214,33 @@ impl super::Context {
         }
     }
 
+    /// Same as `get_top_level_acceleration_structure_sizes`, but for the given
+    /// bottom level structures, which the software ray queries copy into the top level.
+    pub fn get_top_level_acceleration_structure_sizes_for(
+        &self,
+        instance_count: u32,
+        bottom_level: &[super::AccelerationStructure],
+    ) -> crate::AccelerationStructureSizes {
+        if self.device.software_rt.is_none() {
+            return self.get_top_level_acceleration_structure_sizes(instance_count);
+        }
+        let sizes = bottom_level
+            .iter()
+            .map(|blas| blas.size)
+            .collect::<Vec<_>>();
+        software_rt::top_level_sizes_with_copies(instance_count, &sizes)
+    }
+
     pub fn get_top_level_acceleration_structure_sizes(
         &self,
         instance_count: u32,
     ) -> crate::AccelerationStructureSizes {
+        if let Some(ref software) = self.device.software_rt {
+            // The bottom level structures aren't known,
+            // so all the live structures are accounted for.
+            let total_size = software.total_size.load(Ordering::Relaxed);
+            return software_rt::top_level_sizes_with_copies(instance_count, &[total_size]);
+        }
         let geometry = vk::AccelerationStructureGeometryKHR::builder()
             .geometry_type(vk::GeometryTypeKHR::INSTANCES)
             .geometry(vk::AccelerationStructureGeometryDataKHR {
@@ -141,6 +274,24 @@ impl super::Context {
         instances: &[crate::AccelerationStructureInstance],
         bottom_level: &[super::AccelerationStructure],
     ) -> super::Buffer {
+        if self.device.software_rt.is_some() {
+            // The instances reference the bottom level structures by their offsets
+            // in the top level, so they need to be given to the build in the same order.
+            let sizes = bottom_level
+                .iter()
+                .map(|blas| blas.size)
+                .collect::<Vec<_>>();
+            let data = software_rt::instance_data(instances, &sizes);
+            let buffer = self.create_buffer(crate::BufferDesc {
+                name: "instance buffer",
+                size: (instances.len() as u64 * software_rt::INSTANCE_DATA_SIZE).max(1),
+                memory: crate::Memory::Shared,
+            });
+            unsafe {
+                ptr::copy_nonoverlapping(data.as_ptr(), buffer.data(), data.len());
+            }
+            return buffer;
+        }
         let buffer = self.create_buffer(crate::BufferDesc {
             name: "instance buffer",
             size: (instances.len().max(1) * mem::size_of::<vk::AccelerationStructureInstanceKHR>())
@@ -186,27 +337,42 @@ impl crate::traits::ResourceDevice for super::Context {
     type Sampler = super::Sampler;
     type AccelerationStructure = super::AccelerationStructure;
 
-    fn create_buffer(&self, desc: crate::BufferDesc) -> super::Buffer {
+    fn try_create_buffer(&self, desc: crate::BufferDesc) -> Result<super::Buffer, crate::Error> {
         use vk::BufferUsageFlags as Buf;
+        // Host-visible buffers are written by the CPU, so only the
+        // device-local ones get uploaded and filled by other queues.
+        let (sharing_mode, queue_families) = self.sharing(!desc.memory.is_host_visible());
         let mut vk_info = vk::BufferCreateInfo::builder()
             .size(desc.size)
             .usage(
                 Buf::TRANSFER_SRC
                     | Buf::TRANSFER_DST
                     | Buf::STORAGE_BUFFER
+                    | Buf::UNIFORM_BUFFER
                     | Buf::INDEX_BUFFER
                     | Buf::VERTEX_BUFFER
                     | Buf::INDIRECT_BUFFER,
             )
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families);
         if self.device.ray_tracing.is_some() {
             vk_info.usage |=
                 Buf::SHADER_DEVICE_ADDRESS | Buf::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
         }
 
-        let raw = unsafe { self.device.core.create_buffer(&vk_info, None).unwrap() };
+        let raw =
+            unsafe { self.device.core.create_buffer(&vk_info, None) }.map_err(super::map_error)?;
         let requirements = unsafe { self.device.core.get_buffer_memory_requirements(raw) };
-        let allocation = self.allocate_memory(requirements, desc.memory);
+        let allocation = match self
+            .device
+            .allocate_memory(requirements, desc.memory, desc.name)
+        {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                unsafe { self.device.core.destroy_buffer(raw, None) };
+                return Err(e);
+            }
+        };
 
         log::info!(
             "Creating buffer {:?} of size {}, name '{}', handle {:?}",
@@ -215,21 +381,25 @@ impl crate::traits::ResourceDevice for super::Context {
             desc.name,
             allocation.handle
         );
-        unsafe {
+        if let Err(e) = unsafe {
             self.device
                 .core
                 .bind_buffer_memory(raw, allocation.memory, allocation.offset)
-                .unwrap()
-        };
+        } {
+            unsafe { self.device.core.destroy_buffer(raw, None) };
+            self.device.free_memory(allocation.handle);
+            return Err(super::map_error(e));
+        }
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::BUFFER, raw, desc.name);
         }
+        self.resource_counts.buffers.increment();
 
-        super::Buffer {
+        Ok(super::Buffer {
             raw,
             memory_handle: allocation.handle,
             mapped_data: allocation.data,
-        }
+        })
     }
 
     fn sync_buffer(&self, _buffer: super::Buffer) {}
@@ -241,34 +411,86 @@ impl crate::traits::ResourceDevice for super::Context {
             buffer.memory_handle
         );
         unsafe { self.device.core.destroy_buffer(buffer.raw, None) };
-        self.free_memory(buffer.memory_handle);
+        self.device.free_memory(buffer.memory_handle);
+        self.resource_counts.buffers.decrement();
     }
 
-    fn create_texture(&self, desc: crate::TextureDesc) -> super::Texture {
+    fn try_create_texture(&self, desc: crate::TextureDesc) -> Result<super::Texture, crate::Error> {
+        desc.validate(&self.limits, self.format_features(desc.format))?;
         let mut create_flags = vk::ImageCreateFlags::empty();
         if desc.dimension == crate::TextureDimension::D2
-            && desc.size.depth % 6 == 0
-            //&& desc.sample_count == 1
+            && desc.array_layer_count % 6 == 0
+            && desc.sample_count == 1
             && desc.size.width == desc.size.height
         {
             create_flags |= vk::ImageCreateFlags::CUBE_COMPATIBLE;
         }
 
+        let image_type = map_texture_dimension(desc.dimension);
+        let vk_format = self.device.map_texture_format(desc.format);
+        let vk_usage = map_texture_usage(desc.usage, desc.format.aspects());
+        let properties = unsafe {
+            self.instance
+                .core
+                .get_physical_device_image_format_properties(
+                    self.physical_device,
+                    vk_format,
+                    image_type,
+                    vk::ImageTiling::OPTIMAL,
+                    vk_usage,
+                    create_flags,
+                )
+        }
+        .map_err(super::map_error)?;
+        if desc.size.width > properties.max_extent.width
+            || desc.size.height > properties.max_extent.height
+            || desc.size.depth > properties.max_extent.depth
+            || desc.mip_level_count > properties.max_mip_levels
+            || desc.array_layer_count > properties.max_array_layers
+            || !properties
+                .sample_counts
+                .contains(vk::SampleCountFlags::from_raw(desc.sample_count))
+        {
+            log::error!(
+                "Texture '{}' exceeds the limits of format {:?}",
+                desc.name,
+                desc.format
+            );
+            return Err(crate::Error::InvalidDescriptor);
+        }
+
+        // Textures that are only rendered to stay on the general queue.
+        let (sharing_mode, queue_families) = self.sharing(
+            desc.usage
+                .intersects(crate::TextureUsage::COPY | crate::TextureUsage::STORAGE),
+        );
         let vk_info = vk::ImageCreateInfo::builder()
             .flags(create_flags)
-            .image_type(map_texture_dimension(desc.dimension))
-            .format(super::map_texture_format(desc.format))
+            .image_type(image_type)
+            .format(vk_format)
             .extent(super::map_extent_3d(&desc.size))
             .mip_levels(desc.mip_level_count)
             .array_layers(desc.array_layer_count)
-            .samples(vk::SampleCountFlags::from_raw(1)) // desc.sample_count
+            .samples(vk::SampleCountFlags::from_raw(desc.sample_count))
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(map_texture_usage(desc.usage, desc.format.aspects()))
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            .usage(vk_usage)
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families);
 
-        let raw = unsafe { self.device.core.create_image(&vk_info, None).unwrap() };
+        let raw =
+            unsafe { self.device.core.create_image(&vk_info, None) }.map_err(super::map_error)?;
         let requirements = unsafe { self.device.core.get_image_memory_requirements(raw) };
-        let allocation = self.allocate_memory(requirements, crate::Memory::Device);
+        let allocation =
+            match self
+                .device
+                .allocate_memory(requirements, crate::Memory::Device, desc.name)
+            {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    unsafe { self.device.core.destroy_image(raw, None) };
+                    return Err(e);
+                }
+            };
 
         log::info!(
             "Creating texture {:?} of size {} and format {:?}, name '{}', handle {:?}",
@@ -278,22 +500,26 @@ impl crate::traits::ResourceDevice for super::Context {
             desc.name,
             allocation.handle
         );
-        unsafe {
+        if let Err(e) = unsafe {
             self.device
                 .core
                 .bind_image_memory(raw, allocation.memory, allocation.offset)
-                .unwrap()
-        };
+        } {
+            unsafe { self.device.core.destroy_image(raw, None) };
+            self.device.free_memory(allocation.handle);
+            return Err(super::map_error(e));
+        }
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::IMAGE, raw, desc.name);
         }
+        self.resource_counts.textures.increment();
 
-        super::Texture {
+        Ok(super::Texture {
             raw,
             memory_handle: allocation.handle,
             target_size: [desc.size.width as u16, desc.size.height as u16],
             format: desc.format,
-        }
+        })
     }
 
     fn destroy_texture(&self, texture: super::Texture) {
@@ -303,38 +529,47 @@ impl crate::traits::ResourceDevice for super::Context {
             texture.memory_handle
         );
         unsafe { self.device.core.destroy_image(texture.raw, None) };
-        self.free_memory(texture.memory_handle);
+        self.device.free_memory(texture.memory_handle);
+        self.resource_counts.textures.decrement();
     }
 
-    fn create_texture_view(&self, desc: crate::TextureViewDesc) -> super::TextureView {
-        let aspects = desc.format.aspects();
+    fn try_create_texture_view(
+        &self,
+        desc: crate::TextureViewDesc,
+    ) -> Result<super::TextureView, crate::Error> {
+        let aspects = desc.format.select_aspects(desc.subresources.aspects);
         let subresource_range = super::map_subresource_range(desc.subresources, aspects);
         let vk_info = vk::ImageViewCreateInfo::builder()
             .image(desc.texture.raw)
             .view_type(map_view_dimension(desc.dimension))
-            .format(super::map_texture_format(desc.format))
+            .format(self.device.map_texture_format(desc.format))
             .subresource_range(subresource_range);
 
-        let raw = unsafe { self.device.core.create_image_view(&vk_info, None).unwrap() };
+        let raw = unsafe { self.device.core.create_image_view(&vk_info, None) }
+            .map_err(super::map_error)?;
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::IMAGE_VIEW, raw, desc.name);
         }
 
-        super::TextureView {
+        Ok(super::TextureView {
             raw,
+            image: desc.texture.raw,
             target_size: [
                 (desc.texture.target_size[0] >> desc.subresources.base_mip_level).max(1),
                 (desc.texture.target_size[1] >> desc.subresources.base_mip_level).max(1),
             ],
             aspects,
-        }
+            base_mip_level: subresource_range.base_mip_level,
+            base_array_layer: subresource_range.base_array_layer,
+            array_layer_count: subresource_range.layer_count,
+        })
     }
 
     fn destroy_texture_view(&self, view: super::TextureView) {
         unsafe { self.device.core.destroy_image_view(view.raw, None) };
     }
 
-    fn create_sampler(&self, desc: crate::SamplerDesc) -> super::Sampler {
+    fn try_create_sampler(&self, desc: crate::SamplerDesc) -> Result<super::Sampler, crate::Error> {
         let mut vk_info = vk::SamplerCreateInfo::builder()
             .mag_filter(map_filter_mode(desc.mag_filter))
             .min_filter(map_filter_mode(desc.min_filter))
@@ -359,38 +594,83 @@ impl crate::traits::ResourceDevice for super::Context {
             vk_info = vk_info.border_color(map_border_color(color));
         }
 
-        let raw = unsafe { self.device.core.create_sampler(&vk_info, None).unwrap() };
+        let raw =
+            unsafe { self.device.core.create_sampler(&vk_info, None) }.map_err(super::map_error)?;
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::SAMPLER, raw, desc.name);
         }
 
-        super::Sampler { raw }
+        Ok(super::Sampler { raw })
     }
 
     fn destroy_sampler(&self, sampler: super::Sampler) {
         unsafe { self.device.core.destroy_sampler(sampler.raw, None) };
     }
 
-    fn create_acceleration_structure(
+    fn try_create_acceleration_structure(
         &self,
         desc: crate::AccelerationStructureDesc,
-    ) -> super::AccelerationStructure {
+    ) -> Result<super::AccelerationStructure, crate::Error> {
+        let usage = if self.device.ray_tracing.is_some() {
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+        } else {
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST
+        };
+        // Acceleration structures may be built by the compute queue.
+        let (sharing_mode, queue_families) = self.sharing(true);
         let buffer_info = vk::BufferCreateInfo::builder()
             .size(desc.size)
-            .usage(vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            .usage(usage)
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families);
 
-        let buffer = unsafe { self.device.core.create_buffer(&buffer_info, None).unwrap() };
+        let buffer = unsafe { self.device.core.create_buffer(&buffer_info, None) }
+            .map_err(super::map_error)?;
         let requirements = unsafe { self.device.core.get_buffer_memory_requirements(buffer) };
-        let allocation = self.allocate_memory(requirements, crate::Memory::Device);
-
-        unsafe {
+        let allocation =
+            match self
+                .device
+                .allocate_memory(requirements, crate::Memory::Device, desc.name)
+            {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    unsafe { self.device.core.destroy_buffer(buffer, None) };
+                    return Err(e);
+                }
+            };
+        let bind_result = unsafe {
             self.device
                 .core
                 .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
-                .unwrap()
         };
 
+        let rt = match self.device.ray_tracing {
+            Some(ref rt) => rt,
+            None => {
+                if let Err(e) = bind_result {
+                    unsafe { self.device.core.destroy_buffer(buffer, None) };
+                    self.device.free_memory(allocation.handle);
+                    return Err(super::map_error(e));
+                }
+                let software = self.device.software_rt.as_ref().unwrap();
+                software.pipelines.init(self);
+                software
+                    .total_size
+                    .fetch_add(software_rt::copy_size(desc.size), Ordering::Relaxed);
+                if !desc.name.is_empty() {
+                    self.set_object_name(vk::ObjectType::BUFFER, buffer, desc.name);
+                }
+                self.resource_counts.acceleration_structures.increment();
+                return Ok(super::AccelerationStructure {
+                    raw: vk::AccelerationStructureKHR::null(),
+                    buffer,
+                    memory_handle: allocation.handle,
+                    size: desc.size,
+                });
+            }
+        };
         let raw_ty = match desc.ty {
             crate::AccelerationStructureType::TopLevel => {
                 vk::AccelerationStructureTypeKHR::TOP_LEVEL
@@ -404,34 +684,50 @@ impl crate::traits::ResourceDevice for super::Context {
             .buffer(buffer)
             .size(desc.size);
 
-        let rt = self.device.ray_tracing.as_ref().unwrap();
-        let raw = unsafe {
+        let raw = match bind_result.and_then(|()| unsafe {
             rt.acceleration_structure
                 .create_acceleration_structure(&vk_info, None)
-                .unwrap()
+        }) {
+            Ok(raw) => raw,
+            Err(e) => {
+                unsafe { self.device.core.destroy_buffer(buffer, None) };
+                self.device.free_memory(allocation.handle);
+                return Err(super::map_error(e));
+            }
         };
 
         if !desc.name.is_empty() {
             self.set_object_name(vk::ObjectType::BUFFER, buffer, desc.name);
             self.set_object_name(vk::ObjectType::ACCELERATION_STRUCTURE_KHR, raw, desc.name);
         }
-        super::AccelerationStructure {
+        self.resource_counts.acceleration_structures.increment();
+        Ok(super::AccelerationStructure {
             raw,
             buffer,
             memory_handle: allocation.handle,
-        }
+            size: desc.size,
+        })
     }
 
     fn destroy_acceleration_structure(&self, acceleration_structure: super::AccelerationStructure) {
-        let rt = self.device.ray_tracing.as_ref().unwrap();
+        if let Some(ref software) = self.device.software_rt {
+            software.total_size.fetch_sub(
+                software_rt::copy_size(acceleration_structure.size),
+                Ordering::Relaxed,
+            );
+        }
         unsafe {
-            rt.acceleration_structure
-                .destroy_acceleration_structure(acceleration_structure.raw, None);
+            if let Some(ref rt) = self.device.ray_tracing {
+                rt.acceleration_structure
+                    .destroy_acceleration_structure(acceleration_structure.raw, None);
+            }
             self.device
                 .core
                 .destroy_buffer(acceleration_structure.buffer, None);
         }
-        self.free_memory(acceleration_structure.memory_handle);
+        self.device
+            .free_memory(acceleration_structure.memory_handle);
+        self.resource_counts.acceleration_structures.decrement();
     }
 }
 
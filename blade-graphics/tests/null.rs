@@ -0,0 +1,1562 @@
+//! Tests for the host-memory backend, run with:
+//! RUSTFLAGS="--cfg null" cargo test -p blade-graphics
+#![cfg(null)]
+#![allow(irrefutable_let_patterns)]
+
+use blade_graphics as gpu;
+use std::{mem, ptr, slice};
+
+const SHADER: &str = "
+var input: texture_2d<f32>;
+var<uniform> modulator: vec4<f32>;
+var<private> sink: vec4<f32>;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    sink = textureLoad(input, vec2<i32>(global_id.xy), 0) * modulator;
+}
+";
+
+const DRAW_SHADER: &str = "
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(index & 1u), f32(index >> 1u), 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
+";
+
+const VERTEX_SHADER: &str = "
+struct VertexInput {
+    @location(0) pos: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(input: VertexInput) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(input.pos, 0.0, 1.0) * input.color;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
+";
+
+const OVERRIDE_SHADER: &str = "
+@id(0) override block_size: u32 = 8u;
+override scale: f32;
+override enabled = true;
+var<workgroup> cache: array<f32, (block_size * 2u)>;
+
+@compute @workgroup_size(block_size * 2u, 1)
+fn main(@builtin(local_invocation_index) index: u32) {
+    if enabled {
+        cache[index] = f32(index) * scale;
+    }
+}
+";
+
+const STRUCT_SHADER: &str = "
+struct Params {
+    offset: vec3<f32>,
+    count: u32,
+    scale: vec2<f32>,
+}
+var<uniform> params: Params;
+
+@compute @workgroup_size(1)
+fn main() {
+    let scale = params.scale;
+}
+";
+
+const RAY_QUERY_SHADER: &str = "
+var acc_struct: acceleration_structure;
+var<storage, read_write> hits: array<f32>;
+
+fn hit_distance(intersection: RayIntersection) -> f32 {
+    return select(-1.0, intersection.t, intersection.kind != RAY_QUERY_INTERSECTION_NONE);
+}
+
+@compute @workgroup_size(8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var rq: ray_query;
+    let origin = vec3<f32>(f32(global_id.x), 0.0, -5.0);
+    rayQueryInitialize(&rq, acc_struct, RayDesc(0u, 0xFFu, 0.1, 100.0, origin, vec3<f32>(0.0, 0.0, 1.0)));
+    rayQueryProceed(&rq);
+    hits[global_id.x] = hit_distance(rayQueryGetCommittedIntersection(&rq));
+}
+";
+
+struct RayQueryData {
+    acc_struct: gpu::AccelerationStructure,
+    hits: gpu::BufferPiece,
+}
+
+impl gpu::ShaderData for RayQueryData {
+    fn layout() -> gpu::ShaderDataLayout {
+        gpu::ShaderDataLayout {
+            bindings: vec![
+                ("acc_struct", gpu::ShaderBinding::AccelerationStructure),
+                ("hits", gpu::ShaderBinding::Buffer),
+            ],
+        }
+    }
+    fn fill(&self, mut ctx: gpu::PipelineContext) {
+        use gpu::ShaderBindable as _;
+        self.acc_struct.bind_to(&mut ctx, 0);
+        self.hits.bind_to(&mut ctx, 1);
+    }
+}
+
+/// Host version of `Params`, with `scale` placed at a wrong offset.
+#[repr(C)]
+struct Params {
+    offset: [f32; 3],
+    count: u32,
+    pad: u32,
+    scale: [f32; 2],
+    pad2: u32,
+}
+
+impl gpu::ShaderStruct for Params {
+    fn layout() -> gpu::ShaderStructLayout {
+        let member = |name, offset, size, kind| gpu::ShaderStructMember {
+            name,
+            offset,
+            size,
+            ty: gpu::ShaderMemberType::Plain(kind),
+        };
+        gpu::ShaderStructLayout {
+            name: "Params",
+            size: 32,
+            members: vec![
+                member("offset", 0, 12, gpu::ScalarKind::Float),
+                member("count", 12, 4, gpu::ScalarKind::Uint),
+                member("pad", 16, 4, gpu::ScalarKind::Uint),
+                member("scale", 20, 8, gpu::ScalarKind::Float),
+                member("pad2", 28, 4, gpu::ScalarKind::Uint),
+            ],
+        }
+    }
+}
+
+struct Globals {
+    modulator: [f32; 4],
+    input: gpu::TextureView,
+}
+
+impl gpu::ShaderData for Globals {
+    fn layout() -> gpu::ShaderDataLayout {
+        gpu::ShaderDataLayout {
+            bindings: vec![
+                ("modulator", gpu::ShaderBinding::Plain { size: 16 }),
+                ("input", gpu::ShaderBinding::Texture),
+            ],
+        }
+    }
+    fn fill(&self, mut ctx: gpu::PipelineContext) {
+        use gpu::ShaderBindable as _;
+        self.modulator.bind_to(&mut ctx, 0);
+        self.input.bind_to(&mut ctx, 1);
+    }
+}
+
+fn init() -> gpu::Context {
+    unsafe {
+        gpu::Context::init(gpu::ContextDesc {
+            validation: true,
+            capture: false,
+            timing: true,
+            adapter: gpu::AdapterSelector::Any,
+        })
+        .unwrap()
+    }
+}
+
+#[test]
+fn transfer_round_trip() {
+    let context = init();
+    let extent = gpu::Extent {
+        width: 4,
+        height: 4,
+        depth: 1,
+    };
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "texture",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: extent,
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 2,
+        mip_level_count: 2,
+        sample_count: 1,
+        usage: gpu::TextureUsage::COPY,
+    });
+    let upload = context.create_buffer(gpu::BufferDesc {
+        name: "upload",
+        size: 64,
+        memory: gpu::Memory::Upload,
+    });
+    let readback = context.create_buffer(gpu::BufferDesc {
+        name: "readback",
+        size: 256 * 2,
+        memory: gpu::Memory::Shared,
+    });
+    unsafe {
+        let data = slice::from_raw_parts_mut(upload.data() as *mut u32, 16);
+        for (i, value) in data.iter_mut().enumerate() {
+            *value = i as u32;
+        }
+    }
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut transfer = encoder.transfer_labeled("round-trip") {
+        transfer.fill_buffer(readback.into(), 256 * 2, 0xFF);
+        transfer.copy_buffer_to_texture(
+            upload.into(),
+            16,
+            gpu::TexturePiece {
+                texture,
+                mip_level: 0,
+                array_layer: 1,
+                origin: [0; 3],
+                aspects: gpu::TexelAspects::empty(),
+            },
+            extent,
+        );
+        transfer.copy_texture_to_buffer(
+            gpu::TexturePiece {
+                texture,
+                mip_level: 0,
+                array_layer: 1,
+                origin: [1, 2, 0],
+                aspects: gpu::TexelAspects::empty(),
+            },
+            readback.into(),
+            256,
+            gpu::Extent {
+                width: 2,
+                height: 2,
+                depth: 1,
+            },
+        );
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    let result = unsafe { slice::from_raw_parts(readback.data() as *const u32, 128) };
+    assert_eq!(&result[..3], &[9, 10, !0]);
+    assert_eq!(&result[64..67], &[13, 14, !0]);
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(upload);
+    context.destroy_buffer(readback);
+    context.destroy_texture(texture);
+}
+
+#[test]
+fn texture_readback() {
+    let context = init();
+    let extent = gpu::Extent {
+        width: 3,
+        height: 2,
+        depth: 1,
+    };
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "texture",
+        format: gpu::TextureFormat::R32Uint,
+        size: extent,
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::COPY,
+    });
+    let upload = context.create_buffer(gpu::BufferDesc {
+        name: "upload",
+        size: 24,
+        memory: gpu::Memory::Upload,
+    });
+    unsafe {
+        let data = slice::from_raw_parts_mut(upload.data() as *mut u32, 6);
+        data.copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+    }
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder.transfer_labeled("upload").copy_buffer_to_texture(
+        upload.into(),
+        12,
+        texture.into(),
+        extent,
+    );
+    let request = context.request_readback(
+        &mut encoder,
+        gpu::TexturePiece {
+            texture,
+            mip_level: 0,
+            array_layer: 0,
+            origin: [1, 0, 0],
+            aspects: gpu::TexelAspects::empty(),
+        },
+        gpu::TextureFormat::R32Uint,
+        gpu::Extent {
+            width: 2,
+            height: 2,
+            depth: 1,
+        },
+    );
+    let sync_point = context.submit(&mut encoder);
+    let result = request.finish(&context, &sync_point);
+    assert_eq!(bytemuck::cast_slice::<u8, u32>(&result), &[2, 3, 5, 6]);
+
+    let result = context.read_texture(texture.into(), gpu::TextureFormat::R32Uint, extent);
+    assert_eq!(result.len(), 24);
+    assert_eq!(&result[20..], &6u32.to_ne_bytes());
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(upload);
+    context.destroy_texture(texture);
+}
+
+#[test]
+fn packed_format_readback() {
+    let context = init();
+    let extent = gpu::Extent {
+        width: 2,
+        height: 1,
+        depth: 1,
+    };
+    let formats = [
+        gpu::TextureFormat::Rgb10a2Unorm,
+        gpu::TextureFormat::Rg11b10Float,
+        gpu::TextureFormat::Rgb9e5Ufloat,
+        gpu::TextureFormat::Rg16Float,
+    ];
+    for format in formats {
+        assert_eq!(format.block_info().size, 4);
+        let texture = context.create_texture(gpu::TextureDesc {
+            name: "packed",
+            format,
+            size: extent,
+            dimension: gpu::TextureDimension::D2,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: gpu::TextureUsage::COPY,
+        });
+        let upload = context.create_buffer(gpu::BufferDesc {
+            name: "upload",
+            size: 8,
+            memory: gpu::Memory::Upload,
+        });
+        unsafe {
+            let data = slice::from_raw_parts_mut(upload.data() as *mut u32, 2);
+            data.copy_from_slice(&[0xC000_03FF, 0x3FF0_0000]);
+        }
+
+        let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+            name: "test",
+            command_type: gpu::CommandType::General,
+            buffer_count: 1,
+        });
+        encoder.start();
+        encoder.transfer_labeled("upload").copy_buffer_to_texture(
+            upload.into(),
+            8,
+            texture.into(),
+            extent,
+        );
+        let sync_point = context.submit(&mut encoder);
+        context.wait_for(&sync_point, !0);
+
+        let result = context.read_texture(texture.into(), format, extent);
+        assert_eq!(
+            bytemuck::cast_slice::<u8, u32>(&result),
+            &[0xC000_03FF, 0x3FF0_0000]
+        );
+
+        context.destroy_command_encoder(encoder);
+        context.destroy_buffer(upload);
+        context.destroy_texture(texture);
+    }
+}
+
+#[test]
+fn pass_timings() {
+    let context = init();
+    let buffer = context.create_buffer(gpu::BufferDesc {
+        name: "buffer",
+        size: 16,
+        memory: gpu::Memory::Shared,
+    });
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder
+        .transfer_labeled("fill")
+        .fill_buffer(buffer.into(), 16, 1);
+    encoder
+        .transfer_labeled("copy")
+        .copy_buffer_to_buffer(buffer.into(), buffer.at(8), 8);
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    let labels = encoder
+        .timings()
+        .iter()
+        .map(|(label, _)| label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, ["fill", "copy"]);
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(buffer);
+}
+
+#[test]
+fn creation_errors() {
+    let context = init();
+    let buffer = context.try_create_buffer(gpu::BufferDesc {
+        name: "huge",
+        size: u64::MAX / 2,
+        memory: gpu::Memory::Shared,
+    });
+    assert_eq!(buffer.err(), Some(gpu::Error::OutOfMemory));
+
+    let texture = context.try_create_texture(gpu::TextureDesc {
+        name: "no mips",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 0,
+        sample_count: 1,
+        usage: gpu::TextureUsage::COPY,
+    });
+    assert_eq!(texture.err(), Some(gpu::Error::InvalidDescriptor));
+
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "single layer",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::RESOURCE,
+    });
+    let view = context.try_create_texture_view(gpu::TextureViewDesc {
+        name: "second layer",
+        texture,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &gpu::TextureSubresources {
+            base_array_layer: 1,
+            ..Default::default()
+        },
+    });
+    assert_eq!(view.err(), Some(gpu::Error::InvalidDescriptor));
+    context.destroy_texture(texture);
+}
+
+#[test]
+fn msaa_resolve() {
+    let context = init();
+    let sample_count = 4;
+    assert_ne!(context.capabilities().sample_count_mask & sample_count, 0);
+
+    let texture_desc = |name, sample_count| gpu::TextureDesc {
+        name,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 8,
+            height: 8,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count,
+        usage: gpu::TextureUsage::TARGET,
+    };
+    let msaa_texture = context.create_texture(texture_desc("msaa", sample_count));
+    let resolve_texture = context.create_texture(texture_desc("resolve", 1));
+    let subresources = gpu::TextureSubresources::default();
+    let view_desc = |name, texture| gpu::TextureViewDesc {
+        name,
+        texture,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &subresources,
+    };
+    let msaa_view = context.create_texture_view(view_desc("msaa", msaa_texture));
+    let resolve_view = context.create_texture_view(view_desc("resolve", resolve_texture));
+
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: DRAW_SHADER,
+    });
+    let pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
+        name: "draw",
+        data_layouts: &[],
+        vertex: shader.at("vs_main"),
+        vertex_fetches: &[],
+        primitive: gpu::PrimitiveState::default(),
+        depth_stencil: None,
+        fragment: shader.at("fs_main"),
+        color_targets: &[gpu::TextureFormat::Rgba8Unorm.into()],
+        multisample_state: gpu::MultisampleState {
+            sample_count,
+            ..Default::default()
+        },
+        overrides: &[],
+        cache: None,
+    });
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut pass = encoder.render_labeled(
+        "draw",
+        gpu::RenderTargetSet {
+            colors: &[gpu::RenderTarget {
+                view: msaa_view,
+                init_op: gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
+                finish_op: gpu::FinishOp::ResolveTo(resolve_view),
+            }],
+            depth_stencil: None,
+        },
+    ) {
+        pass.with(&pipeline).draw(0, 3, 0, 1);
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_texture_view(msaa_view);
+    context.destroy_texture_view(resolve_view);
+    context.destroy_texture(msaa_texture);
+    context.destroy_texture(resolve_texture);
+}
+
+#[test]
+fn vertex_fetch() {
+    let context = init();
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "target",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::TARGET,
+    });
+    let view = context.create_texture_view(gpu::TextureViewDesc {
+        name: "target",
+        texture,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &Default::default(),
+    });
+    let vertex_buf = context.create_buffer(gpu::BufferDesc {
+        name: "vertices",
+        size: 3 * 12,
+        memory: gpu::Memory::Shared,
+    });
+
+    let layout = gpu::VertexLayout {
+        attributes: vec![
+            gpu::VertexAttribute {
+                offset: 0,
+                format: gpu::VertexFormat::F32Vec2,
+            },
+            gpu::VertexAttribute {
+                offset: 8,
+                format: gpu::VertexFormat::U8Vec4Norm,
+            },
+        ],
+        stride: 12,
+    };
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: VERTEX_SHADER,
+    });
+    let pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
+        name: "draw",
+        data_layouts: &[],
+        vertex: shader.at("vs_main"),
+        vertex_fetches: &[gpu::VertexFetchState {
+            layout: &layout,
+            instanced: false,
+        }],
+        primitive: gpu::PrimitiveState::default(),
+        depth_stencil: None,
+        fragment: shader.at("fs_main"),
+        color_targets: &[gpu::TextureFormat::Rgba8Unorm.into()],
+        multisample_state: gpu::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
+    });
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut pass = encoder.render_labeled(
+        "draw",
+        gpu::RenderTargetSet {
+            colors: &[gpu::RenderTarget {
+                view,
+                init_op: gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
+                finish_op: gpu::FinishOp::Store,
+            }],
+            depth_stencil: None,
+        },
+    ) {
+        let mut pc = pass.with(&pipeline);
+        pc.bind_vertex_buffer(0, vertex_buf.into());
+        pc.draw(0, 3, 0, 1);
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(vertex_buf);
+    context.destroy_texture_view(view);
+    context.destroy_texture(texture);
+}
+
+#[test]
+fn depth_stencil_aspects() {
+    let context = init();
+    let extent = gpu::Extent {
+        width: 2,
+        height: 2,
+        depth: 1,
+    };
+    let format = gpu::TextureFormat::Depth32FloatStencil8;
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "depth-stencil",
+        format,
+        size: extent,
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::TARGET | gpu::TextureUsage::COPY,
+    });
+    let view = context.create_texture_view(gpu::TextureViewDesc {
+        name: "depth-stencil",
+        texture,
+        format,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &Default::default(),
+    });
+    let upload = context.create_buffer(gpu::BufferDesc {
+        name: "upload",
+        size: 20,
+        memory: gpu::Memory::Upload,
+    });
+    unsafe {
+        let depth = slice::from_raw_parts_mut(upload.data() as *mut f32, 4);
+        depth.copy_from_slice(&[0.25, 0.5, 0.75, 1.0]);
+        let stencil = slice::from_raw_parts_mut(upload.data().add(16), 4);
+        stencil.copy_from_slice(&[1, 2, 3, 4]);
+    }
+
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: DRAW_SHADER,
+    });
+    let stencil_face = gpu::StencilFaceState {
+        compare: gpu::CompareFunction::Equal,
+        ..Default::default()
+    };
+    let pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
+        name: "stencil",
+        data_layouts: &[],
+        vertex: shader.at("vs_main"),
+        vertex_fetches: &[],
+        primitive: gpu::PrimitiveState::default(),
+        depth_stencil: Some(gpu::DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: gpu::CompareFunction::Always,
+            stencil: gpu::StencilState {
+                front: stencil_face,
+                back: stencil_face,
+                read_mask: !0,
+                write_mask: 0,
+            },
+            bias: Default::default(),
+        }),
+        fragment: shader.at("fs_main"),
+        color_targets: &[],
+        multisample_state: gpu::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
+    });
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut transfer = encoder.transfer_labeled("upload") {
+        transfer.copy_buffer_to_texture(
+            upload.into(),
+            8,
+            gpu::TexturePiece {
+                aspects: gpu::TexelAspects::DEPTH,
+                ..texture.into()
+            },
+            extent,
+        );
+        transfer.copy_buffer_to_texture(
+            upload.at(16),
+            2,
+            gpu::TexturePiece {
+                aspects: gpu::TexelAspects::STENCIL,
+                ..texture.into()
+            },
+            extent,
+        );
+    }
+    if let mut pass = encoder.render_labeled(
+        "stencil",
+        gpu::RenderTargetSet {
+            colors: &[],
+            depth_stencil: Some(gpu::RenderTarget {
+                view,
+                init_op: gpu::InitOp::Load,
+                finish_op: gpu::FinishOp::Store,
+            }),
+        },
+    ) {
+        let mut pc = pass.with(&pipeline);
+        pc.set_stencil_reference(3);
+        pc.draw(0, 3, 0, 1);
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    let stencil_piece = gpu::TexturePiece {
+        aspects: gpu::TexelAspects::STENCIL,
+        ..texture.into()
+    };
+    let result = context.read_texture(stencil_piece, format, extent);
+    assert_eq!(result, [1, 2, 3, 4]);
+    let depth_piece = gpu::TexturePiece {
+        aspects: gpu::TexelAspects::DEPTH,
+        ..texture.into()
+    };
+    let result = context.read_texture(depth_piece, format, extent);
+    assert_eq!(
+        bytemuck::cast_slice::<u8, f32>(&result),
+        &[0.25, 0.5, 0.75, 1.0]
+    );
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(upload);
+    context.destroy_texture_view(view);
+    context.destroy_texture(texture);
+}
+
+#[test]
+#[should_panic(expected = "need a single aspect")]
+fn depth_stencil_copy_aspects() {
+    let context = init();
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "depth-stencil",
+        format: gpu::TextureFormat::Depth24PlusStencil8,
+        size: gpu::Extent {
+            width: 1,
+            height: 1,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::COPY,
+    });
+    let buffer = context.create_buffer(gpu::BufferDesc {
+        name: "buffer",
+        size: 4,
+        memory: gpu::Memory::Shared,
+    });
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder.transfer_labeled("copy").copy_buffer_to_texture(
+        buffer.into(),
+        4,
+        texture.into(),
+        gpu::Extent {
+            width: 1,
+            height: 1,
+            depth: 1,
+        },
+    );
+}
+
+#[test]
+fn pipeline_layout_validation() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc { source: SHADER });
+    let layout = <Globals as gpu::ShaderData>::layout();
+    let _pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
+        name: "main",
+        data_layouts: &[&layout],
+        compute: shader.at("main"),
+        overrides: &[],
+        cache: None,
+    });
+}
+
+#[test]
+#[should_panic(expected = "Mismatched type for binding 'modulator'")]
+fn pipeline_layout_mismatch() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc { source: SHADER });
+    let layout = gpu::ShaderDataLayout {
+        bindings: vec![
+            ("modulator", gpu::ShaderBinding::Plain { size: 8 }),
+            ("input", gpu::ShaderBinding::Texture),
+        ],
+    };
+    let _pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
+        name: "main",
+        data_layouts: &[&layout],
+        compute: shader.at("main"),
+        overrides: &[],
+        cache: None,
+    });
+}
+
+#[test]
+#[should_panic(expected = "is not supported by Transfer encoder")]
+fn command_type_passes() {
+    let context = init();
+    let buffer = context.create_buffer(gpu::BufferDesc {
+        name: "buffer",
+        size: 16,
+        memory: gpu::Memory::Shared,
+    });
+    let mut upload = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "upload",
+        command_type: gpu::CommandType::Transfer,
+        buffer_count: 1,
+    });
+    upload.start();
+    upload
+        .transfer_labeled("fill")
+        .fill_buffer(buffer.into(), 16, 1);
+    let sync_point = context.submit(&mut upload);
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "compute",
+        command_type: gpu::CommandType::Compute,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder.wait_for(&sync_point);
+    encoder.compute_labeled("after upload");
+    assert!(context.wait_for(&context.submit(&mut encoder), !0));
+
+    upload.start();
+    upload.compute_labeled("not allowed");
+}
+
+#[test]
+fn plain_data_size() {
+    let context = init();
+    let make_pipeline = |count: u32| {
+        let source = format!(
+            "var<uniform> params: array<vec4<f32>, {}>;
+            var<private> sink: vec4<f32>;
+            @compute @workgroup_size(64) fn main() {{ sink = params[0]; }}",
+            count
+        );
+        let shader = context.create_shader(gpu::ShaderDesc { source: &source });
+        let layout = gpu::ShaderDataLayout {
+            bindings: vec![("params", gpu::ShaderBinding::Plain { size: count * 16 })],
+        };
+        context.create_compute_pipeline(gpu::ComputePipelineDesc {
+            name: "main",
+            data_layouts: &[&layout],
+            compute: shader.at("main"),
+            overrides: &[],
+            cache: None,
+        })
+    };
+
+    let max_count = gpu::limits::PLAIN_DATA_SIZE / 16;
+    let _pipeline = make_pipeline(max_count);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        make_pipeline(max_count + 1);
+    }));
+    assert!(result.is_err(), "Plain data above the limit is accepted");
+}
+
+#[test]
+#[should_panic(expected = "out of buffer bounds")]
+fn dispatch_indirect_bounds() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: "@compute @workgroup_size(64) fn main() {}",
+    });
+    let pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
+        name: "main",
+        data_layouts: &[],
+        compute: shader.at("main"),
+        overrides: &[],
+        cache: None,
+    });
+    let args = context.create_buffer(gpu::BufferDesc {
+        name: "args",
+        size: 16,
+        memory: gpu::Memory::Shared,
+    });
+    unsafe {
+        let data = slice::from_raw_parts_mut(args.data() as *mut u32, 4);
+        data.copy_from_slice(&[1, 2, 3, 4]);
+    }
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    {
+        let mut pass = encoder.compute_labeled("indirect");
+        let mut pc = pass.with(&pipeline);
+        pc.dispatch_indirect(args.into());
+        pc.dispatch_indirect(args.at(4));
+        // only 8 bytes are left after this offset
+        pc.dispatch_indirect(args.at(8));
+    }
+}
+
+#[test]
+#[should_panic(expected = "out of buffer bounds")]
+fn multi_draw_indirect_bounds() {
+    let context = init();
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "target",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::TARGET,
+    });
+    let view = context.create_texture_view(gpu::TextureViewDesc {
+        name: "target",
+        texture,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &Default::default(),
+    });
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: DRAW_SHADER,
+    });
+    let pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
+        name: "draw",
+        data_layouts: &[],
+        vertex: shader.at("vs_main"),
+        vertex_fetches: &[],
+        primitive: gpu::PrimitiveState::default(),
+        depth_stencil: None,
+        fragment: shader.at("fs_main"),
+        color_targets: &[gpu::TextureFormat::Rgba8Unorm.into()],
+        multisample_state: gpu::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
+    });
+    let args = [gpu::DrawIndirectArgs {
+        vertex_count: 3,
+        instance_count: 1,
+        ..Default::default()
+    }; 4];
+    let indirect_buf = context.create_buffer(gpu::BufferDesc {
+        name: "indirect",
+        size: std::mem::size_of_val(&args) as u64 + 4,
+        memory: gpu::Memory::Shared,
+    });
+    unsafe {
+        let data = slice::from_raw_parts_mut(indirect_buf.data(), std::mem::size_of_val(&args));
+        data.copy_from_slice(bytemuck::cast_slice(&args));
+        *(indirect_buf.data().add(data.len()) as *mut u32) = 2;
+    }
+    let count_buf = indirect_buf.at(std::mem::size_of_val(&args) as u64);
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut pass = encoder.render_labeled(
+        "draw",
+        gpu::RenderTargetSet {
+            colors: &[gpu::RenderTarget {
+                view,
+                init_op: gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
+                finish_op: gpu::FinishOp::Store,
+            }],
+            depth_stencil: None,
+        },
+    ) {
+        let mut pc = pass.with(&pipeline);
+        pc.multi_draw_indirect(indirect_buf.into(), 4, Some(count_buf));
+        pc.multi_draw_indirect(indirect_buf.at(16), 3, None);
+        // the fifth draw would overlap the count
+        pc.multi_draw_indirect(indirect_buf.into(), 5, Some(count_buf));
+    }
+}
+
+#[test]
+#[should_panic(expected = "Bundle sample count doesn't match")]
+fn render_bundle_sample_count() {
+    let context = init();
+    let size = gpu::Extent {
+        width: 4,
+        height: 4,
+        depth: 1,
+    };
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "target",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size,
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::TARGET,
+    });
+    let view = context.create_texture_view(gpu::TextureViewDesc {
+        name: "target",
+        texture,
+        format: gpu::TextureFormat::Rgba8Unorm,
+        dimension: gpu::ViewDimension::D2,
+        subresources: &Default::default(),
+    });
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: DRAW_SHADER,
+    });
+    let pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
+        name: "draw",
+        data_layouts: &[],
+        vertex: shader.at("vs_main"),
+        vertex_fetches: &[],
+        primitive: gpu::PrimitiveState::default(),
+        depth_stencil: None,
+        fragment: shader.at("fs_main"),
+        color_targets: &[gpu::TextureFormat::Rgba8Unorm.into()],
+        multisample_state: gpu::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
+    });
+
+    let bundle_desc = |sample_count| gpu::RenderBundleDesc {
+        name: "bundle",
+        color_formats: &[gpu::TextureFormat::Rgba8Unorm],
+        depth_stencil_format: None,
+        sample_count,
+        size,
+    };
+    let mut bundle_encoder = context.create_render_bundle_encoder(bundle_desc(1));
+    bundle_encoder.with(&pipeline).draw(0, 3, 0, 1);
+    let bundle = bundle_encoder.finish();
+    let msaa_bundle = context
+        .create_render_bundle_encoder(bundle_desc(4))
+        .finish();
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "test",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    for init_op in [
+        gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
+        gpu::InitOp::Load,
+    ] {
+        let mut pass = encoder.render_labeled(
+            "draw",
+            gpu::RenderTargetSet {
+                colors: &[gpu::RenderTarget {
+                    view,
+                    init_op,
+                    finish_op: gpu::FinishOp::Store,
+                }],
+                depth_stencil: None,
+            },
+        );
+        pass.execute_bundle(&bundle);
+        pass.with(&pipeline).draw(0, 3, 0, 1);
+        pass.execute_bundle(&bundle);
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+    context.destroy_render_bundle(bundle);
+
+    encoder.start();
+    let mut pass = encoder.render_labeled(
+        "draw",
+        gpu::RenderTargetSet {
+            colors: &[gpu::RenderTarget {
+                view,
+                init_op: gpu::InitOp::Load,
+                finish_op: gpu::FinishOp::Store,
+            }],
+            depth_stencil: None,
+        },
+    );
+    pass.execute_bundle(&msaa_bundle);
+}
+
+#[test]
+fn pipeline_overrides() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: OVERRIDE_SHADER,
+    });
+    let create_pipeline = |overrides| {
+        context.create_compute_pipeline(gpu::ComputePipelineDesc {
+            name: "overrides",
+            data_layouts: &[],
+            compute: shader.at("main"),
+            overrides,
+            cache: None,
+        })
+    };
+    assert_eq!(create_pipeline(&[]).get_workgroup_size(), [16, 1, 1]);
+    let pipeline = create_pipeline(&[("block_size", 32.0), ("scale", 0.5), ("enabled", 0.0)]);
+    assert_eq!(pipeline.get_workgroup_size(), [64, 1, 1]);
+    // the identifier refers to the same override, and the last value wins
+    let pipeline = create_pipeline(&[("block_size", 32.0), ("0", 4.0)]);
+    assert_eq!(pipeline.get_workgroup_size(), [8, 1, 1]);
+}
+
+#[test]
+fn invalid_overrides() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: OVERRIDE_SHADER,
+    });
+    for overrides in [
+        &[("size", 16.0)][..],
+        &[("1", 16.0)],
+        &[("block_size", -1.0)],
+        &[("block_size", 0.5)],
+    ] {
+        let result = context.try_create_compute_pipeline(gpu::ComputePipelineDesc {
+            name: "overrides",
+            data_layouts: &[],
+            compute: shader.at("main"),
+            overrides,
+            cache: None,
+        });
+        assert_eq!(
+            result.err(),
+            Some(gpu::Error::InvalidDescriptor),
+            "{overrides:?}"
+        );
+    }
+}
+
+#[test]
+fn malformed_overrides() {
+    let context = init();
+    for source in [
+        "override scale;",
+        "override scale: f32",
+        "override scale: = 1.0;",
+        "override scale = ;",
+        "override;",
+        "override size = 4u; @compute @workgroup_size(size",
+        "@id(70000) override size = 4u;",
+        "override size = 4u; @compute @workgroup_size(1) fn main() { size = 2u; }",
+    ] {
+        let shader = context.try_create_shader(gpu::ShaderDesc { source });
+        assert!(shader.is_err(), "{source}");
+    }
+}
+
+#[test]
+#[should_panic(expected = "Member 'Params::scale' offset doesn't match the shader")]
+fn struct_layout_mismatch() {
+    let context = init();
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: STRUCT_SHADER,
+    });
+    shader.check_struct_size::<Params>();
+    shader.check_struct_layout::<Params>();
+}
+
+#[test]
+fn software_ray_query() {
+    // Nothing is executed here, see the GLES test for the traversal results
+    let context = init();
+    assert!(context.capabilities().software_ray_query);
+    let shader = context.create_shader(gpu::ShaderDesc {
+        source: RAY_QUERY_SHADER,
+    });
+    let pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
+        name: "ray-query",
+        data_layouts: &[&<RayQueryData as gpu::ShaderData>::layout()],
+        compute: shader.at("main"),
+        overrides: &[],
+        cache: None,
+    });
+
+    // A grid of quads, each made of two triangles
+    let quad_count = 16;
+    let mut positions = Vec::new();
+    let mut indices = Vec::<u16>::new();
+    for i in 0..quad_count {
+        let x = i as f32;
+        let base = positions.len() as u16;
+        positions.extend([
+            [x, 0.0, 0.0],
+            [x + 1.0, 0.0, 0.0],
+            [x, 1.0, 0.0],
+            [x + 1.0, 1.0, 0.0],
+        ]);
+        indices.extend([0, 1, 2, 2, 1, 3].map(|index| base + index));
+    }
+    let vertex_buf = context.create_buffer(gpu::BufferDesc {
+        name: "vertices",
+        size: (positions.len() * mem::size_of::<[f32; 3]>()) as u64,
+        memory: gpu::Memory::Shared,
+    });
+    let index_buf = context.create_buffer(gpu::BufferDesc {
+        name: "indices",
+        size: (indices.len() * mem::size_of::<u16>()) as u64,
+        memory: gpu::Memory::Shared,
+    });
+    unsafe {
+        ptr::copy_nonoverlapping(
+            positions.as_ptr(),
+            vertex_buf.data() as *mut [f32; 3],
+            positions.len(),
+        );
+        ptr::copy_nonoverlapping(
+            indices.as_ptr(),
+            index_buf.data() as *mut u16,
+            indices.len(),
+        );
+    }
+
+    let meshes = [gpu::AccelerationStructureMesh {
+        vertex_data: vertex_buf.at(0),
+        vertex_format: gpu::VertexFormat::F32Vec3,
+        vertex_stride: mem::size_of::<[f32; 3]>() as u32,
+        vertex_count: positions.len() as u32,
+        index_data: index_buf.at(0),
+        index_type: Some(gpu::IndexType::U16),
+        triangle_count: indices.len() as u32 / 3,
+        transform_data: gpu::Buffer::default().at(0),
+        is_opaque: true,
+    }];
+    let blas_sizes = context.get_bottom_level_acceleration_structure_sizes(&meshes);
+    let blas = context.create_acceleration_structure(gpu::AccelerationStructureDesc {
+        name: "grid",
+        ty: gpu::AccelerationStructureType::BottomLevel,
+        size: blas_sizes.data,
+    });
+    let instances = [0.0, 2.0].map(|z| gpu::AccelerationStructureInstance {
+        acceleration_structure_index: 0,
+        transform: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, z],
+        ]
+        .into(),
+        mask: 0xFF,
+        custom_index: 0,
+    });
+    let instance_buf = context.create_acceleration_structure_instance_buffer(&instances, &[blas]);
+    let tlas_sizes =
+        context.get_top_level_acceleration_structure_sizes_for(instances.len() as u32, &[blas]);
+    let tlas = context.create_acceleration_structure(gpu::AccelerationStructureDesc {
+        name: "scene",
+        ty: gpu::AccelerationStructureType::TopLevel,
+        size: tlas_sizes.data,
+    });
+    let scratch_buf = context.create_buffer(gpu::BufferDesc {
+        name: "scratch",
+        size: blas_sizes.scratch.max(tlas_sizes.scratch),
+        memory: gpu::Memory::Device,
+    });
+    let hits_buf = context.create_buffer(gpu::BufferDesc {
+        name: "hits",
+        size: quad_count as u64 * 4,
+        memory: gpu::Memory::Device,
+    });
+
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "ray-query",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    if let mut pass = encoder.acceleration_structure_labeled("build") {
+        pass.build_bottom_level(blas, &meshes, scratch_buf.at(0));
+    }
+    if let mut pass = encoder.acceleration_structure_labeled("build") {
+        pass.build_top_level(
+            tlas,
+            &[blas],
+            instances.len() as u32,
+            instance_buf.at(0),
+            scratch_buf.at(0),
+        );
+    }
+    if let mut pass = encoder.compute_labeled("trace") {
+        let mut pc = pass.with(&pipeline);
+        pc.bind(
+            0,
+            &RayQueryData {
+                acc_struct: tlas,
+                hits: hits_buf.at(0),
+            },
+        );
+        pc.dispatch([quad_count / 8, 1, 1]);
+    }
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, 1000));
+}
+
+#[test]
+#[should_panic(expected = "Debug groups are left open in 'groups'")]
+fn debug_groups() {
+    let context = init();
+    let buffer = context.create_buffer(gpu::BufferDesc {
+        name: "buffer",
+        size: 16,
+        memory: gpu::Memory::Shared,
+    });
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "groups",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder.push_debug_group("frame");
+    {
+        let mut pass = encoder.transfer_labeled("fill");
+        pass.push_debug_group("clear");
+        pass.fill_buffer(buffer.into(), 16, 0);
+        pass.insert_debug_marker("cleared");
+        pass.pop_debug_group();
+    }
+    encoder.insert_debug_marker("end of frame");
+    encoder.pop_debug_group();
+    assert!(context.wait_for(&context.submit(&mut encoder), !0));
+
+    encoder.start();
+    encoder.push_debug_group("unbalanced");
+    context.submit(&mut encoder);
+}
+
+#[test]
+fn adapter_selection() {
+    let adapters = unsafe { gpu::Context::enumerate_adapters() };
+    assert_eq!(adapters.len(), 1);
+    assert_eq!(adapters[0].name, "Null");
+
+    let init_with = |adapter| unsafe {
+        gpu::Context::init(gpu::ContextDesc {
+            validation: true,
+            capture: false,
+            timing: false,
+            adapter,
+        })
+    };
+    assert!(init_with(gpu::AdapterSelector::Index(0)).is_ok());
+    assert!(init_with(gpu::AdapterSelector::Name("Null".to_string())).is_ok());
+    assert!(init_with(gpu::AdapterSelector::PreferSoftware).is_ok());
+    assert!(init_with(gpu::AdapterSelector::Index(1)).is_err());
+    assert!(init_with(gpu::AdapterSelector::Name("llvmpipe".to_string())).is_err());
+}
+
+#[test]
+fn limits_and_format_features() {
+    let context = init();
+    let limits = context.limits();
+    assert!(limits.max_texture_dimension_2d >= 4096);
+    assert!(limits.max_workgroup_invocations >= 256);
+
+    let features = context.format_features(gpu::TextureFormat::Bc1Unorm);
+    assert_eq!(
+        features,
+        gpu::TextureUsage::COPY | gpu::TextureUsage::RESOURCE
+    );
+    assert!(context
+        .format_features(gpu::TextureFormat::Depth32Float)
+        .contains(gpu::TextureUsage::TARGET));
+
+    let texture_desc = |width, usage| gpu::TextureDesc {
+        name: "compressed",
+        format: gpu::TextureFormat::Bc1Unorm,
+        size: gpu::Extent {
+            width,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage,
+    };
+    let texture = context.try_create_texture(texture_desc(4, gpu::TextureUsage::STORAGE));
+    assert_eq!(texture.err(), Some(gpu::Error::UnsupportedFormat));
+    let texture = context.try_create_texture(texture_desc(
+        limits.max_texture_dimension_2d * 2,
+        gpu::TextureUsage::RESOURCE,
+    ));
+    assert_eq!(texture.err(), Some(gpu::Error::InvalidDescriptor));
+    let texture = context.create_texture(texture_desc(4, gpu::TextureUsage::RESOURCE));
+    context.destroy_texture(texture);
+}
+
+#[test]
+fn memory_report() {
+    let context = init();
+    let report = context.memory_report();
+    assert_eq!(report.buffer_count, 0);
+    assert_eq!(report.texture_count, 0);
+
+    let buffer = context.create_buffer(gpu::BufferDesc {
+        name: "buffer",
+        size: 16,
+        memory: gpu::Memory::Shared,
+    });
+    let texture = context.create_texture(gpu::TextureDesc {
+        name: "texture",
+        format: gpu::TextureFormat::Rgba8Unorm,
+        size: gpu::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        },
+        dimension: gpu::TextureDimension::D2,
+        array_layer_count: 1,
+        mip_level_count: 1,
+        sample_count: 1,
+        usage: gpu::TextureUsage::COPY,
+    });
+    let report = context.memory_report();
+    assert_eq!(report.buffer_count, 1);
+    assert_eq!(report.texture_count, 1);
+    assert_eq!(report.acceleration_structure_count, 0);
+
+    context.destroy_buffer(buffer);
+    context.destroy_texture(texture);
+    let report = context.memory_report();
+    assert_eq!(report.buffer_count, 0);
+    assert_eq!(report.texture_count, 0);
+}
+
+#[test]
+fn headless_frame() {
+    let context = init();
+    let format = context.resize(gpu::SurfaceConfig {
+        size: gpu::Extent {
+            width: 4,
+            height: 2,
+            depth: 1,
+        },
+        usage: gpu::TextureUsage::TARGET | gpu::TextureUsage::COPY,
+        frame_count: 2,
+        color_space: gpu::ColorSpace::Linear,
+    });
+    assert_eq!(format, gpu::TextureFormat::Rgba8UnormSrgb);
+    let readback = context.create_buffer(gpu::BufferDesc {
+        name: "readback",
+        size: 32,
+        memory: gpu::Memory::Shared,
+    });
+
+    let frame = context.acquire_frame();
+    let mut encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
+        name: "present",
+        command_type: gpu::CommandType::General,
+        buffer_count: 1,
+    });
+    encoder.start();
+    encoder.init_texture(frame.texture());
+    if let mut transfer = encoder.transfer_labeled("readback") {
+        transfer.fill_buffer(readback.into(), 32, 0xFF);
+        transfer.copy_texture_to_buffer(
+            gpu::TexturePiece {
+                texture: frame.texture(),
+                mip_level: 0,
+                array_layer: 0,
+                origin: [0; 3],
+                aspects: gpu::TexelAspects::empty(),
+            },
+            readback.into(),
+            16,
+            gpu::Extent {
+                width: 4,
+                height: 2,
+                depth: 1,
+            },
+        );
+    }
+    encoder.present(frame);
+    let sync_point = context.submit(&mut encoder);
+    assert!(context.wait_for(&sync_point, !0));
+
+    let result = unsafe { slice::from_raw_parts(readback.data() as *const u32, 8) };
+    assert_eq!(result, &[0; 8]);
+
+    context.destroy_command_encoder(encoder);
+    context.destroy_buffer(readback);
+}
@@ -1,6 +1,7 @@
 mod as_primitive;
 mod flat;
 mod shader_data;
+mod shader_struct;
 
 use proc_macro::TokenStream;
 
@@ -23,6 +24,30 @@ pub fn shader_data_derive(input: TokenStream) -> TokenStream {
     stream.into()
 }
 
+/// Derive the `ShaderStruct` trait for a `#[repr(C)]` struct,
+/// so that its layout can be checked against the shader with
+/// `Shader::check_struct_layout`.
+///
+/// ## Example
+///
+/// ```rust
+/// #[derive(blade_macros::ShaderStruct)]
+/// #[repr(C)]
+/// struct Params {
+///   offset: [f32; 2],
+///   scale: f32,
+///   count: u32,
+/// }
+/// ```
+#[proc_macro_derive(ShaderStruct)]
+pub fn shader_struct_derive(input: TokenStream) -> TokenStream {
+    let stream = match shader_struct::generate(input) {
+        Ok(tokens) => tokens,
+        Err(err) => err.into_compile_error(),
+    };
+    stream.into()
+}
+
 /// Derive the `Flat` for a type.
 ///
 /// Can either be used on a struct that has every field already implementing `blade_asset::Flat`:
@@ -0,0 +1,70 @@
+use proc_macro::TokenStream;
+use quote::quote;
+
+pub fn generate(input_stream: TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    let item_struct = syn::parse::<syn::ItemStruct>(input_stream)?;
+    let fields = match item_struct.fields {
+        syn::Fields::Named(ref fields) => fields,
+        _ => {
+            return Err(syn::Error::new(
+                item_struct.struct_token.span,
+                "Structure fields must be named",
+            ))
+        }
+    };
+
+    let mut is_repr_c = false;
+    for attr in item_struct.attrs.iter() {
+        if attr.path().is_ident("repr") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("C") {
+                    is_repr_c = true;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    if !is_repr_c {
+        return Err(syn::Error::new(
+            item_struct.struct_token.span,
+            "Structure must be `#[repr(C)]`",
+        ));
+    }
+    if !item_struct.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item_struct.struct_token.span,
+            "Unsupported generic parameters",
+        ));
+    }
+
+    let mut members = Vec::new();
+    for field in fields.named.iter() {
+        let name = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        members.push(quote! {
+            blade_graphics::ShaderStructMember {
+                name: stringify!(#name),
+                offset: unsafe {
+                    (std::ptr::addr_of!((*base).#name) as *const u8).offset_from(base as *const u8)
+                } as u32,
+                size: std::mem::size_of::<#ty>() as u32,
+                ty: <#ty as blade_graphics::HasShaderMemberType>::MEMBER_TYPE,
+            }
+        });
+    }
+
+    let struct_name = item_struct.ident;
+    Ok(quote! {
+        impl blade_graphics::ShaderStruct for #struct_name {
+            fn layout() -> blade_graphics::ShaderStructLayout {
+                let uninit = std::mem::MaybeUninit::<Self>::uninit();
+                let base = uninit.as_ptr();
+                blade_graphics::ShaderStructLayout {
+                    name: stringify!(#struct_name),
+                    size: std::mem::size_of::<Self>() as u32,
+                    members: vec![#(#members),*],
+                }
+            }
+        }
+    })
+}
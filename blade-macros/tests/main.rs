@@ -11,6 +11,47 @@ struct ShaderParams {
     sprite_sampler: blade_graphics::Sampler,
 }
 
+#[derive(blade_macros::ShaderStruct)]
+#[repr(C)]
+struct Light {
+    position: [f32; 3],
+    color: u32,
+}
+
+#[derive(blade_macros::ShaderStruct)]
+#[repr(C)]
+struct LightParams {
+    light: Light,
+    intensity: f32,
+    count: i32,
+}
+
+#[test]
+fn test_shader_struct() {
+    use blade_graphics::{ShaderMemberType as Smt, ShaderStruct};
+
+    let layout = LightParams::layout();
+    assert_eq!(layout.name, "LightParams");
+    assert_eq!(layout.size, 24);
+    let members = layout
+        .members
+        .iter()
+        .map(|m| (m.name, m.offset, m.size))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        members,
+        [("light", 0, 16), ("intensity", 16, 4), ("count", 20, 4)]
+    );
+    match layout.members[0].ty {
+        Smt::Struct(nested) => assert_eq!(nested().members.len(), 2),
+        Smt::Plain(_) => panic!("Light is not a plain type"),
+    }
+    assert!(matches!(
+        layout.members[2].ty,
+        Smt::Plain(blade_graphics::ScalarKind::Sint)
+    ));
+}
+
 #[derive(blade_macros::Flat, PartialEq, Debug)]
 struct FlatData<'a> {
     array: [u32; 2],
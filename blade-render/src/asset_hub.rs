@@ -1,11 +1,19 @@
 use blade_asset::AssetManager;
-use std::{path::Path, sync::Arc};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+const PIPELINE_CACHE_NAME: &str = "pipelines.raw";
 
 /// A single hub to manage all assets.
 pub struct AssetHub {
     pub textures: Arc<AssetManager<crate::texture::Baker>>,
     pub models: AssetManager<crate::model::Baker>,
     pub shaders: AssetManager<crate::shader::Baker>,
+    gpu_context: Arc<blade_graphics::Context>,
+    /// Encoder for the uploads, which run on the transfer queue.
+    transfer_encoder: Mutex<Option<blade_graphics::CommandEncoder>>,
 }
 
 pub struct LoadContext<'a> {
@@ -37,16 +45,30 @@ impl AssetHub {
         sh_baker.register_enum::<crate::render::DebugMode>();
         sh_baker.register_bitflags::<crate::render::DebugDrawFlags>();
         sh_baker.register_bitflags::<crate::render::DebugTextureFlags>();
+        sh_baker.load_pipeline_cache(&target.join(PIPELINE_CACHE_NAME));
         let shaders = AssetManager::new(target, choir, sh_baker);
 
+        let transfer_encoder =
+            gpu_context.create_command_encoder(blade_graphics::CommandEncoderDesc {
+                name: "asset uploads",
+                command_type: blade_graphics::CommandType::Transfer,
+                buffer_count: 2,
+            });
+
         Self {
             textures,
             models,
             shaders,
+            gpu_context: Arc::clone(gpu_context),
+            transfer_encoder: Mutex::new(Some(transfer_encoder)),
         }
     }
 
-    /// Flush the GPU state updates into the specified command encoder.
+    /// Flush the GPU state updates.
+    ///
+    /// The uploads are submitted on the transfer queue, and the specified
+    /// command encoder is made to wait for them. It also gets the updates
+    /// that can't run on the transfer queue, such as building the BLASes.
     ///
     /// Populates the list of temporary buffers that can be freed when the
     /// relevant submission is completely retired.
@@ -56,15 +78,29 @@ impl AssetHub {
         command_encoder: &mut blade_graphics::CommandEncoder,
         temp_buffers: &mut Vec<blade_graphics::Buffer>,
     ) {
-        self.textures.baker.flush(command_encoder, temp_buffers);
-        self.models.baker.flush(command_encoder, temp_buffers);
+        let mut guard = self.transfer_encoder.lock().unwrap();
+        let transfer_encoder = guard.as_mut().unwrap();
+        transfer_encoder.start();
+        self.textures.baker.flush(transfer_encoder, temp_buffers);
+        self.models
+            .baker
+            .flush(transfer_encoder, command_encoder, temp_buffers);
+        let sync_point = self.gpu_context.submit(transfer_encoder);
+        command_encoder.wait_for(&sync_point);
     }
 
     /// Destroy the hub contents.
+    ///
+    /// Saves the pipeline cache into the target folder.
+    /// The GPU work that uses the assets has to be finished.
     pub fn destroy(&mut self) {
+        self.shaders.baker.save_pipeline_cache();
         self.textures.clear();
         self.models.clear();
         self.shaders.clear();
+        if let Some(transfer_encoder) = self.transfer_encoder.get_mut().unwrap().take() {
+            self.gpu_context.destroy_command_encoder(transfer_encoder);
+        }
     }
 
     pub fn open_context<'a, N: Into<choir::Name>>(
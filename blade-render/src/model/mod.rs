@@ -374,14 +374,18 @@ impl Baker {
         }
     }
 
+    /// Record the pending uploads into the transfer encoder, and the
+    /// acceleration structure builds into the main one, which has to
+    /// wait for the transfers to finish.
     pub fn flush(
         &self,
+        transfer_encoder: &mut blade_graphics::CommandEncoder,
         encoder: &mut blade_graphics::CommandEncoder,
         temp_buffers: &mut Vec<blade_graphics::Buffer>,
     ) {
         let mut pending_ops = self.pending_operations.lock().unwrap();
         if !pending_ops.transfers.is_empty() {
-            let mut pass = encoder.transfer();
+            let mut pass = transfer_encoder.transfer_labeled("model transfers");
             for transfer in pending_ops.transfers.drain(..) {
                 pass.copy_buffer_to_buffer(
                     transfer.stage.into(),
@@ -392,7 +396,7 @@ impl Baker {
             }
         }
         if !pending_ops.blas_constructs.is_empty() {
-            let mut pass = encoder.acceleration_structure();
+            let mut pass = encoder.acceleration_structure_labeled("BLAS");
             for construct in pending_ops.blas_constructs.drain(..) {
                 pass.build_bottom_level(construct.dst, &construct.meshes, construct.scratch.into());
                 temp_buffers.push(construct.scratch);
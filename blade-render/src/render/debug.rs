@@ -9,14 +9,14 @@ pub struct DebugBlit {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, blade_macros::ShaderStruct)]
 pub struct DebugPoint {
     pub pos: [f32; 3],
     pub color: u32,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, blade_macros::ShaderStruct)]
 pub struct DebugLine {
     pub a: DebugPoint,
     pub b: DebugPoint,
@@ -46,7 +46,7 @@ struct DebugDrawData {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, blade_macros::ShaderStruct)]
 struct DebugBlitParams {
     target_offset: [f32; 2],
     target_size: [f32; 2],
@@ -61,9 +61,8 @@ struct DebugBlitData {
     params: DebugBlitParams,
 }
 
-// Has to match the shader!
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, blade_macros::ShaderStruct)]
 pub struct DebugVariance {
     pub color_sum: [f32; 3],
     pad: u32,
@@ -71,9 +70,8 @@ pub struct DebugVariance {
     pub count: u32,
 }
 
-// Has to match the shader!
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, blade_macros::ShaderStruct)]
 pub struct DebugEntry {
     pub custom_index: u32,
     pub depth: f32,
@@ -83,8 +81,8 @@ pub struct DebugEntry {
     pad: [u32; 2],
     pub position: [f32; 3],
     position_w: f32,
-    pub normal: [f32; 3],
-    normal_w: f32,
+    pub flat_normal: [f32; 3],
+    flat_normal_w: f32,
 }
 
 fn create_draw_pipeline(
@@ -92,13 +90,14 @@ fn create_draw_pipeline(
     format: blade_graphics::TextureFormat,
     gpu: &blade_graphics::Context,
 ) -> blade_graphics::RenderPipeline {
-    shader.check_struct_size::<DebugPoint>();
-    shader.check_struct_size::<DebugLine>();
+    shader.check_struct_layout::<DebugPoint>();
+    shader.check_struct_layout::<DebugLine>();
     let layout = <DebugDrawData as blade_graphics::ShaderData>::layout();
     gpu.create_render_pipeline(blade_graphics::RenderPipelineDesc {
         name: "debug-draw",
         data_layouts: &[&layout],
         vertex: shader.at("debug_vs"),
+        vertex_fetches: &[],
         primitive: blade_graphics::PrimitiveState {
             topology: blade_graphics::PrimitiveTopology::LineList,
             ..Default::default()
@@ -110,6 +109,9 @@ fn create_draw_pipeline(
             blend: Some(blade_graphics::BlendState::ALPHA_BLENDING),
             write_mask: blade_graphics::ColorWrites::all(),
         }],
+        multisample_state: blade_graphics::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
     })
 }
 
@@ -118,12 +120,13 @@ fn create_blit_pipeline(
     format: blade_graphics::TextureFormat,
     gpu: &blade_graphics::Context,
 ) -> blade_graphics::RenderPipeline {
-    shader.check_struct_size::<DebugBlitParams>();
+    shader.check_struct_layout::<DebugBlitParams>();
     let layout = <DebugBlitData as blade_graphics::ShaderData>::layout();
     gpu.create_render_pipeline(blade_graphics::RenderPipelineDesc {
         name: "debug-blit",
         data_layouts: &[&layout],
         vertex: shader.at("blit_vs"),
+        vertex_fetches: &[],
         primitive: blade_graphics::PrimitiveState {
             topology: blade_graphics::PrimitiveTopology::TriangleStrip,
             ..Default::default()
@@ -131,6 +134,9 @@ fn create_blit_pipeline(
         depth_stencil: None,
         fragment: shader.at("blit_fs"),
         color_targets: &[format.into()],
+        multisample_state: blade_graphics::MultisampleState::default(),
+        overrides: &[],
+        cache: None,
     })
 }
 
@@ -208,7 +214,7 @@ impl DebugRender {
             );
         }
 
-        let mut transfers = encoder.transfer();
+        let mut transfers = encoder.transfer_labeled("debug init");
         transfers.copy_buffer_to_buffer(
             this.entry_buffer.at(0),
             this.buffer.at(0),
@@ -27,6 +27,7 @@ impl DummyResources {
             size,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             dimension: blade_graphics::TextureDimension::D2,
             usage: blade_graphics::TextureUsage::COPY | blade_graphics::TextureUsage::RESOURCE,
         });
@@ -43,6 +44,7 @@ impl DummyResources {
             size,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             dimension: blade_graphics::TextureDimension::D2,
             usage: blade_graphics::TextureUsage::COPY | blade_graphics::TextureUsage::RESOURCE,
         });
@@ -59,6 +61,7 @@ impl DummyResources {
             size,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             dimension: blade_graphics::TextureDimension::D2,
             usage: blade_graphics::TextureUsage::COPY | blade_graphics::TextureUsage::RESOURCE,
         });
@@ -73,7 +76,7 @@ impl DummyResources {
         command_encoder.init_texture(white_texture);
         command_encoder.init_texture(black_texture);
         command_encoder.init_texture(red_texture);
-        let mut transfers = command_encoder.transfer();
+        let mut transfers = command_encoder.transfer_labeled("init dummy");
         let staging_buf = gpu.create_buffer(blade_graphics::BufferDesc {
             name: "dummy/staging",
             size: 4 * 3,
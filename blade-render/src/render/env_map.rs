@@ -3,7 +3,7 @@ use std::num::NonZeroU32;
 use crate::DummyResources;
 
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, blade_macros::ShaderStruct)]
 struct EnvPreprocParams {
     target_level: u32,
 }
@@ -28,15 +28,18 @@ impl EnvironmentMap {
     pub fn init_pipeline(
         shader: &blade_graphics::Shader,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> Result<blade_graphics::ComputePipeline, &'static str> {
         let layout = <EnvPreprocData as blade_graphics::ShaderData>::layout();
-        shader.check_struct_size::<EnvPreprocParams>();
+        shader.check_struct_layout::<EnvPreprocParams>();
 
         Ok(
             gpu.create_compute_pipeline(blade_graphics::ComputePipelineDesc {
                 name: "env-prepare",
                 data_layouts: &[&layout],
                 compute: shader.at("downsample"),
+                overrides: &[],
+                cache: pipeline_cache,
             }),
         )
     }
@@ -60,7 +63,7 @@ impl EnvironmentMap {
         dummy: &DummyResources,
         gpu: &blade_graphics::Context,
     ) -> Self {
-        Self::with_pipeline(dummy, Self::init_pipeline(shader, gpu).unwrap())
+        Self::with_pipeline(dummy, Self::init_pipeline(shader, gpu, None).unwrap())
     }
 
     fn weight_size(&self) -> blade_graphics::Extent {
@@ -110,6 +113,7 @@ impl EnvironmentMap {
             dimension: blade_graphics::TextureDimension::D2,
             array_layer_count: 1,
             mip_level_count,
+            sample_count: 1,
             usage: blade_graphics::TextureUsage::RESOURCE | blade_graphics::TextureUsage::STORAGE,
         });
         self.weight_view = gpu.create_texture_view(blade_graphics::TextureViewDesc {
@@ -139,7 +143,7 @@ impl EnvironmentMap {
             let groups = self
                 .prepare_pipeline
                 .get_dispatch_for(weight_extent.at_mip_level(target_level));
-            let mut compute = encoder.compute();
+            let mut compute = encoder.compute_labeled("environment weight");
             let mut pass = compute.with(&self.prepare_pipeline);
             pass.bind(
                 0,
@@ -164,6 +164,7 @@ impl<const N: usize> RenderTarget<N> {
             dimension: blade_graphics::TextureDimension::D2,
             array_layer_count: N as u32,
             mip_level_count: 1,
+            sample_count: 1,
             usage: blade_graphics::TextureUsage::RESOURCE | blade_graphics::TextureUsage::STORAGE,
         });
         encoder.init_texture(texture);
@@ -329,7 +330,7 @@ pub struct Renderer {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Default, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, Default, bytemuck::Zeroable, bytemuck::Pod, blade_macros::ShaderStruct)]
 struct CameraParams {
     position: [f32; 3],
     depth: f32,
@@ -339,17 +340,17 @@ struct CameraParams {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, blade_macros::ShaderStruct)]
 struct DebugParams {
     view_mode: u32,
     draw_flags: u32,
     texture_flags: u32,
-    unused: u32,
-    mouse_pos: [i32; 2],
+    pad: u32,
+    mouse_pos: [u32; 2],
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, blade_macros::ShaderStruct)]
 struct MainParams {
     frame_index: u32,
     num_environment_samples: u32,
@@ -357,7 +358,7 @@ struct MainParams {
     temporal_history: u32,
     spatial_taps: u32,
     spatial_tap_history: u32,
-    spatial_radius: u32,
+    spatial_radius: i32,
     use_motion_vectors: u32,
 }
 
@@ -517,6 +518,7 @@ impl ShaderPipelines {
     fn create_gbuf_fill(
         shader: &blade_graphics::Shader,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> blade_graphics::ComputePipeline {
         shader.check_struct_size::<crate::Vertex>();
         shader.check_struct_size::<HitEntry>();
@@ -525,46 +527,57 @@ impl ShaderPipelines {
             name: "fill-gbuf",
             data_layouts: &[&layout],
             compute: shader.at("main"),
+            overrides: &[],
+            cache: pipeline_cache,
         })
     }
     fn create_ray_trace(
         shader: &blade_graphics::Shader,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> blade_graphics::ComputePipeline {
-        shader.check_struct_size::<CameraParams>();
-        shader.check_struct_size::<DebugParams>();
-        shader.check_struct_size::<MainParams>();
-        shader.check_struct_size::<DebugVariance>();
-        shader.check_struct_size::<DebugEntry>();
+        shader.check_struct_layout::<CameraParams>();
+        shader.check_struct_layout::<DebugParams>();
+        shader.check_struct_layout::<MainParams>();
+        shader.check_struct_layout::<DebugVariance>();
+        shader.check_struct_layout::<DebugEntry>();
         let layout = <MainData as blade_graphics::ShaderData>::layout();
         gpu.create_compute_pipeline(blade_graphics::ComputePipelineDesc {
             name: "ray-trace",
             data_layouts: &[&layout],
             compute: shader.at("main"),
+            overrides: &[],
+            cache: pipeline_cache,
         })
     }
 
     fn create_temporal_accum(
         shader: &blade_graphics::Shader,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> blade_graphics::ComputePipeline {
         let layout = <TemporalAccumData as blade_graphics::ShaderData>::layout();
         gpu.create_compute_pipeline(blade_graphics::ComputePipelineDesc {
             name: "temporal-accum",
             data_layouts: &[&layout],
             compute: shader.at("temporal_accum"),
+            overrides: &[],
+            cache: pipeline_cache,
         })
     }
 
     fn create_atrous(
         shader: &blade_graphics::Shader,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> blade_graphics::ComputePipeline {
         let layout = <AtrousData as blade_graphics::ShaderData>::layout();
         gpu.create_compute_pipeline(blade_graphics::ComputePipelineDesc {
             name: "atrous",
             data_layouts: &[&layout],
             compute: shader.at("atrous3x3"),
+            overrides: &[],
+            cache: pipeline_cache,
         })
     }
 
@@ -572,6 +585,7 @@ impl ShaderPipelines {
         shader: &blade_graphics::Shader,
         format: blade_graphics::TextureFormat,
         gpu: &blade_graphics::Context,
+        pipeline_cache: Option<&blade_graphics::PipelineCache>,
     ) -> blade_graphics::RenderPipeline {
         let layout = <PostProcData as blade_graphics::ShaderData>::layout();
         gpu.create_render_pipeline(blade_graphics::RenderPipelineDesc {
@@ -582,9 +596,13 @@ impl ShaderPipelines {
                 ..Default::default()
             },
             vertex: shader.at("blit_vs"),
+            vertex_fetches: &[],
             fragment: shader.at("blit_fs"),
             color_targets: &[format.into()],
             depth_stencil: None,
+            multisample_state: blade_graphics::MultisampleState::default(),
+            overrides: &[],
+            cache: pipeline_cache,
         })
     }
 
@@ -596,19 +614,26 @@ impl ShaderPipelines {
     ) -> Result<Self, &'static str> {
         let sh_main = shader_man[shaders.ray_trace].raw.as_ref().unwrap();
         let sh_blur = shader_man[shaders.blur].raw.as_ref().unwrap();
+        let cache = shader_man.baker.pipeline_cache();
         Ok(Self {
-            fill: Self::create_gbuf_fill(shader_man[shaders.fill_gbuf].raw.as_ref().unwrap(), gpu),
-            main: Self::create_ray_trace(sh_main, gpu),
-            temporal_accum: Self::create_temporal_accum(sh_blur, gpu),
-            atrous: Self::create_atrous(sh_blur, gpu),
+            fill: Self::create_gbuf_fill(
+                shader_man[shaders.fill_gbuf].raw.as_ref().unwrap(),
+                gpu,
+                cache,
+            ),
+            main: Self::create_ray_trace(sh_main, gpu, cache),
+            temporal_accum: Self::create_temporal_accum(sh_blur, gpu, cache),
+            atrous: Self::create_atrous(sh_blur, gpu, cache),
             post_proc: Self::create_post_proc(
                 shader_man[shaders.post_proc].raw.as_ref().unwrap(),
                 config.surface_format,
                 gpu,
+                cache,
             ),
             env_prepare: EnvironmentMap::init_pipeline(
                 shader_man[shaders.env_prepare].raw.as_ref().unwrap(),
                 gpu,
+                cache,
             )?,
             reservoir_size: sh_main.get_struct_size("StoredReservoir"),
         })
@@ -625,9 +650,8 @@ pub struct FrameResources {
 impl Renderer {
     /// Create a new renderer with a given configuration.
     ///
-    /// Panics if the system is not compatible.
+    /// Panics if the system is not compatible, see `try_new`.
     /// Records initialization routines into the given command encoder.
-    #[profiling::function]
     pub fn new(
         encoder: &mut blade_graphics::CommandEncoder,
         gpu: &blade_graphics::Context,
@@ -635,12 +659,33 @@ impl Renderer {
         shader_man: &blade_asset::AssetManager<crate::shader::Baker>,
         config: &RenderConfig,
     ) -> Self {
+        Self::try_new(encoder, gpu, shaders, shader_man, config).unwrap()
+    }
+
+    /// Create a new renderer with a given configuration.
+    ///
+    /// Returns an error if the system is not compatible,
+    /// before recording anything into the command encoder.
+    #[profiling::function]
+    pub fn try_new(
+        encoder: &mut blade_graphics::CommandEncoder,
+        gpu: &blade_graphics::Context,
+        shaders: Shaders,
+        shader_man: &blade_asset::AssetManager<crate::shader::Baker>,
+        config: &RenderConfig,
+    ) -> Result<Self, &'static str> {
         let capabilities = gpu.capabilities();
-        assert!(capabilities
+        if !capabilities
             .ray_query
-            .contains(blade_graphics::ShaderVisibility::COMPUTE));
+            .contains(blade_graphics::ShaderVisibility::COMPUTE)
+        {
+            return Err("Ray queries in compute shaders are required by the renderer");
+        }
+        if !capabilities.binding_arrays {
+            return Err("Binding arrays are required by the renderer");
+        }
 
-        let sp = ShaderPipelines::init(&shaders, config, gpu, shader_man).unwrap();
+        let sp = ShaderPipelines::init(&shaders, config, gpu, shader_man)?;
         let debug = {
             let sh_draw = shader_man[shaders.debug_draw].raw.as_ref().unwrap();
             let sh_blit = shader_man[shaders.debug_blit].raw.as_ref().unwrap();
@@ -676,7 +721,7 @@ impl Renderer {
             }),
         };
 
-        Self {
+        Ok(Self {
             shaders,
             targets,
             post_proc_input_index: 0,
@@ -702,7 +747,7 @@ impl Renderer {
             frame_index: 0,
             frame_scene_built: 0,
             texture_resource_lookup: HashMap::default(),
-        }
+        })
     }
 
     /// Destroy all internally managed GPU resources.
@@ -744,6 +789,7 @@ impl Renderer {
         }
 
         log::info!("Hot reloading shaders");
+        let cache = asset_hub.shaders.baker.pipeline_cache();
         gpu.wait_for(sync_point, !0);
         for task in tasks {
             let _ = task.join();
@@ -751,7 +797,7 @@ impl Renderer {
 
         if self.shaders.fill_gbuf != old.fill_gbuf {
             if let Ok(ref shader) = asset_hub.shaders[self.shaders.fill_gbuf].raw {
-                self.fill_pipeline = ShaderPipelines::create_gbuf_fill(shader, gpu);
+                self.fill_pipeline = ShaderPipelines::create_gbuf_fill(shader, gpu, cache);
             }
         }
         if self.shaders.ray_trace != old.ray_trace {
@@ -760,20 +806,20 @@ impl Renderer {
                     shader.get_struct_size("StoredReservoir"),
                     self.reservoir_size
                 );
-                self.main_pipeline = ShaderPipelines::create_ray_trace(shader, gpu);
+                self.main_pipeline = ShaderPipelines::create_ray_trace(shader, gpu, cache);
             }
         }
         if self.shaders.blur != old.blur {
             if let Ok(ref shader) = asset_hub.shaders[self.shaders.blur].raw {
                 self.blur.temporal_accum_pipeline =
-                    ShaderPipelines::create_temporal_accum(shader, gpu);
-                self.blur.atrous_pipeline = ShaderPipelines::create_atrous(shader, gpu);
+                    ShaderPipelines::create_temporal_accum(shader, gpu, cache);
+                self.blur.atrous_pipeline = ShaderPipelines::create_atrous(shader, gpu, cache);
             }
         }
         if self.shaders.post_proc != old.post_proc {
             if let Ok(ref shader) = asset_hub.shaders[self.shaders.post_proc].raw {
                 self.post_proc_pipeline =
-                    ShaderPipelines::create_post_proc(shader, self.screen_format, gpu);
+                    ShaderPipelines::create_post_proc(shader, self.screen_format, gpu, cache);
             }
         }
         if self.shaders.debug_draw != old.debug_draw {
@@ -865,7 +911,7 @@ impl Renderer {
         });
         temp.buffers.push(hit_staging);
         {
-            let mut transfers = command_encoder.transfer();
+            let mut transfers = command_encoder.transfer_labeled("hit entries");
             transfers.copy_buffer_to_buffer(hit_staging.at(0), self.hit_buffer.at(0), hit_size);
         }
 
@@ -971,7 +1017,8 @@ impl Renderer {
         );
 
         // Needs to be a separate encoder in order to force synchronization
-        let sizes = gpu.get_top_level_acceleration_structure_sizes(instances.len() as u32);
+        let sizes =
+            gpu.get_top_level_acceleration_structure_sizes_for(instances.len() as u32, &blases);
         self.acceleration_structure =
             gpu.create_acceleration_structure(blade_graphics::AccelerationStructureDesc {
                 name: "TLAS",
@@ -985,7 +1032,7 @@ impl Renderer {
             memory: blade_graphics::Memory::Device,
         });
 
-        let mut tlas_encoder = command_encoder.acceleration_structure();
+        let mut tlas_encoder = command_encoder.acceleration_structure_labeled("TLAS");
         tlas_encoder.build_top_level(
             self.acceleration_structure,
             &blases,
@@ -1004,10 +1051,10 @@ impl Renderer {
             view_mode: config.view_mode as u32,
             draw_flags: config.draw_flags.bits(),
             texture_flags: config.texture_flags.bits(),
-            unused: 0,
+            pad: 0,
             mouse_pos: match config.mouse_pos {
-                Some(p) => [p[0], self.screen_size.height as i32 - p[1]],
-                None => [-1; 2],
+                Some(p) => [p[0] as u32, (self.screen_size.height as i32 - p[1]) as u32],
+                None => [!0; 2],
             },
         }
     }
@@ -1036,7 +1083,7 @@ impl Renderer {
         accumulate_variance: bool,
         reset_reservoirs: bool,
     ) {
-        let mut transfer = command_encoder.transfer();
+        let mut transfer = command_encoder.transfer_labeled("prepare");
 
         if enable_debug_draw {
             self.debug.reset_lines(&mut transfer);
@@ -1084,7 +1131,7 @@ impl Renderer {
         let cur = self.frame_index % 2;
         let prev = cur ^ 1;
 
-        if let mut pass = command_encoder.compute() {
+        if let mut pass = command_encoder.compute_labeled("fill-gbuf") {
             let mut pc = pass.with(&self.fill_pipeline);
             let groups = self.fill_pipeline.get_dispatch_for(self.screen_size);
             pc.bind(
@@ -1111,7 +1158,7 @@ impl Renderer {
             pc.dispatch(groups);
         }
 
-        if let mut pass = command_encoder.compute() {
+        if let mut pass = command_encoder.compute_labeled("ray-trace") {
             let mut pc = pass.with(&self.main_pipeline);
             let groups = self.main_pipeline.get_dispatch_for(self.screen_size);
             pc.bind(
@@ -1128,7 +1175,7 @@ impl Renderer {
                         temporal_history: ray_config.temporal_history,
                         spatial_taps: ray_config.spatial_taps,
                         spatial_tap_history: ray_config.spatial_tap_history,
-                        spatial_radius: ray_config.spatial_radius,
+                        spatial_radius: ray_config.spatial_radius as i32,
                         use_motion_vectors: (self.frame_scene_built == self.frame_index) as u32,
                     },
                     acc_struct: self.acceleration_structure,
@@ -1161,6 +1208,7 @@ impl Renderer {
         command_encoder: &mut blade_graphics::CommandEncoder,
         denoiser_config: DenoiserConfig,
     ) {
+        command_encoder.push_debug_group("denoise");
         let mut params = BlurParams {
             extent: [self.screen_size.width, self.screen_size.height],
             temporal_weight: denoiser_config.temporal_weight,
@@ -1173,7 +1221,7 @@ impl Renderer {
         let temp = 2;
 
         if denoiser_config.temporal_weight < 1.0 {
-            let mut pass = command_encoder.compute();
+            let mut pass = command_encoder.compute_labeled("temporal-accum");
             let mut pc = pass.with(&self.blur.temporal_accum_pipeline);
             let groups = self.blur.atrous_pipeline.get_dispatch_for(self.screen_size);
             pc.bind(
@@ -1199,7 +1247,7 @@ impl Renderer {
 
         let mut ping_pong = [temp, prev];
         for _ in 0..denoiser_config.num_passes {
-            let mut pass = command_encoder.compute();
+            let mut pass = command_encoder.compute_labeled("a-trous");
             let mut pc = pass.with(&self.blur.atrous_pipeline);
             let groups = self.blur.atrous_pipeline.get_dispatch_for(self.screen_size);
             pc.bind(
@@ -1217,6 +1265,7 @@ impl Renderer {
             ping_pong.swap(0, 1);
             params.iteration += 1;
         }
+        command_encoder.pop_debug_group();
     }
 
     /// Blit the rendering result into a specified render pass.
@@ -1280,7 +1329,7 @@ impl Renderer {
             custom_index: db_e.custom_index,
             depth: db_e.depth,
             position: db_e.position.into(),
-            normal: db_e.normal.into(),
+            normal: db_e.flat_normal.into(),
             tex_coords: db_e.tex_coords.into(),
             base_color_texture: self
                 .texture_resource_lookup
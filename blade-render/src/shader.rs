@@ -1,4 +1,11 @@
-use std::{any, collections::HashMap, fmt, fs, path::Path, str, sync::Arc};
+use std::{
+    any,
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str,
+    sync::Arc,
+};
 
 const FAILURE_DUMP_NAME: &str = "_failure.wgsl";
 
@@ -41,6 +48,8 @@ impl Expansion {
 pub struct Baker {
     gpu_context: Arc<blade_graphics::Context>,
     expansions: HashMap<String, Expansion>,
+    /// Pipeline cache along with the file it's persisted in.
+    pipeline_cache: Option<(blade_graphics::PipelineCache, PathBuf)>,
 }
 
 impl Baker {
@@ -48,6 +57,39 @@ impl Baker {
         Self {
             gpu_context: Arc::clone(gpu_context),
             expansions: HashMap::default(),
+            pipeline_cache: None,
+        }
+    }
+
+    /// Create the pipeline cache, restoring it from the given file if it exists.
+    /// The same file is written to by `save_pipeline_cache`.
+    pub fn load_pipeline_cache(&mut self, path: &Path) {
+        let initial_data = fs::read(path).ok();
+        let cache = self
+            .gpu_context
+            .create_pipeline_cache(blade_graphics::PipelineCacheDesc {
+                name: "shaders",
+                initial_data: initial_data.as_deref(),
+            });
+        if let Some((old_cache, _)) = self.pipeline_cache.replace((cache, path.to_path_buf())) {
+            self.gpu_context.destroy_pipeline_cache(old_cache);
+        }
+    }
+
+    pub fn pipeline_cache(&self) -> Option<&blade_graphics::PipelineCache> {
+        self.pipeline_cache.as_ref().map(|pair| &pair.0)
+    }
+
+    pub fn save_pipeline_cache(&self) {
+        if let Some((ref cache, ref path)) = self.pipeline_cache {
+            let data = self.gpu_context.get_pipeline_cache_data(cache);
+            if let Err(e) = fs::write(path, data) {
+                log::warn!(
+                    "Unable to save the pipeline cache to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
         }
     }
 
@@ -113,6 +155,14 @@ pub fn parse_shader(
     text_out
 }
 
+impl Drop for Baker {
+    fn drop(&mut self) {
+        if let Some((cache, _)) = self.pipeline_cache.take() {
+            self.gpu_context.destroy_pipeline_cache(cache);
+        }
+    }
+}
+
 impl blade_asset::Baker for Baker {
     type Meta = Meta;
     type Data<'a> = CookedShader<'a>;
@@ -0,0 +1,224 @@
+//! BC6H encoder for unsigned HDR data.
+//!
+//! Only the single-region mode 11 is used, with 10-bit endpoints
+//! and 4-bit indices. Endpoints are fit to the bounding box of the block,
+//! in the space of half-float bits, which is close to logarithmic.
+
+pub const BLOCK_SIZE: usize = 16;
+
+const MODE: u128 = 0x03;
+const ENDPOINT_BITS: u32 = 10;
+const WEIGHTS: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Convert a float into the bits of an unsigned half-float, rounding towards zero.
+pub fn to_half_bits(value: f32) -> u16 {
+    // also takes care of NaN
+    let value = if value > 0.0 { value.min(65504.0) } else { 0.0 };
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    if exponent > 0 {
+        ((exponent as u32) << 10 | mantissa >> 13) as u16
+    } else if exponent >= -10 {
+        ((mantissa | 0x80_0000) >> (14 - exponent)) as u16
+    } else {
+        0
+    }
+}
+
+/// Scale half-float bits into the 16-bit range the decoder interpolates in.
+fn expand(half: u16) -> i32 {
+    (half as i32 * 64) / 31
+}
+
+fn quantize(value: i32) -> i32 {
+    (value >> (16 - ENDPOINT_BITS)).clamp(0, (1 << ENDPOINT_BITS) - 1)
+}
+
+fn unquantize(value: i32) -> i32 {
+    if value == 0 {
+        0
+    } else if value == (1 << ENDPOINT_BITS) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> ENDPOINT_BITS
+    }
+}
+
+fn interpolate(e0: i32, e1: i32, weight: i32) -> i32 {
+    (e0 * (64 - weight) + e1 * weight + 32) >> 6
+}
+
+fn encode_block(texels: &[[i32; 3]; 16]) -> [u8; BLOCK_SIZE] {
+    let mut low = [i32::MAX; 3];
+    let mut high = [i32::MIN; 3];
+    let mut mean = [0i32; 3];
+    for texel in texels.iter() {
+        for c in 0..3 {
+            low[c] = low[c].min(texel[c]);
+            high[c] = high[c].max(texel[c]);
+            mean[c] += texel[c];
+        }
+    }
+    for m in mean.iter_mut() {
+        *m /= texels.len() as i32;
+    }
+
+    // Flip the channels that are anti-correlated with the widest one,
+    // so that the box diagonal follows the colors.
+    let main = (0..3).max_by_key(|&c| high[c] - low[c]).unwrap();
+    for c in 0..3 {
+        let covariance = texels
+            .iter()
+            .map(|t| (t[main] - mean[main]) as i64 * (t[c] - mean[c]) as i64)
+            .sum::<i64>();
+        if covariance < 0 {
+            std::mem::swap(&mut low[c], &mut high[c]);
+        }
+    }
+
+    let mut q0 = low.map(quantize);
+    let mut q1 = high.map(quantize);
+    let e0 = q0.map(unquantize);
+    let e1 = q1.map(unquantize);
+    let palette = WEIGHTS.map(|w| [0, 1, 2].map(|c| interpolate(e0[c], e1[c], w)));
+
+    let mut indices = texels.map(|texel| {
+        (0..WEIGHTS.len())
+            .min_by_key(|&i| {
+                (0..3)
+                    .map(|c| {
+                        let d = (palette[i][c] - texel[c]) as i64;
+                        d * d
+                    })
+                    .sum::<i64>()
+            })
+            .unwrap()
+    });
+
+    // The anchor index is stored without its most significant bit.
+    if indices[0] >= WEIGHTS.len() / 2 {
+        std::mem::swap(&mut q0, &mut q1);
+        for index in indices.iter_mut() {
+            *index = WEIGHTS.len() - 1 - *index;
+        }
+    }
+
+    let mut bits = MODE;
+    let mut offset = 5;
+    for value in q0.iter().chain(q1.iter()) {
+        bits |= (*value as u128) << offset;
+        offset += ENDPOINT_BITS;
+    }
+    for (i, &index) in indices.iter().enumerate() {
+        bits |= (index as u128) << offset;
+        offset += if i == 0 { 3 } else { 4 };
+    }
+    debug_assert_eq!(offset, 128);
+    bits.to_le_bytes()
+}
+
+/// Compress an RGB image into BC6H blocks, laid out row by row.
+/// Edge blocks are padded by repeating the border texels.
+pub fn compress(texels: &[[f32; 3]], width: usize, height: usize) -> Vec<u8> {
+    assert_eq!(texels.len(), width * height);
+    let blocks_x = (width + 3) / 4;
+    let blocks_y = (height + 3) / 4;
+    let mut output = Vec::with_capacity(blocks_x * blocks_y * BLOCK_SIZE);
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut block = [[0i32; 3]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (bx * 4 + i % 4).min(width - 1);
+                let y = (by * 4 + i / 4).min(height - 1);
+                *texel = texels[y * width + x].map(|v| expand(to_half_bits(v)));
+            }
+            output.extend_from_slice(&encode_block(&block));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+fn decode_block(data: &[u8]) -> [[u16; 3]; 16] {
+    let bits = u128::from_le_bytes(data.try_into().unwrap());
+    assert_eq!(bits & 0x1F, MODE);
+    let endpoint = |i: u32| (bits >> (5 + i * ENDPOINT_BITS)) as i32 & 0x3FF;
+    let e0 = [0, 1, 2].map(|c| unquantize(endpoint(c)));
+    let e1 = [0, 1, 2].map(|c| unquantize(endpoint(3 + c)));
+    let mut offset = 65;
+    let mut output = [[0; 3]; 16];
+    for (i, texel) in output.iter_mut().enumerate() {
+        let index_bits = if i == 0 { 3 } else { 4 };
+        let index = (bits >> offset) as usize & ((1 << index_bits) - 1);
+        offset += index_bits;
+        *texel = [0, 1, 2].map(|c| ((interpolate(e0[c], e1[c], WEIGHTS[index]) * 31) >> 6) as u16);
+    }
+    output
+}
+
+#[test]
+fn test_half_bits() {
+    assert_eq!(to_half_bits(0.0), 0);
+    assert_eq!(to_half_bits(-1.0), 0);
+    assert_eq!(to_half_bits(1.0), 0x3C00);
+    assert_eq!(to_half_bits(0.5), 0x3800);
+    assert_eq!(to_half_bits(65504.0), 0x7BFF);
+    assert_eq!(to_half_bits(1.0e10), 0x7BFF);
+    assert_eq!(to_half_bits(f32::NAN), 0);
+    assert_eq!(to_half_bits(0.000_060_975_55), 0x03FF);
+}
+
+#[test]
+fn test_round_trip() {
+    let texels = (0..36)
+        .map(|i| {
+            let v = 1.0 + i as f32 * 0.25;
+            [v, 2.0 * v, 0.5 * v]
+        })
+        .collect::<Vec<_>>();
+    let data = compress(&texels, 6, 6);
+    assert_eq!(data.len(), 4 * BLOCK_SIZE);
+
+    let block = decode_block(&data[..BLOCK_SIZE]);
+    for (i, decoded) in block.iter().enumerate() {
+        let original = texels[(i / 4) * 6 + i % 4].map(to_half_bits);
+        for c in 0..3 {
+            let error = (decoded[c] as i32 - original[c] as i32).abs();
+            assert!(
+                error < 0x80,
+                "Texel {} channel {}: {:x} instead of {:x}",
+                i,
+                c,
+                decoded[c],
+                original[c]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_reference_blocks() {
+    // Mode 11 blocks assembled by hand from the BC6H layout in the D3D spec:
+    // 5 mode bits, 10-bit RGB endpoints, then a 3-bit anchor and 4-bit indices.
+    // 1.0 is 0x3C00 as a half-float, which is 495 once quantized.
+    const CONSTANT_ONE: [u8; BLOCK_SIZE] = [
+        0xE3, 0xBD, 0xF7, 0xDE, 0x7B, 0xEF, 0xBD, 0xF7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    // Endpoints 0 and 495, with the first texel at index 0 and the rest at 15.
+    const ZERO_THEN_ONE: [u8; BLOCK_SIZE] = [
+        0x03, 0x00, 0x00, 0x00, 0x78, 0xEF, 0xBD, 0xF7, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        0xFF,
+    ];
+
+    assert_eq!(compress(&[[1.0; 3]; 16], 4, 4), CONSTANT_ONE);
+    assert_eq!(decode_block(&CONSTANT_ONE), [[0x3C00; 3]; 16]);
+
+    let mut texels = [[1.0; 3]; 16];
+    texels[0] = [0.0; 3];
+    assert_eq!(compress(&texels, 4, 4), ZERO_THEN_ONE);
+    let mut expected = [[0x3C00; 3]; 16];
+    expected[0] = [0; 3];
+    assert_eq!(decode_block(&ZERO_THEN_ONE), expected);
+}
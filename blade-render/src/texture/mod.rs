@@ -3,6 +3,9 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+#[cfg(feature = "asset")]
+mod bc6h;
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, blade_macros::Flat)]
 struct TextureFormatWrap(blade_graphics::TextureFormat);
@@ -33,6 +36,20 @@ impl fmt::Display for Meta {
     }
 }
 
+/// Pick the format for HDR textures, such as environment maps,
+/// preferring BC6H compression where the device supports it.
+pub fn hdr_format(gpu_context: &blade_graphics::Context) -> blade_graphics::TextureFormat {
+    use blade_graphics::{TextureFormat as Tf, TextureUsage as Tu};
+    [Tf::Bc6hUfloat, Tf::Rgba16Float]
+        .into_iter()
+        .find(|&format| {
+            gpu_context
+                .format_features(format)
+                .contains(Tu::COPY | Tu::RESOURCE)
+        })
+        .unwrap_or(Tf::Rgba32Float)
+}
+
 pub struct Texture {
     pub object: blade_graphics::Texture,
     pub view: blade_graphics::TextureView,
@@ -51,6 +68,29 @@ struct Transfer {
     mip_level: u32,
 }
 
+/// Pack an RGB color into the shared exponent format,
+/// following `EXT_texture_shared_exponent`.
+#[cfg(feature = "asset")]
+fn pack_rgb9e5(texel: [f32; 3]) -> u32 {
+    const MANTISSA_BITS: i32 = 9;
+    const EXPONENT_BIAS: i32 = 15;
+    const MAX_VALUE: f32 = 65408.0;
+    // also takes care of NaN
+    let texel = texel.map(|v| if v > 0.0 { v.min(MAX_VALUE) } else { 0.0 });
+    let max = texel[0].max(texel[1]).max(texel[2]);
+    let mut exponent = if max > 0.0 {
+        (max.log2().floor() as i32).max(-EXPONENT_BIAS - 1) + 1 + EXPONENT_BIAS
+    } else {
+        0
+    };
+    let scale = |exponent: i32| 2f32.powi(exponent - EXPONENT_BIAS - MANTISSA_BITS);
+    if (max / scale(exponent) + 0.5).floor() as u32 == 1 << MANTISSA_BITS {
+        exponent += 1;
+    }
+    let [r, g, b] = texel.map(|v| (v / scale(exponent) + 0.5).floor() as u32);
+    r | g << 9 | b << 18 | (exponent as u32) << 27
+}
+
 //TODO: consider this to be shared within the `AssetHub`?
 #[derive(Default)]
 struct PendingOperations {
@@ -81,13 +121,14 @@ impl Baker {
             encoder.init_texture(init.dst);
         }
         if !pending_ops.transfers.is_empty() {
-            let mut pass = encoder.transfer();
+            let mut pass = encoder.transfer_labeled("texture transfers");
             for transfer in pending_ops.transfers.drain(..) {
                 let dst = blade_graphics::TexturePiece {
                     texture: transfer.dst,
                     mip_level: transfer.mip_level,
                     array_layer: 0,
                     origin: [0; 3],
+                    aspects: blade_graphics::TexelAspects::empty(),
                 };
                 pass.copy_buffer_to_texture(
                     transfer.stage.into(),
@@ -350,25 +391,66 @@ impl blade_asset::Baker for Baker {
                     .depend_on(&compress_task);
             }
             PlainData::Hdr(data) => {
-                //TODO: compress as BC6E
-                //Note: we convert RGB32 to RGBA32 here, for now
-                assert_eq!(meta.format, blade_graphics::TextureFormat::Rgba32Float);
-                let in_texel_elements = data[0].len();
-                let out_texel_size = 4 * mem::size_of::<f32>();
-                let mut buf = vec![0u8; data.len() * out_texel_size];
-                for (slice, texel) in buf.chunks_mut(out_texel_size).zip(data) {
-                    unsafe {
-                        ptr::copy_nonoverlapping(
-                            texel.as_ptr(),
-                            slice.as_mut_ptr() as *mut f32,
-                            in_texel_elements,
-                        )
+                let (format, buf) = match meta.format {
+                    Tf::Bc6hUfloat => {
+                        profiling::scope!("compress bc6h");
+                        (meta.format, bc6h::compress(&data, src.width, src.height))
                     }
-                }
+                    Tf::Rgba16Float => {
+                        let mut buf = Vec::with_capacity(data.len() * 4 * mem::size_of::<u16>());
+                        for texel in data {
+                            for value in texel.iter().chain(&[1.0]) {
+                                let half = bc6h::to_half_bits(*value);
+                                buf.extend_from_slice(&half.to_le_bytes());
+                            }
+                        }
+                        (meta.format, buf)
+                    }
+                    Tf::Rg11b10Float => {
+                        let mut buf = Vec::with_capacity(data.len() * mem::size_of::<u32>());
+                        for texel in data {
+                            // the small floats are half-floats with fewer mantissa bits
+                            let [r, g, b] = texel.map(|v| bc6h::to_half_bits(v) as u32);
+                            let packed = r >> 4 | (g >> 4) << 11 | (b >> 5) << 22;
+                            buf.extend_from_slice(&packed.to_le_bytes());
+                        }
+                        (meta.format, buf)
+                    }
+                    Tf::Rgb9e5Ufloat => {
+                        let mut buf = Vec::with_capacity(data.len() * mem::size_of::<u32>());
+                        for texel in data {
+                            buf.extend_from_slice(&pack_rgb9e5(texel).to_le_bytes());
+                        }
+                        (meta.format, buf)
+                    }
+                    other => {
+                        if other != Tf::Rgba32Float {
+                            log::error!(
+                                "Unsupported HDR destination format {:?}, using {:?}",
+                                other,
+                                Tf::Rgba32Float
+                            );
+                        }
+                        //Note: we convert RGB32 to RGBA32 here, for now
+                        let in_texel_elements = data[0].len();
+                        let out_texel_size = 4 * mem::size_of::<f32>();
+                        let mut buf = vec![0u8; data.len() * out_texel_size];
+                        for (slice, texel) in buf.chunks_mut(out_texel_size).zip(data) {
+                            unsafe {
+                                ptr::copy_nonoverlapping(
+                                    texel.as_ptr(),
+                                    slice.as_mut_ptr() as *mut f32,
+                                    in_texel_elements,
+                                )
+                            }
+                        }
+                        (Tf::Rgba32Float, buf)
+                    }
+                };
                 cooker.finish(CookedImage {
                     name: &[],
                     extent: [src.width as u32, src.height as u32, 1],
-                    format: TextureFormatWrap(meta.format),
+                    format: TextureFormatWrap(format),
                     mips: vec![CookedMip { data: &buf }],
                 });
             }
@@ -394,6 +476,7 @@ impl blade_asset::Baker for Baker {
                 size: base_extent,
                 array_layer_count: 1,
                 mip_level_count: image.mips.len() as u32,
+                sample_count: 1,
                 dimension: blade_graphics::TextureDimension::D2,
                 usage: blade_graphics::TextureUsage::COPY | blade_graphics::TextureUsage::RESOURCE,
             });
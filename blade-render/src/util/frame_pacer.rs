@@ -16,6 +16,7 @@ impl FramePacer {
     pub fn new(context: &blade_graphics::Context) -> Self {
         let encoder = context.create_command_encoder(blade_graphics::CommandEncoderDesc {
             name: "main",
+            command_type: blade_graphics::CommandType::General,
             buffer_count: 2,
         });
         Self {
@@ -44,6 +45,11 @@ impl FramePacer {
         self.prev_sync_point.as_ref()
     }
 
+    /// GPU timings of a recently finished frame.
+    pub fn timings(&self) -> &blade_graphics::Timings {
+        self.command_encoder.as_ref().unwrap().timings()
+    }
+
     pub fn destroy(&mut self, context: &blade_graphics::Context) {
         self.wait_for_previous_frame(context);
         context.destroy_command_encoder(self.command_encoder.take().unwrap());
@@ -52,6 +58,13 @@ impl FramePacer {
     pub fn begin_frame(&mut self) -> (&mut blade_graphics::CommandEncoder, &mut FrameResources) {
         let encoder = self.command_encoder.as_mut().unwrap();
         encoder.start();
+        // The timings of the previous submission are resolved by `start`,
+        // so forward them to the profiler before the new frame is recorded.
+        for &(ref name, time) in encoder.timings() {
+            let report = format!("{}: {:.2} ms", name, time.as_secs_f32() * 1000.0);
+            log::trace!("GPU pass {}", report);
+            profiling::scope!("GPU pass", report.as_str());
+        }
         (encoder, &mut self.next_resources)
     }
 
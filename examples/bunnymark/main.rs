@@ -59,6 +59,8 @@ impl Example {
                 gpu::ContextDesc {
                     validation: cfg!(debug_assertions),
                     capture: false,
+                    timing: false,
+                    adapter: gpu::AdapterSelector::Any,
                 },
             )
             .unwrap()
@@ -72,6 +74,7 @@ impl Example {
             },
             usage: gpu::TextureUsage::TARGET,
             frame_count: 3,
+            color_space: gpu::ColorSpace::Linear,
         });
 
         let global_layout = <Params as gpu::ShaderData>::layout();
@@ -88,6 +91,7 @@ impl Example {
             name: "main",
             data_layouts: &[&global_layout, &local_layout],
             vertex: shader.at("vs_main"),
+            vertex_fetches: &[],
             primitive: gpu::PrimitiveState {
                 topology: gpu::PrimitiveTopology::TriangleStrip,
                 ..Default::default()
@@ -99,6 +103,9 @@ impl Example {
                 blend: Some(gpu::BlendState::ALPHA_BLENDING),
                 write_mask: gpu::ColorWrites::default(),
             }],
+            multisample_state: gpu::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
 
         let extent = gpu::Extent {
@@ -113,6 +120,7 @@ impl Example {
             dimension: gpu::TextureDimension::D2,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             usage: gpu::TextureUsage::RESOURCE | gpu::TextureUsage::COPY,
         });
         let view = context.create_texture_view(gpu::TextureViewDesc {
@@ -155,6 +163,7 @@ impl Example {
 
         let mut command_encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
             name: "main",
+            command_type: gpu::CommandType::General,
             buffer_count: 2,
         });
         command_encoder.start();
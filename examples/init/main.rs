@@ -27,6 +27,7 @@ impl EnvMapSampler {
             size,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             dimension: gpu::TextureDimension::D2,
             usage: gpu::TextureUsage::TARGET,
         });
@@ -43,6 +44,7 @@ impl EnvMapSampler {
             name: "env-init",
             data_layouts: &[&layout],
             vertex: shader.at("vs_init"),
+            vertex_fetches: &[],
             fragment: shader.at("fs_init"),
             primitive: gpu::PrimitiveState {
                 topology: gpu::PrimitiveTopology::TriangleStrip,
@@ -54,11 +56,15 @@ impl EnvMapSampler {
                 blend: None,
                 write_mask: gpu::ColorWrites::ALL,
             }],
+            multisample_state: gpu::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
         let accum_pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
             name: "env-accum",
             data_layouts: &[&layout],
             vertex: shader.at("vs_accum"),
+            vertex_fetches: &[],
             fragment: shader.at("fs_accum"),
             primitive: gpu::PrimitiveState {
                 topology: gpu::PrimitiveTopology::PointList,
@@ -70,6 +76,9 @@ impl EnvMapSampler {
                 blend: Some(gpu::BlendState::ADDITIVE),
                 write_mask: gpu::ColorWrites::RED,
             }],
+            multisample_state: gpu::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
 
         Self {
@@ -136,6 +145,8 @@ fn main() {
         gpu::Context::init(gpu::ContextDesc {
             validation: cfg!(debug_assertions),
             capture: true,
+            timing: false,
+            adapter: gpu::AdapterSelector::Any,
         })
         .unwrap()
     });
@@ -170,7 +181,7 @@ fn main() {
         if arg.ends_with(".exr") {
             println!("\tenvironment map = {}", arg);
             let meta = blade_render::texture::Meta {
-                format: blade_graphics::TextureFormat::Rgba32Float,
+                format: blade_render::texture::hdr_format(&context),
                 generate_mips: false,
                 y_flip: false,
             };
@@ -194,6 +205,7 @@ fn main() {
     println!("Flushing GPU work");
     let mut command_encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
         name: "init",
+        command_type: gpu::CommandType::General,
         buffer_count: 1,
     });
     command_encoder.start();
@@ -35,6 +35,8 @@ fn main() {
         gpu::Context::init(gpu::ContextDesc {
             validation: true,
             capture: true,
+            timing: false,
+            adapter: gpu::AdapterSelector::Any,
         })
         .unwrap()
     };
@@ -49,6 +51,8 @@ fn main() {
         name: "main",
         data_layouts: &[&global_layout],
         compute: shader.at("main"),
+        overrides: &[],
+        cache: None,
     });
 
     let extent = gpu::Extent {
@@ -64,6 +68,7 @@ fn main() {
         dimension: gpu::TextureDimension::D2,
         array_layer_count: 1,
         mip_level_count,
+        sample_count: 1,
         usage: gpu::TextureUsage::RESOURCE | gpu::TextureUsage::STORAGE | gpu::TextureUsage::COPY,
     });
     let views = (0..mip_level_count)
@@ -78,6 +83,7 @@ fn main() {
                     mip_level_count: NonZeroU32::new(1),
                     base_array_layer: 0,
                     array_layer_count: None,
+                    aspects: gpu::TexelAspects::empty(),
                 },
             })
         })
@@ -110,6 +116,7 @@ fn main() {
 
     let mut command_encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
         name: "main",
+        command_type: gpu::CommandType::General,
         buffer_count: 1,
     });
     command_encoder.start();
@@ -150,6 +157,7 @@ fn main() {
                 mip_level: mip_level_count - 1,
                 array_layer: 0,
                 origin: Default::default(),
+                aspects: gpu::TexelAspects::empty(),
             },
             result_buffer.into(),
             4,
@@ -21,6 +21,8 @@ impl Example {
                 gpu::ContextDesc {
                     validation: cfg!(debug_assertions),
                     capture: false,
+                    timing: false,
+                    adapter: gpu::AdapterSelector::Any,
                 },
             )
             .unwrap()
@@ -34,6 +36,7 @@ impl Example {
             },
             usage: gpu::TextureUsage::TARGET,
             frame_count: 3,
+            color_space: gpu::ColorSpace::Linear,
         });
         let gui_painter = blade_egui::GuiPainter::new(surface_format, &context);
         let particle_system = particle::System::new(
@@ -47,6 +50,7 @@ impl Example {
 
         let mut command_encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
             name: "main",
+            command_type: gpu::CommandType::General,
             buffer_count: 2,
         });
         command_encoder.start();
@@ -79,16 +79,22 @@ impl System {
             name: &format!("{} - reset", desc.name),
             data_layouts: &[&main_layout],
             compute: shader.at("reset"),
+            overrides: &[],
+            cache: None,
         });
         let emit_pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
             name: &format!("{} - emit", desc.name),
             data_layouts: &[&main_layout, &update_layout],
             compute: shader.at("emit"),
+            overrides: &[],
+            cache: None,
         });
         let update_pipeline = context.create_compute_pipeline(gpu::ComputePipelineDesc {
             name: &format!("{} - update", desc.name),
             data_layouts: &[&main_layout, &update_layout],
             compute: shader.at("update"),
+            overrides: &[],
+            cache: None,
         });
         let draw_pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
             name: &format!("{} - draw", desc.name),
@@ -98,6 +104,7 @@ impl System {
                 ..Default::default()
             },
             vertex: shader.at("draw_vs"),
+            vertex_fetches: &[],
             fragment: shader.at("draw_fs"),
             color_targets: &[gpu::ColorTargetState {
                 format: desc.draw_format,
@@ -105,6 +112,9 @@ impl System {
                 write_mask: gpu::ColorWrites::default(),
             }],
             depth_stencil: None,
+            multisample_state: gpu::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
 
         let wg_width = reset_pipeline.get_workgroup_size()[0] as usize;
@@ -52,6 +52,8 @@ impl Example {
                 gpu::ContextDesc {
                     validation: cfg!(debug_assertions),
                     capture: false,
+                    timing: false,
+                    adapter: gpu::AdapterSelector::Any,
                 },
             )
             .unwrap()
@@ -74,6 +76,7 @@ impl Example {
             dimension: gpu::TextureDimension::D2,
             array_layer_count: 1,
             mip_level_count: 1,
+            sample_count: 1,
             usage: gpu::TextureUsage::RESOURCE | gpu::TextureUsage::STORAGE,
         });
         let target_view = context.create_texture_view(gpu::TextureViewDesc {
@@ -88,6 +91,7 @@ impl Example {
             size: screen_size,
             usage: gpu::TextureUsage::TARGET,
             frame_count: 3,
+            color_space: gpu::ColorSpace::Linear,
         });
 
         let source = std::fs::read_to_string("examples/ray-query/shader.wgsl").unwrap();
@@ -98,6 +102,8 @@ impl Example {
             name: "ray-trace",
             data_layouts: &[&rt_layout],
             compute: shader.at("main"),
+            overrides: &[],
+            cache: None,
         });
         let draw_pipeline = context.create_render_pipeline(gpu::RenderPipelineDesc {
             name: "main",
@@ -107,9 +113,13 @@ impl Example {
                 ..Default::default()
             },
             vertex: shader.at("draw_vs"),
+            vertex_fetches: &[],
             fragment: shader.at("draw_fs"),
             color_targets: &[surface_format.into()],
             depth_stencil: None,
+            multisample_state: gpu::MultisampleState::default(),
+            overrides: &[],
+            cache: None,
         });
 
         let (indices_usize, vertex_values) =
@@ -187,7 +197,8 @@ impl Example {
                 custom_index: 0,
             },
         ];
-        let tlas_sizes = context.get_top_level_acceleration_structure_sizes(instances.len() as u32);
+        let tlas_sizes =
+            context.get_top_level_acceleration_structure_sizes_for(instances.len() as u32, &[blas]);
         let instance_buffer =
             context.create_acceleration_structure_instance_buffer(&instances, &[blas]);
         let tlas = context.create_acceleration_structure(gpu::AccelerationStructureDesc {
@@ -205,6 +216,7 @@ impl Example {
 
         let mut command_encoder = context.create_command_encoder(gpu::CommandEncoderDesc {
             name: "main",
+            command_type: gpu::CommandType::General,
             buffer_count: 2,
         });
         command_encoder.start();
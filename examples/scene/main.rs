@@ -162,6 +162,7 @@ impl Example {
             },
             usage: gpu::TextureUsage::TARGET,
             frame_count: 3,
+            color_space: gpu::ColorSpace::Linear,
         }
     }
 
@@ -175,6 +176,8 @@ impl Example {
                 gpu::ContextDesc {
                     validation: cfg!(debug_assertions),
                     capture: false,
+                    timing: true,
+                    adapter: gpu::AdapterSelector::Any,
                 },
             )
             .unwrap()
@@ -323,7 +326,7 @@ impl Example {
 
         if !config_scene.environment_map.is_empty() {
             let meta = blade_render::texture::Meta {
-                format: gpu::TextureFormat::Rgba32Float,
+                format: blade_render::texture::hdr_format(&self.context),
                 generate_mips: false,
                 y_flip: false,
             };
@@ -861,6 +864,13 @@ impl Example {
                     plot_ui.line(fd_line);
                     plot_ui.hline(egui_plot::HLine::new(1000.0 / 60.0).name("smooth"));
                 });
+            for (name, time) in self.pacer.timings() {
+                let millis = time.as_secs_f32() * 1000.0;
+                ui.horizontal(|ui| {
+                    ui.label(name);
+                    ui.colored_label(egui::Color32::WHITE, format!("{:.2} ms", millis));
+                });
+            }
         });
     }
 
@@ -237,6 +237,7 @@ impl Engine {
             },
             usage: gpu::TextureUsage::TARGET,
             frame_count: 3,
+            color_space: gpu::ColorSpace::Linear,
         }
     }
 
@@ -251,6 +252,8 @@ impl Engine {
                 gpu::ContextDesc {
                     validation: cfg!(debug_assertions),
                     capture: false,
+                    timing: true,
+                    adapter: gpu::AdapterSelector::Any,
                 },
             )
             .unwrap()
@@ -515,14 +518,17 @@ impl Engine {
         let frame = self.gpu_context.acquire_frame();
         command_encoder.init_texture(frame.texture());
 
-        if let mut pass = command_encoder.render(gpu::RenderTargetSet {
-            colors: &[gpu::RenderTarget {
-                view: frame.texture_view(),
-                init_op: gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
-                finish_op: gpu::FinishOp::Store,
-            }],
-            depth_stencil: None,
-        }) {
+        if let mut pass = command_encoder.render_labeled(
+            "draw",
+            gpu::RenderTargetSet {
+                colors: &[gpu::RenderTarget {
+                    view: frame.texture_view(),
+                    init_op: gpu::InitOp::Clear(gpu::TextureColor::TransparentBlack),
+                    finish_op: gpu::FinishOp::Store,
+                }],
+                depth_stencil: None,
+            },
+        ) {
             let screen_desc = blade_egui::ScreenDescriptor {
                 physical_size: (physical_size.width, physical_size.height),
                 scale_factor,
@@ -566,6 +572,35 @@ impl Engine {
                         }
                     });
             });
+        egui::CollapsingHeader::new("GPU timings")
+            .default_open(false)
+            .show(ui, |ui| {
+                for (name, time) in self.pacer.timings() {
+                    let millis = time.as_secs_f32() * 1000.0;
+                    ui.horizontal(|ui| {
+                        ui.label(name);
+                        ui.colored_label(egui::Color32::WHITE, format!("{:.2} ms", millis));
+                    });
+                }
+            });
+        egui::CollapsingHeader::new("Memory")
+            .default_open(false)
+            .show(ui, |ui| {
+                let report = self.gpu_context.memory_report();
+                for (index, heap) in report.heaps.iter().enumerate() {
+                    ui.label(format!(
+                        "Heap {index}: {} / {} MB",
+                        heap.usage >> 20,
+                        heap.budget >> 20
+                    ));
+                }
+                ui.label(format!("Buffers: {}", report.buffer_count));
+                ui.label(format!("Textures: {}", report.texture_count));
+                ui.label(format!(
+                    "Acceleration structures: {}",
+                    report.acceleration_structure_count
+                ));
+            });
         egui::CollapsingHeader::new("Visualize")
             .default_open(true)
             .show(ui, |ui| {
@@ -904,7 +939,7 @@ impl Engine {
             let (handle, task) = self.asset_hub.textures.load(
                 full,
                 blade_render::texture::Meta {
-                    format: gpu::TextureFormat::Rgba32Float,
+                    format: blade_render::texture::hdr_format(&self.gpu_context),
                     generate_mips: false,
                     y_flip: false,
                 },